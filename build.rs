@@ -1,4 +1,5 @@
 fn main() {
     println!("cargo:rustc-link-lib=zfs");
+    println!("cargo:rustc-link-lib=zfs_core");
     println!("cargo:rustc-link-lib=nvpair");
 }
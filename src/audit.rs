@@ -0,0 +1,635 @@
+// SPDX-License-Identifier: MPL-2.0
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Structured audit records for mutating operations, sent to journald.
+
+use std::ops::Deref;
+use std::os::fd::BorrowedFd;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::be::{
+    BootEnvironment, BootRecord, ChildDataset, Client, DestroyPlan, Error, FileChange, Finding,
+    HealthIssue, Label, MountOptions, Property, Replication, RolledBack, Root, Snapshot,
+};
+
+/// The journal MESSAGE_ID of every audit record, so that they can be queried
+/// with `journalctl MESSAGE_ID=...`.
+pub const MESSAGE_ID: &str = "6b3f1c0e8a5d4e2f9c7b1a0d3e5f7a92";
+
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Who requested an operation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Caller {
+    pub uid: u32,
+    pub pid: Option<u32>,
+    /// The Polkit action the caller was authorized for, if any.
+    pub action: Option<String>,
+}
+
+impl Caller {
+    /// This process, for operations carried out directly (i.e. via libzfs).
+    pub fn current() -> Self {
+        Self {
+            uid: unsafe { libc::getuid() },
+            pid: Some(std::process::id()),
+            action: None,
+        }
+    }
+}
+
+/// How a requested operation turned out.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Success,
+    Failure(String),
+    Denied,
+}
+
+impl Outcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Success => "success",
+            Outcome::Failure(_) => "failure",
+            Outcome::Denied => "denied",
+        }
+    }
+}
+
+/// Send an audit record for an operation (e.g., `activate`) on a target
+/// (usually a boot environment or snapshot name) to the journal.
+///
+/// Failing to do so is not fatal, since journald may not be running at all.
+pub fn record(operation: &str, target: Option<&str>, caller: &Caller, outcome: &Outcome) {
+    let _ = send(&fields(operation, target, caller, outcome));
+}
+
+fn fields(
+    operation: &str,
+    target: Option<&str>,
+    caller: &Caller,
+    outcome: &Outcome,
+) -> Vec<(&'static str, String)> {
+    let pid = caller
+        .pid
+        .map_or(String::new(), |pid| format!(" (PID {})", pid));
+    let target_desc = target.map_or(String::new(), |target| format!(" of '{}'", target));
+    let message = match outcome {
+        Outcome::Success => format!(
+            "{}{} by UID {}{} succeeded",
+            operation, target_desc, caller.uid, pid
+        ),
+        Outcome::Failure(error) => format!(
+            "{}{} by UID {}{} failed: {}",
+            operation, target_desc, caller.uid, pid, error
+        ),
+        Outcome::Denied => format!(
+            "{}{} by UID {}{} was denied",
+            operation, target_desc, caller.uid, pid
+        ),
+    };
+    let priority = match outcome {
+        Outcome::Success => 6, // Info
+        _ => 5,                // Notice
+    };
+
+    let mut fields = vec![
+        ("MESSAGE", message),
+        ("MESSAGE_ID", MESSAGE_ID.to_string()),
+        ("PRIORITY", priority.to_string()),
+        ("SYSLOG_IDENTIFIER", "beadm".to_string()),
+        ("BEADM_OPERATION", operation.to_string()),
+        ("BEADM_OUTCOME", outcome.as_str().to_string()),
+        ("BEADM_CALLER_UID", caller.uid.to_string()),
+    ];
+    if let Some(pid) = caller.pid {
+        fields.push(("BEADM_CALLER_PID", pid.to_string()));
+    }
+    if let Some(target) = target {
+        fields.push(("BEADM_TARGET", target.to_string()));
+    }
+    if let Some(action) = &caller.action {
+        fields.push(("BEADM_ACTION", action.clone()));
+    }
+    if let Outcome::Failure(error) = outcome {
+        fields.push(("BEADM_ERROR", error.clone()));
+    }
+    fields
+}
+
+/// Serialize fields using the journal's native protocol.
+fn encode(fields: &[(&str, String)]) -> Vec<u8> {
+    let mut payload = Vec::new();
+    for (key, value) in fields {
+        payload.extend_from_slice(key.as_bytes());
+        if value.contains('\n') {
+            // Multi-line values are sent with an explicit length instead.
+            payload.push(b'\n');
+            payload.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            payload.push(b'=');
+        }
+        payload.extend_from_slice(value.as_bytes());
+        payload.push(b'\n');
+    }
+    payload
+}
+
+fn send(fields: &[(&str, String)]) -> std::io::Result<()> {
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to(&encode(fields), JOURNAL_SOCKET)?;
+    Ok(())
+}
+
+/// An audit record read back from the journal.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuditEntry {
+    /// Unix timestamp for when the record was written.
+    pub timestamp: i64,
+    pub operation: String,
+    pub target: Option<String>,
+    pub uid: Option<u32>,
+    pub pid: Option<u32>,
+    pub action: Option<String>,
+    pub outcome: String,
+    pub error: Option<String>,
+}
+
+impl AuditEntry {
+    /// Parse an entry from `journalctl -o json` output.
+    fn from_journal(entry: &serde_json::Value) -> Option<Self> {
+        let field = |key: &str| entry.get(key)?.as_str().map(String::from);
+        let micros: i64 = field("__REALTIME_TIMESTAMP")?.parse().ok()?;
+        Some(Self {
+            timestamp: micros / 1_000_000,
+            operation: field("BEADM_OPERATION")?,
+            target: field("BEADM_TARGET"),
+            uid: field("BEADM_CALLER_UID").and_then(|uid| uid.parse().ok()),
+            pid: field("BEADM_CALLER_PID").and_then(|pid| pid.parse().ok()),
+            action: field("BEADM_ACTION"),
+            outcome: field("BEADM_OUTCOME")?,
+            error: field("BEADM_ERROR"),
+        })
+    }
+}
+
+/// Query audit records from the journal (via `journalctl`), oldest first.
+/// `since` is passed on to `journalctl --since` and `lines` limits the results
+/// to the most recent records.
+pub fn query(since: Option<&str>, lines: Option<usize>) -> anyhow::Result<Vec<AuditEntry>> {
+    let mut command = Command::new("journalctl");
+    command.args(["--no-pager", "--output=json"]);
+    if let Some(since) = since {
+        command.arg(format!("--since={}", since));
+    }
+    if let Some(lines) = lines {
+        command.arg(format!("--lines={}", lines));
+    }
+    command.arg(format!("MESSAGE_ID={}", MESSAGE_ID));
+    let output = command.output()?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "journalctl failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(parse_journal_output(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+fn parse_journal_output(output: &str) -> Vec<AuditEntry> {
+    output
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .filter_map(|entry| AuditEntry::from_journal(&entry))
+        .collect()
+}
+
+/// A client that sends an audit record for every mutating operation, on
+/// behalf of a particular caller.
+pub struct AuditedClient<C> {
+    inner: C,
+    caller: Caller,
+}
+
+impl<C> AuditedClient<C> {
+    pub fn new(inner: C, caller: Caller) -> Self {
+        Self { inner, caller }
+    }
+
+    fn audit<T>(
+        &self,
+        operation: &str,
+        target: Option<&str>,
+        result: Result<T, Error>,
+    ) -> Result<T, Error> {
+        let outcome = match &result {
+            Ok(_) => Outcome::Success,
+            Err(err) => Outcome::Failure(err.to_string()),
+        };
+        record(operation, target, &self.caller, &outcome);
+        result
+    }
+}
+
+impl<C> Client for AuditedClient<C>
+where
+    C: Deref + Send + Sync,
+    C::Target: Client,
+{
+    fn create(
+        &self,
+        be_name: &str,
+        description: Option<&str>,
+        source: Option<&Label>,
+        properties: &[String],
+        root: Option<&Root>,
+    ) -> Result<(), Error> {
+        let result = self
+            .inner
+            .create(be_name, description, source, properties, root);
+        self.audit("create", Some(be_name), result)
+    }
+
+    fn create_empty(
+        &self,
+        be_name: &str,
+        description: Option<&str>,
+        host_id: Option<&str>,
+        properties: &[String],
+        root: Option<&Root>,
+    ) -> Result<(), Error> {
+        let result = self
+            .inner
+            .create_empty(be_name, description, host_id, properties, root);
+        self.audit("create-empty", Some(be_name), result)
+    }
+
+    fn destroy(
+        &self,
+        target: &Label,
+        force_unmount: bool,
+        snapshots: bool,
+        unpin: bool,
+        dry_run: bool,
+        root: Option<&Root>,
+    ) -> Result<DestroyPlan, Error> {
+        let result = self
+            .inner
+            .destroy(target, force_unmount, snapshots, unpin, dry_run, root);
+        if dry_run {
+            return result; // Nothing changed.
+        }
+        self.audit("destroy", Some(&target.to_string()), result)
+    }
+
+    fn mount(
+        &self,
+        be_name: &str,
+        mountpoint: Option<&Path>,
+        options: &MountOptions,
+        root: Option<&Root>,
+    ) -> Result<PathBuf, Error> {
+        let result = self.inner.mount(be_name, mountpoint, options, root);
+        self.audit("mount", Some(be_name), result)
+    }
+
+    fn unmount(
+        &self,
+        be_name: &str,
+        force: bool,
+        root: Option<&Root>,
+    ) -> Result<Option<PathBuf>, Error> {
+        let result = self.inner.unmount(be_name, force, root);
+        self.audit("unmount", Some(be_name), result)
+    }
+
+    fn hostid(&self, be_name: &str, root: Option<&Root>) -> Result<Option<u32>, Error> {
+        self.inner.hostid(be_name, root)
+    }
+
+    fn rename(&self, be_name: &str, new_name: &str, root: Option<&Root>) -> Result<(), Error> {
+        let result = self.inner.rename(be_name, new_name, root);
+        self.audit("rename", Some(be_name), result)
+    }
+
+    fn rename_snapshot(
+        &self,
+        be_name: &str,
+        snapshot: &str,
+        new_snapshot: &str,
+        root: Option<&Root>,
+    ) -> Result<(), Error> {
+        let result = self
+            .inner
+            .rename_snapshot(be_name, snapshot, new_snapshot, root);
+        self.audit("rename", Some(&format!("{}@{}", be_name, snapshot)), result)
+    }
+
+    fn check(&self, be_name: &str, root: Option<&Root>) -> Result<Vec<HealthIssue>, Error> {
+        self.inner.check(be_name, root)
+    }
+
+    fn doctor(&self, fix: bool, root: Option<&Root>) -> Result<Vec<Finding>, Error> {
+        let result = self.inner.doctor(fix, root);
+        if !fix {
+            return result;
+        }
+        self.audit("doctor-fix", root.map(Root::as_str), result)
+    }
+
+    fn activate(&self, be_name: &str, temporary: bool, root: Option<&Root>) -> Result<(), Error> {
+        let result = self.inner.activate(be_name, temporary, root);
+        let operation = if temporary {
+            "activate-temporary"
+        } else {
+            "activate"
+        };
+        self.audit(operation, Some(be_name), result)
+    }
+
+    fn clear_boot_once(&self, root: Option<&Root>) -> Result<(), Error> {
+        let result = self.inner.clear_boot_once(root);
+        self.audit("clear-boot-once", None, result)
+    }
+
+    fn activate_trial(&self, be_name: &str, root: Option<&Root>) -> Result<(), Error> {
+        let result = self.inner.activate_trial(be_name, root);
+        self.audit("activate-trial", Some(be_name), result)
+    }
+
+    fn cancel_trial(&self, root: Option<&Root>) -> Result<(), Error> {
+        let result = self.inner.cancel_trial(root);
+        self.audit("cancel-trial", None, result)
+    }
+
+    fn confirm(&self, root: Option<&Root>) -> Result<Option<String>, Error> {
+        let result = self.inner.confirm(root);
+        let target = match &result {
+            Ok(Some(name)) => Some(name.clone()),
+            Ok(None) => return result, // Nothing changed.
+            Err(Error::TrialNotBooted { name }) | Err(Error::TrialFailed { name }) => {
+                Some(name.clone())
+            }
+            Err(_) => None,
+        };
+        self.audit("confirm", target.as_deref(), result)
+    }
+
+    fn record_boot(&self, root: Option<&Root>) -> Result<Option<BootRecord>, Error> {
+        self.inner.record_boot(root)
+    }
+
+    fn get_boot_history(&self, root: Option<&Root>) -> Result<Vec<BootRecord>, Error> {
+        self.inner.get_boot_history(root)
+    }
+
+    fn rollback(
+        &self,
+        be_name: &str,
+        snapshot: &str,
+        destroy_newer: bool,
+        force: bool,
+        safety_snapshot: bool,
+        root: Option<&Root>,
+    ) -> Result<RolledBack, Error> {
+        let result = self.inner.rollback(
+            be_name,
+            snapshot,
+            destroy_newer,
+            force,
+            safety_snapshot,
+            root,
+        );
+        self.audit(
+            "rollback",
+            Some(&format!("{}@{}", be_name, snapshot)),
+            result,
+        )
+    }
+
+    fn get_boot_environments(&self, root: Option<&Root>) -> Result<Vec<BootEnvironment>, Error> {
+        self.inner.get_boot_environments(root)
+    }
+
+    fn get_snapshots(&self, be_name: &str, root: Option<&Root>) -> Result<Vec<Snapshot>, Error> {
+        self.inner.get_snapshots(be_name, root)
+    }
+
+    fn get_orphaned_snapshots(&self, root: Option<&Root>) -> Result<Vec<Snapshot>, Error> {
+        self.inner.get_orphaned_snapshots(root)
+    }
+
+    fn get_datasets(&self, be_name: &str, root: Option<&Root>) -> Result<Vec<ChildDataset>, Error> {
+        self.inner.get_datasets(be_name, root)
+    }
+
+    fn snapshot(
+        &self,
+        source: Option<&Label>,
+        description: Option<&str>,
+        root: Option<&Root>,
+    ) -> Result<String, Error> {
+        let result = self.inner.snapshot(source, description, root);
+        let target = match (&result, source) {
+            (Ok(name), _) => Some(name.clone()),
+            (Err(_), Some(source)) => Some(source.to_string()),
+            (Err(_), None) => None,
+        };
+        self.audit("snapshot", target.as_deref(), result)
+    }
+
+    fn init(&self, pool: &str) -> Result<(), Error> {
+        let result = self.inner.init(pool);
+        self.audit("init", Some(pool), result)
+    }
+
+    fn describe(
+        &self,
+        target: &Label,
+        description: &str,
+        root: Option<&Root>,
+    ) -> Result<(), Error> {
+        let result = self.inner.describe(target, description, root);
+        self.audit("describe", Some(&target.to_string()), result)
+    }
+
+    fn pin(&self, target: &Label, root: Option<&Root>) -> Result<(), Error> {
+        let result = self.inner.pin(target, root);
+        self.audit("pin", Some(&target.to_string()), result)
+    }
+
+    fn unpin(&self, target: &Label, root: Option<&Root>) -> Result<(), Error> {
+        let result = self.inner.unpin(target, root);
+        self.audit("unpin", Some(&target.to_string()), result)
+    }
+
+    fn tag(
+        &self,
+        target: &Label,
+        key: &str,
+        value: &str,
+        root: Option<&Root>,
+    ) -> Result<(), Error> {
+        let result = self.inner.tag(target, key, value, root);
+        self.audit("tag", Some(&target.to_string()), result)
+    }
+
+    fn untag(&self, target: &Label, key: &str, root: Option<&Root>) -> Result<(), Error> {
+        let result = self.inner.untag(target, key, root);
+        self.audit("untag", Some(&target.to_string()), result)
+    }
+
+    fn diff(
+        &self,
+        from: &Label,
+        to: Option<&Label>,
+        paths: &[PathBuf],
+        root: Option<&Root>,
+    ) -> Result<Vec<FileChange>, Error> {
+        self.inner.diff(from, to, paths, root)
+    }
+
+    fn export(
+        &self,
+        target: &Label,
+        base: Option<&str>,
+        fd: BorrowedFd<'_>,
+        root: Option<&Root>,
+    ) -> Result<String, Error> {
+        let result = self.inner.export(target, base, fd, root);
+        self.audit("export", Some(&target.to_string()), result)
+    }
+
+    fn import(&self, be_name: &str, fd: BorrowedFd<'_>, root: Option<&Root>) -> Result<(), Error> {
+        let result = self.inner.import(be_name, fd, root);
+        self.audit("import", Some(be_name), result)
+    }
+
+    fn replicate(
+        &self,
+        dest: &Root,
+        names: &[String],
+        root: Option<&Root>,
+    ) -> Result<Vec<Replication>, Error> {
+        let result = self.inner.replicate(dest, names, root);
+        self.audit("replicate", Some(dest.as_str()), result)
+    }
+
+    fn get_zfs_properties(
+        &self,
+        target: &Label,
+        names: &[String],
+        root: Option<&Root>,
+    ) -> Result<Vec<Property>, Error> {
+        self.inner.get_zfs_properties(target, names, root)
+    }
+
+    fn set_zfs_properties(
+        &self,
+        be_name: &str,
+        properties: &[String],
+        root: Option<&Root>,
+    ) -> Result<(), Error> {
+        let result = self.inner.set_zfs_properties(be_name, properties, root);
+        self.audit("set", Some(be_name), result)
+    }
+
+    fn active_root(&self) -> Option<&Root> {
+        self.inner.active_root()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fields() {
+        let caller = Caller {
+            uid: 1000,
+            pid: Some(4242),
+            action: Some("ca.kamacite.BootEnvironments1.manage".to_string()),
+        };
+        let record = fields("activate", Some("alt"), &caller, &Outcome::Success);
+        assert_eq!(
+            record[0],
+            (
+                "MESSAGE",
+                "activate of 'alt' by UID 1000 (PID 4242) succeeded".to_string()
+            )
+        );
+        assert!(record.contains(&("MESSAGE_ID", MESSAGE_ID.to_string())));
+        assert!(record.contains(&("BEADM_OUTCOME", "success".to_string())));
+        assert!(record.contains(&(
+            "BEADM_ACTION",
+            "ca.kamacite.BootEnvironments1.manage".to_string()
+        )));
+        assert!(!record.iter().any(|(key, _)| *key == "BEADM_ERROR"));
+
+        let caller = Caller {
+            uid: 0,
+            pid: None,
+            action: None,
+        };
+        let outcome = Outcome::Failure("Boot environment 'x' not found".to_string());
+        let record = fields("destroy", Some("x"), &caller, &outcome);
+        assert_eq!(
+            record[0].1,
+            "destroy of 'x' by UID 0 failed: Boot environment 'x' not found"
+        );
+        assert!(record.contains(&("BEADM_ERROR", "Boot environment 'x' not found".to_string())));
+        assert!(!record.iter().any(|(key, _)| *key == "BEADM_CALLER_PID"));
+    }
+
+    #[test]
+    fn test_encode() {
+        let encoded = encode(&[
+            ("MESSAGE", "hello".to_string()),
+            ("BEADM_ERROR", "two\nlines".to_string()),
+        ]);
+        let mut expected = b"MESSAGE=hello\nBEADM_ERROR\n".to_vec();
+        expected.extend_from_slice(&9u64.to_le_bytes());
+        expected.extend_from_slice(b"two\nlines\n");
+        assert_eq!(encoded, expected);
+    }
+
+    #[test]
+    fn test_parse_journal_output() {
+        let output = r#"{"__REALTIME_TIMESTAMP":"1623301740123456","MESSAGE_ID":"6b3f1c0e8a5d4e2f9c7b1a0d3e5f7a92","BEADM_OPERATION":"activate","BEADM_TARGET":"alt","BEADM_CALLER_UID":"1000","BEADM_CALLER_PID":"4242","BEADM_ACTION":"ca.kamacite.BootEnvironments1.manage","BEADM_OUTCOME":"success"}
+not json
+{"__REALTIME_TIMESTAMP":"1623305460000000","BEADM_OPERATION":"clear-boot-once","BEADM_CALLER_UID":"0","BEADM_OUTCOME":"failure","BEADM_ERROR":"oops"}
+"#;
+        assert_eq!(
+            parse_journal_output(output),
+            vec![
+                AuditEntry {
+                    timestamp: 1623301740,
+                    operation: "activate".to_string(),
+                    target: Some("alt".to_string()),
+                    uid: Some(1000),
+                    pid: Some(4242),
+                    action: Some("ca.kamacite.BootEnvironments1.manage".to_string()),
+                    outcome: "success".to_string(),
+                    error: None,
+                },
+                AuditEntry {
+                    timestamp: 1623305460,
+                    operation: "clear-boot-once".to_string(),
+                    target: None,
+                    uid: Some(0),
+                    pid: None,
+                    action: None,
+                    outcome: "failure".to_string(),
+                    error: Some("oops".to_string()),
+                },
+            ]
+        );
+    }
+}
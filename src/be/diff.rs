@@ -0,0 +1,596 @@
+// SPDX-License-Identifier: MPL-2.0
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, Metadata};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use super::Error;
+
+/// How a file differs between two boot environments or snapshots.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Modified,
+    Renamed,
+}
+
+impl ChangeKind {
+    /// The indicator used by `zfs diff`.
+    pub fn as_char(self) -> char {
+        match self {
+            ChangeKind::Added => '+',
+            ChangeKind::Removed => '-',
+            ChangeKind::Modified => 'M',
+            ChangeKind::Renamed => 'R',
+        }
+    }
+
+    fn from_char(c: char) -> Option<Self> {
+        match c {
+            '+' => Some(ChangeKind::Added),
+            '-' => Some(ChangeKind::Removed),
+            'M' => Some(ChangeKind::Modified),
+            'R' => Some(ChangeKind::Renamed),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ChangeKind::Added => "added",
+            ChangeKind::Removed => "removed",
+            ChangeKind::Modified => "modified",
+            ChangeKind::Renamed => "renamed",
+        }
+    }
+}
+
+impl FromStr for ChangeKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [
+            ChangeKind::Added,
+            ChangeKind::Removed,
+            ChangeKind::Modified,
+            ChangeKind::Renamed,
+        ]
+        .into_iter()
+        .find(|kind| kind.as_str() == s)
+        .ok_or_else(|| Error::InvalidChange {
+            value: s.to_string(),
+        })
+    }
+}
+
+/// The type of a changed file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FileType {
+    File,
+    Directory,
+    Symlink,
+    Socket,
+    Fifo,
+    BlockDevice,
+    CharDevice,
+    Door,
+    EventPort,
+}
+
+const FILE_TYPES: [FileType; 9] = [
+    FileType::File,
+    FileType::Directory,
+    FileType::Symlink,
+    FileType::Socket,
+    FileType::Fifo,
+    FileType::BlockDevice,
+    FileType::CharDevice,
+    FileType::Door,
+    FileType::EventPort,
+];
+
+impl FileType {
+    /// The indicator used by `zfs diff -F`.
+    pub fn as_char(self) -> char {
+        match self {
+            FileType::File => 'F',
+            FileType::Directory => '/',
+            FileType::Symlink => '@',
+            FileType::Socket => '=',
+            FileType::Fifo => '|',
+            FileType::BlockDevice => 'B',
+            FileType::CharDevice => 'C',
+            FileType::Door => '>',
+            FileType::EventPort => 'P',
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FileType::File => "file",
+            FileType::Directory => "directory",
+            FileType::Symlink => "symlink",
+            FileType::Socket => "socket",
+            FileType::Fifo => "fifo",
+            FileType::BlockDevice => "block-device",
+            FileType::CharDevice => "char-device",
+            FileType::Door => "door",
+            FileType::EventPort => "event-port",
+        }
+    }
+
+    fn from_metadata(metadata: &Metadata) -> Self {
+        let file_type = metadata.file_type();
+        if file_type.is_dir() {
+            FileType::Directory
+        } else if file_type.is_symlink() {
+            FileType::Symlink
+        } else if file_type.is_socket() {
+            FileType::Socket
+        } else if file_type.is_fifo() {
+            FileType::Fifo
+        } else if file_type.is_block_device() {
+            FileType::BlockDevice
+        } else if file_type.is_char_device() {
+            FileType::CharDevice
+        } else {
+            FileType::File
+        }
+    }
+}
+
+impl FromStr for FileType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        FILE_TYPES
+            .into_iter()
+            .find(|file_type| file_type.as_str() == s)
+            .ok_or_else(|| Error::InvalidChange {
+                value: s.to_string(),
+            })
+    }
+}
+
+/// A file that differs between two boot environments or snapshots.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FileChange {
+    /// The path relative to the root of the boot environment (e.g.,
+    /// `/etc/hostname`). As with `zfs diff`, spaces and non-printable bytes
+    /// are escaped as `\NNNN` in octal.
+    pub path: String,
+    pub change: ChangeKind,
+    pub file_type: FileType,
+    /// For renames, the new path.
+    pub new_path: Option<String>,
+}
+
+impl FileChange {
+    /// Whether the change falls beneath any of the given paths, or there are
+    /// none to filter by.
+    pub(crate) fn matches(&self, paths: &[PathBuf]) -> bool {
+        paths.is_empty()
+            || paths.iter().any(|filter| {
+                is_beneath(&self.path, filter)
+                    || self
+                        .new_path
+                        .as_ref()
+                        .is_some_and(|path| is_beneath(path, filter))
+            })
+    }
+
+    /// The same change seen from the other direction.
+    fn inverted(self) -> Self {
+        match self.change {
+            ChangeKind::Added => FileChange {
+                change: ChangeKind::Removed,
+                ..self
+            },
+            ChangeKind::Removed => FileChange {
+                change: ChangeKind::Added,
+                ..self
+            },
+            ChangeKind::Modified => self,
+            ChangeKind::Renamed => FileChange {
+                path: self.new_path.unwrap_or_default(),
+                new_path: Some(self.path),
+                ..self
+            },
+        }
+    }
+}
+
+/// Path filters must be absolute paths within the boot environment.
+pub(crate) fn validate_paths(paths: &[PathBuf]) -> Result<(), Error> {
+    match paths.iter().find(|path| !path.is_absolute()) {
+        Some(path) => Err(Error::InvalidPath {
+            path: path.display().to_string(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Whether a (escaped) path is, or is beneath, a filter path.
+fn is_beneath(path: &str, filter: &Path) -> bool {
+    let filter = escape(filter.as_os_str().as_bytes());
+    let filter = filter.trim_end_matches('/');
+    match path.strip_prefix(filter) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+/// Escape a path the way `zfs diff` does: anything other than printable
+/// ASCII (including spaces and backslashes) is written as `\NNNN` in octal.
+fn escape(bytes: &[u8]) -> String {
+    let mut escaped = String::with_capacity(bytes.len());
+    for &byte in bytes {
+        if byte > b' ' && byte != b'\\' && byte < 0o177 {
+            escaped.push(byte as char);
+        } else {
+            escaped.push_str(&format!("\\{:04o}", byte));
+        }
+    }
+    escaped
+}
+
+/// Filter, sort and (optionally) invert changes, for when a diff had to be
+/// taken in the opposite direction.
+pub(crate) fn finish(changes: Vec<FileChange>, paths: &[PathBuf], invert: bool) -> Vec<FileChange> {
+    let mut changes: Vec<FileChange> = changes
+        .into_iter()
+        .map(|change| if invert { change.inverted() } else { change })
+        .filter(|change| change.matches(paths))
+        .collect();
+    changes.sort();
+    changes
+}
+
+/// Parse the output of `zfs diff -FH` for a filesystem mounted at
+/// `mountpoint`, making paths relative to `base` (the mountpoint of the boot
+/// environment itself). Lines that can't be parsed are skipped.
+pub(crate) fn parse_zfs_diff(output: &str, base: &Path) -> Vec<FileChange> {
+    let base = escape(base.as_os_str().as_bytes());
+    let base = base.trim_end_matches('/');
+    let relative = |path: &str| -> String {
+        match path.strip_prefix(base) {
+            Some("") => "/".to_string(),
+            Some(rest) if rest.starts_with('/') => rest.to_string(),
+            _ => path.to_string(),
+        }
+    };
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let change = ChangeKind::from_char(single_char(fields.next()?)?)?;
+            let file_type = single_char(fields.next()?)?;
+            let file_type = FILE_TYPES.into_iter().find(|t| t.as_char() == file_type)?;
+            let path = relative(fields.next()?);
+            // Modifications may be followed by a change in link count, e.g.
+            // `(+1)`, which isn't of interest.
+            let new_path = match change {
+                ChangeKind::Renamed => Some(relative(fields.next()?)),
+                _ => None,
+            };
+            Some(FileChange {
+                path,
+                change,
+                file_type,
+                new_path,
+            })
+        })
+        .collect()
+}
+
+fn single_char(field: &str) -> Option<char> {
+    let mut chars = field.chars();
+    let c = chars.next()?;
+    chars.next().is_none().then_some(c)
+}
+
+/// The directories backing a boot environment (or snapshot) and each of its
+/// child datasets, keyed by where they appear within the boot environment
+/// (e.g., `/` and `/var`).
+pub(crate) struct Tree {
+    roots: BTreeMap<PathBuf, PathBuf>,
+    devices: HashSet<u64>,
+}
+
+impl Tree {
+    pub fn new(roots: BTreeMap<PathBuf, PathBuf>) -> Result<Self, Error> {
+        // Snapshots of child datasets created since then don't exist, and
+        // their contents just appear to be missing.
+        let mut existing = BTreeMap::new();
+        let mut devices = HashSet::new();
+        for (path, dir) in roots {
+            match fs::metadata(&dir) {
+                Ok(metadata) => {
+                    devices.insert(metadata.dev());
+                    existing.insert(path, dir);
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(Tree {
+            roots: existing,
+            devices,
+        })
+    }
+
+    /// Where a path within the boot environment actually lives.
+    fn resolve(&self, path: &Path) -> Option<PathBuf> {
+        self.roots
+            .iter()
+            .rev()
+            .find_map(|(prefix, dir)| Some(dir.join(path.strip_prefix(prefix).ok()?)))
+    }
+
+    /// Look up a file, or `None` if it doesn't exist. Anything mounted over
+    /// from outside the boot environment (e.g. `/proc` when comparing the
+    /// running system) is reported as an empty directory.
+    fn entry(&self, path: &Path) -> Result<Option<Entry>, Error> {
+        let Some(real) = self.resolve(path) else {
+            return Ok(None);
+        };
+        let metadata = match fs::symlink_metadata(&real) {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let foreign = !self.devices.contains(&metadata.dev());
+        let target = if metadata.file_type().is_symlink() {
+            Some(fs::read_link(&real)?)
+        } else {
+            None
+        };
+        Ok(Some(Entry {
+            real,
+            file_type: FileType::from_metadata(&metadata),
+            metadata: if foreign { None } else { Some(metadata) },
+            target,
+        }))
+    }
+}
+
+struct Entry {
+    real: PathBuf,
+    file_type: FileType,
+    /// None for foreign mounts, which aren't compared or descended into.
+    metadata: Option<Metadata>,
+    target: Option<PathBuf>,
+}
+
+impl Entry {
+    fn differs(&self, other: &Entry) -> bool {
+        match (&self.metadata, &other.metadata) {
+            (Some(a), Some(b)) => {
+                a.mode() != b.mode()
+                    || a.uid() != b.uid()
+                    || a.gid() != b.gid()
+                    || a.mtime() != b.mtime()
+                    || a.mtime_nsec() != b.mtime_nsec()
+                    || (self.file_type != FileType::Directory && a.size() != b.size())
+                    || self.target != other.target
+            }
+            _ => false,
+        }
+    }
+
+    fn children(&self) -> Result<Vec<PathBuf>, Error> {
+        if self.file_type != FileType::Directory || self.metadata.is_none() {
+            return Ok(vec![]);
+        }
+        let mut names = vec![];
+        for entry in fs::read_dir(&self.real)? {
+            names.push(PathBuf::from(entry?.file_name()));
+        }
+        Ok(names)
+    }
+}
+
+/// Compare two trees file by file, for boot environments that don't share
+/// any history. Files are compared by type, mode, ownership, size,
+/// modification time and (for symlinks) target, and renames aren't detected.
+pub(crate) fn walk_diff(
+    from: &Tree,
+    to: &Tree,
+    paths: &[PathBuf],
+) -> Result<Vec<FileChange>, Error> {
+    let mut changes = vec![];
+    walk(from, to, Path::new("/"), paths, &mut changes)?;
+    Ok(finish(changes, paths, false))
+}
+
+fn walk(
+    from: &Tree,
+    to: &Tree,
+    dir: &Path,
+    paths: &[PathBuf],
+    changes: &mut Vec<FileChange>,
+) -> Result<(), Error> {
+    let mut names = BTreeMap::new();
+    for (side, tree) in [(0, from), (1, to)] {
+        if let Some(entry) = tree.entry(dir)? {
+            for name in entry.children()? {
+                names.entry(name).or_insert([false; 2])[side] = true;
+            }
+        }
+    }
+
+    for name in names.keys() {
+        let path = dir.join(name);
+        if !is_relevant(&path, paths) {
+            continue;
+        }
+        match (from.entry(&path)?, to.entry(&path)?) {
+            (Some(a), Some(b)) if a.file_type == b.file_type => {
+                if a.differs(&b) {
+                    changes.push(change(&path, ChangeKind::Modified, a.file_type));
+                }
+                if a.file_type == FileType::Directory {
+                    walk(from, to, &path, paths, changes)?;
+                }
+            }
+            (a, b) => {
+                if let Some(a) = a {
+                    record_all(from, &path, a, ChangeKind::Removed, changes)?;
+                }
+                if let Some(b) = b {
+                    record_all(to, &path, b, ChangeKind::Added, changes)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Record a file, and anything beneath it, as added or removed.
+fn record_all(
+    tree: &Tree,
+    path: &Path,
+    entry: Entry,
+    kind: ChangeKind,
+    changes: &mut Vec<FileChange>,
+) -> Result<(), Error> {
+    changes.push(change(path, kind, entry.file_type));
+    for name in entry.children()? {
+        let child = path.join(name);
+        if let Some(entry) = tree.entry(&child)? {
+            record_all(tree, &child, entry, kind, changes)?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether a directory could contain something matching the filters.
+fn is_relevant(path: &Path, paths: &[PathBuf]) -> bool {
+    paths.is_empty()
+        || paths
+            .iter()
+            .any(|filter| path.starts_with(filter) || filter.starts_with(path))
+}
+
+fn change(path: &Path, change: ChangeKind, file_type: FileType) -> FileChange {
+    FileChange {
+        path: escape(path.as_os_str().as_bytes()),
+        change,
+        file_type,
+        new_path: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_change(change: ChangeKind, file_type: FileType, path: &str) -> FileChange {
+        FileChange {
+            path: path.to_string(),
+            change,
+            file_type,
+            new_path: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_zfs_diff() {
+        let output = "M\t/\t/run/be/abc/etc\n\
+                      +\tF\t/run/be/abc/etc/new\\0040file\n\
+                      -\t@\t/run/be/abc/etc/old-link\n\
+                      M\tF\t/run/be/abc/etc/passwd\t(+1)\n\
+                      R\tF\t/run/be/abc/etc/a\t/run/be/abc/etc/b\n\
+                      garbage\n";
+        let changes = parse_zfs_diff(output, Path::new("/run/be/abc/"));
+        assert_eq!(
+            changes,
+            vec![
+                file_change(ChangeKind::Modified, FileType::Directory, "/etc"),
+                file_change(ChangeKind::Added, FileType::File, "/etc/new\\0040file"),
+                file_change(ChangeKind::Removed, FileType::Symlink, "/etc/old-link"),
+                file_change(ChangeKind::Modified, FileType::File, "/etc/passwd"),
+                FileChange {
+                    new_path: Some("/etc/b".to_string()),
+                    ..file_change(ChangeKind::Renamed, FileType::File, "/etc/a")
+                },
+            ]
+        );
+
+        // Relative to the running system.
+        let changes = parse_zfs_diff("+\tF\t/etc/new\n", Path::new("/"));
+        assert_eq!(changes[0].path, "/etc/new");
+    }
+
+    #[test]
+    fn test_finish() {
+        let changes = vec![
+            file_change(ChangeKind::Added, FileType::File, "/var/log/messages"),
+            FileChange {
+                new_path: Some("/etc/b".to_string()),
+                ..file_change(ChangeKind::Renamed, FileType::File, "/etc/a")
+            },
+            file_change(ChangeKind::Modified, FileType::File, "/etcetera"),
+        ];
+        let filtered = finish(changes.clone(), &[PathBuf::from("/etc/")], false);
+        assert_eq!(filtered, vec![changes[1].clone()]);
+
+        let inverted = finish(
+            changes,
+            &[PathBuf::from("/var"), PathBuf::from("/etc/b")],
+            true,
+        );
+        assert_eq!(
+            inverted,
+            vec![
+                FileChange {
+                    new_path: Some("/etc/a".to_string()),
+                    ..file_change(ChangeKind::Renamed, FileType::File, "/etc/b")
+                },
+                file_change(ChangeKind::Removed, FileType::File, "/var/log/messages"),
+            ]
+        );
+
+        assert!(validate_paths(&[PathBuf::from("etc")]).is_err());
+    }
+
+    #[test]
+    fn test_walk_diff() {
+        let from = tempfile::tempdir().unwrap();
+        let to = tempfile::tempdir().unwrap();
+        for dir in [&from, &to] {
+            fs::create_dir_all(dir.path().join("etc")).unwrap();
+            fs::write(dir.path().join("etc/hostname"), "example\n").unwrap();
+        }
+        fs::write(from.path().join("etc/hostname"), "example.org\n").unwrap();
+        fs::create_dir_all(from.path().join("opt/app/bin")).unwrap();
+        fs::write(to.path().join("etc/motd"), "hello\n").unwrap();
+        std::os::unix::fs::symlink("hostname", to.path().join("etc/name")).unwrap();
+
+        let tree = |dir: &Path| Tree::new(BTreeMap::from([(PathBuf::from("/"), dir.into())]));
+        let (from, to) = (tree(from.path()).unwrap(), tree(to.path()).unwrap());
+        let changes = walk_diff(&from, &to, &[]).unwrap();
+        let summary: Vec<(ChangeKind, FileType, &str)> = changes
+            .iter()
+            .map(|c| (c.change, c.file_type, c.path.as_str()))
+            .collect();
+        assert!(summary.contains(&(ChangeKind::Modified, FileType::File, "/etc/hostname")));
+        assert!(summary.contains(&(ChangeKind::Added, FileType::File, "/etc/motd")));
+        assert!(summary.contains(&(ChangeKind::Added, FileType::Symlink, "/etc/name")));
+        assert!(summary.contains(&(ChangeKind::Removed, FileType::Directory, "/opt")));
+        assert!(summary.contains(&(ChangeKind::Removed, FileType::Directory, "/opt/app/bin")));
+
+        // Only look beneath the filters.
+        let changes = walk_diff(&from, &to, &[PathBuf::from("/opt/app")]).unwrap();
+        let paths: Vec<&str> = changes.iter().map(|c| c.path.as_str()).collect();
+        assert_eq!(paths, vec!["/opt/app", "/opt/app/bin"]);
+    }
+}
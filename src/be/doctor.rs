@@ -0,0 +1,302 @@
+// SPDX-License-Identifier: MPL-2.0
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Findings from `beadm doctor`, which checks the boot environment layout
+//! and pool state for misconfigurations that would otherwise only show up at
+//! boot.
+
+use std::collections::HashSet;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "dbus")]
+use zvariant::{DeserializeDict, SerializeDict, Type};
+
+use super::Error;
+
+/// Directories whose contents belong to a boot environment. A dataset shared
+/// between boot environments that is mounted at (or above, or beneath) one of
+/// these gets out of step with the rest of the system when switching between
+/// them, e.g. a shared `/var` takes the package database with it.
+const SYSTEM_DIRS: &[&str] = &[
+    "/bin", "/boot", "/etc", "/lib", "/lib32", "/lib64", "/sbin", "/usr", "/var/lib",
+];
+
+/// Directories beneath [`SYSTEM_DIRS`] that are fine to share.
+const SHAREABLE_DIRS: &[&str] = &["/usr/local"];
+
+/// A problem found by `beadm doctor`.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "dbus", derive(SerializeDict, DeserializeDict, Type))]
+#[cfg_attr(
+    feature = "dbus",
+    zvariant(signature = "a{sv}", rename_all = "PascalCase")
+)]
+pub struct Finding {
+    /// The check that found it: `layout`, `bootfs`, `previous-bootfs`,
+    /// `temp-mountpoint`, `shared` or `home`.
+    pub check: String,
+    /// The dataset, boot environment or path concerned.
+    pub name: String,
+    /// What is wrong.
+    pub message: String,
+    /// Why it matters, and what to do about it by hand.
+    pub explanation: String,
+    /// What `--fix` does about it, if it can.
+    pub fix: Option<String>,
+    /// Whether the fix was applied.
+    pub fixed: bool,
+}
+
+impl Finding {
+    pub(crate) fn new(check: &str, name: &str, message: String, explanation: &str) -> Self {
+        Self {
+            check: check.to_string(),
+            name: name.to_string(),
+            message,
+            explanation: explanation.to_string(),
+            fix: None,
+            fixed: false,
+        }
+    }
+
+    /// Describe how the problem can be fixed automatically.
+    pub(crate) fn with_fix(mut self, fix: String) -> Self {
+        self.fix = Some(fix);
+        self
+    }
+
+    /// Apply the fix, if there is one and `fix` is set. A failure to fix the
+    /// problem is reported alongside it rather than as an error, so that the
+    /// remaining checks still run.
+    pub(crate) fn apply(mut self, fix: bool, apply: impl FnOnce() -> Result<(), Error>) -> Self {
+        if fix && self.fix.is_some() {
+            match apply() {
+                Ok(()) => self.fixed = true,
+                Err(err) => self.message = format!("{} (failed to fix: {})", self.message, err),
+            }
+        }
+        self
+    }
+}
+
+/// Whether a dataset mounted at `mountpoint` holds files that belong to a
+/// boot environment, and so should not be shared between them.
+pub(crate) fn is_system_mountpoint(mountpoint: &str) -> bool {
+    let path = Path::new(mountpoint);
+    if !path.is_absolute() {
+        return false; // e.g. `none` or `legacy`.
+    }
+    if SHAREABLE_DIRS.iter().any(|dir| path.starts_with(dir)) {
+        return false;
+    }
+    SYSTEM_DIRS
+        .iter()
+        .any(|dir| path.starts_with(dir) || Path::new(dir).starts_with(path))
+}
+
+/// Check for temporary mountpoint directories (e.g., `/run/be/ab`) left
+/// behind by a boot environment that was never unmounted cleanly. With `fix`,
+/// empty ones are removed.
+///
+/// Mounts made in a private mount namespace (e.g., by `beadm exec`) aren't
+/// visible here, so the mount tables of every process under `proc` are
+/// consulted as well.
+pub(crate) fn check_temp_mountpoints(dir: &Path, proc: &Path, fix: bool) -> Vec<Finding> {
+    let dir_dev = match fs::metadata(dir) {
+        Ok(metadata) => metadata.dev(),
+        Err(_) => return vec![], // Nothing has been mounted since boot.
+    };
+    let in_use = mountpoints_in_use(proc);
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+    paths.sort();
+
+    let mut findings = vec![];
+    for path in paths {
+        let metadata = match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.is_dir() => metadata,
+            _ => continue,
+        };
+        if metadata.dev() != dir_dev || in_use.contains(&path) {
+            continue; // Something is still mounted here.
+        }
+        let name = path.display().to_string();
+        let empty = fs::read_dir(&path).is_ok_and(|mut entries| entries.next().is_none());
+        if empty {
+            findings.push(
+                Finding::new(
+                    "temp-mountpoint",
+                    &name,
+                    format!("'{}' is a leftover temporary mountpoint", name),
+                    "Nothing is mounted here any more; a boot environment was probably unmounted \
+                     outside of beadm, or beadm was interrupted. It is harmless, but takes up one \
+                     of the names beadm picks temporary mountpoints from.",
+                )
+                .with_fix(format!("remove '{}'", name))
+                .apply(fix, || Ok(fs::remove_dir(&path)?)),
+            );
+        } else {
+            findings.push(Finding::new(
+                "temp-mountpoint",
+                &name,
+                format!("'{}' is not a mountpoint but has files in it", name),
+                "Something wrote into a temporary mountpoint while no boot environment was \
+                 mounted there, so those files are in /run rather than a boot environment. Check \
+                 what they are and remove the directory by hand.",
+            ));
+        }
+    }
+    findings
+}
+
+/// Get the mountpoints of every process under `proc`, from their
+/// `mountinfo` files. Processes that have gone away, or whose mount tables
+/// can't be read, are skipped.
+fn mountpoints_in_use(proc: &Path) -> HashSet<PathBuf> {
+    let mut mountpoints = HashSet::new();
+    for entry in fs::read_dir(proc).into_iter().flatten().flatten() {
+        let is_pid = entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| name.bytes().all(|b| b.is_ascii_digit()));
+        if !is_pid {
+            continue;
+        }
+        let Ok(mountinfo) = fs::read_to_string(entry.path().join("mountinfo")) else {
+            continue;
+        };
+        // The mountpoint is the fifth field, with whitespace and backslashes
+        // escaped in octal.
+        mountpoints.extend(
+            mountinfo
+                .lines()
+                .filter_map(|line| line.split(' ').nth(4))
+                .map(|mountpoint| PathBuf::from(unescape_octal(mountpoint))),
+        );
+    }
+    mountpoints
+}
+
+/// Undo the octal escaping (e.g., `\040` for a space) of paths in
+/// `/proc/<pid>/mountinfo`.
+fn unescape_octal(value: &str) -> String {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&first, tail)) = rest.split_first() {
+        if first == b'\\'
+            && tail.len() >= 3
+            && let Ok(byte) = u8::from_str_radix(&String::from_utf8_lossy(&tail[..3]), 8)
+        {
+            bytes.push(byte);
+            rest = &tail[3..];
+        } else {
+            bytes.push(first);
+            rest = tail;
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_system_mountpoint() {
+        for mountpoint in ["/", "/usr", "/var", "/var/lib", "/var/lib/dpkg", "/etc/ssh"] {
+            assert!(is_system_mountpoint(mountpoint), "{}", mountpoint);
+        }
+        for mountpoint in [
+            "none",
+            "legacy",
+            "/home",
+            "/srv",
+            "/var/log",
+            "/var/cache",
+            "/usr/local",
+            "/usr/local/lib",
+        ] {
+            assert!(!is_system_mountpoint(mountpoint), "{}", mountpoint);
+        }
+    }
+
+    #[test]
+    fn test_check_temp_mountpoints() {
+        let dir = tempfile::tempdir().unwrap();
+        let proc = tempfile::tempdir().unwrap();
+        assert_eq!(
+            check_temp_mountpoints(&dir.path().join("missing"), proc.path(), true),
+            vec![]
+        );
+
+        fs::create_dir(dir.path().join("ab")).unwrap();
+        fs::create_dir(dir.path().join("cd")).unwrap();
+        fs::write(dir.path().join("cd/stray"), "").unwrap();
+        fs::write(dir.path().join("file"), "").unwrap();
+
+        let findings = check_temp_mountpoints(dir.path(), proc.path(), false);
+        assert_eq!(findings.len(), 2);
+        assert!(findings[0].fix.is_some() && !findings[0].fixed);
+        assert_eq!(
+            findings[0].name,
+            dir.path().join("ab").display().to_string()
+        );
+        assert!(findings[1].fix.is_none());
+        assert!(dir.path().join("ab").exists());
+
+        let findings = check_temp_mountpoints(dir.path(), proc.path(), true);
+        assert!(findings[0].fixed);
+        assert!(!dir.path().join("ab").exists());
+        assert!(!findings[1].fixed);
+        assert!(dir.path().join("cd/stray").exists());
+
+        // Directories mounted on in another mount namespace are left alone.
+        fs::create_dir(dir.path().join("e f")).unwrap();
+        fs::create_dir(proc.path().join("42")).unwrap();
+        fs::write(
+            proc.path().join("42/mountinfo"),
+            format!(
+                "1 0 0:1 / / rw - zfs zroot/ROOT/default rw\n\
+                 2 1 0:2 / {}/e\\040f ro - zfs zroot/ROOT/alt ro\n",
+                dir.path().display()
+            ),
+        )
+        .unwrap();
+        let findings = check_temp_mountpoints(dir.path(), proc.path(), true);
+        assert_eq!(findings.len(), 1);
+        assert!(dir.path().join("e f").exists());
+    }
+
+    #[test]
+    fn test_unescape_octal() {
+        assert_eq!(unescape_octal("/run/be/ab"), "/run/be/ab");
+        assert_eq!(unescape_octal("/mnt/a\\040b\\134c"), "/mnt/a b\\c");
+        assert_eq!(unescape_octal("/mnt/a\\04"), "/mnt/a\\04");
+    }
+
+    #[test]
+    fn test_finding_apply() {
+        let finding = || {
+            Finding::new("bootfs", "zroot/ROOT", "broken".to_string(), "")
+                .with_fix("fix it".to_string())
+        };
+        assert!(!finding().apply(false, || Ok(())).fixed);
+        assert!(finding().apply(true, || Ok(())).fixed);
+
+        let failed = finding().apply(true, || Err(Error::NoActiveBootEnvironment));
+        assert!(!failed.fixed);
+        assert!(failed.message.starts_with("broken (failed to fix: "));
+
+        // Findings without a fix are left alone.
+        let unfixable = Finding::new("shared", "zroot/var", "shared".to_string(), "");
+        assert!(!unfixable.apply(true, || panic!("no fix to apply")).fixed);
+    }
+}
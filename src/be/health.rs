@@ -0,0 +1,412 @@
+// SPDX-License-Identifier: MPL-2.0
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Checks that a boot environment looks bootable, run before activating it.
+
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+#[cfg(feature = "dbus")]
+use zvariant::{DeserializeDict, SerializeDict, Type};
+
+use super::{Client, Error, Root};
+
+/// Kernel image name prefixes. What follows is usually the kernel version,
+/// but can be a flavour instead (e.g., `vmlinuz-lts` on Alpine and
+/// `vmlinuz-linux` on Arch).
+const KERNEL_PREFIXES: &[&str] = &["vmlinuz-", "vmlinux-"];
+
+/// Symlinks followed when resolving a path, as in the kernel.
+const MAX_SYMLINKS: usize = 40;
+
+/// A problem found by checking a boot environment.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "dbus", derive(SerializeDict, DeserializeDict, Type))]
+#[cfg_attr(
+    feature = "dbus",
+    zvariant(signature = "a{sv}", rename_all = "PascalCase")
+)]
+pub struct HealthIssue {
+    /// The check that found it: `kernel`, `initramfs`, `init`, `hostid` or
+    /// `os-release`.
+    pub check: String,
+    /// Whether the boot environment is unlikely to boot because of it, in
+    /// which case it is not activated unless forced. Otherwise this is only
+    /// a warning.
+    pub fatal: bool,
+    pub message: String,
+}
+
+impl HealthIssue {
+    fn error(check: &str, message: String) -> Self {
+        Self {
+            check: check.to_string(),
+            fatal: true,
+            message,
+        }
+    }
+
+    fn warning(check: &str, message: String) -> Self {
+        Self {
+            check: check.to_string(),
+            fatal: false,
+            message,
+        }
+    }
+}
+
+/// Check a boot environment before activating it. Fatal issues are an error
+/// unless `force` is set; otherwise all issues found are returned, to be
+/// reported as warnings.
+pub fn check_activation<C: Client + ?Sized>(
+    client: &C,
+    be_name: &str,
+    force: bool,
+    root: Option<&Root>,
+) -> Result<Vec<HealthIssue>, Error> {
+    let issues = client.check(be_name, root)?;
+    if !force && issues.iter().any(|issue| issue.fatal) {
+        return Err(Error::Unhealthy {
+            name: be_name.to_string(),
+            issues: issues
+                .into_iter()
+                .filter(|issue| issue.fatal)
+                .map(|issue| issue.message)
+                .collect(),
+        });
+    }
+    Ok(issues)
+}
+
+/// Activate a boot environment if it passes [`check_activation`], returning
+/// the issues found.
+pub fn activate<C: Client + ?Sized>(
+    client: &C,
+    be_name: &str,
+    temporary: bool,
+    force: bool,
+    root: Option<&Root>,
+) -> Result<Vec<HealthIssue>, Error> {
+    let issues = check_activation(client, be_name, force, root)?;
+    client.activate(be_name, temporary, root)?;
+    Ok(issues)
+}
+
+/// Activate a boot environment for a trial boot if it passes
+/// [`check_activation`], returning the issues found.
+pub fn activate_trial<C: Client + ?Sized>(
+    client: &C,
+    be_name: &str,
+    force: bool,
+    root: Option<&Root>,
+) -> Result<Vec<HealthIssue>, Error> {
+    let issues = check_activation(client, be_name, force, root)?;
+    client.activate_trial(be_name, root)?;
+    Ok(issues)
+}
+
+/// Check the contents of a boot environment mounted at `mountpoint`: that it
+/// has a kernel with a matching initramfs, an init, and an os-release file,
+/// and that its host ID matches the pool's (if known).
+pub fn check_mounted(mountpoint: &Path, pool_hostid: Option<u32>) -> Vec<HealthIssue> {
+    let mut issues = check_kernels(mountpoint);
+    if !resolve_in_root(mountpoint, Path::new("/sbin/init")).is_some_and(|path| path.is_file()) {
+        issues.push(HealthIssue::error(
+            "init",
+            "/sbin/init is missing".to_string(),
+        ));
+    }
+    issues.extend(check_hostid(mountpoint, pool_hostid));
+    issues.extend(check_os_release(mountpoint));
+    issues
+}
+
+/// Check that `/boot` has at least one kernel, and an initramfs for each.
+fn check_kernels(mountpoint: &Path) -> Vec<HealthIssue> {
+    let mut names: Vec<String> = resolve_in_root(mountpoint, Path::new("/boot"))
+        .and_then(|boot| fs::read_dir(boot).ok())
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .collect();
+    names.sort();
+
+    let kernels: Vec<&String> = names
+        .iter()
+        .filter(|name| {
+            KERNEL_PREFIXES
+                .iter()
+                .any(|prefix| name.len() > prefix.len() && name.starts_with(prefix))
+        })
+        .collect();
+    if kernels.is_empty() {
+        return vec![HealthIssue::error(
+            "kernel",
+            "No kernel found in /boot".to_string(),
+        )];
+    }
+
+    let missing: Vec<&String> = kernels
+        .iter()
+        .copied()
+        .filter(|kernel| {
+            let suffix = &kernel[kernel.find('-').unwrap_or(0) + 1..];
+            !initramfs_names(suffix)
+                .iter()
+                .any(|initramfs| names.contains(initramfs))
+        })
+        .collect();
+    // One kernel without an initramfs is only a problem if there's no other
+    // to choose from the boot menu.
+    let fatal = missing.len() == kernels.len();
+    missing
+        .into_iter()
+        .map(|kernel| {
+            let message = format!("No initramfs found for kernel '/boot/{}'", kernel);
+            if fatal {
+                HealthIssue::error("initramfs", message)
+            } else {
+                HealthIssue::warning("initramfs", message)
+            }
+        })
+        .collect()
+}
+
+/// The names distributions give the initramfs for a kernel with a suffix.
+fn initramfs_names(suffix: &str) -> [String; 4] {
+    [
+        format!("initrd.img-{}", suffix),    // Debian, Ubuntu
+        format!("initramfs-{}.img", suffix), // Fedora, Arch
+        format!("initramfs-{}", suffix),     // Alpine
+        format!("initrd-{}", suffix),        // openSUSE
+    ]
+}
+
+/// Check that the boot environment's `/etc/hostid` matches the pool's, or
+/// else the pool may not be imported at boot.
+fn check_hostid(mountpoint: &Path, pool_hostid: Option<u32>) -> Option<HealthIssue> {
+    let pool_hostid = pool_hostid?;
+    let path = mountpoint.join("etc/hostid");
+    match fs::read(&path) {
+        Ok(bytes) => match <[u8; 4]>::try_from(bytes.as_slice()) {
+            Ok(bytes) if u32::from_le_bytes(bytes) == pool_hostid => None,
+            Ok(bytes) => Some(HealthIssue::error(
+                "hostid",
+                format!(
+                    "/etc/hostid (0x{:08x}) does not match the pool's host ID (0x{:08x})",
+                    u32::from_le_bytes(bytes),
+                    pool_hostid
+                ),
+            )),
+            Err(_) => Some(HealthIssue::error(
+                "hostid",
+                "/etc/hostid is malformed".to_string(),
+            )),
+        },
+        Err(_) => Some(HealthIssue::warning(
+            "hostid",
+            format!(
+                "/etc/hostid is missing; the pool's host ID is 0x{:08x}",
+                pool_hostid
+            ),
+        )),
+    }
+}
+
+/// Check that the boot environment has an os-release(5) file, and that every
+/// line in it is a comment or an assignment.
+fn check_os_release(mountpoint: &Path) -> Option<HealthIssue> {
+    let Some(contents) = ["/etc/os-release", "/usr/lib/os-release"]
+        .iter()
+        .filter_map(|path| resolve_in_root(mountpoint, Path::new(path)))
+        .find_map(|path| fs::read_to_string(path).ok())
+    else {
+        return Some(HealthIssue::error(
+            "os-release",
+            "No os-release file found".to_string(),
+        ));
+    };
+    let malformed = contents.lines().position(|line| {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return false;
+        }
+        !line.split_once('=').is_some_and(|(key, _)| {
+            !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        })
+    });
+    malformed.map(|i| {
+        HealthIssue::error(
+            "os-release",
+            format!("os-release is malformed at line {}", i + 1),
+        )
+    })
+}
+
+/// Resolve a path inside a boot environment mounted at `root`, following
+/// symlinks as they would be followed once it is booted (i.e., absolute
+/// targets are relative to `root`, not the running system). Returns None if
+/// any part of it does not exist.
+fn resolve_in_root(root: &Path, path: &Path) -> Option<PathBuf> {
+    let mut resolved = PathBuf::new();
+    let mut pending = components(path);
+    let mut symlinks = 0;
+    while let Some(component) = pending.pop() {
+        if component == ".." {
+            resolved.pop();
+            continue;
+        }
+        resolved.push(&component);
+        let full = root.join(&resolved);
+        if !fs::symlink_metadata(&full).ok()?.file_type().is_symlink() {
+            continue;
+        }
+        symlinks += 1;
+        if symlinks > MAX_SYMLINKS {
+            return None;
+        }
+        let target = fs::read_link(&full).ok()?;
+        resolved.pop();
+        if target.is_absolute() {
+            resolved.clear();
+        }
+        pending.extend(components(&target));
+    }
+    Some(root.join(resolved))
+}
+
+/// The components of a path that need resolving, in reverse order.
+fn components(path: &Path) -> Vec<OsString> {
+    path.components()
+        .rev()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_os_string()),
+            Component::ParentDir => Some(OsString::from("..")),
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+
+    /// Lay out a minimal bootable root filesystem.
+    fn bootable_root() -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        for dir in ["boot", "etc", "usr/lib/systemd", "usr/sbin"] {
+            fs::create_dir_all(root.path().join(dir)).unwrap();
+        }
+        fs::write(root.path().join("boot/vmlinuz-6.12.0-1-amd64"), "").unwrap();
+        fs::write(root.path().join("boot/initrd.img-6.12.0-1-amd64"), "").unwrap();
+        fs::write(root.path().join("usr/lib/systemd/systemd"), "").unwrap();
+        // Merged /usr, with an absolute symlink that must resolve inside the
+        // boot environment rather than on the running system.
+        symlink("usr/sbin", root.path().join("sbin")).unwrap();
+        symlink(
+            "/usr/lib/systemd/systemd",
+            root.path().join("usr/sbin/init"),
+        )
+        .unwrap();
+        fs::write(
+            root.path().join("etc/os-release"),
+            "# Comment\nID=debian\nPRETTY_NAME=\"Debian GNU/Linux 13\"\n",
+        )
+        .unwrap();
+        fs::write(root.path().join("etc/hostid"), 0x00bab10cu32.to_le_bytes()).unwrap();
+        root
+    }
+
+    fn checks(issues: &[HealthIssue]) -> Vec<(&str, bool)> {
+        issues
+            .iter()
+            .map(|issue| (issue.check.as_str(), issue.fatal))
+            .collect()
+    }
+
+    #[test]
+    fn test_check_mounted_healthy() {
+        let root = bootable_root();
+        assert_eq!(check_mounted(root.path(), Some(0x00bab10c)), vec![]);
+        assert_eq!(check_mounted(root.path(), None), vec![]);
+    }
+
+    #[test]
+    fn test_check_mounted_empty() {
+        let root = tempfile::tempdir().unwrap();
+        assert_eq!(
+            checks(&check_mounted(root.path(), Some(0x00bab10c))),
+            vec![
+                ("kernel", true),
+                ("init", true),
+                ("hostid", false),
+                ("os-release", true)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_mounted_problems() {
+        let root = bootable_root();
+        // A second kernel without an initramfs is only a warning...
+        fs::write(root.path().join("boot/vmlinuz-6.13.0-1-amd64"), "").unwrap();
+        fs::write(root.path().join("etc/os-release"), "ID=debian\nbogus\n").unwrap();
+        let issues = check_mounted(root.path(), Some(0x12345678));
+        assert_eq!(
+            checks(&issues),
+            vec![("initramfs", false), ("hostid", true), ("os-release", true)]
+        );
+        assert_eq!(
+            issues[1].message,
+            "/etc/hostid (0x00bab10c) does not match the pool's host ID (0x12345678)"
+        );
+        assert_eq!(issues[2].message, "os-release is malformed at line 2");
+
+        // ...but not when no kernel has one.
+        fs::remove_file(root.path().join("boot/initrd.img-6.12.0-1-amd64")).unwrap();
+        fs::remove_file(root.path().join("usr/lib/systemd/systemd")).unwrap();
+        assert_eq!(
+            checks(&check_mounted(root.path(), None))[..3],
+            [("initramfs", true), ("initramfs", true), ("init", true)]
+        );
+    }
+
+    #[test]
+    fn test_initramfs_names() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir(root.path().join("boot")).unwrap();
+        for name in [
+            "vmlinuz-linux",
+            "initramfs-linux.img",
+            "vmlinuz-lts",
+            "initramfs-lts",
+        ] {
+            fs::write(root.path().join("boot").join(name), "").unwrap();
+        }
+        assert_eq!(check_kernels(root.path()), vec![]);
+    }
+
+    #[test]
+    fn test_resolve_in_root() {
+        let root = bootable_root();
+        assert_eq!(
+            resolve_in_root(root.path(), Path::new("/sbin/init")),
+            Some(root.path().join("usr/lib/systemd/systemd"))
+        );
+        // As in the kernel, ".." applies to where a symlink leads.
+        assert_eq!(
+            resolve_in_root(root.path(), Path::new("/sbin/../lib/systemd")),
+            Some(root.path().join("usr/lib/systemd"))
+        );
+        assert_eq!(resolve_in_root(root.path(), Path::new("/missing")), None);
+
+        symlink("loop", root.path().join("loop")).unwrap();
+        assert_eq!(resolve_in_root(root.path(), Path::new("/loop")), None);
+    }
+}
@@ -0,0 +1,134 @@
+// SPDX-License-Identifier: MPL-2.0
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+#[cfg(feature = "dbus")]
+use zvariant::{DeserializeDict, SerializeDict, Type};
+
+/// The number of boots kept in the history.
+pub(crate) const MAX_BOOT_HISTORY: usize = 64;
+
+/// An upper bound on the length of the encoded history, which keeps it well
+/// clear of the limit on ZFS property values (8K).
+const MAX_ENCODED_LEN: usize = 4096;
+
+/// A boot recorded in the boot history.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "dbus", derive(SerializeDict, DeserializeDict, Type))]
+#[cfg_attr(
+    feature = "dbus",
+    zvariant(signature = "a{sv}", rename_all = "PascalCase")
+)]
+pub struct BootRecord {
+    /// Unix timestamp for when the boot was recorded.
+    pub timestamp: i64,
+    /// The ZFS dataset GUID of the boot environment that was booted.
+    pub guid: u64,
+    /// The name of the boot environment at the time.
+    pub name: String,
+    /// Whether the boot environment was only activated temporarily.
+    pub boot_once: bool,
+}
+
+/// Decode the boot history from a property value, oldest first. Malformed
+/// entries are skipped.
+pub(crate) fn decode(value: &str) -> Vec<BootRecord> {
+    value
+        .split(';')
+        .filter_map(|entry| {
+            // Boot environment names can't contain commas, so the name is
+            // simply the last field.
+            let mut fields = entry.splitn(4, ',');
+            let timestamp = fields.next()?.parse().ok()?;
+            let guid = u64::from_str_radix(fields.next()?, 16).ok()?;
+            let boot_once = match fields.next()? {
+                "0" => false,
+                "1" => true,
+                _ => return None,
+            };
+            let name = fields.next()?.to_string();
+            Some(BootRecord {
+                timestamp,
+                guid,
+                name,
+                boot_once,
+            })
+        })
+        .collect()
+}
+
+/// Encode the boot history as a property value, dropping the oldest entries
+/// as needed to keep it bounded.
+pub(crate) fn encode(records: &[BootRecord]) -> String {
+    let mut entries: Vec<String> = vec![];
+    let mut len = 0;
+    for record in records.iter().rev().take(MAX_BOOT_HISTORY) {
+        let entry = format!(
+            "{},{:x},{},{}",
+            record.timestamp, record.guid, record.boot_once as u8, record.name
+        );
+        len += entry.len() + 1;
+        if len > MAX_ENCODED_LEN {
+            break;
+        }
+        entries.push(entry);
+    }
+    entries.reverse();
+    entries.join(";")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(timestamp: i64, name: &str) -> BootRecord {
+        BootRecord {
+            timestamp,
+            guid: 0x9a3f_0000_0000_0001,
+            name: name.to_string(),
+            boot_once: false,
+        }
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let records = vec![
+            record(1623301740, "default"),
+            BootRecord {
+                boot_once: true,
+                ..record(1623305460, "alt")
+            },
+        ];
+        let encoded = encode(&records);
+        assert_eq!(
+            encoded,
+            "1623301740,9a3f000000000001,0,default;1623305460,9a3f000000000001,1,alt"
+        );
+        assert_eq!(decode(&encoded), records);
+
+        // Unset properties and junk are tolerated.
+        assert!(decode("").is_empty());
+        assert!(decode("-").is_empty());
+        assert_eq!(
+            decode("junk;1623301740,9a3f000000000001,0,default;1,2,3"),
+            vec![record(1623301740, "default")]
+        );
+    }
+
+    #[test]
+    fn test_encode_bounded() {
+        let records: Vec<BootRecord> = (0..100).map(|i| record(i, "default")).collect();
+        let decoded = decode(&encode(&records));
+        assert_eq!(decoded.len(), MAX_BOOT_HISTORY);
+        assert_eq!(decoded.last(), records.last());
+
+        // Long names are dropped by length instead.
+        let name = "x".repeat(200);
+        let records: Vec<BootRecord> = (0..100).map(|i| record(i, &name)).collect();
+        let encoded = encode(&records);
+        assert!(encoded.len() <= MAX_ENCODED_LEN);
+        assert_eq!(decode(&encoded).last(), records.last());
+    }
+}
@@ -5,26 +5,53 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use std::collections::hash_map::DefaultHasher;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
 use std::hash::{Hash, Hasher};
+use std::os::fd::BorrowedFd;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::{Arc, RwLock};
 
 use chrono::Utc;
 
-use super::validation::{validate_be_name, validate_component};
+use super::diff::{self, ChangeKind, FileChange, FileType};
+use super::history::MAX_BOOT_HISTORY;
+use super::stream::{ExportHeader, ExportedDataset};
+use super::validation::{validate_be_name, validate_component, validate_tag};
 use super::{
-    BootEnvironment, Client, Error, Label, MountMode, Root, Snapshot, generate_snapshot_name,
-    generate_temp_mountpoint,
+    BootEnvironment, BootRecord, ChildDataset, Client, DestroyPlan, Error, Finding, HealthIssue,
+    Label, MountOptions, Property, Replication, RolledBack, Root, Snapshot, SpaceUsage,
+    check_destroyable, format_zfs_bytes, generate_snapshot_name, generate_temp_mountpoint,
+    record_replication, recorded_replication,
 };
 
+/// The user property recording the latest snapshot replicated to other
+/// roots, as in the libzfs client.
+const REPLICATED_PROP: &str = "ca.kamacite:replicated";
+
+/// Native properties the emulator knows about, with their defaults.
+const DEFAULT_PROPERTIES: &[(&str, &str)] = &[
+    ("atime", "on"),
+    ("compression", "off"),
+    ("quota", "none"),
+    ("recordsize", "128K"),
+];
+
 /// A boot environment client populated with static data that operates
 /// entirely in-memory with no side effects.
 pub struct EmulatorClient {
     active_root: Root,
     bes: RwLock<Vec<BootEnvironment>>,
     properties: Arc<RwLock<HashMap<String, HashMap<String, String>>>>,
+    history: RwLock<Vec<BootRecord>>,
+    pinned_snapshots: RwLock<HashSet<String>>,
+    snapshot_tags: RwLock<HashMap<String, BTreeMap<String, String>>>,
+    /// The current names of renamed sample snapshots, keyed by their
+    /// original names.
+    renamed_snapshots: RwLock<HashMap<String, String>>,
+    /// The issues `check` finds, keyed like properties.
+    health_issues: RwLock<HashMap<String, Vec<HealthIssue>>>,
 }
 
 impl EmulatorClient {
@@ -33,6 +60,11 @@ impl EmulatorClient {
             active_root: Root::from_str("zfake/ROOT").unwrap(),
             bes: RwLock::new(bes),
             properties: Arc::new(RwLock::new(HashMap::new())),
+            history: RwLock::new(vec![]),
+            pinned_snapshots: RwLock::new(HashSet::new()),
+            snapshot_tags: RwLock::new(HashMap::new()),
+            renamed_snapshots: RwLock::new(HashMap::new()),
+            health_issues: RwLock::new(HashMap::new()),
         }
     }
 
@@ -49,11 +81,26 @@ impl EmulatorClient {
             active_root: Root::from_str("zfake/ROOT").unwrap(),
             bes: RwLock::new(vec![]),
             properties: Arc::new(RwLock::new(HashMap::new())),
+            history: RwLock::new(vec![]),
+            pinned_snapshots: RwLock::new(HashSet::new()),
+            snapshot_tags: RwLock::new(HashMap::new()),
+            renamed_snapshots: RwLock::new(HashMap::new()),
+            health_issues: RwLock::new(HashMap::new()),
         }
     }
 
+    /// Make `check` find issues with a boot environment, which otherwise has
+    /// no contents to check.
+    #[cfg(test)]
+    pub fn set_health_issues(&self, be_name: &str, issues: Vec<HealthIssue>) {
+        let key = Self::property_key(&self.active_root, be_name);
+        self.health_issues.write().unwrap().insert(key, issues);
+    }
+
     pub fn sampled() -> Self {
-        Self::new(sample_boot_environments())
+        let client = Self::new(sample_boot_environments());
+        *client.history.write().unwrap() = sample_boot_history();
+        client
     }
 
     /// Get the effective root to use for an operation.
@@ -70,6 +117,170 @@ impl EmulatorClient {
         format!("{}/{}", root.as_str(), be_name)
     }
 
+    /// Get the (sample) snapshots of a boot environment under their current
+    /// names.
+    fn current_snapshots(&self, root: &Root, be_name: &str) -> Vec<Snapshot> {
+        let renamed = self.renamed_snapshots.read().unwrap();
+        let mut snapshots = sample_snapshots(be_name);
+        for snapshot in &mut snapshots {
+            if let Some(name) = renamed.get(&Self::property_key(root, &snapshot.name)) {
+                snapshot.name = name.clone();
+            }
+        }
+        snapshots
+    }
+
+    /// Get the (sample) snapshots of a boot environment, marking those that
+    /// have been pinned or tagged.
+    fn snapshots_of(&self, root: &Root, be_name: &str) -> Vec<Snapshot> {
+        let mut snapshots = self.current_snapshots(root, be_name);
+        let pinned = self.pinned_snapshots.read().unwrap();
+        let tags = self.snapshot_tags.read().unwrap();
+        for snapshot in &mut snapshots {
+            let key = Self::property_key(root, &snapshot.name);
+            snapshot.pinned = pinned.contains(&key);
+            snapshot.tags = tags.get(&key).cloned().unwrap_or_default();
+        }
+        snapshots
+    }
+
+    /// Build the key used to track state for a snapshot, checking that it
+    /// exists.
+    fn snapshot_key(
+        &self,
+        bes: &[BootEnvironment],
+        root: &Root,
+        name: &str,
+        snapshot: &str,
+    ) -> Result<String, Error> {
+        if !bes.iter().any(|be| be.name == name && be.root == *root) {
+            return Err(Error::not_found(name));
+        }
+        let label = format!("{}@{}", name, snapshot);
+        if !self
+            .current_snapshots(root, name)
+            .iter()
+            .any(|snapshot| snapshot.name == label)
+        {
+            return Err(Error::not_found(&label));
+        }
+        Ok(Self::property_key(root, &label))
+    }
+
+    /// Pin or unpin a boot environment or snapshot.
+    fn set_pinned(&self, target: &Label, pinned: bool, root: Option<&Root>) -> Result<(), Error> {
+        let root = self.effective_root(root);
+        let mut bes = self.bes.write().unwrap();
+        match target {
+            Label::Snapshot(name, snapshot) => {
+                let key = self.snapshot_key(&bes, root, name, snapshot)?;
+                let mut pinned_snapshots = self.pinned_snapshots.write().unwrap();
+                if pinned {
+                    pinned_snapshots.insert(key);
+                } else {
+                    pinned_snapshots.remove(&key);
+                }
+            }
+            Label::Name(name) => {
+                let be = bes
+                    .iter_mut()
+                    .find(|be| be.name == *name && be.root == *root)
+                    .ok_or_else(|| Error::not_found(name))?;
+                be.pinned = pinned;
+            }
+        }
+        Ok(())
+    }
+
+    /// Set (or, without a value, remove) a tag on a boot environment or
+    /// snapshot.
+    fn set_tag(
+        &self,
+        target: &Label,
+        key: &str,
+        value: Option<&str>,
+        root: Option<&Root>,
+    ) -> Result<(), Error> {
+        validate_tag(key, value)?;
+        let root = self.effective_root(root);
+        let mut bes = self.bes.write().unwrap();
+        let mut snapshot_tags = self.snapshot_tags.write().unwrap();
+        let tags = match target {
+            Label::Snapshot(name, snapshot) => snapshot_tags
+                .entry(self.snapshot_key(&bes, root, name, snapshot)?)
+                .or_default(),
+            Label::Name(name) => {
+                &mut bes
+                    .iter_mut()
+                    .find(|be| be.name == *name && be.root == *root)
+                    .ok_or_else(|| Error::not_found(name))?
+                    .tags
+            }
+        };
+        match value {
+            Some(value) => tags.insert(key.to_string(), value.to_string()),
+            None => tags.remove(key),
+        };
+        Ok(())
+    }
+
+    /// Get the emulated ZFS properties of a boot environment or snapshot, as
+    /// (value, source) pairs by name.
+    fn emulated_properties(
+        &self,
+        target: &Label,
+        root: &Root,
+    ) -> Result<BTreeMap<String, (String, String)>, Error> {
+        let mut props = BTreeMap::new();
+        let mut set = |name: &str, value: &str, source: &str| {
+            props.insert(name.to_string(), (value.to_string(), source.to_string()));
+        };
+        match target {
+            Label::Snapshot(name, snapshot) => {
+                self.snapshot_key(&self.bes.read().unwrap(), root, name, snapshot)?;
+                let label = target.to_string();
+                let snapshot = self
+                    .snapshots_of(root, name)
+                    .into_iter()
+                    .find(|snapshot| snapshot.name == label)
+                    .ok_or_else(|| Error::not_found(&label))?;
+                set("used", &format_zfs_bytes(snapshot.space), "-");
+                if let Some(description) = &snapshot.description {
+                    set("ca.kamacite:description", description, "local");
+                }
+                for (key, value) in &snapshot.tags {
+                    set(&format!("ca.kamacite:tag:{}", key), value, "local");
+                }
+            }
+            Label::Name(name) => {
+                let bes = self.bes.read().unwrap();
+                let be = bes
+                    .iter()
+                    .find(|be| be.name == *name && be.root == *root)
+                    .ok_or_else(|| Error::not_found(name))?;
+                for (name, value) in DEFAULT_PROPERTIES {
+                    set(name, value, "default");
+                }
+                set("canmount", "noauto", "local");
+                set("mountpoint", "/", "local");
+                set("used", &format_zfs_bytes(be.space), "-");
+                if let Some(description) = &be.description {
+                    set("ca.kamacite:description", description, "local");
+                }
+                for (key, value) in &be.tags {
+                    set(&format!("ca.kamacite:tag:{}", key), value, "local");
+                }
+                let key = Self::property_key(root, name);
+                if let Some(stored) = self.properties.read().unwrap().get(&key) {
+                    for (name, value) in stored {
+                        set(name, value, "local");
+                    }
+                }
+            }
+        }
+        Ok(props)
+    }
+
     /// Get properties for a boot environment in the active root (for testing).
     #[cfg(test)]
     pub fn get_properties(&self, be_name: &str) -> Option<HashMap<String, String>> {
@@ -93,7 +304,7 @@ impl Client for EmulatorClient {
 
         let mut bes = self.bes.write().unwrap();
 
-        let source_space = match source {
+        let (source_space, source_referenced, origin) = match source {
             Some(Label::Snapshot(name, snapshot)) => {
                 // Case #1: beadm create -e EXISTING@SNAPSHOT NAME, which
                 // creates the clone from an existing snapshot of a boot
@@ -109,7 +320,11 @@ impl Client for EmulatorClient {
                     .ok_or_else(|| Error::not_found(&format!("{}@{}", name, snapshot)))?;
 
                 // Clone from snapshot - inherit space from source BE
-                source_be.space
+                (
+                    source_be.space,
+                    source_be.usage.referenced,
+                    format!("{}@{}", name, snapshot),
+                )
             }
             Some(Label::Name(name)) => {
                 // Case #2: beadm create -e EXISTING NAME, which creates the
@@ -124,7 +339,11 @@ impl Client for EmulatorClient {
                     .ok_or_else(|| Error::not_found(name))?;
 
                 // Clone from existing BE - inherit space
-                source_be.space
+                (
+                    source_be.space,
+                    source_be.usage.referenced,
+                    format!("{}@{}", name, generate_snapshot_name()),
+                )
             }
             None => {
                 // Case #3: beadm create NAME, which creates the clone from a
@@ -135,7 +354,11 @@ impl Client for EmulatorClient {
                     .ok_or_else(|| Error::NoActiveBootEnvironment)?;
 
                 // Clone from active BE - inherit space
-                active_be.space
+                (
+                    active_be.space,
+                    active_be.usage.referenced,
+                    format!("{}@{}", active_be.name, generate_snapshot_name()),
+                )
             }
         };
 
@@ -176,7 +399,16 @@ impl Client for EmulatorClient {
             active: false,
             next_boot: false,
             boot_once: false,
+            trial: false,
+            pinned: false,
+            tags: BTreeMap::new(),
             space: source_space, // Inherit space from source
+            usage: SpaceUsage {
+                // A fresh clone shares all of its data with its origin.
+                referenced: source_referenced,
+                ..SpaceUsage::default()
+            },
+            origin: Some(origin),
             created: Utc::now().timestamp(),
         });
         Ok(())
@@ -186,10 +418,15 @@ impl Client for EmulatorClient {
         &self,
         be_name: &str,
         description: Option<&str>,
-        _host_id: Option<&str>,
+        host_id: Option<&str>,
         properties: &[String],
         root: Option<&Root>,
     ) -> Result<(), Error> {
+        // There's no filesystem to write it to, but we can still validate it.
+        if let Some(value) = host_id {
+            super::parse_hostid(value)?;
+        }
+
         let root = self.effective_root(root);
         let mut bes = self.bes.write().unwrap();
 
@@ -231,7 +468,12 @@ impl Client for EmulatorClient {
             active: false,
             next_boot: false,
             boot_once: false,
+            trial: false,
+            pinned: false,
+            tags: BTreeMap::new(),
             space: 8192, // ZFS datasets consume 8K to start.
+            usage: SpaceUsage::default(),
+            origin: None,
             created: Utc::now().timestamp(),
         });
         Ok(())
@@ -242,9 +484,12 @@ impl Client for EmulatorClient {
         target: &Label,
         force_unmount: bool,
         snapshots: bool,
+        unpin: bool,
+        dry_run: bool,
         root: Option<&Root>,
-    ) -> Result<(), Error> {
+    ) -> Result<DestroyPlan, Error> {
         let root = self.effective_root(root);
+        let mut plan = DestroyPlan::default();
 
         match target {
             Label::Name(be_name) => {
@@ -270,40 +515,147 @@ impl Client for EmulatorClient {
                         });
                     }
 
-                    if !force_unmount && be.mountpoint.is_some() {
-                        return Err(Error::Mounted {
-                            name: be.name.to_string(),
-                            mountpoint: be.mountpoint.as_ref().unwrap().display().to_string(),
-                        });
+                    if be.pinned {
+                        if !unpin {
+                            return Err(Error::protected(&be.name));
+                        }
+                        plan.unpin.push(be.name.clone());
+                    }
+
+                    if let Some(mountpoint) = &be.mountpoint {
+                        if !force_unmount {
+                            return Err(Error::mounted(&be.name, mountpoint));
+                        }
+                        plan.unmount.push(mountpoint.display().to_string());
                     }
+
+                    // Snapshots are generated on-the-fly, so they are only
+                    // ever destroyed in the plan.
+                    let mut space = be.space;
+                    if snapshots {
+                        let mut be_snapshots = self.snapshots_of(root, be_name);
+                        be_snapshots.sort_by_key(|snapshot| snapshot.created);
+
+                        // Promote the oldest clone, which takes over its
+                        // origin and any earlier snapshots.
+                        let prefix = format!("{}@", be_name);
+                        let oldest_clone = bes
+                            .iter()
+                            .filter(|other| other.root == *root)
+                            .filter(|other| {
+                                other
+                                    .origin
+                                    .as_ref()
+                                    .is_some_and(|origin| origin.starts_with(&prefix))
+                            })
+                            .min_by_key(|other| other.created);
+                        let mut kept = 0;
+                        if let Some(clone) = oldest_clone {
+                            plan.promote.push(clone.name.clone());
+                            kept = be_snapshots
+                                .iter()
+                                .position(|snapshot| clone.origin.as_ref() == Some(&snapshot.name))
+                                .map_or(0, |index| index + 1);
+                        }
+                        for snapshot in be_snapshots.iter().take(kept) {
+                            space = space.saturating_sub(snapshot.space);
+                        }
+                        for snapshot in be_snapshots.iter().skip(kept) {
+                            if snapshot.pinned {
+                                if !unpin {
+                                    return Err(Error::protected(&snapshot.name));
+                                }
+                                plan.unpin.push(snapshot.name.clone());
+                            }
+                        }
+                        plan.snapshots
+                            .extend(be_snapshots.into_iter().skip(kept).map(|s| s.name));
+                    }
+                    plan.space = space;
+
+                    // Children must be destroyed before their parents.
+                    plan.datasets.extend(
+                        sample_datasets(be_name)
+                            .into_iter()
+                            .rev()
+                            .map(|dataset| dataset.name),
+                    );
+                    plan.datasets.push(be_name.to_string());
                 } // Release the borrow here
 
-                if snapshots {
-                    unimplemented!("Mocking does not yet track snapshots");
+                if dry_run {
+                    return Ok(plan);
                 }
 
                 // Now we can safely borrow mutably to remove the BE (matching both name and root)
-                self.bes
-                    .write()
-                    .unwrap()
-                    .retain(|x| !(x.name == *be_name && x.root == *root));
+                let mut bes = self.bes.write().unwrap();
+                bes.retain(|x| !(x.name == *be_name && x.root == *root));
+                for be in bes.iter_mut() {
+                    if be.root == *root && plan.promote.contains(&be.name) {
+                        be.origin = None;
+                    }
+                }
 
                 // Drop any properties tracked for the destroyed BE.
                 let key = Self::property_key(root, be_name);
                 self.properties.write().unwrap().remove(&key);
+                let prefix = format!("{}@", key);
+                self.pinned_snapshots
+                    .write()
+                    .unwrap()
+                    .retain(|name| !name.starts_with(&prefix));
+                self.snapshot_tags
+                    .write()
+                    .unwrap()
+                    .retain(|name, _| !name.starts_with(&prefix));
 
-                Ok(())
+                Ok(plan)
             }
-            Label::Snapshot(be_name, _snapshot_name) => {
+            Label::Snapshot(be_name, snapshot_name) => {
                 // Destroy a snapshot - for mock implementation, we just validate the BE exists with matching root
                 let bes = self.bes.read().unwrap();
                 if !bes.iter().any(|be| be.name == *be_name && be.root == *root) {
                     return Err(Error::not_found(be_name));
                 }
 
+                let name = format!("{}@{}", be_name, snapshot_name);
+                let key = Self::property_key(root, &name);
+                if self.pinned_snapshots.read().unwrap().contains(&key) {
+                    if !unpin {
+                        return Err(Error::protected(&name));
+                    }
+                    plan.unpin.push(name.clone());
+                }
+
+                // Any clones of the snapshot would need to be promoted first.
+                if let Some(clone) = bes
+                    .iter()
+                    .filter(|be| be.root == *root && be.origin.as_ref() == Some(&name))
+                    .min_by_key(|be| be.created)
+                {
+                    plan.promote.push(clone.name.clone());
+                }
+
+                // The matching snapshots of child datasets go first.
+                plan.snapshots.extend(
+                    sample_datasets(be_name)
+                        .into_iter()
+                        .map(|dataset| format!("{}@{}", dataset.name, snapshot_name)),
+                );
+                plan.space = self
+                    .current_snapshots(root, be_name)
+                    .iter()
+                    .find(|snapshot| snapshot.name == name)
+                    .map_or(0, |snapshot| snapshot.space);
+                plan.snapshots.push(name);
+
                 // For mock implementation, snapshots are generated on-the-fly
                 // so we can't actually destroy them, but we can pretend to succeed
-                Ok(())
+                if !dry_run {
+                    self.pinned_snapshots.write().unwrap().remove(&key);
+                    self.snapshot_tags.write().unwrap().remove(&key);
+                }
+                Ok(plan)
             }
         }
     }
@@ -312,7 +664,7 @@ impl Client for EmulatorClient {
         &self,
         be_name: &str,
         mountpoint: Option<&Path>,
-        _mode: MountMode,
+        _options: &MountOptions,
         root: Option<&Root>,
     ) -> Result<PathBuf, Error> {
         let root = self.effective_root(root);
@@ -465,6 +817,359 @@ impl Client for EmulatorClient {
         Ok(())
     }
 
+    fn rename_snapshot(
+        &self,
+        be_name: &str,
+        snapshot: &str,
+        new_snapshot: &str,
+        root: Option<&Root>,
+    ) -> Result<(), Error> {
+        let root = self.effective_root(root);
+        validate_component(new_snapshot, false)?;
+        let mut bes = self.bes.write().unwrap();
+        let old_key = self.snapshot_key(&bes, root, be_name, snapshot)?;
+
+        let old_name = format!("{}@{}", be_name, snapshot);
+        let new_name = format!("{}@{}", be_name, new_snapshot);
+        let current = self.current_snapshots(root, be_name);
+        if current.iter().any(|snapshot| snapshot.name == new_name) {
+            return Err(Error::conflict(&new_name));
+        }
+
+        // Record the new name against the original sample snapshot.
+        let original = sample_snapshots(be_name)
+            .into_iter()
+            .zip(current)
+            .find(|(_, current)| current.name == old_name)
+            .map(|(original, _)| original.name)
+            .ok_or_else(|| Error::not_found(&old_name))?;
+        self.renamed_snapshots
+            .write()
+            .unwrap()
+            .insert(Self::property_key(root, &original), new_name.clone());
+
+        // Pins, tags and clones follow the snapshot.
+        let new_key = Self::property_key(root, &new_name);
+        let mut pinned = self.pinned_snapshots.write().unwrap();
+        if pinned.remove(&old_key) {
+            pinned.insert(new_key.clone());
+        }
+        let mut tags = self.snapshot_tags.write().unwrap();
+        if let Some(snapshot_tags) = tags.remove(&old_key) {
+            tags.insert(new_key, snapshot_tags);
+        }
+        for be in bes.iter_mut() {
+            if be.root == *root && be.origin.as_ref() == Some(&old_name) {
+                be.origin = Some(new_name.clone());
+            }
+        }
+        Ok(())
+    }
+
+    fn diff(
+        &self,
+        from: &Label,
+        to: Option<&Label>,
+        paths: &[PathBuf],
+        root: Option<&Root>,
+    ) -> Result<Vec<FileChange>, Error> {
+        diff::validate_paths(paths)?;
+        let root = self.effective_root(root);
+        let bes = self.bes.read().unwrap();
+        let to = match to {
+            Some(label) => label.clone(),
+            None => bes
+                .iter()
+                .find(|be| be.active && be.root == *root)
+                .map(|be| Label::Name(be.name.clone()))
+                .ok_or(Error::NoActiveBootEnvironment)?,
+        };
+        for label in [from, &to] {
+            match label {
+                Label::Name(name) => {
+                    if !bes.iter().any(|be| be.name == *name && be.root == *root) {
+                        return Err(Error::not_found(name));
+                    }
+                }
+                Label::Snapshot(name, snapshot) => {
+                    self.snapshot_key(&bes, root, name, snapshot)?;
+                }
+            }
+        }
+        if *from == to {
+            return Ok(vec![]);
+        }
+
+        // Everything else differs in the same way.
+        let change = |change, file_type, path: &str| FileChange {
+            path: path.to_string(),
+            change,
+            file_type,
+            new_path: None,
+        };
+        let changes = vec![
+            change(ChangeKind::Modified, FileType::Directory, "/etc"),
+            change(ChangeKind::Modified, FileType::File, "/etc/hostname"),
+            change(ChangeKind::Added, FileType::File, "/etc/motd"),
+            change(
+                ChangeKind::Removed,
+                FileType::Symlink,
+                "/usr/lib/libfoo.so.1",
+            ),
+            FileChange {
+                new_path: Some("/usr/local/bin/tool".to_string()),
+                ..change(ChangeKind::Renamed, FileType::File, "/usr/bin/tool")
+            },
+        ];
+        Ok(diff::finish(changes, paths, false))
+    }
+
+    fn export(
+        &self,
+        target: &Label,
+        base: Option<&str>,
+        fd: BorrowedFd<'_>,
+        root: Option<&Root>,
+    ) -> Result<String, Error> {
+        let root = self.effective_root(root);
+        let bes = self.bes.read().unwrap();
+        let (name, snapshot) = match target {
+            Label::Name(name) => (name, generate_snapshot_name()),
+            Label::Snapshot(name, snapshot) => {
+                self.snapshot_key(&bes, root, name, snapshot)?;
+                (name, snapshot.clone())
+            }
+        };
+        let be = bes
+            .iter()
+            .find(|be| be.name == *name && be.root == *root)
+            .ok_or_else(|| Error::not_found(name))?;
+        if let Some(base) = base {
+            self.snapshot_key(&bes, root, name, base)?;
+        }
+
+        // There is no data to send, so the stream is just the header.
+        let header = ExportHeader {
+            name: name.clone(),
+            snapshot: snapshot.clone(),
+            base: base.map(|s| s.to_string()),
+            description: be.description.clone(),
+            tags: be.tags.clone(),
+            os_release: None,
+            hostid: None,
+            datasets: vec![ExportedDataset::default()],
+        };
+        header.write_to(File::from(fd.try_clone_to_owned()?))?;
+        Ok(format!("{}@{}", name, snapshot))
+    }
+
+    fn import(&self, be_name: &str, fd: BorrowedFd<'_>, root: Option<&Root>) -> Result<(), Error> {
+        let header = ExportHeader::read_from(File::from(fd.try_clone_to_owned()?))?;
+        let root = self.effective_root(root);
+        validate_be_name(be_name, root.as_str())?;
+        let mut bes = self.bes.write().unwrap();
+
+        if let Some(base) = &header.base {
+            self.snapshot_key(&bes, root, be_name, base)?;
+            // Safe to unwrap because snapshot_key() checks it exists.
+            let be = bes
+                .iter_mut()
+                .find(|be| be.name == be_name && be.root == *root)
+                .unwrap();
+            if let Some(description) = header.description {
+                be.description = Some(description);
+            }
+            be.tags.extend(header.tags);
+            return Ok(());
+        }
+
+        if bes.iter().any(|be| be.name == be_name && be.root == *root) {
+            return Err(Error::conflict(be_name));
+        }
+        bes.push(BootEnvironment {
+            name: be_name.to_string(),
+            root: root.clone(),
+            guid: Self::generate_guid(be_name),
+            description: header.imported_description(),
+            mountpoint: None,
+            active: false,
+            next_boot: false,
+            boot_once: false,
+            trial: false,
+            pinned: false,
+            tags: header.tags,
+            space: 8192,
+            usage: SpaceUsage::default(),
+            origin: None,
+            created: Utc::now().timestamp(),
+        });
+        Ok(())
+    }
+
+    fn check(&self, be_name: &str, root: Option<&Root>) -> Result<Vec<HealthIssue>, Error> {
+        let root = self.effective_root(root);
+        if !self
+            .bes
+            .read()
+            .unwrap()
+            .iter()
+            .any(|be| be.name == be_name && be.root == *root)
+        {
+            return Err(Error::not_found(be_name));
+        }
+        // Emulated boot environments have no contents to check, so they are
+        // healthy unless told otherwise.
+        let key = Self::property_key(root, be_name);
+        Ok(self
+            .health_issues
+            .read()
+            .unwrap()
+            .get(&key)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn doctor(&self, fix: bool, root: Option<&Root>) -> Result<Vec<Finding>, Error> {
+        let root = self.effective_root(root);
+        let pool = root.as_str().split('/').next().unwrap_or_default();
+        let mut bes = self.bes.write().unwrap();
+        let mut findings = vec![];
+
+        // Emulated boot environments always have the right properties and
+        // are never left mounted, so only the activation state can go wrong.
+        if !bes
+            .iter()
+            .any(|be| be.root == *root && (be.next_boot || be.boot_once))
+        {
+            let finding = Finding::new(
+                "bootfs",
+                pool,
+                "The pool has no bootfs set".to_string(),
+                "There is no boot environment to boot by default.",
+            );
+            findings.push(
+                match bes.iter().position(|be| be.root == *root && be.active) {
+                    Some(index) => finding
+                        .with_fix(format!("activate '{}'", bes[index].name))
+                        .apply(fix, || {
+                            bes[index].next_boot = true;
+                            Ok(())
+                        }),
+                    None => finding,
+                },
+            );
+        }
+        if let Some(be) = bes
+            .iter_mut()
+            .find(|be| be.root == *root && be.active && be.boot_once)
+        {
+            // The emulator doesn't record what a temporary activation falls
+            // back to, so this is the closest it gets to a stale one.
+            let name = be.name.clone();
+            findings.push(
+                Finding::new(
+                    "previous-bootfs",
+                    &name,
+                    format!(
+                        "'{}' was activated temporarily and has been booted, but the temporary \
+                         activation was never cleared",
+                        name
+                    ),
+                    "The temporary activation should have been cleared early in the boot.",
+                )
+                .with_fix(format!("activate '{}' again", name))
+                .apply(fix, || {
+                    be.boot_once = false;
+                    be.next_boot = true;
+                    Ok(())
+                }),
+            );
+        }
+        Ok(findings)
+    }
+
+    fn replicate(
+        &self,
+        dest: &Root,
+        names: &[String],
+        root: Option<&Root>,
+    ) -> Result<Vec<Replication>, Error> {
+        let root = self.effective_root(root);
+        if dest == root {
+            return Err(Error::invalid_root(dest.as_str()));
+        }
+        let mut bes = self.bes.write().unwrap();
+        if let Some(name) = names
+            .iter()
+            .find(|name| !bes.iter().any(|be| be.name == **name && be.root == *root))
+        {
+            return Err(Error::not_found(name));
+        }
+        let sources: Vec<BootEnvironment> = bes
+            .iter()
+            .filter(|be| be.root == *root && (names.is_empty() || names.contains(&be.name)))
+            .cloned()
+            .collect();
+
+        let mut props = self.properties.write().unwrap();
+        let mut replications = vec![];
+        for source in sources {
+            // There is no data, so just send the (sample) snapshots newer
+            // than the last one replicated to the destination.
+            let key = Self::property_key(root, &source.name);
+            let replica = format!("{}/{}", dest.as_str(), source.name);
+            let recorded = props
+                .get(&key)
+                .and_then(|p| p.get(REPLICATED_PROP))
+                .cloned();
+            let replicated = recorded
+                .as_deref()
+                .and_then(|recorded| recorded_replication(recorded, &replica));
+            let snapshots: Vec<String> = self
+                .current_snapshots(root, &source.name)
+                .into_iter()
+                .filter_map(|s| s.name.split_once('@').map(|(_, s)| s.to_string()))
+                .collect();
+            let start = replicated
+                .and_then(|r| snapshots.iter().position(|s| *s == r))
+                .map_or(0, |i| i + 1);
+            let sent = snapshots[start..].to_vec();
+
+            match bes
+                .iter_mut()
+                .find(|be| be.name == source.name && be.root == *dest)
+            {
+                Some(be) => {
+                    be.description = source.description.clone();
+                    be.tags = source.tags.clone();
+                }
+                None => bes.push(BootEnvironment {
+                    root: dest.clone(),
+                    mountpoint: None,
+                    active: false,
+                    next_boot: false,
+                    boot_once: false,
+                    trial: false,
+                    pinned: false,
+                    ..source.clone()
+                }),
+            }
+            if let Some(latest) = snapshots.last() {
+                let recorded = record_replication(recorded.as_deref(), &replica, latest);
+                props
+                    .entry(key)
+                    .or_default()
+                    .insert(REPLICATED_PROP.to_string(), recorded);
+            }
+            replications.push(Replication {
+                name: source.name,
+                snapshots: sent,
+                resumed: false,
+            });
+        }
+        Ok(replications)
+    }
+
     fn activate(&self, be_name: &str, temporary: bool, root: Option<&Root>) -> Result<(), Error> {
         let root = self.effective_root(root);
         let mut bes = self.bes.write().unwrap();
@@ -485,18 +1190,22 @@ impl Client for EmulatorClient {
         if temporary {
             // Set temporary activation (boot_once only)
             // Only one BE can have boot_once=true within the same root, and no BE should have next_boot=true when using temporary activation
+            // It also supersedes a trial of any other BE.
             for be in bes.iter_mut().filter(|be| be.root == *root) {
                 be.boot_once = false;
                 be.next_boot = false;
+                be.trial &= be.name == be_name;
             }
             bes[target_index].boot_once = true;
         } else {
             // Permanent activation - this would normally require a reboot
             // For simulation purposes, we'll set it as the next boot environment
             // Only one BE can have next_boot=true within the same root, and no BE should have boot_once=true
+            // A permanent activation also supersedes any trial.
             for be in bes.iter_mut().filter(|be| be.root == *root) {
                 be.next_boot = false;
                 be.boot_once = false;
+                be.trial = false;
             }
             bes[target_index].next_boot = true;
         }
@@ -504,6 +1213,62 @@ impl Client for EmulatorClient {
         Ok(())
     }
 
+    fn activate_trial(&self, be_name: &str, root: Option<&Root>) -> Result<(), Error> {
+        self.activate(be_name, true, root)?;
+        let root = self.effective_root(root);
+        for be in self
+            .bes
+            .write()
+            .unwrap()
+            .iter_mut()
+            .filter(|be| be.root == *root)
+        {
+            be.trial = be.name == be_name;
+        }
+        Ok(())
+    }
+
+    fn cancel_trial(&self, root: Option<&Root>) -> Result<(), Error> {
+        let root = self.effective_root(root);
+        for be in self
+            .bes
+            .write()
+            .unwrap()
+            .iter_mut()
+            .filter(|be| be.root == *root)
+        {
+            be.trial = false;
+        }
+        Ok(())
+    }
+
+    fn confirm(&self, root: Option<&Root>) -> Result<Option<String>, Error> {
+        let root = self.effective_root(root);
+        let mut bes = self.bes.write().unwrap();
+        let index = match bes.iter().position(|be| be.trial && be.root == *root) {
+            Some(index) => index,
+            None => return Ok(None), // Nothing to confirm.
+        };
+        let name = bes[index].name.clone();
+
+        // The emulator can't reboot, so a trial boot has only happened if the
+        // boot environment on trial is already active.
+        if bes[index].active {
+            for be in bes.iter_mut().filter(|be| be.root == *root) {
+                be.next_boot = false;
+                be.boot_once = false;
+                be.trial = false;
+            }
+            bes[index].next_boot = true;
+            return Ok(Some(name));
+        }
+        if bes[index].boot_once {
+            return Err(Error::TrialNotBooted { name });
+        }
+        bes[index].trial = false;
+        Err(Error::TrialFailed { name })
+    }
+
     fn clear_boot_once(&self, root: Option<&Root>) -> Result<(), Error> {
         let root = self.effective_root(root);
         let mut bes = self.bes.write().unwrap();
@@ -527,20 +1292,130 @@ impl Client for EmulatorClient {
         Ok(())
     }
 
-    fn rollback(&self, be_name: &str, _snapshot: &str, root: Option<&Root>) -> Result<(), Error> {
+    fn record_boot(&self, root: Option<&Root>) -> Result<Option<BootRecord>, Error> {
         let root = self.effective_root(root);
-        if !self
+        let record = match self
             .bes
             .read()
             .unwrap()
             .iter()
-            .any(|be| be.name == be_name && be.root == *root)
+            .find(|be| be.active && be.root == *root)
         {
-            return Err(Error::NotFound {
+            Some(be) => BootRecord {
+                timestamp: Utc::now().timestamp(),
+                guid: be.guid,
+                name: be.name.clone(),
+                boot_once: be.boot_once,
+            },
+            None => return Ok(None),
+        };
+        let mut history = self.history.write().unwrap();
+        history.push(record.clone());
+        let excess = history.len().saturating_sub(MAX_BOOT_HISTORY);
+        history.drain(..excess);
+        Ok(Some(record))
+    }
+
+    fn get_boot_history(&self, _root: Option<&Root>) -> Result<Vec<BootRecord>, Error> {
+        Ok(self.history.read().unwrap().clone())
+    }
+
+    fn rollback(
+        &self,
+        be_name: &str,
+        snapshot: &str,
+        destroy_newer: bool,
+        force: bool,
+        safety_snapshot: bool,
+        root: Option<&Root>,
+    ) -> Result<RolledBack, Error> {
+        let root = self.effective_root(root);
+        let be = {
+            let bes = self.bes.read().unwrap();
+            self.snapshot_key(&bes, root, be_name, snapshot)?;
+            // Safe to unwrap because snapshot_key() checks it exists.
+            bes.iter()
+                .find(|be| be.name == be_name && be.root == *root)
+                .unwrap()
+                .clone()
+        };
+        if be.active && !force {
+            return Err(Error::RollbackActive {
+                name: be_name.to_string(),
+                snapshot: snapshot.to_string(),
+            });
+        }
+        let label = format!("{}@{}", be_name, snapshot);
+        let mut snapshots = self.current_snapshots(root, be_name);
+        snapshots.sort_by_key(|snapshot| snapshot.created);
+        let newer: Vec<String> = snapshots
+            .into_iter()
+            .map(|snapshot| snapshot.name)
+            .skip_while(|name| *name != label)
+            .skip(1)
+            .collect();
+        if !newer.is_empty() && !destroy_newer {
+            return Err(Error::NewerSnapshots {
                 name: be_name.to_string(),
+                snapshot: snapshot.to_string(),
+                newer,
             });
         }
-        unimplemented!("Mocking does not yet track snapshots");
+
+        let short_names: Vec<String> = newer
+            .iter()
+            .filter_map(|name| name.split_once('@').map(|(_, s)| s.to_string()))
+            .collect();
+        check_destroyable(self, be_name, &short_names, Some(root))?;
+
+        let mut rolled_back = RolledBack::default();
+        for name in newer.into_iter().rev() {
+            if let Some((_, snapshot)) = name.split_once('@') {
+                let target = Label::Snapshot(be_name.to_string(), snapshot.to_string());
+                self.destroy(&target, false, false, false, false, Some(root))?;
+            }
+            rolled_back.destroyed.push(name);
+        }
+
+        // There is no data to roll back, but the previous state is kept in a
+        // boot environment of its own, as with libzfs.
+        if safety_snapshot {
+            let kept_name = format!(
+                "{}-pre-rollback-{}",
+                be_name,
+                Utc::now().format("%Y%m%d-%H%M%S")
+            );
+            let mut bes = self.bes.write().unwrap();
+            if bes
+                .iter()
+                .any(|be| be.name == kept_name && be.root == *root)
+            {
+                return Err(Error::conflict(&kept_name));
+            }
+            bes.push(BootEnvironment {
+                name: kept_name.clone(),
+                guid: Self::generate_guid(&kept_name),
+                description: Some(format!("'{}' before rollback to '{}'", be_name, snapshot)),
+                active: false,
+                next_boot: false,
+                boot_once: false,
+                trial: false,
+                pinned: false,
+                origin: Some(label),
+                created: Utc::now().timestamp(),
+                ..be
+            });
+            drop(bes);
+            // The previous state keeps its properties, as the renamed
+            // dataset would, and the recreated one gets a copy of them.
+            let mut props = self.properties.write().unwrap();
+            if let Some(be_props) = props.get(&Self::property_key(root, be_name)).cloned() {
+                props.insert(Self::property_key(root, &kept_name), be_props);
+            }
+            rolled_back.safety_snapshot =
+                Some(format!("{}@{}", kept_name, generate_snapshot_name()));
+        }
+        Ok(rolled_back)
     }
 
     fn get_boot_environments(&self, root: Option<&Root>) -> Result<Vec<BootEnvironment>, Error> {
@@ -568,7 +1443,42 @@ impl Client for EmulatorClient {
                 name: be_name.to_string(),
             });
         }
-        Ok(sample_snapshots(be_name))
+        Ok(self.snapshots_of(root, be_name))
+    }
+
+    fn get_orphaned_snapshots(&self, root: Option<&Root>) -> Result<Vec<Snapshot>, Error> {
+        let root = self.effective_root(root);
+        let bes = self.bes.read().unwrap();
+        let mut orphans = vec![];
+        for be in bes.iter().filter(|be| be.root == *root) {
+            // In the sample data, the snapshots without a description are the
+            // ones taken by `beadm create`.
+            orphans.extend(
+                self.snapshots_of(root, &be.name)
+                    .into_iter()
+                    .filter(|snapshot| {
+                        let has_clones = bes
+                            .iter()
+                            .any(|other| other.origin.as_ref() == Some(&snapshot.name));
+                        super::is_orphaned(snapshot, has_clones)
+                    }),
+            );
+        }
+        Ok(orphans)
+    }
+
+    fn get_datasets(&self, be_name: &str, root: Option<&Root>) -> Result<Vec<ChildDataset>, Error> {
+        let root = self.effective_root(root);
+        if !self
+            .bes
+            .read()
+            .unwrap()
+            .iter()
+            .any(|be| be.name == be_name && be.root == *root)
+        {
+            return Err(Error::not_found(be_name));
+        }
+        Ok(sample_datasets(be_name))
     }
 
     fn snapshot(
@@ -661,6 +1571,109 @@ impl Client for EmulatorClient {
         }
     }
 
+    fn pin(&self, target: &Label, root: Option<&Root>) -> Result<(), Error> {
+        self.set_pinned(target, true, root)
+    }
+
+    fn unpin(&self, target: &Label, root: Option<&Root>) -> Result<(), Error> {
+        self.set_pinned(target, false, root)
+    }
+
+    fn tag(
+        &self,
+        target: &Label,
+        key: &str,
+        value: &str,
+        root: Option<&Root>,
+    ) -> Result<(), Error> {
+        self.set_tag(target, key, Some(value), root)
+    }
+
+    fn untag(&self, target: &Label, key: &str, root: Option<&Root>) -> Result<(), Error> {
+        self.set_tag(target, key, None, root)
+    }
+
+    fn get_zfs_properties(
+        &self,
+        target: &Label,
+        names: &[String],
+        root: Option<&Root>,
+    ) -> Result<Vec<Property>, Error> {
+        let root = self.effective_root(root);
+        let mut props = self.emulated_properties(target, root)?;
+        if names.is_empty() {
+            return Ok(props
+                .into_iter()
+                .map(|(name, (value, source))| Property {
+                    name,
+                    value,
+                    source,
+                })
+                .collect());
+        }
+        names
+            .iter()
+            .map(|name| {
+                let (value, source) = match props.remove(name) {
+                    Some(prop) => prop,
+                    // Unset user properties.
+                    None if name.contains(':') => ("-".to_string(), "-".to_string()),
+                    None => return Err(Error::UnknownProp { name: name.clone() }),
+                };
+                Ok(Property {
+                    name: name.clone(),
+                    value,
+                    source,
+                })
+            })
+            .collect()
+    }
+
+    fn set_zfs_properties(
+        &self,
+        be_name: &str,
+        properties: &[String],
+        root: Option<&Root>,
+    ) -> Result<(), Error> {
+        let root = self.effective_root(root);
+
+        // Parse and check all properties before setting any of them.
+        let mut props_map = HashMap::new();
+        for prop_string in properties {
+            let (name, value) = super::parse_property(prop_string)?;
+
+            // Protect critical properties that are essential for boot environments
+            if name == "canmount" || name == "mountpoint" {
+                return Err(Error::invalid_prop(name, value));
+            }
+            super::check_reserved_property(name)?;
+            if !name.contains(':') && !DEFAULT_PROPERTIES.iter().any(|(known, _)| *known == name) {
+                return Err(Error::UnknownProp {
+                    name: name.to_string(),
+                });
+            }
+
+            props_map.insert(name.to_string(), value.to_string());
+        }
+
+        if !self
+            .bes
+            .read()
+            .unwrap()
+            .iter()
+            .any(|be| be.name == be_name && be.root == *root)
+        {
+            return Err(Error::not_found(be_name));
+        }
+        self.properties
+            .write()
+            .unwrap()
+            .entry(Self::property_key(root, be_name))
+            .or_default()
+            .extend(props_map);
+        Ok(())
+    }
+
     fn active_root(&self) -> Option<&Root> {
         Some(&self.active_root)
     }
@@ -677,7 +1690,18 @@ fn sample_boot_environments() -> Vec<BootEnvironment> {
             active: true,
             next_boot: true,
             boot_once: false,
-            space: 950_000_000,  // ~906M
+            trial: false,
+            pinned: false,
+            tags: BTreeMap::new(),
+            space: 950_000_000, // ~906M
+            usage: SpaceUsage {
+                used_by_dataset: 949_192_000,
+                used_by_snapshots: 808_000,
+                referenced: 949_192_000,
+                written: 1_228_800,
+                compress_ratio: 1.85,
+            },
+            origin: None,
             created: 1623301740, // 2021-06-10 01:09
         },
         BootEnvironment {
@@ -689,12 +1713,46 @@ fn sample_boot_environments() -> Vec<BootEnvironment> {
             active: false,
             next_boot: false,
             boot_once: false,
-            space: 8192,         // 8K
+            trial: false,
+            pinned: false,
+            tags: BTreeMap::new(),
+            space: 8192, // 8K
+            usage: SpaceUsage {
+                used_by_dataset: 7168,
+                used_by_snapshots: 1024,
+                referenced: 949_196_800,
+                written: 7168,
+                compress_ratio: 1.85,
+            },
+            origin: Some("default@2021-06-10-05:10".to_string()),
             created: 1623305460, // 2021-06-10 02:11
         },
     ]
 }
 
+fn sample_boot_history() -> Vec<BootRecord> {
+    vec![
+        BootRecord {
+            timestamp: 1623306600, // 2021-06-10 06:30
+            guid: EmulatorClient::generate_guid("default"),
+            name: "default".to_string(),
+            boot_once: false,
+        },
+        BootRecord {
+            timestamp: 1623310200, // 2021-06-10 07:30
+            guid: EmulatorClient::generate_guid("alt"),
+            name: "alt".to_string(),
+            boot_once: true,
+        },
+        BootRecord {
+            timestamp: 1623313800, // 2021-06-10 08:30
+            guid: EmulatorClient::generate_guid("default"),
+            name: "default".to_string(),
+            boot_once: false,
+        },
+    ]
+}
+
 fn sample_snapshots(be_name: &str) -> Vec<Snapshot> {
     match be_name {
         "default" => vec![
@@ -702,28 +1760,72 @@ fn sample_snapshots(be_name: &str) -> Vec<Snapshot> {
                 name: "default@2021-06-10-04:30".to_string(),
                 root: Root::from_str("zfake/ROOT").unwrap(),
                 description: Some("Automatic snapshot".to_string()),
-                space: 404_000,      // 404K
+                space: 404_000, // 404K
+                referenced: 948_000_000,
+                written: 948_000_000,
                 created: 1623303000, // 2021-06-10 04:30
+                pinned: false,
+                tags: BTreeMap::new(),
             },
             Snapshot {
                 name: "default@2021-06-10-05:10".to_string(),
                 root: Root::from_str("zfake/ROOT").unwrap(),
                 description: None,
-                space: 404_000,      // 404K
+                space: 404_000, // 404K
+                referenced: 949_000_000,
+                written: 1_404_000,
                 created: 1623305400, // 2021-06-10 05:10
+                pinned: false,
+                tags: BTreeMap::new(),
             },
         ],
         "alt" => vec![Snapshot {
             name: "alt@backup".to_string(),
             root: Root::from_str("zfake/ROOT").unwrap(),
             description: Some("Manual backup".to_string()),
-            space: 1024,         // 1K
+            space: 1024, // 1K
+            referenced: 949_196_800,
+            written: 8192,
             created: 1623306000, // 2021-06-10 05:06:40
+            pinned: false,
+            tags: BTreeMap::new(),
         }],
         _ => vec![],
     }
 }
 
+fn sample_datasets(be_name: &str) -> Vec<ChildDataset> {
+    match be_name {
+        "default" => vec![
+            ChildDataset {
+                name: "default/usr".to_string(),
+                root: Root::from_str("zfake/ROOT").unwrap(),
+                mountpoint: None,
+                canmount: "off".to_string(),
+                space: 204_800_000,  // 195M
+                created: 1623301740, // 2021-06-10 05:09
+            },
+            ChildDataset {
+                name: "default/usr/local".to_string(),
+                root: Root::from_str("zfake/ROOT").unwrap(),
+                mountpoint: Some(PathBuf::from("/usr/local")),
+                canmount: "on".to_string(),
+                space: 4_096_000,    // 3.91M
+                created: 1623301740, // 2021-06-10 05:09
+            },
+            ChildDataset {
+                name: "default/var".to_string(),
+                root: Root::from_str("zfake/ROOT").unwrap(),
+                mountpoint: Some(PathBuf::from("/var")),
+                canmount: "on".to_string(),
+                space: 52_428_800,   // 50M
+                created: 1623301740, // 2021-06-10 05:09
+            },
+        ],
+        _ => vec![],
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -752,14 +1854,19 @@ mod tests {
     #[test]
     fn test_emulated_new_with_host_id() {
         let client = EmulatorClient::sampled();
-        // Host ID is accepted but ignored in the mock implementation
+        // Host ID is validated but otherwise ignored in the mock implementation
         client
-            .create_empty("test-hostid", None, Some("test-host"), &[], None)
+            .create_empty("test-hostid", None, Some("00bab10c"), &[], None)
             .unwrap();
 
         let bes = client.get_boot_environments(None).unwrap();
         let test_be = bes.iter().find(|be| be.name == "test-hostid").unwrap();
         assert_eq!(test_be.description, None);
+
+        let result = client.create_empty("test-bad-hostid", None, Some("test-host"), &[], None);
+        assert!(matches!(result, Err(Error::InvalidHostId { .. })));
+        let bes = client.get_boot_environments(None).unwrap();
+        assert!(!bes.iter().any(|be| be.name == "test-bad-hostid"));
     }
 
     #[test]
@@ -811,7 +1918,12 @@ mod tests {
             active: false,
             next_boot: false,
             boot_once: false,
+            trial: false,
+            pinned: false,
+            tags: BTreeMap::new(),
             space: 8192,
+            usage: SpaceUsage::default(),
+            origin: None,
             created: 1623301740,
         };
 
@@ -823,7 +1935,14 @@ mod tests {
         assert_eq!(bes[0].name, "destroyable");
 
         // Destroy it
-        let result = client.destroy(&Label::Name("destroyable".to_string()), false, false, None);
+        let result = client.destroy(
+            &Label::Name("destroyable".to_string()),
+            false,
+            false,
+            false,
+            false,
+            None,
+        );
         assert!(result.is_ok());
 
         // Verify it's gone
@@ -834,7 +1953,14 @@ mod tests {
     #[test]
     fn test_emulated_destroy_not_found() {
         let client = EmulatorClient::empty();
-        let result = client.destroy(&Label::Name("nonexistent".to_string()), false, false, None);
+        let result = client.destroy(
+            &Label::Name("nonexistent".to_string()),
+            false,
+            false,
+            false,
+            false,
+            None,
+        );
         assert!(matches!(result, Err(Error::NotFound { name }) if name == "nonexistent"));
     }
 
@@ -850,14 +1976,26 @@ mod tests {
             active: true,
             next_boot: true,
             boot_once: false,
+            trial: false,
+            pinned: false,
+            tags: BTreeMap::new(),
             space: 950_000_000,
+            usage: SpaceUsage::default(),
+            origin: None,
             created: 1623301740,
         };
 
         let client = EmulatorClient::new(vec![active_be]);
 
         // Try to destroy the active boot environment - should fail
-        let result = client.destroy(&Label::Name("active-be".to_string()), false, false, None);
+        let result = client.destroy(
+            &Label::Name("active-be".to_string()),
+            false,
+            false,
+            false,
+            false,
+            None,
+        );
         assert!(matches!(result, Err(Error::CannotDestroyActive { name }) if name == "active-be"));
 
         // Verify it still exists
@@ -878,14 +2016,26 @@ mod tests {
             active: false,
             next_boot: false,
             boot_once: false,
+            trial: false,
+            pinned: false,
+            tags: BTreeMap::new(),
             space: 8192,
+            usage: SpaceUsage::default(),
+            origin: None,
             created: 1623301740,
         };
 
         let client = EmulatorClient::new(vec![mounted_be]);
 
         // Try to destroy without force_unmount - should fail
-        let result = client.destroy(&Label::Name("mounted-be".to_string()), false, false, None);
+        let result = client.destroy(
+            &Label::Name("mounted-be".to_string()),
+            false,
+            false,
+            false,
+            false,
+            None,
+        );
         assert!(matches!(result, Err(Error::Mounted { name, mountpoint })
             if name == "mounted-be" && mountpoint == "/mnt/test"));
 
@@ -895,7 +2045,14 @@ mod tests {
         assert_eq!(bes[0].name, "mounted-be");
 
         // Try to destroy with force_unmount - should succeed
-        let result = client.destroy(&Label::Name("mounted-be".to_string()), true, false, None);
+        let result = client.destroy(
+            &Label::Name("mounted-be".to_string()),
+            true,
+            false,
+            false,
+            false,
+            None,
+        );
         assert!(result.is_ok());
 
         // Verify it's gone
@@ -903,6 +2060,170 @@ mod tests {
         assert_eq!(bes.len(), 0);
     }
 
+    #[test]
+    fn test_emulated_destroy_dry_run() {
+        let client = EmulatorClient::sampled();
+
+        // A dry run reports the plan without destroying anything.
+        let plan = client
+            .destroy(
+                &Label::Name("alt".to_string()),
+                false,
+                true,
+                false,
+                true,
+                None,
+            )
+            .unwrap();
+        assert_eq!(
+            plan,
+            DestroyPlan {
+                unpin: vec![],
+                unmount: vec![],
+                promote: vec![],
+                snapshots: vec!["alt@backup".to_string()],
+                datasets: vec!["alt".to_string()],
+                space: 8192,
+            }
+        );
+        let bes = client.get_boot_environments(None).unwrap();
+        assert!(bes.iter().any(|be| be.name == "alt"));
+
+        // Clones of a snapshot are promoted before it (and the matching
+        // snapshots of child datasets) are destroyed.
+        let label = Label::Snapshot("default".to_string(), "2021-06-10-05:10".to_string());
+        let plan = client
+            .destroy(&label, false, false, false, true, None)
+            .unwrap();
+        assert_eq!(plan.promote, vec!["alt".to_string()]);
+        assert_eq!(
+            plan.snapshots,
+            vec![
+                "default/usr@2021-06-10-05:10".to_string(),
+                "default/usr/local@2021-06-10-05:10".to_string(),
+                "default/var@2021-06-10-05:10".to_string(),
+                "default@2021-06-10-05:10".to_string(),
+            ]
+        );
+        assert_eq!(plan.space, 404_000);
+
+        // Dry runs are still subject to the same checks.
+        let result = client.destroy(
+            &Label::Name("default".to_string()),
+            false,
+            true,
+            false,
+            true,
+            None,
+        );
+        assert!(matches!(result, Err(Error::CannotDestroyActive { .. })));
+
+        // The real thing follows the same plan.
+        let dry_run = client
+            .destroy(
+                &Label::Name("alt".to_string()),
+                false,
+                true,
+                false,
+                true,
+                None,
+            )
+            .unwrap();
+        let plan = client
+            .destroy(
+                &Label::Name("alt".to_string()),
+                false,
+                true,
+                false,
+                false,
+                None,
+            )
+            .unwrap();
+        assert_eq!(plan, dry_run);
+        let bes = client.get_boot_environments(None).unwrap();
+        assert!(!bes.iter().any(|be| be.name == "alt"));
+    }
+
+    #[test]
+    fn test_emulated_destroy_dry_run_promotes_clone() {
+        let client = EmulatorClient::sampled();
+        client
+            .create(
+                "clone",
+                None,
+                Some(&Label::Snapshot("alt".to_string(), "backup".to_string())),
+                &[],
+                None,
+            )
+            .unwrap();
+        client
+            .mount(
+                "alt",
+                Some(Path::new("/mnt/alt")),
+                &MountOptions::default(),
+                None,
+            )
+            .unwrap();
+
+        // Mounted boot environments need -f, even for a dry run.
+        let result = client.destroy(
+            &Label::Name("alt".to_string()),
+            false,
+            true,
+            false,
+            true,
+            None,
+        );
+        assert!(matches!(result, Err(Error::Mounted { .. })));
+
+        // The clone takes over alt@backup, so it isn't destroyed.
+        let plan = client
+            .destroy(
+                &Label::Name("alt".to_string()),
+                true,
+                true,
+                false,
+                false,
+                None,
+            )
+            .unwrap();
+        assert_eq!(plan.unmount, vec!["/mnt/alt".to_string()]);
+        assert_eq!(plan.promote, vec!["clone".to_string()]);
+        assert!(plan.snapshots.is_empty());
+        assert_eq!(plan.datasets, vec!["alt".to_string()]);
+        assert_eq!(plan.space, 8192 - 1024);
+
+        let bes = client.get_boot_environments(None).unwrap();
+        let clone = bes.iter().find(|be| be.name == "clone").unwrap();
+        assert_eq!(clone.origin, None);
+    }
+
+    #[test]
+    fn test_emulated_orphaned_snapshots() {
+        let client = EmulatorClient::sampled();
+
+        // default@2021-06-10-05:10 is still the origin of alt.
+        assert!(client.get_orphaned_snapshots(None).unwrap().is_empty());
+
+        client
+            .destroy(
+                &Label::Name("alt".to_string()),
+                false,
+                true,
+                false,
+                false,
+                None,
+            )
+            .unwrap();
+        let orphans: Vec<String> = client
+            .get_orphaned_snapshots(None)
+            .unwrap()
+            .into_iter()
+            .map(|snapshot| snapshot.name)
+            .collect();
+        assert_eq!(orphans, vec!["default@2021-06-10-05:10".to_string()]);
+    }
+
     #[test]
     fn test_emulated_create_and_destroy_integration() {
         let client = EmulatorClient::new(vec![]);
@@ -922,7 +2243,14 @@ mod tests {
         assert_eq!(bes[0].description, Some("Temporary BE".to_string()));
 
         // Destroy it
-        let result = client.destroy(&Label::Name("temp-be".to_string()), false, false, None);
+        let result = client.destroy(
+            &Label::Name("temp-be".to_string()),
+            false,
+            false,
+            false,
+            false,
+            None,
+        );
         assert!(result.is_ok());
 
         // Verify it's gone
@@ -930,7 +2258,14 @@ mod tests {
         assert_eq!(bes.len(), 0);
 
         // Try to destroy it again - should fail
-        let result = client.destroy(&Label::Name("temp-be".to_string()), false, false, None);
+        let result = client.destroy(
+            &Label::Name("temp-be".to_string()),
+            false,
+            false,
+            false,
+            false,
+            None,
+        );
         assert!(matches!(result, Err(Error::NotFound { name }) if name == "temp-be"));
     }
 
@@ -945,7 +2280,12 @@ mod tests {
             active: false,
             next_boot: false,
             boot_once: false,
+            trial: false,
+            pinned: false,
+            tags: BTreeMap::new(),
             space: 8192,
+            usage: SpaceUsage::default(),
+            origin: None,
             created: 1623301740,
         };
 
@@ -953,7 +2293,12 @@ mod tests {
 
         // Mount the BE
         let path = PathBuf::from("/mnt/test");
-        let result = client.mount("test-be", Some(path.as_path()), MountMode::ReadWrite, None);
+        let result = client.mount(
+            "test-be",
+            Some(path.as_path()),
+            &MountOptions::default(),
+            None,
+        );
         assert!(result.is_ok());
 
         // Verify it's mounted
@@ -967,7 +2312,7 @@ mod tests {
     #[test]
     fn test_emulated_mount_not_found() {
         let client = EmulatorClient::new(vec![]);
-        let result = client.mount("nonexistent", None, MountMode::ReadWrite, None);
+        let result = client.mount("nonexistent", None, &MountOptions::default(), None);
         assert!(matches!(result, Err(Error::NotFound { name }) if name == "nonexistent"));
     }
 
@@ -982,12 +2327,22 @@ mod tests {
             active: false,
             next_boot: false,
             boot_once: false,
+            trial: false,
+            pinned: false,
+            tags: BTreeMap::new(),
             space: 8192,
+            usage: SpaceUsage::default(),
+            origin: None,
             created: 1623301740,
         };
         let client = EmulatorClient::new(vec![test_be]);
         let path = PathBuf::from("/mnt/test");
-        let result = client.mount("test-be", Some(path.as_path()), MountMode::ReadWrite, None);
+        let result = client.mount(
+            "test-be",
+            Some(path.as_path()),
+            &MountOptions::default(),
+            None,
+        );
         assert!(matches!(result, Err(Error::Mounted { name, mountpoint })
             if name == "test-be" && mountpoint == "/mnt/existing"));
     }
@@ -1003,7 +2358,12 @@ mod tests {
             active: false,
             next_boot: false,
             boot_once: false,
+            trial: false,
+            pinned: false,
+            tags: BTreeMap::new(),
             space: 8192,
+            usage: SpaceUsage::default(),
+            origin: None,
             created: 1623301740,
         };
 
@@ -1016,13 +2376,18 @@ mod tests {
             active: false,
             next_boot: false,
             boot_once: false,
+            trial: false,
+            pinned: false,
+            tags: BTreeMap::new(),
             space: 8192,
+            usage: SpaceUsage::default(),
+            origin: None,
             created: 1623305460,
         };
 
         let client = EmulatorClient::new(vec![be1, be2]);
         let path = PathBuf::from("/mnt/test");
-        let result = client.mount("be2", Some(path.as_path()), MountMode::ReadWrite, None);
+        let result = client.mount("be2", Some(path.as_path()), &MountOptions::default(), None);
         assert!(matches!(result, Err(Error::MountPointInUse { path }) if path == "/mnt/test"));
     }
 
@@ -1037,7 +2402,12 @@ mod tests {
             active: false,
             next_boot: false,
             boot_once: false,
+            trial: false,
+            pinned: false,
+            tags: BTreeMap::new(),
             space: 8192,
+            usage: SpaceUsage::default(),
+            origin: None,
             created: 1623301740,
         };
 
@@ -1063,7 +2433,12 @@ mod tests {
             active: false,
             next_boot: false,
             boot_once: false,
+            trial: false,
+            pinned: false,
+            tags: BTreeMap::new(),
             space: 8192,
+            usage: SpaceUsage::default(),
+            origin: None,
             created: 1623301740,
         };
 
@@ -1089,7 +2464,12 @@ mod tests {
             active: false,
             next_boot: false,
             boot_once: false,
+            trial: false,
+            pinned: false,
+            tags: BTreeMap::new(),
             space: 8192,
+            usage: SpaceUsage::default(),
+            origin: None,
             created: 1623301740,
         };
 
@@ -1111,7 +2491,12 @@ mod tests {
             active: false,
             next_boot: false,
             boot_once: false,
+            trial: false,
+            pinned: false,
+            tags: BTreeMap::new(),
             space: 8192,
+            usage: SpaceUsage::default(),
+            origin: None,
             created: 1623301740,
         };
 
@@ -1139,7 +2524,12 @@ mod tests {
             active: false,
             next_boot: false,
             boot_once: false,
+            trial: false,
+            pinned: false,
+            tags: BTreeMap::new(),
             space: 8192,
+            usage: SpaceUsage::default(),
+            origin: None,
             created: 1623301740,
         };
 
@@ -1162,7 +2552,12 @@ mod tests {
             active: false,
             next_boot: false,
             boot_once: false,
+            trial: false,
+            pinned: false,
+            tags: BTreeMap::new(),
             space: 8192,
+            usage: SpaceUsage::default(),
+            origin: None,
             created: 1623301740,
         };
 
@@ -1187,7 +2582,12 @@ mod tests {
             active: false,
             next_boot: false,
             boot_once: false,
+            trial: false,
+            pinned: false,
+            tags: BTreeMap::new(),
             space: 8192,
+            usage: SpaceUsage::default(),
+            origin: None,
             created: 1623301740,
         };
 
@@ -1220,7 +2620,12 @@ mod tests {
             active: false,
             next_boot: false,
             boot_once: false,
+            trial: false,
+            pinned: false,
+            tags: BTreeMap::new(),
             space: 8192,
+            usage: SpaceUsage::default(),
+            origin: None,
             created: 1623301740,
         };
 
@@ -1233,7 +2638,12 @@ mod tests {
             active: false,
             next_boot: false,
             boot_once: false,
+            trial: false,
+            pinned: false,
+            tags: BTreeMap::new(),
             space: 8192,
+            usage: SpaceUsage::default(),
+            origin: None,
             created: 1623305460,
         };
 
@@ -1254,7 +2664,12 @@ mod tests {
             active: true,
             next_boot: true,
             boot_once: false,
+            trial: false,
+            pinned: false,
+            tags: BTreeMap::new(),
             space: 8192,
+            usage: SpaceUsage::default(),
+            origin: None,
             created: 1623301740,
         };
 
@@ -1267,7 +2682,12 @@ mod tests {
             active: false,
             next_boot: false,
             boot_once: false,
+            trial: false,
+            pinned: false,
+            tags: BTreeMap::new(),
             space: 8192,
+            usage: SpaceUsage::default(),
+            origin: None,
             created: 1623305460,
         };
 
@@ -1294,7 +2714,12 @@ mod tests {
             active: true,
             next_boot: true,
             boot_once: false,
+            trial: false,
+            pinned: false,
+            tags: BTreeMap::new(),
             space: 8192,
+            usage: SpaceUsage::default(),
+            origin: None,
             created: 1623301740,
         };
 
@@ -1307,7 +2732,12 @@ mod tests {
             active: false,
             next_boot: false,
             boot_once: false,
+            trial: false,
+            pinned: false,
+            tags: BTreeMap::new(),
             space: 8192,
+            usage: SpaceUsage::default(),
+            origin: None,
             created: 1623305460,
         };
 
@@ -1335,7 +2765,12 @@ mod tests {
             active: true,
             next_boot: true, // Initially set as next boot
             boot_once: false,
+            trial: false,
+            pinned: false,
+            tags: BTreeMap::new(),
             space: 8192,
+            usage: SpaceUsage::default(),
+            origin: None,
             created: 1623301740,
         };
 
@@ -1348,7 +2783,12 @@ mod tests {
             active: false,
             next_boot: false,
             boot_once: false,
+            trial: false,
+            pinned: false,
+            tags: BTreeMap::new(),
             space: 8192,
+            usage: SpaceUsage::default(),
+            origin: None,
             created: 1623305460,
         };
 
@@ -1414,7 +2854,7 @@ mod tests {
         assert!(result.is_ok());
 
         // Mount it
-        let result = client.mount("test-be", None, MountMode::ReadWrite, None);
+        let result = client.mount("test-be", None, &MountOptions::default(), None);
         assert!(result.is_ok());
 
         // Verify it's mounted
@@ -1446,7 +2886,14 @@ mod tests {
         assert!(bes[0].boot_once); // Should have boot_once for temporary activation
 
         // Destroy it (should work since it's not active)
-        let result = client.destroy(&Label::Name("renamed-be".to_string()), false, false, None);
+        let result = client.destroy(
+            &Label::Name("renamed-be".to_string()),
+            false,
+            false,
+            false,
+            false,
+            None,
+        );
         assert!(result.is_ok());
 
         // Verify it's gone
@@ -1495,7 +2942,12 @@ mod tests {
             active: false,
             next_boot: false,
             boot_once: false,
+            trial: false,
+            pinned: false,
+            tags: BTreeMap::new(),
             space: 8192,
+            usage: SpaceUsage::default(),
+            origin: None,
             created: 1623301740,
         };
 
@@ -1504,6 +2956,25 @@ mod tests {
         assert_eq!(snapshots.len(), 0);
     }
 
+    #[test]
+    fn test_emulated_datasets() {
+        let client = EmulatorClient::sampled();
+
+        let datasets = client.get_datasets("default", None).unwrap();
+        assert_eq!(datasets.len(), 3);
+        assert_eq!(datasets[0].name, "default/usr");
+        assert_eq!(datasets[0].canmount, "off");
+        assert_eq!(datasets[0].mountpoint, None);
+        assert_eq!(datasets[2].name, "default/var");
+        assert_eq!(datasets[2].mountpoint, Some(PathBuf::from("/var")));
+
+        // Boot environments need not have any child datasets.
+        assert!(client.get_datasets("alt", None).unwrap().is_empty());
+
+        let result = client.get_datasets("nonexistent", None);
+        assert!(matches!(result, Err(Error::NotFound { name }) if name == "nonexistent"));
+    }
+
     #[test]
     fn test_emulated_create_from_existing() {
         let client = EmulatorClient::sampled();
@@ -1674,7 +3145,12 @@ mod tests {
             active: true, // This is the currently active BE
             next_boot: false,
             boot_once: false,
+            trial: false,
+            pinned: false,
+            tags: BTreeMap::new(),
             space: 8192,
+            usage: SpaceUsage::default(),
+            origin: None,
             created: 1623301740,
         };
 
@@ -1687,7 +3163,12 @@ mod tests {
             active: false,
             next_boot: false,
             boot_once: false,
+            trial: false,
+            pinned: false,
+            tags: BTreeMap::new(),
             space: 8192,
+            usage: SpaceUsage::default(),
+            origin: None,
             created: 1623305460,
         };
 
@@ -1740,7 +3221,12 @@ mod tests {
             active: false, // No active BE
             next_boot: false,
             boot_once: true, // Temporary activation
+            trial: false,
+            pinned: false,
+            tags: BTreeMap::new(),
             space: 8192,
+            usage: SpaceUsage::default(),
+            origin: None,
             created: 1623301740,
         };
 
@@ -1768,7 +3254,12 @@ mod tests {
             active: true,    // This is the current active BE
             next_boot: true, // Initially set as next boot
             boot_once: false,
+            trial: false,
+            pinned: false,
+            tags: BTreeMap::new(),
             space: 950_000_000,
+            usage: SpaceUsage::default(),
+            origin: None,
             created: 1623301740,
         };
 
@@ -1781,7 +3272,12 @@ mod tests {
             active: false,
             next_boot: false,
             boot_once: false,
+            trial: false,
+            pinned: false,
+            tags: BTreeMap::new(),
             space: 8192,
+            usage: SpaceUsage::default(),
+            origin: None,
             created: 1623305460,
         };
 
@@ -1827,6 +3323,653 @@ mod tests {
         assert!(!temporary.boot_once); // Temporary activation cleared
     }
 
+    #[test]
+    fn test_emulated_trial_boot() {
+        let client = EmulatorClient::sampled();
+
+        // There is nothing to confirm to begin with.
+        assert_eq!(client.confirm(None).unwrap(), None);
+
+        // A trial is a temporary activation that can't be confirmed until
+        // the boot environment has been booted.
+        client.activate_trial("alt", None).unwrap();
+        let bes = client.get_boot_environments(None).unwrap();
+        let alt = bes.iter().find(|be| be.name == "alt").unwrap();
+        assert!(alt.boot_once);
+        assert!(alt.trial);
+        let result = client.confirm(None);
+        assert!(matches!(result, Err(Error::TrialNotBooted { name }) if name == "alt"));
+
+        // If the trial boot doesn't complete, we fall back.
+        client.clear_boot_once(None).unwrap();
+        let result = client.confirm(None);
+        assert!(matches!(result, Err(Error::TrialFailed { name }) if name == "alt"));
+        let bes = client.get_boot_environments(None).unwrap();
+        assert!(bes.iter().all(|be| !be.trial));
+        let default = bes.iter().find(|be| be.name == "default").unwrap();
+        assert!(default.next_boot);
+
+        // Simulate a successful trial boot into alt.
+        client.activate_trial("alt", None).unwrap();
+        client.clear_boot_once(None).unwrap();
+        for be in client.bes.write().unwrap().iter_mut() {
+            be.active = be.name == "alt";
+        }
+        assert_eq!(client.confirm(None).unwrap(), Some("alt".to_string()));
+        let bes = client.get_boot_environments(None).unwrap();
+        let alt = bes.iter().find(|be| be.name == "alt").unwrap();
+        assert!(alt.next_boot);
+        assert!(!alt.trial);
+        assert_eq!(client.confirm(None).unwrap(), None);
+    }
+
+    #[test]
+    fn test_emulated_trial_superseded() {
+        let client = EmulatorClient::sampled();
+        let on_trial = |client: &EmulatorClient| {
+            client
+                .get_boot_environments(None)
+                .unwrap()
+                .into_iter()
+                .find(|be| be.trial)
+                .map(|be| be.name)
+        };
+
+        // Temporarily activating the boot environment on trial again keeps
+        // the trial, but activating another one ends it.
+        client.activate_trial("alt", None).unwrap();
+        client.activate("alt", true, None).unwrap();
+        assert_eq!(on_trial(&client), Some("alt".to_string()));
+        client.activate("default", true, None).unwrap();
+        assert_eq!(on_trial(&client), None);
+        assert_eq!(client.confirm(None).unwrap(), None);
+
+        // Removing the temporary activation at boot keeps the trial, but the
+        // user doing so with -T cancels it.
+        client.activate_trial("alt", None).unwrap();
+        client.clear_boot_once(None).unwrap();
+        assert_eq!(on_trial(&client), Some("alt".to_string()));
+        client.cancel_trial(None).unwrap();
+        assert_eq!(on_trial(&client), None);
+        assert_eq!(client.confirm(None).unwrap(), None);
+    }
+
+    #[test]
+    fn test_emulated_pin() {
+        let client = EmulatorClient::sampled();
+        let alt = Label::Name("alt".to_string());
+        let backup = Label::Snapshot("alt".to_string(), "backup".to_string());
+
+        client.pin(&alt, None).unwrap();
+        client.pin(&backup, None).unwrap();
+        // Pinning twice is fine.
+        client.pin(&backup, None).unwrap();
+        let bes = client.get_boot_environments(None).unwrap();
+        assert!(bes.iter().find(|be| be.name == "alt").unwrap().pinned);
+        assert!(client.get_snapshots("alt", None).unwrap()[0].pinned);
+        assert!(matches!(
+            client.pin(
+                &Label::Snapshot("alt".to_string(), "missing".to_string()),
+                None
+            ),
+            Err(Error::NotFound { .. })
+        ));
+
+        // Pinned boot environments and snapshots can't be destroyed without
+        // unpinning them.
+        let result = client.destroy(&backup, false, false, false, false, None);
+        assert!(matches!(result, Err(Error::Protected { name }) if name == "alt@backup"));
+        let result = client.destroy(&alt, false, true, false, false, None);
+        assert!(matches!(result, Err(Error::Protected { name }) if name == "alt"));
+        client.unpin(&alt, None).unwrap();
+        let result = client.destroy(&alt, false, true, false, false, None);
+        assert!(matches!(result, Err(Error::Protected { name }) if name == "alt@backup"));
+
+        let plan = client.destroy(&alt, false, true, true, true, None).unwrap();
+        assert_eq!(plan.unpin, vec!["alt@backup".to_string()]);
+        client
+            .destroy(&alt, false, true, true, false, None)
+            .unwrap();
+        assert!(client.pinned_snapshots.read().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_emulated_tag() {
+        let client = EmulatorClient::sampled();
+        let alt = Label::Name("alt".to_string());
+        let backup = Label::Snapshot("alt".to_string(), "backup".to_string());
+
+        client.tag(&alt, "purpose", "testing", None).unwrap();
+        client.tag(&backup, "ticket", "1234", None).unwrap();
+        let bes = client.get_boot_environments(None).unwrap();
+        let tags = &bes.iter().find(|be| be.name == "alt").unwrap().tags;
+        assert_eq!(tags.get("purpose").map(String::as_str), Some("testing"));
+        let snapshots = client.get_snapshots("alt", None).unwrap();
+        assert_eq!(
+            snapshots[0].tags.get("ticket").map(String::as_str),
+            Some("1234")
+        );
+        assert!(matches!(
+            client.tag(&alt, "Not Valid", "x", None),
+            Err(Error::InvalidTag { .. })
+        ));
+
+        // Removing a tag that isn't set is fine.
+        client.untag(&alt, "purpose", None).unwrap();
+        client.untag(&alt, "purpose", None).unwrap();
+        let bes = client.get_boot_environments(None).unwrap();
+        assert!(
+            bes.iter()
+                .find(|be| be.name == "alt")
+                .unwrap()
+                .tags
+                .is_empty()
+        );
+
+        // Snapshot tags go away with the boot environment.
+        client
+            .destroy(&alt, false, true, false, false, None)
+            .unwrap();
+        assert!(client.snapshot_tags.read().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_emulated_properties() {
+        let client = EmulatorClient::sampled();
+        let alt = Label::Name("alt".to_string());
+
+        client
+            .set_zfs_properties(
+                "alt",
+                &[
+                    "compression=lz4".to_string(),
+                    "org.example:owner=ops".to_string(),
+                ],
+                None,
+            )
+            .unwrap();
+        let props = client
+            .get_zfs_properties(
+                &alt,
+                &[
+                    "compression".to_string(),
+                    "atime".to_string(),
+                    "org.example:owner".to_string(),
+                    "org.example:unset".to_string(),
+                ],
+                None,
+            )
+            .unwrap();
+        let values: Vec<(&str, &str)> = props
+            .iter()
+            .map(|prop| (prop.value.as_str(), prop.source.as_str()))
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                ("lz4", "local"),
+                ("on", "default"),
+                ("ops", "local"),
+                ("-", "-")
+            ]
+        );
+
+        // Everything is returned if no names are given.
+        let props = client.get_zfs_properties(&alt, &[], None).unwrap();
+        assert!(props.iter().any(|prop| prop.name == "canmount"));
+        assert!(
+            props
+                .iter()
+                .any(|prop| prop.name == "ca.kamacite:description")
+        );
+        let snapshot = Label::Snapshot("alt".to_string(), "backup".to_string());
+        let props = client.get_zfs_properties(&snapshot, &[], None).unwrap();
+        assert!(props.iter().any(|prop| prop.name == "used"));
+
+        // The same properties are protected as for create.
+        assert!(matches!(
+            client.set_zfs_properties("alt", &["canmount=on".to_string()], None),
+            Err(Error::InvalidProp { .. })
+        ));
+        assert!(matches!(
+            client.set_zfs_properties("alt", &["mountpoint=/mnt".to_string()], None),
+            Err(Error::InvalidProp { .. })
+        ));
+
+        // beadm's own properties have dedicated commands.
+        assert!(matches!(
+            client.set_zfs_properties("alt", &["ca.kamacite:pinned=on".to_string()], None),
+            Err(Error::ReservedProp { .. })
+        ));
+        let bes = client.get_boot_environments(None).unwrap();
+        assert!(!bes.iter().find(|be| be.name == "alt").unwrap().pinned);
+        assert!(matches!(
+            client.get_zfs_properties(&alt, &["bogus".to_string()], None),
+            Err(Error::UnknownProp { .. })
+        ));
+        assert!(matches!(
+            client.set_zfs_properties("missing", &["atime=off".to_string()], None),
+            Err(Error::NotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn test_emulated_diff() {
+        let client = EmulatorClient::sampled();
+        let snapshot = Label::from_str("default@2021-06-10-04:30").unwrap();
+
+        // Against the active boot environment by default.
+        let changes = client.diff(&snapshot, None, &[], None).unwrap();
+        assert_eq!(changes.len(), 5);
+        let changes = client
+            .diff(
+                &Label::Name("alt".to_string()),
+                None,
+                &[PathBuf::from("/etc")],
+                None,
+            )
+            .unwrap();
+        let paths: Vec<&str> = changes.iter().map(|c| c.path.as_str()).collect();
+        assert_eq!(paths, vec!["/etc", "/etc/hostname", "/etc/motd"]);
+
+        let same = Label::Name("default".to_string());
+        assert!(
+            client
+                .diff(&same, Some(&same), &[], None)
+                .unwrap()
+                .is_empty()
+        );
+
+        let missing = Label::from_str("default@missing").unwrap();
+        assert!(matches!(
+            client.diff(&missing, None, &[], None),
+            Err(Error::NotFound { .. })
+        ));
+        assert!(matches!(
+            client.diff(&snapshot, None, &[PathBuf::from("etc")], None),
+            Err(Error::InvalidPath { .. })
+        ));
+    }
+
+    #[test]
+    fn test_emulated_doctor() {
+        let client = EmulatorClient::sampled();
+        assert_eq!(client.doctor(false, None).unwrap(), vec![]);
+
+        // A temporary activation of the running boot environment that was
+        // never cleared.
+        client.activate("default", true, None).unwrap();
+        let findings = client.doctor(false, None).unwrap();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].check, "previous-bootfs");
+        assert!(findings[0].fix.is_some() && !findings[0].fixed);
+
+        let findings = client.doctor(true, None).unwrap();
+        assert!(findings[0].fixed);
+        assert_eq!(client.doctor(false, None).unwrap(), vec![]);
+        let bes = client.get_boot_environments(None).unwrap();
+        assert!(bes[0].next_boot && !bes[0].boot_once);
+
+        // Nothing to boot at all.
+        for be in client.bes.write().unwrap().iter_mut() {
+            be.next_boot = false;
+        }
+        let findings = client.doctor(true, None).unwrap();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].check, "bootfs");
+        assert_eq!(findings[0].name, "zfake");
+        assert!(findings[0].fixed);
+        assert!(client.get_boot_environments(None).unwrap()[0].next_boot);
+
+        // Other roots are checked separately.
+        let other = Root::from_str("zother/ROOT").unwrap();
+        assert_eq!(client.doctor(false, Some(&other)).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_emulated_rollback() {
+        let client = EmulatorClient::sampled();
+
+        // Newer snapshots are only destroyed when asked to.
+        assert!(matches!(
+            client.rollback("default", "2021-06-10-04:30", false, true, false, None),
+            Err(Error::NewerSnapshots { newer, .. }) if newer == ["default@2021-06-10-05:10"]
+        ));
+        // The active boot environment needs forcing.
+        assert!(matches!(
+            client.rollback("default", "2021-06-10-05:10", false, false, true, None),
+            Err(Error::RollbackActive { .. })
+        ));
+        assert!(matches!(
+            client.rollback("alt", "missing", false, false, true, None),
+            Err(Error::NotFound { .. })
+        ));
+
+        let rolled_back = client
+            .rollback("alt", "backup", false, false, false, None)
+            .unwrap();
+        assert_eq!(rolled_back, RolledBack::default());
+
+        // Newer snapshots aren't destroyed if any is pinned or has clones;
+        // alt is a clone of default@2021-06-10-05:10.
+        client
+            .pin(
+                &Label::Snapshot("default".to_string(), "2021-06-10-05:10".to_string()),
+                None,
+            )
+            .unwrap();
+        assert!(matches!(
+            client.rollback("default", "2021-06-10-04:30", true, true, true, None),
+            Err(Error::Protected { name }) if name == "default@2021-06-10-05:10"
+        ));
+        client
+            .unpin(
+                &Label::Snapshot("default".to_string(), "2021-06-10-05:10".to_string()),
+                None,
+            )
+            .unwrap();
+        assert!(matches!(
+            client.rollback("default", "2021-06-10-04:30", true, true, true, None),
+            Err(Error::HasClones { names }) if names == ["default@2021-06-10-05:10"]
+        ));
+        assert_eq!(client.get_boot_environments(None).unwrap().len(), 2);
+
+        client
+            .destroy(
+                &Label::Name("alt".to_string()),
+                false,
+                true,
+                false,
+                false,
+                None,
+            )
+            .unwrap();
+        let rolled_back = client
+            .rollback("default", "2021-06-10-04:30", true, true, true, None)
+            .unwrap();
+        assert_eq!(rolled_back.destroyed, vec!["default@2021-06-10-05:10"]);
+        let safety = rolled_back.safety_snapshot.unwrap();
+        assert!(safety.starts_with("default-pre-rollback-"));
+        let (kept_name, _) = safety.split_once('@').unwrap();
+        let bes = client.get_boot_environments(None).unwrap();
+        let kept = bes.iter().find(|be| be.name == kept_name).unwrap();
+        assert!(!kept.active && !kept.next_boot);
+    }
+
+    #[test]
+    fn test_emulated_activate_checked() {
+        let client = EmulatorClient::sampled();
+        let next_boot = |name: &str| {
+            let bes = client.get_boot_environments(None).unwrap();
+            let be = bes.iter().find(|be| be.name == name).unwrap();
+            be.next_boot || be.boot_once || be.trial
+        };
+        let issue = |fatal| HealthIssue {
+            check: "kernel".to_string(),
+            fatal,
+            message: "no kernel found in /boot".to_string(),
+        };
+
+        // Fatal issues prevent activation unless forced.
+        client.set_health_issues("alt", vec![issue(true)]);
+        assert!(matches!(
+            client.activate_checked("alt", false, false, None),
+            Err(Error::Unhealthy { .. })
+        ));
+        assert!(matches!(
+            client.activate_trial_checked("alt", false, None),
+            Err(Error::Unhealthy { .. })
+        ));
+        assert!(!next_boot("alt"));
+        let issues = client.activate_checked("alt", true, true, None).unwrap();
+        assert_eq!(issues, vec![issue(true)]);
+        assert!(next_boot("alt"));
+
+        // Warnings are only reported.
+        client.set_health_issues("default", vec![issue(false)]);
+        let issues = client
+            .activate_trial_checked("default", false, None)
+            .unwrap();
+        assert_eq!(issues, vec![issue(false)]);
+        assert!(next_boot("default"));
+    }
+
+    #[test]
+    fn test_emulated_rollback_pinned() {
+        let client = EmulatorClient::sampled();
+        let alt = Label::Name("alt".to_string());
+        client.pin(&alt, None).unwrap();
+        client
+            .set_zfs_properties("alt", &["org.example:owner=ops".to_string()], None)
+            .unwrap();
+
+        // The recreated boot environment keeps the pin and properties. The
+        // one keeping the previous state has the properties but isn't pinned.
+        let rolled_back = client
+            .rollback("alt", "backup", false, false, true, None)
+            .unwrap();
+        let (kept_name, _) = rolled_back
+            .safety_snapshot
+            .as_ref()
+            .unwrap()
+            .split_once('@')
+            .unwrap();
+        let bes = client.get_boot_environments(None).unwrap();
+        assert!(bes.iter().find(|be| be.name == "alt").unwrap().pinned);
+        assert!(!bes.iter().find(|be| be.name == kept_name).unwrap().pinned);
+
+        let owner = |name: &str| {
+            client
+                .get_zfs_properties(
+                    &Label::Name(name.to_string()),
+                    &["org.example:owner".to_string()],
+                    None,
+                )
+                .unwrap()[0]
+                .value
+                .clone()
+        };
+        assert_eq!(owner("alt"), "ops");
+        assert_eq!(owner(kept_name), "ops");
+    }
+
+    #[test]
+    fn test_emulated_replicate() {
+        let client = EmulatorClient::sampled();
+        let dest = Root::from_str("zbackup/ROOT").unwrap();
+        client
+            .describe(&Label::Name("default".to_string()), "Known good", None)
+            .unwrap();
+
+        let replications = client
+            .replicate(&dest, &["default".to_string()], None)
+            .unwrap();
+        assert_eq!(replications.len(), 1);
+        assert_eq!(
+            replications[0].snapshots,
+            vec!["2021-06-10-04:30", "2021-06-10-05:10"]
+        );
+        let copies = client.get_boot_environments(Some(&dest)).unwrap();
+        assert_eq!(copies.len(), 1);
+        assert_eq!(copies[0].description.as_deref(), Some("Known good"));
+        assert!(!copies[0].active && !copies[0].next_boot);
+
+        // Nothing new to send the second time around.
+        let replications = client
+            .replicate(&dest, &["default".to_string()], None)
+            .unwrap();
+        assert!(replications[0].snapshots.is_empty());
+
+        // Another destination has none of them yet.
+        let other = Root::from_str("zother/ROOT").unwrap();
+        let replications = client
+            .replicate(&other, &["default".to_string()], None)
+            .unwrap();
+        assert_eq!(replications[0].snapshots.len(), 2);
+
+        // The latest snapshot each destination has is recorded.
+        let props = client.get_properties("default").unwrap();
+        assert_eq!(
+            props.get(REPLICATED_PROP).map(String::as_str),
+            Some("zbackup/ROOT/default@2021-06-10-05:10,zother/ROOT/default@2021-06-10-05:10")
+        );
+        let replications = client
+            .replicate(&dest, &["default".to_string()], None)
+            .unwrap();
+        assert!(replications[0].snapshots.is_empty());
+
+        assert!(matches!(
+            client.replicate(&dest, &["missing".to_string()], None),
+            Err(Error::NotFound { .. })
+        ));
+        assert!(matches!(
+            client.replicate(&Root::from_str("zfake/ROOT").unwrap(), &[], None),
+            Err(Error::InvalidBootEnvironmentRoot { .. })
+        ));
+    }
+
+    #[test]
+    fn test_emulated_export_import() {
+        use std::io::{Seek, SeekFrom};
+        use std::os::fd::AsFd;
+
+        let client = EmulatorClient::sampled();
+        client
+            .describe(&Label::Name("alt".to_string()), "Known good", None)
+            .unwrap();
+        client
+            .tag(&Label::Name("alt".to_string()), "owner", "ops", None)
+            .unwrap();
+
+        let mut file = tempfile::tempfile().unwrap();
+        let snapshot = client
+            .export(&Label::Name("alt".to_string()), None, file.as_fd(), None)
+            .unwrap();
+        assert!(snapshot.starts_with("alt@"));
+
+        file.seek(SeekFrom::Start(0)).unwrap();
+        client.import("copy", file.as_fd(), None).unwrap();
+        let bes = client.get_boot_environments(None).unwrap();
+        let copy = bes.iter().find(|be| be.name == "copy").unwrap();
+        assert_eq!(copy.description.as_deref(), Some("Known good"));
+        assert_eq!(copy.tags.get("owner").map(String::as_str), Some("ops"));
+        assert!(!copy.active && !copy.next_boot);
+
+        // Full streams can't overwrite an existing boot environment.
+        file.seek(SeekFrom::Start(0)).unwrap();
+        assert!(matches!(
+            client.import("copy", file.as_fd(), None),
+            Err(Error::Conflict { .. })
+        ));
+
+        // Incremental streams need their base snapshot on both sides.
+        assert!(matches!(
+            client.export(
+                &Label::Name("default".to_string()),
+                Some("missing"),
+                file.as_fd(),
+                None
+            ),
+            Err(Error::NotFound { .. })
+        ));
+        let mut file = tempfile::tempfile().unwrap();
+        client
+            .export(
+                &Label::from_str("default@2021-06-10-05:10").unwrap(),
+                Some("2021-06-10-04:30"),
+                file.as_fd(),
+                None,
+            )
+            .unwrap();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        assert!(matches!(
+            client.import("copy", file.as_fd(), None),
+            Err(Error::NotFound { .. })
+        ));
+        file.seek(SeekFrom::Start(0)).unwrap();
+        client.import("default", file.as_fd(), None).unwrap();
+    }
+
+    #[test]
+    fn test_emulated_rename_snapshot() {
+        let client = EmulatorClient::sampled();
+        let snapshot = Label::Snapshot("default".to_string(), "2021-06-10-05:10".to_string());
+        client.pin(&snapshot, None).unwrap();
+
+        client
+            .rename_snapshot("default", "2021-06-10-05:10", "good", None)
+            .unwrap();
+        let snapshots = client.get_snapshots("default", None).unwrap();
+        let renamed = snapshots
+            .iter()
+            .find(|snapshot| snapshot.name == "default@good")
+            .unwrap();
+        assert!(renamed.pinned);
+        assert!(
+            !snapshots
+                .iter()
+                .any(|snapshot| snapshot.name == "default@2021-06-10-05:10")
+        );
+        // Clones follow their origin.
+        let bes = client.get_boot_environments(None).unwrap();
+        let alt = bes.iter().find(|be| be.name == "alt").unwrap();
+        assert_eq!(alt.origin.as_deref(), Some("default@good"));
+
+        // Renamed snapshots can be renamed again.
+        client
+            .rename_snapshot("default", "good", "better", None)
+            .unwrap();
+        assert!(
+            client
+                .get_snapshots("default", None)
+                .unwrap()
+                .iter()
+                .any(|snapshot| snapshot.name == "default@better")
+        );
+
+        assert!(matches!(
+            client.rename_snapshot("default", "good", "other", None),
+            Err(Error::NotFound { .. })
+        ));
+        assert!(matches!(
+            client.rename_snapshot("default", "better", "2021-06-10-04:30", None),
+            Err(Error::Conflict { .. })
+        ));
+        assert!(matches!(
+            client.rename_snapshot("default", "better", "bad@name", None),
+            Err(Error::InvalidName { .. })
+        ));
+    }
+
+    #[test]
+    fn test_emulated_record_boot() {
+        let client = EmulatorClient::sampled();
+        assert_eq!(client.get_boot_history(None).unwrap().len(), 3);
+
+        client.activate("alt", true, None).unwrap();
+        for be in client.bes.write().unwrap().iter_mut() {
+            be.active = be.name == "alt";
+        }
+        let record = client.record_boot(None).unwrap().unwrap();
+        assert_eq!(record.name, "alt");
+        assert!(record.boot_once);
+        assert_eq!(client.get_boot_history(None).unwrap().last(), Some(&record));
+
+        // The history is bounded.
+        for _ in 0..MAX_BOOT_HISTORY {
+            client.record_boot(None).unwrap();
+        }
+        assert_eq!(
+            client.get_boot_history(None).unwrap().len(),
+            MAX_BOOT_HISTORY
+        );
+
+        // Nothing is recorded when not booted into a boot environment.
+        let client = EmulatorClient::empty();
+        assert_eq!(client.record_boot(None).unwrap(), None);
+        assert!(client.get_boot_history(None).unwrap().is_empty());
+    }
+
     #[test]
     fn test_emulated_describe_boot_environment() {
         let client = EmulatorClient::sampled();
@@ -1969,7 +4112,14 @@ mod tests {
         let root = Root::from_str("zfake/ROOT").unwrap();
 
         // Destroy with matching root should work
-        let result = client.destroy(&Label::Name("alt".to_string()), false, false, Some(&root));
+        let result = client.destroy(
+            &Label::Name("alt".to_string()),
+            false,
+            false,
+            false,
+            false,
+            Some(&root),
+        );
         assert!(result.is_ok());
 
         let bes = client.get_boot_environments(Some(&root)).unwrap();
@@ -1986,6 +4136,8 @@ mod tests {
             &Label::Name("alt".to_string()),
             false,
             false,
+            false,
+            false,
             Some(&other_root),
         );
         assert!(matches!(result, Err(Error::NotFound { name }) if name == "alt"));
@@ -2002,7 +4154,7 @@ mod tests {
         let root = Root::from_str("zfake/ROOT").unwrap();
 
         // Mount with matching root should work
-        let result = client.mount("alt", None, MountMode::ReadWrite, Some(&root));
+        let result = client.mount("alt", None, &MountOptions::default(), Some(&root));
         assert!(result.is_ok());
 
         let bes = client.get_boot_environments(Some(&root)).unwrap();
@@ -2016,7 +4168,7 @@ mod tests {
         let other_root = Root::from_str("zother/ROOT").unwrap();
 
         // Mount with non-matching root should fail
-        let result = client.mount("alt", None, MountMode::ReadWrite, Some(&other_root));
+        let result = client.mount("alt", None, &MountOptions::default(), Some(&other_root));
         assert!(matches!(result, Err(Error::NotFound { name }) if name == "alt"));
     }
 
@@ -2073,6 +4225,8 @@ mod tests {
                 &Label::Name("same-name".to_string()),
                 false,
                 false,
+                false,
+                false,
                 Some(&root1),
             )
             .unwrap();
@@ -2428,7 +4582,14 @@ mod tests {
         assert!(client.get_properties("test-be").is_some());
 
         client
-            .destroy(&Label::Name("test-be".to_string()), false, false, None)
+            .destroy(
+                &Label::Name("test-be".to_string()),
+                false,
+                false,
+                false,
+                false,
+                None,
+            )
             .unwrap();
 
         // Properties should be dropped along with the BE.
@@ -4,20 +4,35 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use std::collections::BTreeMap;
+use std::os::fd::BorrowedFd;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use clap::ValueEnum;
 use thiserror::Error as ThisError;
 #[cfg(feature = "dbus")]
-use zvariant::{DeserializeDict, SerializeDict, Type};
+use zvariant::{DeserializeDict, SerializeDict, Type, Value};
 
+mod diff;
+mod doctor;
+pub(crate) mod health;
+mod history;
 mod mock;
+pub(crate) mod packages;
+mod prune;
 pub(crate) mod scan;
+mod stream;
 mod validation;
 mod zfs;
 
+pub use diff::FileChange;
+pub use doctor::Finding;
+pub use health::HealthIssue;
+pub use history::BootRecord;
 pub use mock::EmulatorClient;
+pub use packages::PackageChange;
+pub use prune::{Pruned, RetentionPolicy};
 pub use zfs::{LibZfsClient, format_zfs_bytes};
 
 #[derive(ThisError, Debug)]
@@ -37,6 +52,27 @@ pub enum Error {
     #[error("Boot environment '{name}' has snapshots and cannot be destroyed")]
     HasSnapshots { name: String },
 
+    #[error(
+        "Cannot roll back active boot environment '{name}'; create a new boot environment from '{name}@{snapshot}' instead"
+    )]
+    RollbackActive { name: String, snapshot: String },
+
+    #[error("Boot environment '{name}' failed health checks: {}", .issues.join("; "))]
+    Unhealthy { name: String, issues: Vec<String> },
+
+    #[error("Snapshot '{name}@{snapshot}' is not the latest; newer snapshots: {}", .newer.join(", "))]
+    NewerSnapshots {
+        name: String,
+        snapshot: String,
+        newer: Vec<String>,
+    },
+
+    #[error("'{name}' is pinned and cannot be destroyed")]
+    Protected { name: String },
+
+    #[error("Snapshots with clones cannot be destroyed: {}", .names.join(", "))]
+    HasClones { names: Vec<String> },
+
     #[error("Invalid boot environment name '{name}': {reason}")]
     InvalidName { name: String, reason: String },
 
@@ -52,6 +88,48 @@ pub enum Error {
     #[error("Invalid property '{name}={value}'")]
     InvalidProp { name: String, value: String },
 
+    #[error("Unknown property '{name}'")]
+    UnknownProp { name: String },
+
+    #[error("Property '{name}' is managed by beadm; use pin, tag or describe instead")]
+    ReservedProp { name: String },
+
+    #[error("Invalid file change '{value}'")]
+    InvalidChange { value: String },
+
+    #[error("Invalid export stream: {reason}")]
+    InvalidStream { reason: String },
+
+    #[error("Unsupported mount option '{option}'")]
+    InvalidMountOption { option: String },
+
+    #[error("Invalid host ID '{value}': expected up to eight hexadecimal digits")]
+    InvalidHostId { value: String },
+
+    #[error("Invalid tag '{key}': {reason}")]
+    InvalidTag { key: String, reason: String },
+
+    #[error("Boot environment '{name}' has not been booted for its trial yet")]
+    TrialNotBooted { name: String },
+
+    #[error("The trial boot of boot environment '{name}' did not complete; it was not activated")]
+    TrialFailed { name: String },
+
+    #[error("No retention policy given; refusing to prune everything")]
+    NoRetentionPolicy,
+
+    #[error("No supported package database found in boot environment '{name}'")]
+    NoPackageDatabase { name: String },
+
+    #[error("Command '{command}' failed ({status})")]
+    CommandFailed {
+        command: String,
+        status: std::process::ExitStatus,
+    },
+
+    #[error("Failed to replicate boot environment '{name}': {reason}")]
+    ReplicationFailed { name: String, reason: String },
+
     #[error("The root filesystem is not a ZFS boot environment")]
     NoActiveBootEnvironment,
 
@@ -78,11 +156,29 @@ impl From<Error> for zbus::fdo::Error {
             Error::MountPointInUse { .. } => zbus::fdo::Error::NotSupported(err.to_string()),
             Error::CannotDestroyActive { .. } => zbus::fdo::Error::NotSupported(err.to_string()),
             Error::HasSnapshots { .. } => zbus::fdo::Error::NotSupported(err.to_string()),
+            Error::RollbackActive { .. } => zbus::fdo::Error::NotSupported(err.to_string()),
+            Error::NewerSnapshots { .. } => zbus::fdo::Error::NotSupported(err.to_string()),
+            Error::Unhealthy { .. } => zbus::fdo::Error::Failed(err.to_string()),
+            Error::Protected { .. } => zbus::fdo::Error::NotSupported(err.to_string()),
+            Error::HasClones { .. } => zbus::fdo::Error::NotSupported(err.to_string()),
             Error::InvalidName { .. } => zbus::fdo::Error::InvalidArgs(err.to_string()),
             Error::InvalidPath { .. } => zbus::fdo::Error::InvalidArgs(err.to_string()),
             Error::Mounted { .. } => zbus::fdo::Error::NotSupported(err.to_string()),
             Error::NotMounted { .. } => zbus::fdo::Error::NotSupported(err.to_string()),
             Error::InvalidProp { .. } => zbus::fdo::Error::InvalidArgs(err.to_string()),
+            Error::UnknownProp { .. } => zbus::fdo::Error::InvalidArgs(err.to_string()),
+            Error::ReservedProp { .. } => zbus::fdo::Error::InvalidArgs(err.to_string()),
+            Error::InvalidChange { .. } => zbus::fdo::Error::InvalidArgs(err.to_string()),
+            Error::InvalidStream { .. } => zbus::fdo::Error::InvalidArgs(err.to_string()),
+            Error::InvalidMountOption { .. } => zbus::fdo::Error::InvalidArgs(err.to_string()),
+            Error::InvalidHostId { .. } => zbus::fdo::Error::InvalidArgs(err.to_string()),
+            Error::InvalidTag { .. } => zbus::fdo::Error::InvalidArgs(err.to_string()),
+            Error::TrialNotBooted { .. } => zbus::fdo::Error::NotSupported(err.to_string()),
+            Error::TrialFailed { .. } => zbus::fdo::Error::Failed(err.to_string()),
+            Error::NoRetentionPolicy => zbus::fdo::Error::InvalidArgs(err.to_string()),
+            Error::NoPackageDatabase { .. } => zbus::fdo::Error::NotSupported(err.to_string()),
+            Error::CommandFailed { .. } => zbus::fdo::Error::Failed(err.to_string()),
+            Error::ReplicationFailed { .. } => zbus::fdo::Error::Failed(err.to_string()),
             Error::NoActiveBootEnvironment => zbus::fdo::Error::Failed(err.to_string()),
             Error::InvalidBootEnvironmentRoot { .. } => {
                 zbus::fdo::Error::InvalidArgs(err.to_string())
@@ -134,6 +230,12 @@ impl Error {
         }
     }
 
+    pub fn invalid_stream(reason: &str) -> Self {
+        Error::InvalidStream {
+            reason: reason.to_string(),
+        }
+    }
+
     pub fn not_mounted(name: &str) -> Self {
         Error::NotMounted {
             name: name.to_string(),
@@ -151,13 +253,20 @@ impl Error {
             name: name.to_string(),
         }
     }
+
+    pub fn protected(name: &str) -> Self {
+        Error::Protected {
+            name: name.to_string(),
+        }
+    }
 }
 
 /// Whether a boot environment is mounted read-write (the default) or
 /// read-only.
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 pub enum MountMode {
     /// Mount read-write.
+    #[default]
     #[value(name = "rw")]
     ReadWrite,
     /// Mount read-only.
@@ -165,6 +274,83 @@ pub enum MountMode {
     ReadOnly,
 }
 
+/// Additional restrictions that can be placed on a mounted boot environment,
+/// e.g. for safely inspecting its contents.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum MountOption {
+    /// Ignore set-user-ID and set-group-ID bits.
+    #[value(name = "nosuid")]
+    Nosuid,
+    /// Do not interpret character or block special devices.
+    #[value(name = "nodev")]
+    Nodev,
+    /// Do not allow direct execution of any binaries.
+    #[value(name = "noexec")]
+    Noexec,
+}
+
+impl MountOption {
+    /// The name of this option, as understood by mount(8).
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MountOption::Nosuid => "nosuid",
+            MountOption::Nodev => "nodev",
+            MountOption::Noexec => "noexec",
+        }
+    }
+}
+
+impl FromStr for MountOption {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        <Self as ValueEnum>::from_str(s, false).map_err(|_| Error::InvalidMountOption {
+            option: s.to_string(),
+        })
+    }
+}
+
+/// Options for mounting a boot environment.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MountOptions {
+    /// Mount read-write or read-only.
+    pub mode: MountMode,
+    /// Any additional restrictions on the mount.
+    pub options: Vec<MountOption>,
+}
+
+impl MountOptions {
+    /// Options for safely inspecting the contents of a boot environment:
+    /// read-only, and ignoring set-user-ID bits, devices and executables.
+    pub fn inspect() -> Self {
+        Self {
+            mode: MountMode::ReadOnly,
+            options: vec![MountOption::Nosuid, MountOption::Nodev, MountOption::Noexec],
+        }
+    }
+
+    /// Whether the mount is read-only.
+    pub fn read_only(&self) -> bool {
+        self.mode == MountMode::ReadOnly
+    }
+
+    /// Format these options as a comma-separated string, as accepted by
+    /// mount(8).
+    pub fn to_option_string(&self) -> String {
+        let mode = match self.mode {
+            MountMode::ReadWrite => "rw",
+            MountMode::ReadOnly => "ro",
+        };
+        let mut opts = vec![mode];
+        for option in &self.options {
+            if !opts.contains(&option.as_str()) {
+                opts.push(option.as_str());
+            }
+        }
+        opts.join(",")
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 #[cfg_attr(feature = "dbus", derive(SerializeDict, DeserializeDict, Type))]
 #[cfg_attr(
@@ -188,12 +374,61 @@ pub struct BootEnvironment {
     pub next_boot: bool,
     /// Whether the system will reboot into this environment temporarily.
     pub boot_once: bool,
+    /// Whether this boot environment is on trial, i.e. it will only become
+    /// the default once a boot into it is confirmed.
+    pub trial: bool,
+    /// Whether this boot environment is pinned, i.e. protected from being
+    /// destroyed.
+    pub pinned: bool,
+    /// User-defined tags, by key.
+    pub tags: BTreeMap<String, String>,
     /// Bytes on the filesystem associated with this boot environment.
     pub space: u64,
+    /// A breakdown of the space used by this boot environment.
+    pub usage: SpaceUsage,
+    /// The snapshot this boot environment was cloned from, if any (e.g.,
+    /// `default@snapshot`).
+    pub origin: Option<String>,
     /// Unix timestamp for when this boot environment was created.
     pub created: i64,
 }
 
+/// A breakdown of the space used by a boot environment, corresponding to the
+/// ZFS properties of the same names.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "dbus", derive(SerializeDict, DeserializeDict, Type, Value))]
+#[cfg_attr(
+    feature = "dbus",
+    zvariant(signature = "a{sv}", rename_all = "PascalCase")
+)]
+pub struct SpaceUsage {
+    /// Bytes used by the boot environment's own data (`usedbydataset`).
+    pub used_by_dataset: u64,
+    /// Bytes that would be freed if all of its snapshots were destroyed
+    /// (`usedbysnapshots`).
+    pub used_by_snapshots: u64,
+    /// Bytes accessible from the boot environment, including any shared with
+    /// its origin or other boot environments (`referenced`).
+    pub referenced: u64,
+    /// Bytes written since its latest snapshot, or its origin (`written`).
+    pub written: u64,
+    /// The compression ratio achieved for the referenced data
+    /// (`compressratio`).
+    pub compress_ratio: f64,
+}
+
+impl Default for SpaceUsage {
+    fn default() -> Self {
+        Self {
+            used_by_dataset: 0,
+            used_by_snapshots: 0,
+            referenced: 0,
+            written: 0,
+            compress_ratio: 1.0,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Snapshot {
     /// The name of this snapshot (e.g., `default@snapshot`).
@@ -202,15 +437,126 @@ pub struct Snapshot {
     pub root: Root,
     /// Optional description for this snapshot.
     pub description: Option<String>,
-    /// Bytes used by this snapshot.
+    /// Bytes used by this snapshot, i.e. that would be freed by destroying
+    /// it.
     pub space: u64,
+    /// Bytes accessible from this snapshot, including any shared with the boot
+    /// environment or other snapshots.
+    pub referenced: u64,
+    /// Bytes written between the previous snapshot and this one.
+    pub written: u64,
     /// Unix timestamp for when this snapshot was created.
     pub created: i64,
+    /// Whether this snapshot is pinned (i.e. has a hold placed by `beadm
+    /// pin`), protecting it from being destroyed.
+    pub pinned: bool,
+    /// User-defined tags, by key.
+    pub tags: BTreeMap<String, String>,
+}
+
+/// A subordinate (i.e. descendant) filesystem of a boot environment, such as
+/// `default/var`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "dbus", derive(SerializeDict, DeserializeDict, Type))]
+#[cfg_attr(
+    feature = "dbus",
+    zvariant(signature = "a{sv}", rename_all = "PascalCase")
+)]
+pub struct ChildDataset {
+    /// The name of this dataset relative to the boot environment root (e.g.,
+    /// `default/var`).
+    pub name: String,
+    /// The boot environment root.
+    pub root: Root,
+    /// If the dataset is currently mounted, this is its mountpoint.
+    pub mountpoint: Option<PathBuf>,
+    /// The value of the `canmount` property (e.g., `on` or `noauto`).
+    pub canmount: String,
+    /// Bytes used by this dataset.
+    pub space: u64,
+    /// Unix timestamp for when this dataset was created.
+    pub created: i64,
+}
+
+/// A ZFS property of a boot environment or snapshot, as reported by `beadm
+/// get`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "dbus", derive(SerializeDict, DeserializeDict, Type))]
+#[cfg_attr(
+    feature = "dbus",
+    zvariant(signature = "a{sv}", rename_all = "PascalCase")
+)]
+pub struct Property {
+    /// The name of the property (e.g., `compression` or `org.example:tag`).
+    pub name: String,
+    /// The value, or `-` for user properties that are not set.
+    pub value: String,
+    /// Where the value comes from, in the same terms as `zfs get` (e.g.,
+    /// `local`, `default` or `inherited from zroot/ROOT`), or `-`.
+    pub source: String,
+}
+
+/// The outcome of rolling a boot environment back to a snapshot.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "dbus", derive(SerializeDict, DeserializeDict, Type))]
+#[cfg_attr(
+    feature = "dbus",
+    zvariant(signature = "a{sv}", rename_all = "PascalCase")
+)]
+pub struct RolledBack {
+    /// The snapshot of the state before the rollback (e.g.,
+    /// `default-pre-rollback-20261017-093000@2026-10-17T09:30:00Z`), which
+    /// is kept in a boot environment of its own.
+    pub safety_snapshot: Option<String>,
+    /// Snapshots newer than the one rolled back to that were destroyed.
+    pub destroyed: Vec<String>,
+}
+
+/// The outcome of replicating a boot environment to another root.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "dbus", derive(SerializeDict, DeserializeDict, Type))]
+#[cfg_attr(
+    feature = "dbus",
+    zvariant(signature = "a{sv}", rename_all = "PascalCase")
+)]
+pub struct Replication {
+    pub name: String,
+    /// The snapshots that were sent, oldest first. Empty if the destination
+    /// was already up to date.
+    pub snapshots: Vec<String>,
+    /// Whether an interrupted transfer was resumed.
+    pub resumed: bool,
+}
+
+/// The steps taken (or, for a dry run, that would be taken) to destroy a boot
+/// environment or snapshot, in the order they are carried out.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "dbus", derive(SerializeDict, DeserializeDict, Type))]
+#[cfg_attr(
+    feature = "dbus",
+    zvariant(signature = "a{sv}", rename_all = "PascalCase")
+)]
+pub struct DestroyPlan {
+    /// Pinned boot environments and snapshots that are unpinned first.
+    pub unpin: Vec<String>,
+    /// Mountpoints that are forcibly unmounted.
+    pub unmount: Vec<String>,
+    /// Clones that are promoted so that they no longer depend on the
+    /// snapshots being destroyed (e.g., `alt`). A promoted clone takes over
+    /// the snapshots it was created from, which are then not destroyed.
+    pub promote: Vec<String>,
+    /// Snapshots that are destroyed (e.g., `default@snapshot`).
+    pub snapshots: Vec<String>,
+    /// Boot environments and child datasets that are destroyed, children
+    /// first (e.g., `default/var`, then `default`).
+    pub datasets: Vec<String>,
+    /// An estimate of the bytes reclaimed.
+    pub space: u64,
 }
 
 /// Represents either a named boot environment or a snapshot of one. Used for
 /// operations that are valid for either.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Label {
     /// A named boot environment.
     Name(String),
@@ -334,19 +680,27 @@ pub trait Client: Send + Sync {
         root: Option<&Root>,
     ) -> Result<(), Error>;
 
+    /// Destroy a boot environment or snapshot, returning the steps that were
+    /// taken. When `dry_run` is set, nothing is changed and the returned plan
+    /// describes what would have been done.
+    ///
+    /// Pinned boot environments and snapshots are refused with
+    /// [`Error::Protected`] unless `unpin` is set.
     fn destroy(
         &self,
         target: &Label,
         force_unmount: bool,
         snapshots: bool,
+        unpin: bool,
+        dry_run: bool,
         root: Option<&Root>,
-    ) -> Result<(), Error>;
+    ) -> Result<DestroyPlan, Error>;
 
     fn mount(
         &self,
         be_name: &str,
         mountpoint: Option<&Path>,
-        mode: MountMode,
+        options: &MountOptions,
         root: Option<&Root>,
     ) -> Result<PathBuf, Error>;
 
@@ -361,12 +715,120 @@ pub trait Client: Send + Sync {
 
     fn rename(&self, be_name: &str, new_name: &str, root: Option<&Root>) -> Result<(), Error>;
 
+    /// Rename a snapshot of a boot environment (along with the matching
+    /// snapshots of its child datasets). Snapshots stay with their boot
+    /// environment; they cannot be renamed to another one.
+    fn rename_snapshot(
+        &self,
+        be_name: &str,
+        snapshot: &str,
+        new_snapshot: &str,
+        root: Option<&Root>,
+    ) -> Result<(), Error>;
+
+    /// List the files that differ between two boot environments or
+    /// snapshots, optionally limited to those beneath `paths`. When `to` is
+    /// None, compare against the active boot environment.
+    ///
+    /// `zfs diff` is used where one is a snapshot that the other descends
+    /// from; otherwise both are mounted (read-only, if they aren't already)
+    /// and compared file by file.
+    fn diff(
+        &self,
+        from: &Label,
+        to: Option<&Label>,
+        paths: &[PathBuf],
+        root: Option<&Root>,
+    ) -> Result<Vec<FileChange>, Error>;
+
+    /// Write a boot environment, or a snapshot of one, to `fd` as an export
+    /// stream: a header with its metadata followed by a ZFS send stream for
+    /// it and each of its children. Exporting a boot environment snapshots
+    /// it first. With `base`, the send streams are incremental from that
+    /// snapshot. Returns the name of the snapshot that was exported.
+    fn export(
+        &self,
+        target: &Label,
+        base: Option<&str>,
+        fd: BorrowedFd<'_>,
+        root: Option<&Root>,
+    ) -> Result<String, Error>;
+
+    /// Receive an export stream from `fd` as a new boot environment or, for
+    /// an incremental stream, on top of an existing one that has its base
+    /// snapshot.
+    fn import(&self, be_name: &str, fd: BorrowedFd<'_>, root: Option<&Root>) -> Result<(), Error>;
+
+    /// Replicate boot environments (all of them if `names` is empty) and
+    /// their snapshots to another boot environment root, which may be on
+    /// another pool. The boot environment is snapshotted first if it has
+    /// changed, and only snapshots that the destination does not have yet
+    /// are sent. Interrupted transfers are resumed. The latest replicated
+    /// snapshot is recorded on the source boot environment, per destination.
+    fn replicate(
+        &self,
+        dest: &Root,
+        names: &[String],
+        root: Option<&Root>,
+    ) -> Result<Vec<Replication>, Error>;
+
+    /// Check that a boot environment looks bootable, mounting it read-only
+    /// if need be. See [`Client::activate_checked`], which runs this before
+    /// activation.
+    fn check(&self, be_name: &str, root: Option<&Root>) -> Result<Vec<HealthIssue>, Error>;
+
+    /// Check the boot environment layout and pool state for problems that
+    /// would only show up at boot: the `canmount` and `mountpoint` properties
+    /// of the root and its boot environments, stale `bootfs` and temporary
+    /// activation state, leftover temporary mountpoints, and datasets shared
+    /// between boot environments that shouldn't be. With `fix`, the problems
+    /// that can be fixed automatically are.
+    fn doctor(&self, fix: bool, root: Option<&Root>) -> Result<Vec<Finding>, Error>;
+
     fn activate(&self, be_name: &str, temporary: bool, root: Option<&Root>) -> Result<(), Error>;
 
     /// Clear temporary boot environment activation.
     fn clear_boot_once(&self, root: Option<&Root>) -> Result<(), Error>;
 
-    fn rollback(&self, be_name: &str, snapshot: &str, root: Option<&Root>) -> Result<(), Error>;
+    /// Activate a boot environment for a single trial boot. It only becomes
+    /// the default once that boot is confirmed; otherwise the system falls
+    /// back to the current default on the next boot.
+    fn activate_trial(&self, be_name: &str, root: Option<&Root>) -> Result<(), Error>;
+
+    /// Cancel a pending trial boot, e.g. when the temporary activation it
+    /// relies on is removed. Unlike a failed trial, this is not reported by
+    /// `confirm`.
+    fn cancel_trial(&self, root: Option<&Root>) -> Result<(), Error>;
+
+    /// Confirm a trial boot from within it, making the boot environment on
+    /// trial the default. Returns its name, or `None` if there is no trial.
+    fn confirm(&self, root: Option<&Root>) -> Result<Option<String>, Error>;
+
+    /// Record the current boot in the boot history. Returns the new entry, or
+    /// `None` if the system is not booted into a boot environment in this
+    /// root.
+    fn record_boot(&self, root: Option<&Root>) -> Result<Option<BootRecord>, Error>;
+
+    /// Get the boot history, oldest first.
+    fn get_boot_history(&self, root: Option<&Root>) -> Result<Vec<BootRecord>, Error>;
+
+    /// Roll back a boot environment (and its children) to an earlier
+    /// snapshot. Any newer snapshots are an error unless `destroy_newer` is
+    /// set, in which case they are destroyed. The active boot environment is
+    /// only rolled back with `force`.
+    ///
+    /// With `safety_snapshot`, the current state is snapshotted first and
+    /// kept: the boot environment is renamed out of the way and recreated
+    /// from the snapshot, rather than rolled back in place.
+    fn rollback(
+        &self,
+        be_name: &str,
+        snapshot: &str,
+        destroy_newer: bool,
+        force: bool,
+        safety_snapshot: bool,
+        root: Option<&Root>,
+    ) -> Result<RolledBack, Error>;
 
     /// Get a snapshot of the boot environments.
     fn get_boot_environments(&self, root: Option<&Root>) -> Result<Vec<BootEnvironment>, Error>;
@@ -374,6 +836,14 @@ pub trait Client: Send + Sync {
     /// Get snapshots for a specific boot environment.
     fn get_snapshots(&self, be_name: &str, root: Option<&Root>) -> Result<Vec<Snapshot>, Error>;
 
+    /// Get the snapshots left behind by `create` that nothing depends on any
+    /// more, i.e. that have no clones and no description.
+    fn get_orphaned_snapshots(&self, root: Option<&Root>) -> Result<Vec<Snapshot>, Error>;
+
+    /// Get all descendant filesystems of a specific boot environment (but not
+    /// the boot environment itself).
+    fn get_datasets(&self, be_name: &str, root: Option<&Root>) -> Result<Vec<ChildDataset>, Error>;
+
     /// Create a snapshot of a source boot environment. When `source` is None,
     /// snapshot the active boot environment.
     ///
@@ -393,8 +863,104 @@ pub trait Client: Send + Sync {
     fn describe(&self, target: &Label, description: &str, root: Option<&Root>)
     -> Result<(), Error>;
 
+    /// Pin a boot environment or snapshot, protecting it from being
+    /// destroyed. It is not an error if it is already pinned.
+    fn pin(&self, target: &Label, root: Option<&Root>) -> Result<(), Error>;
+
+    /// Unpin a boot environment or snapshot. It is not an error if it is not
+    /// pinned.
+    fn unpin(&self, target: &Label, root: Option<&Root>) -> Result<(), Error>;
+
+    /// Set a tag on a boot environment or snapshot, replacing any existing
+    /// value.
+    fn tag(&self, target: &Label, key: &str, value: &str, root: Option<&Root>)
+    -> Result<(), Error>;
+
+    /// Remove a tag from a boot environment or snapshot. It is not an error if
+    /// the tag is not set.
+    fn untag(&self, target: &Label, key: &str, root: Option<&Root>) -> Result<(), Error>;
+
+    /// Get ZFS properties of a boot environment or snapshot. If no names are
+    /// given, all properties are returned.
+    fn get_zfs_properties(
+        &self,
+        target: &Label,
+        names: &[String],
+        root: Option<&Root>,
+    ) -> Result<Vec<Property>, Error>;
+
+    /// Set ZFS properties (in the form `property=value`) on an existing boot
+    /// environment. As with `create`, `canmount` and `mountpoint` cannot be
+    /// set, and nor can beadm's own `ca.kamacite:` properties.
+    fn set_zfs_properties(
+        &self,
+        be_name: &str,
+        properties: &[String],
+        root: Option<&Root>,
+    ) -> Result<(), Error>;
+
     /// Get the active boot environment root, if any.
     fn active_root(&self) -> Option<&Root>;
+
+    /// Destroy snapshots (or, with `boot_environments`, whole boot
+    /// environments) that fall outside a retention policy, returning what was
+    /// destroyed. When `dry_run` is set, nothing is changed.
+    fn prune(
+        &self,
+        policy: &RetentionPolicy,
+        boot_environments: bool,
+        dry_run: bool,
+        root: Option<&Root>,
+    ) -> Result<Vec<Pruned>, Error> {
+        prune::prune(self, policy, boot_environments, dry_run, root)
+    }
+
+    /// Compare the packages installed in two boot environments (or, when
+    /// `to` is None, the active one), mounting them read-only as needed.
+    fn diff_packages(
+        &self,
+        from: &str,
+        to: Option<&str>,
+        root: Option<&Root>,
+    ) -> Result<Vec<PackageChange>, Error> {
+        packages::diff_packages(self, from, to, root)
+    }
+
+    /// Check that a boot environment looks bootable and, unless there are
+    /// fatal issues and `force` is not set, activate it. Returns the issues
+    /// found, to be reported as warnings.
+    fn activate_checked(
+        &self,
+        be_name: &str,
+        temporary: bool,
+        force: bool,
+        root: Option<&Root>,
+    ) -> Result<Vec<HealthIssue>, Error> {
+        health::activate(self, be_name, temporary, force, root)
+    }
+
+    /// Like [`Client::activate_checked`], but for a trial boot.
+    fn activate_trial_checked(
+        &self,
+        be_name: &str,
+        force: bool,
+        root: Option<&Root>,
+    ) -> Result<Vec<HealthIssue>, Error> {
+        health::activate_trial(self, be_name, force, root)
+    }
+}
+
+/// Parse a host ID in the hexadecimal format printed by hostid(1) and
+/// `beadm hostid`, with or without a leading `0x`.
+pub(crate) fn parse_hostid(value: &str) -> Result<u32, Error> {
+    let digits = value.strip_prefix("0x").unwrap_or(value);
+    if digits.is_empty() || digits.len() > 8 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(Error::InvalidHostId {
+            value: value.to_string(),
+        });
+    }
+    // Safe to unwrap because we've checked the digits above.
+    Ok(u32::from_str_radix(digits, 16).unwrap())
 }
 
 /// Generate a snapshot name based on the current time.
@@ -440,6 +1006,53 @@ pub(crate) fn is_temp_mountpoint(path: &PathBuf) -> bool {
     path.to_string_lossy().starts_with(prefix.to_str().unwrap())
 }
 
+/// Run `f` with the mountpoints of some boot environments, temporarily
+/// mounting (for inspection only) any that aren't mounted already and
+/// unmounting them again afterwards.
+pub(crate) fn with_mounted<C, T>(
+    client: &C,
+    names: &[&str],
+    root: Option<&Root>,
+    f: impl FnOnce(&[PathBuf]) -> Result<T, Error>,
+) -> Result<T, Error>
+where
+    C: Client + ?Sized,
+{
+    let bes = client.get_boot_environments(root)?;
+    let mut mountpoints: Vec<PathBuf> = vec![];
+    let mut mounted: Vec<&str> = vec![];
+    let mut result = Ok(());
+    for (i, name) in names.iter().enumerate() {
+        let existing = bes
+            .iter()
+            .find(|be| be.name == *name)
+            .and_then(|be| be.mountpoint.clone())
+            .or_else(|| {
+                let earlier = names[..i].iter().position(|other| other == name)?;
+                Some(mountpoints[earlier].clone())
+            });
+        if let Some(mountpoint) = existing {
+            mountpoints.push(mountpoint);
+            continue;
+        }
+        match client.mount(name, None, &MountOptions::inspect(), root) {
+            Ok(mountpoint) => {
+                mountpoints.push(mountpoint);
+                mounted.push(name);
+            }
+            Err(err) => {
+                result = Err(err);
+                break;
+            }
+        }
+    }
+    let result = result.and_then(|()| f(&mountpoints));
+    for name in mounted {
+        _ = client.unmount(name, false, root);
+    }
+    result
+}
+
 /// Parse a "property=value" string into a (property, value) tuple.
 ///
 /// Validates format only; property validation is done by clients.
@@ -463,6 +1076,84 @@ pub fn parse_property(prop_string: &str) -> Result<(&str, &str), Error> {
     Ok((name, value))
 }
 
+/// Whether a snapshot taken by `beadm create` to clone from is orphaned: it
+/// has no clones, and nobody has described or pinned it to keep it.
+pub fn is_orphaned(snapshot: &Snapshot, has_clones: bool) -> bool {
+    !has_clones && snapshot.description.is_none() && !snapshot.pinned
+}
+
+/// The latest snapshot replicated to `replica` (a boot environment in another
+/// root), if recorded. `recorded` is the source boot environment's
+/// `ca.kamacite:replicated` property, a comma-separated list of the latest
+/// replicated snapshot in each destination.
+pub(crate) fn recorded_replication<'a>(recorded: &'a str, replica: &str) -> Option<&'a str> {
+    recorded.split(',').find_map(|entry| {
+        entry
+            .split_once('@')
+            .filter(|(dataset, _)| *dataset == replica)
+            .map(|(_, snapshot)| snapshot)
+    })
+}
+
+/// Record `snapshot` as the latest one replicated to `replica`, replacing any
+/// earlier entry for it in `recorded` (see [`recorded_replication`]).
+pub(crate) fn record_replication(recorded: Option<&str>, replica: &str, snapshot: &str) -> String {
+    let mut entries: Vec<&str> = recorded
+        .unwrap_or_default()
+        .split(',')
+        .filter(|entry| {
+            !entry.is_empty()
+                && entry
+                    .split_once('@')
+                    .is_none_or(|(dataset, _)| dataset != replica)
+        })
+        .collect();
+    let entry = format!("{}@{}", replica, snapshot);
+    entries.push(&entry);
+    entries.join(",")
+}
+
+/// Check that a boot environment's snapshots, given by their short names, can
+/// all be destroyed without unpinning them or promoting their clones, so that
+/// a rollback can fail before destroying any of them.
+pub(crate) fn check_destroyable<C: Client + ?Sized>(
+    client: &C,
+    be_name: &str,
+    snapshots: &[String],
+    root: Option<&Root>,
+) -> Result<(), Error> {
+    let mut pinned = vec![];
+    let mut cloned = vec![];
+    for snapshot in snapshots {
+        let label = Label::Snapshot(be_name.to_string(), snapshot.clone());
+        match client.destroy(&label, false, false, false, true, root) {
+            Ok(plan) if !plan.promote.is_empty() => cloned.push(label.to_string()),
+            Ok(_) => {}
+            Err(Error::Protected { .. }) => pinned.push(label.to_string()),
+            Err(err) => return Err(err),
+        }
+    }
+    if !pinned.is_empty() {
+        return Err(Error::protected(&pinned.join(", ")));
+    }
+    if !cloned.is_empty() {
+        return Err(Error::HasClones { names: cloned });
+    }
+    Ok(())
+}
+
+/// Reject properties in beadm's own `ca.kamacite:` namespace, which are
+/// managed with dedicated commands (`pin`, `tag`, `describe`, etc.) rather
+/// than set directly.
+pub fn check_reserved_property(name: &str) -> Result<(), Error> {
+    if name.starts_with("ca.kamacite:") {
+        return Err(Error::ReservedProp {
+            name: name.to_string(),
+        });
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -533,4 +1224,100 @@ mod tests {
         // Empty string
         assert!(parse_property("").is_err());
     }
+
+    #[test]
+    fn test_is_orphaned() {
+        let snapshot = Snapshot {
+            name: "default@2021-06-10-05:10".to_string(),
+            root: Root::from_str("zfake/ROOT").unwrap(),
+            description: None,
+            space: 0,
+            referenced: 0,
+            written: 0,
+            created: 0,
+            pinned: false,
+            tags: BTreeMap::new(),
+        };
+        assert!(is_orphaned(&snapshot, false));
+        assert!(!is_orphaned(&snapshot, true));
+
+        // Described or pinned snapshots are kept.
+        let described = Snapshot {
+            description: Some("Known good".to_string()),
+            ..snapshot.clone()
+        };
+        assert!(!is_orphaned(&described, false));
+        let pinned = Snapshot {
+            pinned: true,
+            ..snapshot
+        };
+        assert!(!is_orphaned(&pinned, false));
+    }
+
+    #[test]
+    fn test_mount_options() {
+        assert_eq!(MountOptions::default().to_option_string(), "rw");
+        let options = MountOptions {
+            mode: MountMode::ReadOnly,
+            options: vec![],
+        };
+        assert_eq!(options.to_option_string(), "ro");
+
+        let options = MountOptions {
+            mode: MountMode::ReadOnly,
+            options: vec![
+                MountOption::Nosuid,
+                MountOption::Nodev,
+                MountOption::Noexec,
+                MountOption::Nodev,
+            ],
+        };
+        assert!(options.read_only());
+        assert_eq!(options.to_option_string(), "ro,nosuid,nodev,noexec");
+
+        assert_eq!(
+            "noexec".parse::<MountOption>().unwrap(),
+            MountOption::Noexec
+        );
+        assert!(matches!(
+            "suid".parse::<MountOption>(),
+            Err(Error::InvalidMountOption { .. })
+        ));
+    }
+
+    #[test]
+    fn test_recorded_replication() {
+        let recorded = record_replication(None, "zbackup/ROOT/default", "a");
+        assert_eq!(recorded, "zbackup/ROOT/default@a");
+        let recorded = record_replication(Some(&recorded), "zother/ROOT/default", "b");
+        let recorded = record_replication(Some(&recorded), "zbackup/ROOT/default", "c");
+        assert_eq!(recorded, "zother/ROOT/default@b,zbackup/ROOT/default@c");
+
+        assert_eq!(
+            recorded_replication(&recorded, "zbackup/ROOT/default"),
+            Some("c")
+        );
+        assert_eq!(
+            recorded_replication(&recorded, "zother/ROOT/default"),
+            Some("b")
+        );
+        assert_eq!(recorded_replication(&recorded, "zother/ROOT/alt"), None);
+        assert_eq!(recorded_replication("", "zother/ROOT/default"), None);
+    }
+
+    #[test]
+    fn test_parse_hostid() {
+        assert_eq!(parse_hostid("00bab10c").unwrap(), 0x00bab10c);
+        assert_eq!(parse_hostid("0xdeadbeef").unwrap(), 0xdeadbeef);
+        assert_eq!(parse_hostid("0").unwrap(), 0);
+
+        assert!(matches!(
+            parse_hostid("test-host"),
+            Err(Error::InvalidHostId { .. })
+        ));
+        assert!(parse_hostid("").is_err());
+        assert!(parse_hostid("0x").is_err());
+        assert!(parse_hostid("0x100000000").is_err());
+        assert!(parse_hostid("+1234").is_err());
+    }
 }
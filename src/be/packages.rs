@@ -0,0 +1,246 @@
+// SPDX-License-Identifier: MPL-2.0
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+
+use super::scan::{PackageManager, Packages};
+use super::{Client, Error, Root, with_mounted};
+
+/// How a package differs between two boot environments.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PackageChangeKind {
+    Added,
+    Removed,
+    Upgraded,
+    Downgraded,
+}
+
+impl PackageChangeKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PackageChangeKind::Added => "added",
+            PackageChangeKind::Removed => "removed",
+            PackageChangeKind::Upgraded => "upgraded",
+            PackageChangeKind::Downgraded => "downgraded",
+        }
+    }
+}
+
+/// A package that differs between two boot environments.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PackageChange {
+    pub name: String,
+    pub change: PackageChangeKind,
+    /// The version in the first boot environment, unless it was added.
+    pub old_version: Option<String>,
+    /// The version in the second boot environment, unless it was removed.
+    pub new_version: Option<String>,
+}
+
+/// Compare the packages installed in two boot environments (or, when `to` is
+/// None, the active one), mounting them read-only as needed. Both must use
+/// the same package manager.
+pub(crate) fn diff_packages<C: Client + ?Sized>(
+    client: &C,
+    from: &str,
+    to: Option<&str>,
+    root: Option<&Root>,
+) -> Result<Vec<PackageChange>, Error> {
+    let to = match to {
+        Some(name) => name.to_string(),
+        None => client
+            .get_boot_environments(root)?
+            .into_iter()
+            .find(|be| be.active)
+            .map(|be| be.name)
+            .ok_or(Error::NoActiveBootEnvironment)?,
+    };
+    with_mounted(client, &[from, &to], root, |mountpoints| {
+        let manager =
+            PackageManager::detect(&mountpoints[0]).ok_or_else(|| Error::NoPackageDatabase {
+                name: from.to_string(),
+            })?;
+        if !manager.is_present(&mountpoints[1]) {
+            return Err(Error::NoPackageDatabase { name: to.clone() });
+        }
+        let old = manager.installed_packages(&mountpoints[0])?;
+        let new = manager.installed_packages(&mountpoints[1])?;
+        Ok(compare_packages(manager, &old, &new))
+    })
+}
+
+/// Compare two sets of installed packages. Packages installed at more than
+/// one version on either side are compared version by version instead,
+/// reporting each as added or removed.
+pub(crate) fn compare_packages(
+    manager: PackageManager,
+    old: &Packages,
+    new: &Packages,
+) -> Vec<PackageChange> {
+    let names: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    let mut changes = vec![];
+    for name in names {
+        let change =
+            |change, old_version: Option<&String>, new_version: Option<&String>| PackageChange {
+                name: name.clone(),
+                change,
+                old_version: old_version.cloned(),
+                new_version: new_version.cloned(),
+            };
+        let old_versions = old.get(name).map_or(&[][..], Vec::as_slice);
+        let new_versions = new.get(name).map_or(&[][..], Vec::as_slice);
+        match (old_versions, new_versions) {
+            ([old_version], [new_version]) => {
+                match manager.compare_versions(old_version, new_version) {
+                    Ordering::Less => changes.push(change(
+                        PackageChangeKind::Upgraded,
+                        Some(old_version),
+                        Some(new_version),
+                    )),
+                    Ordering::Greater => changes.push(change(
+                        PackageChangeKind::Downgraded,
+                        Some(old_version),
+                        Some(new_version),
+                    )),
+                    Ordering::Equal => {}
+                }
+            }
+            _ => {
+                for version in old_versions.iter().filter(|v| !new_versions.contains(v)) {
+                    changes.push(change(PackageChangeKind::Removed, Some(version), None));
+                }
+                for version in new_versions.iter().filter(|v| !old_versions.contains(v)) {
+                    changes.push(change(PackageChangeKind::Added, None, Some(version)));
+                }
+            }
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::be::{EmulatorClient, MountOptions};
+
+    fn packages(entries: &[(&str, &str)]) -> Packages {
+        let mut packages = Packages::new();
+        for (name, version) in entries {
+            packages
+                .entry(name.to_string())
+                .or_default()
+                .push(version.to_string());
+        }
+        packages
+    }
+
+    #[test]
+    fn test_compare_packages() {
+        let old = packages(&[
+            ("bash", "5.2.15-2"),
+            ("curl", "8.5.0-1"),
+            ("linux-image", "6.1.0-1"),
+            ("linux-image", "6.1.0-2"),
+            ("vim", "9.1.0-1"),
+            ("zsh", "5.9-4"),
+        ]);
+        let new = packages(&[
+            ("bash", "5.2.15-2"),
+            ("curl", "8.4.0-1"),
+            ("linux-image", "6.1.0-2"),
+            ("linux-image", "6.1.0-3"),
+            ("vim", "9.1.0-2"),
+            ("htop", "3.3.0-4"),
+        ]);
+        let changes = compare_packages(PackageManager::Dpkg, &old, &new);
+        let summary: Vec<(&str, PackageChangeKind, Option<&str>, Option<&str>)> = changes
+            .iter()
+            .map(|c| {
+                (
+                    c.name.as_str(),
+                    c.change,
+                    c.old_version.as_deref(),
+                    c.new_version.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    "curl",
+                    PackageChangeKind::Downgraded,
+                    Some("8.5.0-1"),
+                    Some("8.4.0-1")
+                ),
+                ("htop", PackageChangeKind::Added, None, Some("3.3.0-4")),
+                (
+                    "linux-image",
+                    PackageChangeKind::Removed,
+                    Some("6.1.0-1"),
+                    None
+                ),
+                (
+                    "linux-image",
+                    PackageChangeKind::Added,
+                    None,
+                    Some("6.1.0-3")
+                ),
+                (
+                    "vim",
+                    PackageChangeKind::Upgraded,
+                    Some("9.1.0-1"),
+                    Some("9.1.0-2")
+                ),
+                ("zsh", PackageChangeKind::Removed, Some("5.9-4"), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_packages() {
+        let client = EmulatorClient::sampled();
+        client.create("new", None, None, &[], None).unwrap();
+        let old_root = tempfile::tempdir().unwrap();
+        let new_root = tempfile::tempdir().unwrap();
+        for (dir, version) in [(&old_root, "1.2.5-r0"), (&new_root, "1.2.5-r1")] {
+            fs::create_dir_all(dir.path().join("lib/apk/db")).unwrap();
+            fs::write(
+                dir.path().join("lib/apk/db/installed"),
+                format!("P:musl\nV:{}\n", version),
+            )
+            .unwrap();
+        }
+        let options = MountOptions::default();
+        client
+            .mount("alt", Some(old_root.path()), &options, None)
+            .unwrap();
+        client
+            .mount("new", Some(new_root.path()), &options, None)
+            .unwrap();
+
+        let changes = diff_packages(&client, "alt", Some("new"), None).unwrap();
+        assert_eq!(
+            changes,
+            vec![PackageChange {
+                name: "musl".to_string(),
+                change: PackageChangeKind::Upgraded,
+                old_version: Some("1.2.5-r0".to_string()),
+                new_version: Some("1.2.5-r1".to_string()),
+            }]
+        );
+
+        // Both need a database for the same package manager.
+        fs::remove_dir_all(new_root.path().join("lib")).unwrap();
+        assert!(matches!(
+            diff_packages(&client, "alt", Some("new"), None),
+            Err(Error::NoPackageDatabase { name }) if name == "new"
+        ));
+    }
+}
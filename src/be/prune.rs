@@ -0,0 +1,338 @@
+// SPDX-License-Identifier: MPL-2.0
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::collections::HashSet;
+
+use chrono::{Datelike, Local, TimeZone, Utc};
+#[cfg(feature = "dbus")]
+use zvariant::{DeserializeDict, SerializeDict, Type};
+
+use super::{Client, Error, Label, Root};
+
+/// Rules for which snapshots (or boot environments) to keep when pruning.
+/// Anything matched by at least one rule is kept and everything else is
+/// destroyed.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "dbus", derive(SerializeDict, DeserializeDict, Type))]
+#[cfg_attr(
+    feature = "dbus",
+    zvariant(signature = "a{sv}", rename_all = "PascalCase")
+)]
+pub struct RetentionPolicy {
+    /// Keep this many of the most recent.
+    pub keep_last: Option<u32>,
+    /// Keep anything created within this many seconds.
+    pub keep_newer_than: Option<u64>,
+    /// Keep the most recent from each of this many days.
+    pub keep_daily: Option<u32>,
+    /// Keep the most recent from each of this many (ISO) weeks.
+    pub keep_weekly: Option<u32>,
+    /// Only consider those with a description starting with this prefix
+    /// (e.g., `before apt`), leaving everything else alone.
+    pub description_prefix: Option<String>,
+}
+
+impl RetentionPolicy {
+    /// Whether the policy has no rules for what to keep, which would prune
+    /// everything.
+    pub fn is_empty(&self) -> bool {
+        self.keep_last.is_none()
+            && self.keep_newer_than.is_none()
+            && self.keep_daily.is_none()
+            && self.keep_weekly.is_none()
+    }
+
+    /// Whether something with this description is subject to the policy.
+    fn applies_to(&self, description: Option<&str>) -> bool {
+        match &self.description_prefix {
+            Some(prefix) => description.is_some_and(|desc| desc.starts_with(prefix.as_str())),
+            None => true,
+        }
+    }
+
+    /// Decide which of a set of candidates to keep, given their creation
+    /// timestamps. Returns a flag for each candidate, in the same order.
+    fn select(&self, created: &[i64], now: i64) -> Vec<bool> {
+        let mut keep = vec![false; created.len()];
+
+        // Consider candidates newest first.
+        let mut order: Vec<usize> = (0..created.len()).collect();
+        order.sort_by_key(|&i| std::cmp::Reverse(created[i]));
+
+        if let Some(n) = self.keep_last {
+            for &i in order.iter().take(n as usize) {
+                keep[i] = true;
+            }
+        }
+
+        if let Some(seconds) = self.keep_newer_than {
+            for &i in &order {
+                if now.saturating_sub(created[i]) < seconds as i64 {
+                    keep[i] = true;
+                }
+            }
+        }
+
+        // Bucket by local date, keeping the newest in each of the most recent
+        // buckets.
+        let date = |timestamp: i64| match Local.timestamp_opt(timestamp, 0) {
+            chrono::LocalResult::Single(dt) => Some(dt.date_naive()),
+            _ => None,
+        };
+        let mut keep_buckets = |n: u32, bucket: &dyn Fn(i64) -> Option<(i32, u32)>| {
+            let mut seen = HashSet::new();
+            for &i in &order {
+                if seen.len() >= n as usize {
+                    break;
+                }
+                if bucket(created[i]).is_some_and(|key| seen.insert(key)) {
+                    keep[i] = true;
+                }
+            }
+        };
+        if let Some(n) = self.keep_daily {
+            keep_buckets(n, &|t| date(t).map(|d| (d.year(), d.ordinal())));
+        }
+        if let Some(n) = self.keep_weekly {
+            keep_buckets(n, &|t| {
+                date(t).map(|d| (d.iso_week().year(), d.iso_week().week()))
+            });
+        }
+
+        keep
+    }
+}
+
+/// A snapshot or boot environment destroyed (or, for a dry run, that would be
+/// destroyed) by pruning.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "dbus", derive(SerializeDict, DeserializeDict, Type))]
+#[cfg_attr(
+    feature = "dbus",
+    zvariant(signature = "a{sv}", rename_all = "PascalCase")
+)]
+pub struct Pruned {
+    /// The name of the snapshot (e.g., `default@snapshot`) or boot
+    /// environment.
+    pub name: String,
+    /// An estimate of the bytes reclaimed.
+    pub space: u64,
+}
+
+/// Destroy the snapshots of every boot environment (or, with
+/// `boot_environments`, the boot environments themselves) that fall outside
+/// a retention policy. Snapshots are considered separately for each boot
+/// environment.
+///
+/// The active, next and boot once boot environments are never pruned, nor
+/// are mounted or pinned ones. Anything whose destruction would require
+/// promoting a clone (i.e. clone origins) or unpinning something (i.e. boot
+/// environments with pinned snapshots) is skipped as well.
+pub(crate) fn prune<C: Client + ?Sized>(
+    client: &C,
+    policy: &RetentionPolicy,
+    boot_environments: bool,
+    dry_run: bool,
+    root: Option<&Root>,
+) -> Result<Vec<Pruned>, Error> {
+    if policy.is_empty() {
+        return Err(Error::NoRetentionPolicy);
+    }
+    let now = Utc::now().timestamp();
+
+    let mut targets = vec![];
+    let bes = client.get_boot_environments(root)?;
+    if boot_environments {
+        let candidates: Vec<_> = bes
+            .iter()
+            .filter(|be| !(be.active || be.next_boot || be.boot_once))
+            .filter(|be| be.mountpoint.is_none())
+            .filter(|be| !be.pinned)
+            .filter(|be| policy.applies_to(be.description.as_deref()))
+            .collect();
+        let created: Vec<i64> = candidates.iter().map(|be| be.created).collect();
+        for (be, keep) in candidates.iter().zip(policy.select(&created, now)) {
+            if !keep {
+                targets.push(Label::Name(be.name.clone()));
+            }
+        }
+    } else {
+        for be in &bes {
+            let candidates: Vec<_> = client
+                .get_snapshots(&be.name, root)?
+                .into_iter()
+                .filter(|snapshot| !snapshot.pinned)
+                .filter(|snapshot| policy.applies_to(snapshot.description.as_deref()))
+                .collect();
+            let created: Vec<i64> = candidates.iter().map(|s| s.created).collect();
+            for (snapshot, keep) in candidates.iter().zip(policy.select(&created, now)) {
+                if keep {
+                    continue;
+                }
+                if let Some((name, snapshot)) = snapshot.name.split_once('@') {
+                    targets.push(Label::Snapshot(name.to_string(), snapshot.to_string()));
+                }
+            }
+        }
+    }
+
+    let mut pruned = vec![];
+    for target in targets {
+        let plan = match client.destroy(&target, false, true, false, true, root) {
+            Ok(plan) => plan,
+            Err(Error::Protected { .. }) => continue,
+            Err(err) => return Err(err),
+        };
+        if !plan.promote.is_empty() {
+            continue;
+        }
+        if !dry_run {
+            client.destroy(&target, false, true, false, false, root)?;
+        }
+        pruned.push(Pruned {
+            name: target.to_string(),
+            space: plan.space,
+        });
+    }
+    Ok(pruned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::be::EmulatorClient;
+
+    const DAY: i64 = 24 * 60 * 60;
+
+    // Noon UTC on Wednesday 2021-06-09, so that day and week buckets are
+    // stable regardless of the local timezone.
+    const WEDNESDAY: i64 = 1623240000;
+
+    #[test]
+    fn test_select_keep_last() {
+        let policy = RetentionPolicy {
+            keep_last: Some(2),
+            ..Default::default()
+        };
+        let created = [WEDNESDAY, WEDNESDAY + 2 * DAY, WEDNESDAY + DAY];
+        assert_eq!(
+            policy.select(&created, WEDNESDAY + 3 * DAY),
+            vec![false, true, true]
+        );
+    }
+
+    #[test]
+    fn test_select_keep_newer_than() {
+        let policy = RetentionPolicy {
+            keep_newer_than: Some(DAY as u64),
+            ..Default::default()
+        };
+        let created = [WEDNESDAY - DAY, WEDNESDAY - 60];
+        assert_eq!(policy.select(&created, WEDNESDAY), vec![false, true]);
+    }
+
+    #[test]
+    fn test_select_buckets() {
+        // Two snapshots a day for three days.
+        let created: Vec<i64> = (0..6).map(|i| WEDNESDAY + (i / 2) * DAY + i % 2).collect();
+        let policy = RetentionPolicy {
+            keep_daily: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            policy.select(&created, WEDNESDAY + 3 * DAY),
+            vec![false, false, false, true, false, true]
+        );
+
+        // One a week for three weeks; keep the two most recent weeks.
+        let created = [WEDNESDAY, WEDNESDAY + 7 * DAY, WEDNESDAY + 14 * DAY];
+        let policy = RetentionPolicy {
+            keep_weekly: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            policy.select(&created, WEDNESDAY + 15 * DAY),
+            vec![false, true, true]
+        );
+
+        // Rules are combined.
+        let policy = RetentionPolicy {
+            keep_last: Some(1),
+            keep_newer_than: Some((14 * DAY) as u64),
+            ..Default::default()
+        };
+        assert_eq!(
+            policy.select(&created, WEDNESDAY + 15 * DAY),
+            vec![false, true, true]
+        );
+    }
+
+    #[test]
+    fn test_prune_snapshots() {
+        let client = EmulatorClient::sampled();
+
+        let result = prune(&client, &RetentionPolicy::default(), false, true, None);
+        assert!(matches!(result, Err(Error::NoRetentionPolicy)));
+
+        // default@2021-06-10-05:10 is the origin of alt, so it is kept.
+        let policy = RetentionPolicy {
+            keep_last: Some(0),
+            ..Default::default()
+        };
+        let pruned = prune(&client, &policy, false, true, None).unwrap();
+        assert_eq!(
+            pruned,
+            vec![
+                Pruned {
+                    name: "default@2021-06-10-04:30".to_string(),
+                    space: 404_000,
+                },
+                Pruned {
+                    name: "alt@backup".to_string(),
+                    space: 1024,
+                },
+            ]
+        );
+
+        // Only snapshots with a matching description are considered.
+        let policy = RetentionPolicy {
+            keep_last: Some(0),
+            description_prefix: Some("Automatic".to_string()),
+            ..Default::default()
+        };
+        let pruned = prune(&client, &policy, false, true, None).unwrap();
+        assert_eq!(pruned.len(), 1);
+        assert_eq!(pruned[0].name, "default@2021-06-10-04:30");
+    }
+
+    #[test]
+    fn test_prune_boot_environments() {
+        let client = EmulatorClient::sampled();
+        client.create("old", None, None, &[], None).unwrap();
+        client.activate("old", true, None).unwrap();
+
+        // The active and boot once boot environments are never pruned.
+        let policy = RetentionPolicy {
+            keep_last: Some(0),
+            ..Default::default()
+        };
+        let pruned = prune(&client, &policy, true, false, None).unwrap();
+        assert_eq!(
+            pruned,
+            vec![Pruned {
+                name: "alt".to_string(),
+                space: 8192,
+            }]
+        );
+        let names: Vec<String> = client
+            .get_boot_environments(None)
+            .unwrap()
+            .into_iter()
+            .map(|be| be.name)
+            .collect();
+        assert_eq!(names, vec!["default".to_string(), "old".to_string()]);
+    }
+}
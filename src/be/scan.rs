@@ -4,8 +4,11 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::Path;
+use std::process::Command;
 
 /// Relevant content from an `/etc/os-release` file.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -46,6 +49,457 @@ impl OsRelease {
     }
 }
 
+/// Installed packages by name, with their versions. A package can be
+/// installed at more than one version at a time (e.g., kernels under RPM).
+pub type Packages = BTreeMap<String, Vec<String>>;
+
+/// A package manager whose database of installed packages can be read from a
+/// boot environment root.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PackageManager {
+    Dpkg,
+    Apk,
+    Pacman,
+    Rpm,
+}
+
+impl PackageManager {
+    /// Detect the package manager used by the system at `root`, by looking
+    /// for its database.
+    pub fn detect<P: AsRef<Path>>(root: P) -> Option<Self> {
+        [
+            PackageManager::Dpkg,
+            PackageManager::Apk,
+            PackageManager::Pacman,
+            PackageManager::Rpm,
+        ]
+        .into_iter()
+        .find(|manager| manager.is_present(root.as_ref()))
+    }
+
+    /// Whether the package manager's database exists at `root`.
+    pub fn is_present(self, root: &Path) -> bool {
+        match self {
+            PackageManager::Dpkg => root.join("var/lib/dpkg/status").is_file(),
+            PackageManager::Apk => root.join("lib/apk/db/installed").is_file(),
+            PackageManager::Pacman => root.join("var/lib/pacman/local").is_dir(),
+            PackageManager::Rpm => {
+                root.join("usr/lib/sysimage/rpm").is_dir() || root.join("var/lib/rpm").is_dir()
+            }
+        }
+    }
+
+    /// Read the packages installed at `root`.
+    ///
+    /// The RPM database can't be read directly, so this runs `rpm --root`.
+    pub fn installed_packages<P: AsRef<Path>>(self, root: P) -> std::io::Result<Packages> {
+        let root = root.as_ref();
+        let entries = match self {
+            PackageManager::Dpkg => {
+                parse_dpkg_status(&fs::read_to_string(root.join("var/lib/dpkg/status"))?)
+            }
+            PackageManager::Apk => {
+                parse_apk_installed(&fs::read_to_string(root.join("lib/apk/db/installed"))?)
+            }
+            PackageManager::Pacman => {
+                let mut entries = vec![];
+                for entry in fs::read_dir(root.join("var/lib/pacman/local"))? {
+                    let desc = entry?.path().join("desc");
+                    if desc.is_file() {
+                        entries.extend(parse_pacman_desc(&fs::read_to_string(desc)?));
+                    }
+                }
+                entries
+            }
+            PackageManager::Rpm => {
+                let output = Command::new("rpm")
+                    .arg("--root")
+                    .arg(root)
+                    .args([
+                        "--query",
+                        "--all",
+                        "--queryformat",
+                        "%{NAME}\t%|EPOCH?{%{EPOCH}:}:{}|%{VERSION}-%{RELEASE}\n",
+                    ])
+                    .output()?;
+                if !output.status.success() {
+                    return Err(std::io::Error::other(format!(
+                        "rpm failed: {}",
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    )));
+                }
+                String::from_utf8_lossy(&output.stdout)
+                    .lines()
+                    .filter_map(|line| line.split_once('\t'))
+                    .map(|(name, version)| (name.to_string(), version.to_string()))
+                    .collect()
+            }
+        };
+        let mut packages = Packages::new();
+        for (name, version) in entries {
+            packages.entry(name).or_default().push(version);
+        }
+        for versions in packages.values_mut() {
+            versions.sort_by(|a, b| self.compare_versions(a, b));
+        }
+        Ok(packages)
+    }
+
+    /// Order two versions according to the package manager's rules.
+    pub fn compare_versions(self, a: &str, b: &str) -> Ordering {
+        match self {
+            PackageManager::Dpkg => dpkg_compare_versions(a, b),
+            PackageManager::Apk => apk_compare_versions(a, b),
+            PackageManager::Pacman | PackageManager::Rpm => evr_compare_versions(a, b),
+        }
+    }
+}
+
+/// Parse a dpkg status database (`/var/lib/dpkg/status`) into the names and
+/// versions of installed packages. Multi-arch packages that can be installed
+/// for several architectures at once are named with their architecture
+/// (e.g., `libc6:amd64`), as dpkg does.
+pub fn parse_dpkg_status(contents: &str) -> Vec<(String, String)> {
+    contents
+        .split("\n\n")
+        .filter_map(|stanza| {
+            let fields = parse_control_fields(stanza);
+            let installed = fields
+                .get("Status")
+                .is_some_and(|status| status.split_whitespace().nth(2) == Some("installed"));
+            if !installed {
+                return None;
+            }
+            let mut name = fields.get("Package")?.to_string();
+            if fields.get("Multi-Arch") == Some(&"same")
+                && let Some(arch) = fields.get("Architecture")
+            {
+                name = format!("{}:{}", name, arch);
+            }
+            Some((name, fields.get("Version")?.to_string()))
+        })
+        .collect()
+}
+
+/// Parse the `Field: value` lines of a Debian control stanza, ignoring
+/// continuation lines.
+fn parse_control_fields(stanza: &str) -> BTreeMap<&str, &str> {
+    stanza
+        .lines()
+        .filter(|line| !line.starts_with([' ', '\t']))
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key, value.trim()))
+        .collect()
+}
+
+/// Parse an APK installed database (`/lib/apk/db/installed`) into the names
+/// and versions of installed packages.
+pub fn parse_apk_installed(contents: &str) -> Vec<(String, String)> {
+    contents
+        .split("\n\n")
+        .filter_map(|stanza| {
+            let mut name = None;
+            let mut version = None;
+            for line in stanza.lines() {
+                if let Some(value) = line.strip_prefix("P:") {
+                    name = Some(value.to_string());
+                } else if let Some(value) = line.strip_prefix("V:") {
+                    version = Some(value.to_string());
+                }
+            }
+            Some((name?, version?))
+        })
+        .collect()
+}
+
+/// Parse the name and version from a pacman local database `desc` file.
+pub fn parse_pacman_desc(contents: &str) -> Option<(String, String)> {
+    let mut name = None;
+    let mut version = None;
+    let mut lines = contents.lines();
+    while let Some(line) = lines.next() {
+        match line {
+            "%NAME%" => name = lines.next().map(str::to_string),
+            "%VERSION%" => version = lines.next().map(str::to_string),
+            _ => {}
+        }
+    }
+    Some((name?, version?))
+}
+
+/// Compare Debian versions (`[epoch:]upstream[-revision]`), as in
+/// deb-version(7).
+fn dpkg_compare_versions(a: &str, b: &str) -> Ordering {
+    let split = |version: &str| -> (u64, String, String) {
+        let (epoch, rest) = match version.split_once(':') {
+            Some((epoch, rest)) => (epoch.parse().unwrap_or(0), rest),
+            None => (0, version),
+        };
+        let (upstream, revision) = match rest.rsplit_once('-') {
+            Some((upstream, revision)) => (upstream, revision),
+            None => (rest, ""),
+        };
+        (epoch, upstream.to_string(), revision.to_string())
+    };
+    let (a_epoch, a_upstream, a_revision) = split(a);
+    let (b_epoch, b_upstream, b_revision) = split(b);
+    a_epoch
+        .cmp(&b_epoch)
+        .then_with(|| dpkg_verrevcmp(&a_upstream, &b_upstream))
+        .then_with(|| dpkg_verrevcmp(&a_revision, &b_revision))
+}
+
+/// Compare one part of a Debian version: alternating non-digit parts (where
+/// `~` sorts before everything, even the end, and letters before other
+/// characters) and numeric parts.
+fn dpkg_verrevcmp(a: &str, b: &str) -> Ordering {
+    fn order(c: Option<&u8>) -> i32 {
+        match c {
+            None => 0,
+            Some(b'~') => -1,
+            Some(c) if c.is_ascii_digit() => 0,
+            Some(c) if c.is_ascii_alphabetic() => *c as i32,
+            Some(c) => *c as i32 + 256,
+        }
+    }
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        while a.get(i).is_some_and(|c| !c.is_ascii_digit())
+            || b.get(j).is_some_and(|c| !c.is_ascii_digit())
+        {
+            let (ac, bc) = (order(a.get(i)), order(b.get(j)));
+            if ac != bc {
+                return ac.cmp(&bc);
+            }
+            i += 1;
+            j += 1;
+        }
+        while a.get(i) == Some(&b'0') {
+            i += 1;
+        }
+        while b.get(j) == Some(&b'0') {
+            j += 1;
+        }
+        let mut first_diff = Ordering::Equal;
+        while a.get(i).is_some_and(u8::is_ascii_digit) && b.get(j).is_some_and(u8::is_ascii_digit) {
+            if first_diff == Ordering::Equal {
+                first_diff = a[i].cmp(&b[j]);
+            }
+            i += 1;
+            j += 1;
+        }
+        if a.get(i).is_some_and(u8::is_ascii_digit) {
+            return Ordering::Greater;
+        }
+        if b.get(j).is_some_and(u8::is_ascii_digit) {
+            return Ordering::Less;
+        }
+        if first_diff != Ordering::Equal {
+            return first_diff;
+        }
+    }
+    Ordering::Equal
+}
+
+/// Compare `[epoch:]version[-release]` versions, as used by RPM and pacman.
+fn evr_compare_versions(a: &str, b: &str) -> Ordering {
+    let split = |version: &str| -> (u64, String, Option<String>) {
+        let (epoch, rest) = match version.split_once(':') {
+            Some((epoch, rest)) => (epoch.parse().unwrap_or(0), rest),
+            None => (0, version),
+        };
+        match rest.rsplit_once('-') {
+            Some((version, release)) => (epoch, version.to_string(), Some(release.to_string())),
+            None => (epoch, rest.to_string(), None),
+        }
+    };
+    let (a_epoch, a_version, a_release) = split(a);
+    let (b_epoch, b_version, b_release) = split(b);
+    a_epoch
+        .cmp(&b_epoch)
+        .then_with(|| rpmvercmp(&a_version, &b_version))
+        .then_with(|| match (a_release, b_release) {
+            (Some(a), Some(b)) => rpmvercmp(&a, &b),
+            _ => Ordering::Equal,
+        })
+}
+
+/// Compare versions segment by segment, as rpmvercmp() does: numeric segments
+/// numerically (and newer than alphabetic ones), alphabetic segments
+/// lexically, with `~` sorting before everything and `^` after the end.
+fn rpmvercmp(a: &str, b: &str) -> Ordering {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let (mut i, mut j) = (0, 0);
+    let separator = |c: &u8| !c.is_ascii_alphanumeric() && *c != b'~' && *c != b'^';
+    loop {
+        while a.get(i).is_some_and(separator) {
+            i += 1;
+        }
+        while b.get(j).is_some_and(separator) {
+            j += 1;
+        }
+
+        if a.get(i) == Some(&b'~') || b.get(j) == Some(&b'~') {
+            if a.get(i) != Some(&b'~') {
+                return Ordering::Greater;
+            }
+            if b.get(j) != Some(&b'~') {
+                return Ordering::Less;
+            }
+            i += 1;
+            j += 1;
+            continue;
+        }
+        if a.get(i) == Some(&b'^') || b.get(j) == Some(&b'^') {
+            if i == a.len() {
+                return Ordering::Less;
+            }
+            if j == b.len() {
+                return Ordering::Greater;
+            }
+            if a[i] != b'^' {
+                return Ordering::Greater;
+            }
+            if b[j] != b'^' {
+                return Ordering::Less;
+            }
+            i += 1;
+            j += 1;
+            continue;
+        }
+        if i == a.len() || j == b.len() {
+            break;
+        }
+
+        let numeric = a[i].is_ascii_digit();
+        let class = |c: &u8| {
+            if numeric {
+                c.is_ascii_digit()
+            } else {
+                c.is_ascii_alphabetic()
+            }
+        };
+        let a_end = a[i..]
+            .iter()
+            .position(|c| !class(c))
+            .map_or(a.len(), |n| i + n);
+        let b_end = b[j..]
+            .iter()
+            .position(|c| !class(c))
+            .map_or(b.len(), |n| j + n);
+        let (a_segment, b_segment) = (&a[i..a_end], &b[j..b_end]);
+        if b_segment.is_empty() {
+            // Numeric segments are newer than alphabetic ones.
+            return if numeric {
+                Ordering::Greater
+            } else {
+                Ordering::Less
+            };
+        }
+        let ordering = if numeric {
+            let trim = |segment: &[u8]| -> Vec<u8> {
+                segment.iter().copied().skip_while(|c| *c == b'0').collect()
+            };
+            let (a_segment, b_segment) = (trim(a_segment), trim(b_segment));
+            a_segment
+                .len()
+                .cmp(&b_segment.len())
+                .then_with(|| a_segment.cmp(&b_segment))
+        } else {
+            a_segment.cmp(b_segment)
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+        i = a_end;
+        j = b_end;
+    }
+    // Whichever has segments left over is newer.
+    (a.len() - i).min(1).cmp(&(b.len() - j).min(1))
+}
+
+/// A component of an APK version.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum ApkToken {
+    /// Pre-release suffixes (`_alpha`, `_beta`, `_pre`, `_rc`), which sort
+    /// before the end of a version.
+    PreSuffix(u8, u64),
+    End,
+    /// Post-release suffixes (e.g., `_p`, `_git`).
+    PostSuffix(u8, u64),
+    Revision(u64),
+    Letter(u8),
+    Number(u64),
+}
+
+/// Compare APK versions (e.g., `1.2.3a_rc1-r2`), as apk-version(8) does.
+fn apk_compare_versions(a: &str, b: &str) -> Ordering {
+    let mut a_tokens = apk_tokens(a).into_iter();
+    let mut b_tokens = apk_tokens(b).into_iter();
+    loop {
+        let (a, b) = (
+            a_tokens.next().unwrap_or(ApkToken::End),
+            b_tokens.next().unwrap_or(ApkToken::End),
+        );
+        if a == ApkToken::End && b == ApkToken::End {
+            return Ordering::Equal;
+        }
+        match a.cmp(&b) {
+            Ordering::Equal => continue,
+            ordering => return ordering,
+        }
+    }
+}
+
+fn apk_tokens(version: &str) -> Vec<ApkToken> {
+    let number = |digits: &str| digits.parse().unwrap_or(u64::MAX);
+    let mut tokens = vec![];
+    let mut rest = version;
+    while let Some(c) = rest.chars().next() {
+        if c.is_ascii_digit() {
+            let end = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            tokens.push(ApkToken::Number(number(&rest[..end])));
+            rest = &rest[end..];
+        } else if c.is_ascii_lowercase() {
+            tokens.push(ApkToken::Letter(c as u8));
+            rest = &rest[1..];
+        } else if let Some(suffix) = rest.strip_prefix('_') {
+            let name_end = suffix
+                .find(|c: char| !c.is_ascii_alphabetic())
+                .unwrap_or(suffix.len());
+            let digits_end = suffix[name_end..]
+                .find(|c: char| !c.is_ascii_digit())
+                .map_or(suffix.len(), |n| name_end + n);
+            let n = number(&suffix[name_end..digits_end]).min(u64::MAX - 1);
+            let n = if name_end == digits_end { 0 } else { n };
+            let pre = ["alpha", "beta", "pre", "rc"];
+            let post = ["cvs", "svn", "git", "hg", "p"];
+            let name = &suffix[..name_end];
+            if let Some(rank) = pre.iter().position(|s| *s == name) {
+                tokens.push(ApkToken::PreSuffix(rank as u8, n));
+            } else if let Some(rank) = post.iter().position(|s| *s == name) {
+                tokens.push(ApkToken::PostSuffix(rank as u8, n));
+            }
+            rest = &suffix[digits_end..];
+        } else if let Some(revision) = rest.strip_prefix("-r") {
+            let end = revision
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(revision.len());
+            tokens.push(ApkToken::Revision(number(&revision[..end])));
+            rest = &revision[end..];
+        } else if c == '~' {
+            // A commit hash, which has no order.
+            rest = rest.find('-').map_or("", |n| &rest[n..]);
+        } else {
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    tokens
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -210,4 +664,112 @@ DISTRIB_ID="void"
             assert_eq!(parsed, expected, "Failed for input: {:?}", input);
         }
     }
+
+    #[test]
+    fn test_parse_package_databases() {
+        let status = "Package: libc6
+Status: install ok installed
+Multi-Arch: same
+Architecture: amd64
+Version: 2.36-9+deb12u4
+Description: GNU C Library: Shared libraries
+ Contains the standard libraries.
+
+Package: removed
+Status: deinstall ok config-files
+Version: 1.0
+
+Package: bash
+Status: install ok installed
+Architecture: amd64
+Version: 5.2.15-2+b2
+";
+        assert_eq!(
+            parse_dpkg_status(status),
+            vec![
+                ("libc6:amd64".to_string(), "2.36-9+deb12u4".to_string()),
+                ("bash".to_string(), "5.2.15-2+b2".to_string()),
+            ]
+        );
+
+        let installed = "C:Q1abc=\nP:musl\nV:1.2.5-r9\nA:x86_64\n\nP:busybox\nV:1.37.0-r12\n";
+        assert_eq!(
+            parse_apk_installed(installed),
+            vec![
+                ("musl".to_string(), "1.2.5-r9".to_string()),
+                ("busybox".to_string(), "1.37.0-r12".to_string()),
+            ]
+        );
+
+        let desc = "%NAME%\nlinux\n\n%VERSION%\n6.9.7.arch1-1\n\n%BASE%\nlinux\n";
+        assert_eq!(
+            parse_pacman_desc(desc),
+            Some(("linux".to_string(), "6.9.7.arch1-1".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_installed_packages() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(PackageManager::detect(dir.path()), None);
+
+        fs::create_dir_all(dir.path().join("lib/apk/db")).unwrap();
+        fs::write(
+            dir.path().join("lib/apk/db/installed"),
+            "P:linux-lts\nV:6.6.31-r0\n\nP:linux-lts\nV:6.6.30-r0\n\nP:musl\nV:1.2.5-r0\n",
+        )
+        .unwrap();
+        let manager = PackageManager::detect(dir.path()).unwrap();
+        assert_eq!(manager, PackageManager::Apk);
+        let packages = manager.installed_packages(dir.path()).unwrap();
+        assert_eq!(packages["linux-lts"], vec!["6.6.30-r0", "6.6.31-r0"]);
+        assert_eq!(packages["musl"], vec!["1.2.5-r0"]);
+    }
+
+    #[test]
+    fn test_compare_versions() {
+        use Ordering::*;
+        let cases = [
+            (PackageManager::Dpkg, "1.0", "1.0", Equal),
+            (PackageManager::Dpkg, "1.0~rc1", "1.0", Less),
+            (PackageManager::Dpkg, "1.0-2", "1.0-10", Less),
+            (PackageManager::Dpkg, "1:0.9", "2.0", Greater),
+            (
+                PackageManager::Dpkg,
+                "2.36-9+deb12u4",
+                "2.36-9+deb12u10",
+                Less,
+            ),
+            (PackageManager::Dpkg, "1.0a", "1.0+", Less),
+            (PackageManager::Rpm, "1.0-1.fc40", "1.0-2.fc40", Less),
+            (PackageManager::Rpm, "1.0~rc1-1", "1.0-1", Less),
+            (PackageManager::Rpm, "1.0^git1-1", "1.0-1", Greater),
+            (PackageManager::Rpm, "1.10", "1.9", Greater),
+            (PackageManager::Rpm, "1.0a", "1.0.1", Less),
+            (PackageManager::Pacman, "1:1.0-1", "2.0-1", Greater),
+            (
+                PackageManager::Pacman,
+                "6.9.7.arch1-1",
+                "6.9.10.arch1-1",
+                Less,
+            ),
+            (PackageManager::Apk, "1.2.5-r9", "1.2.5-r10", Less),
+            (PackageManager::Apk, "1.2.5_rc1", "1.2.5", Less),
+            (PackageManager::Apk, "1.2.5_p1", "1.2.5", Greater),
+            (PackageManager::Apk, "1.2.5a", "1.2.5", Greater),
+            (PackageManager::Apk, "1.2.10", "1.2.9", Greater),
+            (PackageManager::Apk, "1.2_alpha2", "1.2_beta1", Less),
+        ];
+        for (manager, a, b, expected) in cases {
+            assert_eq!(
+                manager.compare_versions(a, b),
+                expected,
+                "{:?} {} vs {}",
+                manager,
+                a,
+                b
+            );
+            assert_eq!(manager.compare_versions(b, a), expected.reverse());
+        }
+    }
 }
@@ -0,0 +1,253 @@
+// SPDX-License-Identifier: MPL-2.0
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The format written by `beadm export`: a small header describing the boot
+//! environment, followed by one ZFS send stream for each of its datasets, in
+//! the order they are listed in the header (parents first).
+//!
+//! The header is the magic bytes [`MAGIC`], the length of the rest of the
+//! header as a big-endian u32, and then a JSON object. It is read without any
+//! buffering so that the send streams can be received straight from the same
+//! file descriptor afterwards.
+
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+use serde_json::{Value, json};
+
+use super::Error;
+use super::scan::OsRelease;
+
+/// The magic bytes at the start of every export stream.
+pub const MAGIC: &[u8; 8] = b"BEADMEXP";
+
+/// The version of the header written by this version of beadm.
+pub const VERSION: u64 = 1;
+
+/// An upper bound on the header length, to avoid allocating absurd amounts
+/// of memory for corrupt streams.
+const MAX_HEADER_LEN: u32 = 1 << 20;
+
+/// The metadata at the start of an export stream.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExportHeader {
+    /// The name of the exported boot environment.
+    pub name: String,
+    /// The snapshot of it that was sent.
+    pub snapshot: String,
+    /// For incremental streams, the snapshot they were sent from.
+    pub base: Option<String>,
+    pub description: Option<String>,
+    pub tags: BTreeMap<String, String>,
+    /// The contents of the boot environment's os-release(5) file.
+    pub os_release: Option<String>,
+    /// The boot environment's host ID, from its `/etc/hostid`.
+    pub hostid: Option<u32>,
+    /// The datasets that follow, parents first.
+    pub datasets: Vec<ExportedDataset>,
+}
+
+/// A dataset with a send stream in an export stream.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExportedDataset {
+    /// The dataset's name relative to the boot environment, or empty for the
+    /// boot environment itself.
+    pub name: String,
+    /// The dataset's mountpoint, if it was set locally.
+    pub mountpoint: Option<String>,
+}
+
+impl ExportHeader {
+    /// Write the header to the start of an export stream.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        let datasets: Vec<Value> = self
+            .datasets
+            .iter()
+            .map(|dataset| {
+                json!({
+                    "name": dataset.name,
+                    "mountpoint": dataset.mountpoint,
+                })
+            })
+            .collect();
+        let body = json!({
+            "version": VERSION,
+            "name": self.name,
+            "snapshot": self.snapshot,
+            "base": self.base,
+            "description": self.description,
+            "tags": self.tags,
+            "os_release": self.os_release,
+            "hostid": self.hostid,
+            "datasets": datasets,
+        })
+        .to_string();
+        writer.write_all(MAGIC)?;
+        writer.write_all(&(body.len() as u32).to_be_bytes())?;
+        writer.write_all(body.as_bytes())
+    }
+
+    /// The description to give a boot environment imported from this
+    /// stream: the exported one's, or else the name of its distribution.
+    pub fn imported_description(&self) -> Option<String> {
+        self.description.clone().or_else(|| {
+            self.os_release
+                .as_ref()
+                .map(|contents| OsRelease::parse(contents).pretty)
+        })
+    }
+
+    /// Read the header from the start of an export stream, consuming exactly
+    /// as many bytes as were written by [`ExportHeader::write_to`].
+    pub fn read_from<R: Read>(mut reader: R) -> Result<Self, Error> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::invalid_stream("not a beadm export stream"));
+        }
+        let mut len = [0u8; 4];
+        reader.read_exact(&mut len)?;
+        let len = u32::from_be_bytes(len);
+        if len > MAX_HEADER_LEN {
+            return Err(Error::invalid_stream("header is too large"));
+        }
+        let mut body = vec![0u8; len as usize];
+        reader.read_exact(&mut body)?;
+        let body: Value = serde_json::from_slice(&body)
+            .map_err(|err| Error::invalid_stream(&format!("malformed header: {}", err)))?;
+        Self::from_json(&body)
+    }
+
+    fn from_json(body: &Value) -> Result<Self, Error> {
+        match body.get("version").and_then(Value::as_u64) {
+            Some(VERSION) => {}
+            Some(version) => {
+                return Err(Error::invalid_stream(&format!(
+                    "unsupported version {}",
+                    version
+                )));
+            }
+            None => return Err(Error::invalid_stream("header has no version")),
+        }
+        let string = |key: &str| body.get(key).and_then(Value::as_str).map(str::to_string);
+        let required = |key: &str| {
+            string(key).ok_or_else(|| Error::invalid_stream(&format!("header has no {}", key)))
+        };
+
+        let mut tags = BTreeMap::new();
+        if let Some(map) = body.get("tags").and_then(Value::as_object) {
+            for (key, value) in map {
+                if let Some(value) = value.as_str() {
+                    tags.insert(key.clone(), value.to_string());
+                }
+            }
+        }
+        let datasets: Vec<ExportedDataset> = body
+            .get("datasets")
+            .and_then(Value::as_array)
+            .map(|datasets| {
+                datasets
+                    .iter()
+                    .filter_map(|dataset| {
+                        Some(ExportedDataset {
+                            name: dataset.get("name")?.as_str()?.to_string(),
+                            mountpoint: dataset
+                                .get("mountpoint")
+                                .and_then(Value::as_str)
+                                .map(str::to_string),
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        // The boot environment itself must come first, so that its children
+        // have somewhere to go.
+        if datasets
+            .first()
+            .is_none_or(|dataset| !dataset.name.is_empty())
+        {
+            return Err(Error::invalid_stream(
+                "header does not list the boot environment",
+            ));
+        }
+
+        Ok(Self {
+            name: required("name")?,
+            snapshot: required("snapshot")?,
+            base: string("base"),
+            description: string("description"),
+            tags,
+            os_release: string("os_release"),
+            hostid: body
+                .get("hostid")
+                .and_then(Value::as_u64)
+                .and_then(|hostid| u32::try_from(hostid).ok()),
+            datasets,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_round_trip() {
+        let header = ExportHeader {
+            name: "default".to_string(),
+            snapshot: "2026-10-17T09:30:00Z".to_string(),
+            base: Some("2026-09-01T08:00:00Z".to_string()),
+            description: Some("Known good".to_string()),
+            tags: BTreeMap::from([("owner".to_string(), "ops".to_string())]),
+            os_release: Some("ID=debian\nPRETTY_NAME=\"Debian GNU/Linux 13\"\n".to_string()),
+            hostid: Some(0x00bab10c),
+            datasets: vec![
+                ExportedDataset::default(),
+                ExportedDataset {
+                    name: "var".to_string(),
+                    mountpoint: Some("/var".to_string()),
+                },
+            ],
+        };
+        let mut stream = vec![];
+        header.write_to(&mut stream).unwrap();
+        // Whatever follows the header is left alone.
+        stream.extend_from_slice(b"send stream");
+
+        let mut reader = stream.as_slice();
+        assert_eq!(ExportHeader::read_from(&mut reader).unwrap(), header);
+        assert_eq!(reader, b"send stream");
+    }
+
+    #[test]
+    fn test_header_invalid() {
+        let read = |bytes: &[u8]| ExportHeader::read_from(bytes);
+        assert!(matches!(
+            read(b"\x00\x00\x00\x00\x00\x00\x00\x00"),
+            Err(Error::InvalidStream { .. })
+        ));
+        assert!(matches!(read(b"BEADMEXP"), Err(Error::Io(_))));
+
+        let framed = |body: &str| {
+            let mut stream = MAGIC.to_vec();
+            stream.extend_from_slice(&(body.len() as u32).to_be_bytes());
+            stream.extend_from_slice(body.as_bytes());
+            stream
+        };
+        for body in [
+            "not json",
+            r#"{"version": 2, "name": "a", "snapshot": "b", "datasets": [{"name": ""}]}"#,
+            r#"{"version": 1, "snapshot": "b", "datasets": [{"name": ""}]}"#,
+            r#"{"version": 1, "name": "a", "snapshot": "b", "datasets": [{"name": "var"}]}"#,
+        ] {
+            assert!(
+                matches!(read(&framed(body)), Err(Error::InvalidStream { .. })),
+                "{}",
+                body
+            );
+        }
+    }
+}
@@ -120,10 +120,62 @@ pub(crate) fn validate_component(name: &str, is_dataset: bool) -> Result<(), Err
     Ok(())
 }
 
+/// Validates a tag key and (if setting it) value.
+///
+/// Tags are stored as ZFS user properties, so keys are limited to the
+/// characters allowed in user property names: lowercase letters, digits, '-',
+/// '.' and '_'. They must also leave room for our namespace within the
+/// 255-character limit on property names.
+pub(crate) fn validate_tag(key: &str, value: Option<&str>) -> Result<(), Error> {
+    let invalid = |reason: String| Error::InvalidTag {
+        key: key.to_string(),
+        reason,
+    };
+    if key.is_empty() {
+        return Err(invalid("key cannot be empty".to_string()));
+    }
+    if key.len() > 200 {
+        return Err(invalid("key too long".to_string()));
+    }
+    for c in key.chars() {
+        if !c.is_ascii_lowercase() && !c.is_ascii_digit() && c != '.' && c != '-' && c != '_' {
+            return Err(invalid(format!("invalid character '{}' in key", c)));
+        }
+    }
+    if let Some(value) = value {
+        if value.is_empty() {
+            return Err(invalid("value cannot be empty".to_string()));
+        }
+        if value.len() >= 8192 {
+            return Err(invalid("value too long".to_string()));
+        }
+        if value.contains('\0') {
+            return Err(invalid("value cannot contain a nul byte".to_string()));
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_tag_validation() {
+        assert!(validate_tag("ticket", Some("1234")).is_ok());
+        assert!(validate_tag("pre-kernel-6.12", Some("yes")).is_ok());
+        assert!(validate_tag("release_candidate", None).is_ok());
+        assert!(validate_tag("stage", Some("release candidate")).is_ok()); // values are free text
+
+        assert!(validate_tag("", Some("x")).is_err()); // empty key
+        assert!(validate_tag("Ticket", Some("1234")).is_err()); // uppercase
+        assert!(validate_tag("ticket:id", Some("1234")).is_err()); // colon
+        assert!(validate_tag("my ticket", Some("1234")).is_err()); // space
+        assert!(validate_tag(&"a".repeat(201), None).is_err()); // too long
+        assert!(validate_tag("ticket", Some("")).is_err()); // empty value
+        assert!(validate_tag("ticket", Some(&"a".repeat(8192))).is_err()); // value too long
+    }
+
     #[test]
     fn test_be_name_validation() {
         assert!(validate_be_name("valid-name", "zfake/ROOT").is_ok());
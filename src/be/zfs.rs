@@ -4,22 +4,43 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use std::collections::BTreeMap;
 use std::ffi::{CStr, CString, OsStr, c_char, c_int, c_void};
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, Read, Seek};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd};
 use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
 use std::path::{Path, PathBuf};
 use std::ptr;
 use std::sync::{LazyLock, Mutex, MutexGuard};
 
-use super::validation::{validate_component, validate_dataset_name};
+use chrono::Utc;
+
+use super::diff::{self, FileChange};
+use super::doctor::{self, Finding};
+use super::health;
+use super::history;
+use super::stream::{ExportHeader, ExportedDataset};
+use super::validation::{validate_component, validate_dataset_name, validate_tag};
 use super::{
-    BootEnvironment, Client, Error, Label, MountMode, Root, Snapshot, generate_snapshot_name,
-    generate_temp_mountpoint, is_temp_mountpoint,
+    BootEnvironment, BootRecord, ChildDataset, Client, DestroyPlan, Error, HealthIssue, Label,
+    MountOptions, Property, Replication, RolledBack, Root, Snapshot, SpaceUsage, check_destroyable,
+    generate_snapshot_name, generate_temp_mountpoint, is_temp_mountpoint, parse_hostid,
+    record_replication, recorded_replication, with_mounted,
 };
 
 const DESCRIPTION_PROP: &str = "ca.kamacite:description";
 const PREVIOUS_BOOTFS_PROP: &str = "ca.kamacite:previous-bootfs";
+const CREATED_FOR_PROP: &str = "ca.kamacite:created-for";
+const TRIAL_PROP: &str = "ca.kamacite:trial";
+const BOOT_HISTORY_PROP: &str = "ca.kamacite:boot-history";
+const PINNED_PROP: &str = "ca.kamacite:pinned";
+const REPLICATED_PROP: &str = "ca.kamacite:replicated";
+/// The tag used for the holds that pin snapshots.
+const PIN_HOLD_TAG: &str = "ca.kamacite:pinned";
+/// Tags are stored as user properties named with this prefix and their key.
+const TAG_PROP_PREFIX: &str = "ca.kamacite:tag:";
 
 /// A ZFS boot environment client backed by libzfs.
 pub struct LibZfsClient {
@@ -49,6 +70,58 @@ impl LibZfsClient {
         }
         Err(Error::NoActiveBootEnvironment)
     }
+
+    /// Compare two mounted boot environments (or snapshots of them), with
+    /// `zfs diff` if one descends from the other and file by file otherwise.
+    fn diff_mounted(
+        &self,
+        from: &Label,
+        to: &Label,
+        from_mountpoint: &Path,
+        to_mountpoint: &Path,
+        paths: &[PathBuf],
+        root: Option<&Root>,
+    ) -> Result<Vec<FileChange>, Error> {
+        if from == to {
+            return Ok(vec![]);
+        }
+        let from_children = self.mounted_children(label_name(from), root)?;
+        let to_children = self.mounted_children(label_name(to), root)?;
+
+        {
+            let root = self.effective_root(root)?;
+            let lzh = LibHandle::get();
+            if let Some(changes) = zfs_diff(&lzh, &root, from, to, &to_children, to_mountpoint)? {
+                return Ok(diff::finish(changes, paths, false));
+            }
+            if let Some(changes) = zfs_diff(&lzh, &root, to, from, &from_children, from_mountpoint)?
+            {
+                return Ok(diff::finish(changes, paths, true));
+            }
+        }
+
+        let from_tree = diff_tree(from, from_mountpoint, &from_children)?;
+        let to_tree = diff_tree(to, to_mountpoint, &to_children)?;
+        diff::walk_diff(&from_tree, &to_tree, paths)
+    }
+
+    /// The mounted child datasets of a boot environment, by their name
+    /// relative to it (e.g., `var`) and their mountpoint.
+    fn mounted_children(
+        &self,
+        be_name: &str,
+        root: Option<&Root>,
+    ) -> Result<Vec<(String, PathBuf)>, Error> {
+        let prefix = format!("{}/", be_name);
+        Ok(self
+            .get_datasets(be_name, root)?
+            .into_iter()
+            .filter_map(|dataset| {
+                let name = dataset.name.strip_prefix(&prefix)?.to_string();
+                Some((name, dataset.mountpoint?))
+            })
+            .collect())
+    }
 }
 
 impl Client for LibZfsClient {
@@ -64,12 +137,11 @@ impl Client for LibZfsClient {
         let be_path = root.append(be_name)?;
         let lzh = LibHandle::get();
 
-        // Create properties nvlist with description if provided
-        let props = if let Some(desc) = description {
-            Some(NvList::from(&[(DESCRIPTION_PROP, desc)])?)
-        } else {
-            None
-        };
+        // Properties for the snapshot we take of the source, if we need one.
+        // It is tagged so that `beadm gc` can find it once nothing depends on
+        // it any more. The description belongs to the new boot environment,
+        // not the snapshot.
+        let props = NvList::from(&[(CREATED_FOR_PROP, be_name)])?;
 
         // Build the clone properties (and validate user-specified properties)
         // before creating any snapshot, so that invalid input fails without
@@ -91,14 +163,15 @@ impl Client for LibZfsClient {
             clone_props.add_string(name, value)?;
         }
 
-        let snapshot = match source {
+        let (source_path, snapshot_name) = match source {
             Some(Label::Snapshot(name, snapshot)) => {
                 // Case #1: beadm create -e EXISTING@SNAPSHOT NAME, which
                 // creates the clone from an existing snapshot of a boot
                 // environment.
 
                 // Build the full snapshot path (which handles validation).
-                let snapshot_path = root.append(name)?.snapshot(snapshot)?;
+                let source_path = root.append(name)?;
+                let snapshot_path = source_path.snapshot(snapshot)?;
 
                 // Open the snapshot (which also verifies it exists).
                 Dataset::snapshot(&lzh, &snapshot_path).map_err(|err| {
@@ -111,14 +184,17 @@ impl Client for LibZfsClient {
                         return Error::not_found(&format!("{}@{}", name, snapshot));
                     }
                     err
-                })
+                })?;
+                (source_path, snapshot.to_string())
             }
             Some(Label::Name(name)) => {
                 // Case #2: beadm create -e EXISTING NAME, which creates the
                 // clone from a new snapshot of a source boot environment.
-                let snapshot_path = root.append(name)?.generate_snapshot()?;
+                let source_path = root.append(name)?;
+                let snapshot_name = generate_snapshot_name();
+                let snapshot_path = source_path.snapshot(&snapshot_name)?;
 
-                Dataset::create_snapshot(&lzh, &snapshot_path, props.as_ref()).map_err(|err| {
+                Dataset::create_snapshot(&lzh, &snapshot_path, Some(&props)).map_err(|err| {
                     // Special casing for EZFS_NOENT.
                     if let Error::LibzfsError(LibzfsError {
                         errno: ffi::EZFS_NOENT,
@@ -128,23 +204,23 @@ impl Client for LibZfsClient {
                         return Error::not_found(name);
                     }
                     err
-                })
+                })?;
+                (source_path, snapshot_name)
             }
             None => {
                 // Case #3: beadm create NAME, which creates the clone from a
                 // snapshot of the active boot environment.
-                let snapshot_path = get_rootfs()?
-                    .ok_or_else(|| Error::NoActiveBootEnvironment)?
-                    .generate_snapshot()?;
+                let source_path = get_rootfs()?.ok_or_else(|| Error::NoActiveBootEnvironment)?;
+                let snapshot_name = generate_snapshot_name();
+                let snapshot_path = source_path.snapshot(&snapshot_name)?;
 
-                Dataset::create_snapshot(&lzh, &snapshot_path, props.as_ref())
+                Dataset::create_snapshot(&lzh, &snapshot_path, Some(&props))?;
+                (source_path, snapshot_name)
             }
-        }?;
+        };
 
         // Clone the source snapshot to create the new boot environment.
-        //
-        // TODO: Investigate 'beadm' for whether we need to handle recursion.
-        // In 'bectl' it is manually specified.
+        let snapshot = Dataset::snapshot(&lzh, &source_path.snapshot(&snapshot_name)?)?;
         snapshot
             .clone(&lzh, &be_path, Some(&clone_props))
             .map_err(|err| {
@@ -157,17 +233,35 @@ impl Client for LibZfsClient {
                     return Error::conflict(be_name);
                 }
                 err
-            })
+            })?;
+
+        // Then clone the matching snapshots of any child datasets (e.g. a
+        // separate /var) into the same positions beneath the new boot
+        // environment. If this fails part way through, remove what we've
+        // created rather than leave a partial boot environment behind.
+        let source = Dataset::filesystem(&lzh, &source_path)?;
+        clone_children(&lzh, &source, &source_path, &snapshot_name, &be_path).inspect_err(|_| {
+            if let Ok(dataset) = Dataset::filesystem(&lzh, &be_path) {
+                _ = destroy_children(&lzh, &dataset).and_then(|_| dataset.destroy(&lzh));
+            }
+        })
     }
 
     fn create_empty(
         &self,
         be_name: &str,
         description: Option<&str>,
-        _host_id: Option<&str>,
+        host_id: Option<&str>,
         properties: &[String],
         root: Option<&Root>,
     ) -> Result<(), Error> {
+        // Without a matching host ID, the new system won't be able to import
+        // the pool at boot. Default to the running system's /etc/hostid.
+        let hostid = match host_id {
+            Some(value) => Some(parse_hostid(value)?),
+            None => read_hostid(Some("/"))?,
+        };
+
         let mut props = NvList::from(&[("canmount", "noauto"), ("mountpoint", "/")])?;
         if let Some(desc) = description {
             props.add_string(DESCRIPTION_PROP, desc)?;
@@ -197,7 +291,27 @@ impl Client for LibZfsClient {
                 return Error::conflict(be_name);
             }
             err
-        })
+        })?;
+
+        // Mount the new boot environment temporarily to seed its identity
+        // files. If we can't, remove it rather than leave behind a boot
+        // environment that won't boot.
+        let dataset = Dataset::filesystem(&lzh, &be_path)?;
+        let mountpoint = generate_temp_mountpoint();
+        let result = std::fs::create_dir_all(&mountpoint)
+            .map_err(Error::from)
+            .and_then(|_| dataset.mount_at(&lzh, mountpoint.as_os_str(), &MountOptions::default()))
+            .and_then(|_| {
+                let seeded = seed_empty_root(&mountpoint, hostid).map_err(Error::from);
+                dataset.unmount(&lzh, false).and(seeded)
+            });
+        _ = std::fs::remove_dir(&mountpoint);
+        if let Err(err) = result {
+            _ = dataset.unmount(&lzh, true);
+            _ = dataset.destroy(&lzh);
+            return Err(err);
+        }
+        Ok(())
     }
 
     fn destroy(
@@ -205,12 +319,19 @@ impl Client for LibZfsClient {
         target: &Label,
         force_unmount: bool,
         destroy_snapshots: bool,
+        unpin: bool,
+        dry_run: bool,
         root: Option<&Root>,
-    ) -> Result<(), Error> {
+    ) -> Result<DestroyPlan, Error> {
         let root = self.effective_root(root)?;
         let lzh = LibHandle::get();
+        let prefix = format!("{}/", root.to_string());
 
-        let dataset = match target {
+        // Work out every step up front, so that a dry run reports exactly
+        // what would be done. Dataset names in the plan are absolute until
+        // it has been carried out.
+        let mut plan = DestroyPlan::default();
+        let unmount = match target {
             Label::Name(name) => {
                 let zpool = Zpool::open(&lzh, &root.pool())?;
                 let path = root.append(name)?;
@@ -239,98 +360,187 @@ impl Client for LibZfsClient {
                     }
                 }
 
-                let mountpoint = dataset.get_mountpoint();
-                if mountpoint.is_some() {
-                    if !force_unmount {
-                        return Err(Error::Mounted {
-                            name: name.to_string(),
-                            mountpoint: mountpoint.unwrap().display().to_string(),
-                        });
-                    } else {
-                        // Best-effort attempt to unmount the dataset.
-                        _ = dataset.unmount(&lzh, true);
+                if dataset.get_user_property(PINNED_PROP, false).as_deref() == Some("on") {
+                    if !unpin {
+                        return Err(Error::protected(name));
                     }
+                    plan.unpin.push(path.to_string());
                 }
 
-                // If there are any clones of this boot environment, we need to
-                // promote one of them, ideally the oldest one.
-                let mut oldest_clone: Option<DatasetName> = None;
-                let mut oldest_time: i64 = i64::MAX;
-                dataset.iter_snapshots(&lzh, |snapshot| {
-                    // Bail if the boot environment has snapshots but we aren't
-                    // willing to destroy them.
-                    if !destroy_snapshots {
-                        return Err(Error::has_snapshots(name));
-                    }
+                // The boot environment and its descendants, parents first.
+                let children = descendant_filesystems(&lzh, &dataset)?
+                    .iter()
+                    .map(|child| Dataset::filesystem(&lzh, child))
+                    .collect::<Result<Vec<_>, _>>()?;
 
-                    snapshot.iter_clones(&lzh, false, |clone| {
-                        // TODO: Do we need to verify the origin property
-                        // matches here?
-                        if let Some(name) = clone.get_name() {
-                            let creation_time = clone.get_creation_time();
-                            if creation_time < oldest_time {
-                                oldest_time = creation_time;
-                                oldest_clone = Some(name);
+                let mountpoint = dataset.get_mountpoint();
+                if let Some(mountpoint) = &mountpoint {
+                    if !force_unmount {
+                        return Err(Error::mounted(name, mountpoint));
+                    }
+                    // Children are unmounted before their parents.
+                    for child in children.iter().rev() {
+                        if let Some(child_mountpoint) = child.get_mountpoint() {
+                            if mountpoint != Path::new("/")
+                                && child_mountpoint.starts_with(mountpoint)
+                            {
+                                plan.unmount.push(child_mountpoint.display().to_string());
                             }
                         }
-                        Ok(())
-                    })?;
+                    }
+                    plan.unmount.push(mountpoint.display().to_string());
+                }
 
-                    Ok(())
-                })?;
+                // Bail if any part of the boot environment has snapshots but
+                // we aren't willing to destroy them.
+                if !destroy_snapshots {
+                    for member in std::iter::once(&dataset).chain(&children) {
+                        member.iter_snapshots(&lzh, |_| Err(Error::has_snapshots(name)))?;
+                    }
+                }
 
-                if let Some(name) = oldest_clone {
-                    let target = Dataset::filesystem(&lzh, &name)?;
-                    target.promote(&lzh)?;
+                for member in std::iter::once(&dataset).chain(&children) {
+                    plan_snapshot_removal(&lzh, member, &mut plan)?;
+                    plan.space += member
+                        .get_numeric_property(ffi::ZFS_PROP_USEDDS)
+                        .unwrap_or(0);
+                }
+                // Without any promotions, everything the boot environment uses
+                // is reclaimed.
+                if plan.promote.is_empty() {
+                    plan.space = dataset.get_used_space();
                 }
 
-                // Second pass: actually destroy the snapshots.
-                dataset.iter_snapshots(&lzh, |snapshot| {
-                    if !destroy_snapshots {
-                        return Err(Error::has_snapshots(name));
+                for snapshot_name in &plan.snapshots {
+                    let snapshot = Dataset::snapshot(&lzh, &DatasetName::new(snapshot_name)?)?;
+                    if snapshot.has_hold(PIN_HOLD_TAG) {
+                        if !unpin {
+                            let relative = snapshot_name.strip_prefix(&prefix);
+                            return Err(Error::protected(relative.unwrap_or(snapshot_name)));
+                        }
+                        plan.unpin.push(snapshot_name.clone());
                     }
-                    snapshot.destroy(&lzh)
-                })?;
+                }
 
-                dataset
+                // Children must be destroyed before their parents.
+                for child in children.iter().rev() {
+                    if let Some(child_name) = child.get_name() {
+                        plan.datasets.push(child_name.to_string());
+                    }
+                }
+                plan.datasets.push(path.to_string());
+
+                mountpoint.map(|_| dataset)
             }
             Label::Snapshot(name, snapshot) => {
-                let path = root.append(name)?.snapshot(snapshot)?;
+                let be_path = root.append(name)?;
+                let path = be_path.snapshot(snapshot)?;
                 let dataset = Dataset::snapshot(&lzh, &path)?;
 
-                // If this snapshot is the basis for any clones, we need to
-                // promote one of them first, ideally the oldest one.
-                let mut oldest_clone: Option<DatasetName> = None;
-                let mut oldest_time: i64 = i64::MAX;
-                dataset.iter_clones(&lzh, false, |clone| {
-                    // TODO: Do we need to verify the origin property matches
-                    // here?
-                    if let Some(name) = clone.get_name() {
-                        let creation_time = clone.get_creation_time();
-                        if creation_time < oldest_time {
-                            oldest_time = creation_time;
-                            oldest_clone = Some(name);
+                // The matching snapshots of any child datasets go too.
+                let mut snapshots = vec![dataset];
+                let be_dataset = Dataset::filesystem(&lzh, &be_path)?;
+                for child in descendant_filesystems(&lzh, &be_dataset)? {
+                    match Dataset::snapshot(&lzh, &child.snapshot(snapshot)?) {
+                        Ok(child_snapshot) => snapshots.push(child_snapshot),
+                        // The child was created after the snapshot was taken.
+                        Err(Error::LibzfsError(LibzfsError {
+                            errno: ffi::EZFS_NOENT,
+                            ..
+                        })) => continue,
+                        Err(err) => return Err(err),
+                    }
+                }
+
+                // Holds are placed on the snapshots of child datasets too.
+                for snapshot in &snapshots {
+                    if snapshot.has_hold(PIN_HOLD_TAG) {
+                        if !unpin {
+                            return Err(Error::protected(&target.to_string()));
                         }
+                        plan.unpin
+                            .extend(snapshot.get_name().map(|n| n.to_string()));
                     }
-                    Ok(())
-                })?;
-                if let Some(name) = oldest_clone {
-                    let target = Dataset::filesystem(&lzh, &name)?;
-                    target.promote(&lzh)?;
                 }
 
-                dataset
+                // If any of these snapshots is the basis for clones, we need
+                // to promote one of them first, ideally the oldest one.
+                for snapshot in &snapshots {
+                    let mut oldest_clone: Option<(DatasetName, i64)> = None;
+                    find_oldest_clone(&lzh, snapshot, &mut oldest_clone)?;
+                    if let Some((name, _)) = oldest_clone {
+                        plan.promote.push(name.to_string());
+                    }
+                }
+
+                // Destroy the child snapshots, leaving the boot environment's
+                // own snapshot for last.
+                snapshots.rotate_left(1);
+                for snapshot in &snapshots {
+                    if let Some(name) = snapshot.get_name() {
+                        plan.snapshots.push(name.to_string());
+                    }
+                    plan.space += snapshot.get_used_space();
+                }
+
+                None
             }
         };
 
-        dataset.destroy(&lzh)
+        if !dry_run {
+            for name in &plan.unpin {
+                match name.split_once('@') {
+                    Some((parent, snapshot)) => {
+                        Dataset::filesystem(&lzh, &DatasetName::new(parent)?)?.release(
+                            &lzh,
+                            snapshot,
+                            PIN_HOLD_TAG,
+                            false,
+                        )?;
+                    }
+                    None => {
+                        Dataset::filesystem(&lzh, &DatasetName::new(name)?)?
+                            .inherit_property(&lzh, PINNED_PROP)?;
+                    }
+                }
+            }
+            if let Some(dataset) = unmount {
+                // Best-effort attempt to unmount the dataset and its children.
+                _ = unmount_children(&lzh, &dataset, true);
+                _ = dataset.unmount(&lzh, true);
+            }
+            for name in &plan.promote {
+                Dataset::filesystem(&lzh, &DatasetName::new(name)?)?.promote(&lzh)?;
+            }
+            for name in &plan.snapshots {
+                Dataset::snapshot(&lzh, &DatasetName::new(name)?)?.destroy(&lzh)?;
+            }
+            for name in &plan.datasets {
+                Dataset::filesystem(&lzh, &DatasetName::new(name)?)?.destroy(&lzh)?;
+            }
+        }
+
+        // Report dataset names relative to the boot environment root, as we
+        // do everywhere else.
+        for name in plan
+            .unpin
+            .iter_mut()
+            .chain(plan.promote.iter_mut())
+            .chain(plan.snapshots.iter_mut())
+            .chain(plan.datasets.iter_mut())
+        {
+            if let Some(relative) = name.strip_prefix(&prefix) {
+                *name = relative.to_string();
+            }
+        }
+        Ok(plan)
     }
 
     fn mount(
         &self,
         be_name: &str,
         mountpoint: Option<&Path>,
-        _mode: MountMode,
+        options: &MountOptions,
         root: Option<&Root>,
     ) -> Result<PathBuf, Error> {
         let be_path = self.effective_root(root)?.append(be_name)?;
@@ -358,8 +568,15 @@ impl Client for LibZfsClient {
             mp
         };
 
-        // TODO: Support recursively mounting child datasets.
-        dataset.mount_at(&lzh, mountpoint.as_os_str())?;
+        dataset.mount_at(&lzh, mountpoint.as_os_str(), options)?;
+
+        // Mount any child datasets beneath the boot environment, backing out
+        // the whole mount if one of them fails.
+        if let Err(err) = mount_children(&lzh, &dataset, &mountpoint, options) {
+            _ = unmount_children(&lzh, &dataset, true);
+            _ = dataset.unmount(&lzh, true);
+            return Err(err);
+        }
         Ok(mountpoint)
     }
 
@@ -376,7 +593,7 @@ impl Client for LibZfsClient {
         match dataset.get_mountpoint() {
             None => Ok(None), // Nothing to do.
             Some(mountpoint) => {
-                // TODO: Support recursively unmounting child datasets.
+                unmount_children(&lzh, &dataset, force)?;
                 dataset.unmount(&lzh, force)?;
 
                 // Clean up the mountpoint if we created it.
@@ -409,6 +626,9 @@ impl Client for LibZfsClient {
             .rename(
                 &lzh,
                 &new_path,
+                // Renaming a filesystem always moves its descendants along with
+                // it, so child datasets follow the boot environment; the
+                // recursive flag only applies to snapshots.
                 ffi::RenameFlags {
                     recursive: 0,
                     nounmount: 1, // Leave boot environment mounts in place.
@@ -428,6 +648,302 @@ impl Client for LibZfsClient {
             })
     }
 
+    fn rename_snapshot(
+        &self,
+        be_name: &str,
+        snapshot: &str,
+        new_snapshot: &str,
+        root: Option<&Root>,
+    ) -> Result<(), Error> {
+        let root = self.effective_root(root)?;
+        let new_path = root.append(be_name)?.snapshot(new_snapshot)?;
+        let lzh = LibHandle::get();
+        let dataset = open_snapshot(&lzh, &root, be_name, snapshot)?;
+        dataset
+            .rename(
+                &lzh,
+                &new_path,
+                // Rename the matching snapshots of child datasets too, since
+                // they are taken together.
+                ffi::RenameFlags {
+                    recursive: 1,
+                    nounmount: 0,
+                    forceunmount: 0,
+                },
+            )
+            .map_err(|err| {
+                // Special casing for EZFS_EEXIST.
+                if let Error::LibzfsError(LibzfsError {
+                    errno: ffi::EZFS_EEXIST,
+                    ..
+                }) = err
+                {
+                    return Error::conflict(&format!("{}@{}", be_name, new_snapshot));
+                }
+                err
+            })
+    }
+
+    fn diff(
+        &self,
+        from: &Label,
+        to: Option<&Label>,
+        paths: &[PathBuf],
+        root: Option<&Root>,
+    ) -> Result<Vec<FileChange>, Error> {
+        diff::validate_paths(paths)?;
+        let to = match to {
+            Some(label) => label.clone(),
+            None => self
+                .get_boot_environments(root)?
+                .into_iter()
+                .find(|be| be.active)
+                .map(|be| Label::Name(be.name))
+                .ok_or(Error::NoActiveBootEnvironment)?,
+        };
+
+        // Both have to be mounted to read their contents.
+        with_mounted(
+            self,
+            &[label_name(from), label_name(&to)],
+            root,
+            |mountpoints| {
+                self.diff_mounted(from, &to, &mountpoints[0], &mountpoints[1], paths, root)
+            },
+        )
+    }
+
+    fn export(
+        &self,
+        target: &Label,
+        base: Option<&str>,
+        fd: BorrowedFd<'_>,
+        root: Option<&Root>,
+    ) -> Result<String, Error> {
+        let be_name = label_name(target);
+        let be = self
+            .get_boot_environments(root)?
+            .into_iter()
+            .find(|be| be.name == be_name)
+            .ok_or_else(|| Error::not_found(be_name))?;
+        // These are read from the boot environment as it is now, which may
+        // differ from an older snapshot of it.
+        let (os_release, hostid) = with_mounted(self, &[be_name], root, |mountpoints| {
+            let os_release = ["etc/os-release", "usr/lib/os-release"]
+                .iter()
+                .find_map(|path| std::fs::read_to_string(mountpoints[0].join(path)).ok());
+            Ok((
+                os_release,
+                read_hostid(Some(&mountpoints[0])).ok().flatten(),
+            ))
+        })?;
+
+        let root = self.effective_root(root)?;
+        let be_path = root.append(be_name)?;
+        let (snapshot, datasets) = {
+            let lzh = LibHandle::get();
+            let dataset = Dataset::boot_environment(&lzh, be_name, &be_path)?;
+            if let Some(base) = base {
+                open_snapshot(&lzh, &root, be_name, base)?;
+            }
+            let snapshot = match target {
+                Label::Snapshot(_, snapshot) => {
+                    open_snapshot(&lzh, &root, be_name, snapshot)?;
+                    snapshot.clone()
+                }
+                Label::Name(_) => {
+                    let snapshot = generate_snapshot_name();
+                    Dataset::create_snapshot(&lzh, &be_path.snapshot(&snapshot)?, None)?;
+                    snapshot
+                }
+            };
+            (
+                snapshot.clone(),
+                exported_datasets(&lzh, &dataset, &be_path, &snapshot)?,
+            )
+        };
+
+        let header = ExportHeader {
+            name: be_name.to_string(),
+            snapshot: snapshot.clone(),
+            base: base.map(|s| s.to_string()),
+            description: be.description,
+            tags: be.tags,
+            os_release,
+            hostid,
+            datasets,
+        };
+        header.write_to(File::from(fd.try_clone_to_owned()?))?;
+        // The libzfs handle isn't needed (or locked) while sending, which can
+        // take a long time.
+        for exported in &header.datasets {
+            let path = exported_path(&be_path, exported)?;
+            let from = base.map(|base| path.snapshot(base)).transpose()?;
+            send(&path.snapshot(&snapshot)?, from.as_ref(), fd)?;
+        }
+        Ok(format!("{}@{}", be_name, snapshot))
+    }
+
+    fn import(&self, be_name: &str, fd: BorrowedFd<'_>, root: Option<&Root>) -> Result<(), Error> {
+        let header = ExportHeader::read_from(File::from(fd.try_clone_to_owned()?))?;
+        for (key, value) in &header.tags {
+            validate_tag(key, Some(value))?;
+        }
+        let root_path = self.effective_root(root)?;
+        let be_path = root_path.append(be_name)?;
+        let paths = header
+            .datasets
+            .iter()
+            .map(|exported| exported_path(&be_path, exported))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if let Some(base) = &header.base {
+            // Incremental streams update an existing boot environment, which
+            // has to have the snapshot they were sent from.
+            open_snapshot(&LibHandle::get(), &root_path, be_name, base)?;
+            for path in &paths {
+                receive(&path.snapshot(&header.snapshot)?, fd)?;
+            }
+            let lzh = LibHandle::get();
+            let dataset = Dataset::filesystem(&lzh, &be_path)?;
+            return set_imported_metadata(&lzh, &dataset, &header);
+        }
+
+        if Dataset::filesystem(&LibHandle::get(), &be_path).is_ok() {
+            return Err(Error::conflict(be_name));
+        }
+        let result = header
+            .datasets
+            .iter()
+            .zip(&paths)
+            .try_for_each(|(exported, path)| {
+                receive(&path.snapshot(&header.snapshot)?, fd)?;
+                // Like cloned boot environments (and their children), imported
+                // ones must not be mounted automatically.
+                let lzh = LibHandle::get();
+                let mut props = NvList::from(&[("canmount", "noauto")])?;
+                if exported.name.is_empty() {
+                    props.add_string("mountpoint", "/")?;
+                } else if let Some(mountpoint) = &exported.mountpoint {
+                    props.add_string("mountpoint", mountpoint)?;
+                }
+                Dataset::filesystem(&lzh, path)?.set_properties(&lzh, &props)
+            })
+            .and_then(|()| {
+                let lzh = LibHandle::get();
+                let dataset = Dataset::filesystem(&lzh, &be_path)?;
+                set_imported_metadata(&lzh, &dataset, &header)
+            });
+        if result.is_err() {
+            // Remove whatever was received rather than leave a partial boot
+            // environment behind.
+            _ = self.destroy(
+                &Label::Name(be_name.to_string()),
+                true,
+                true,
+                true,
+                false,
+                root,
+            );
+        }
+        result
+    }
+
+    fn replicate(
+        &self,
+        dest: &Root,
+        names: &[String],
+        root: Option<&Root>,
+    ) -> Result<Vec<Replication>, Error> {
+        let root_path = self.effective_root(root)?;
+        let dest_path = dest.to_dataset();
+        if dest_path == root_path {
+            return Err(Error::invalid_root(dest.as_str()));
+        }
+        let mut bes = self.get_boot_environments(root)?;
+        if let Some(name) = names
+            .iter()
+            .find(|name| !bes.iter().any(|be| be.name == **name))
+        {
+            return Err(Error::not_found(name));
+        }
+        if !names.is_empty() {
+            bes.retain(|be| names.contains(&be.name));
+        }
+        // Clones can be sent incrementally from their origin, but only once
+        // it has been replicated itself.
+        bes.sort_by_key(|be| be.created);
+
+        {
+            let lzh = LibHandle::get();
+            match Dataset::filesystem(&lzh, &dest_path) {
+                Ok(_) => {}
+                Err(Error::LibzfsError(LibzfsError {
+                    errno: ffi::EZFS_NOENT,
+                    ..
+                })) => {
+                    // Create it, as `beadm init` would.
+                    let props = NvList::from(&[("mountpoint", "none")])?;
+                    Dataset::create(&lzh, &dest_path, &props)?;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        bes.iter()
+            .map(|be| {
+                replicate_boot_environment(&root_path, &dest_path, be).map_err(|err| {
+                    Error::ReplicationFailed {
+                        name: be.name.clone(),
+                        reason: err.to_string(),
+                    }
+                })
+            })
+            .collect()
+    }
+
+    fn check(&self, be_name: &str, root: Option<&Root>) -> Result<Vec<HealthIssue>, Error> {
+        let pool_hostid = {
+            let root = self.effective_root(root)?;
+            let lzh = LibHandle::get();
+            Dataset::boot_environment(&lzh, be_name, &root.append(be_name)?)?;
+            Zpool::open(&lzh, &root.pool())?.get_hostid()
+        };
+        with_mounted(self, &[be_name], root, |mountpoints| {
+            Ok(health::check_mounted(&mountpoints[0], pool_hostid))
+        })
+    }
+
+    fn doctor(&self, fix: bool, root: Option<&Root>) -> Result<Vec<Finding>, Error> {
+        let root = match self.effective_root(root) {
+            Ok(root) => root,
+            // The layout may be exactly what is wrong, in which case the
+            // active root wasn't recognised. Check the one the root
+            // filesystem is in instead.
+            Err(Error::NoActiveBootEnvironment) => get_rootfs()?
+                .and_then(|rootfs| rootfs.parent())
+                .ok_or(Error::NoActiveBootEnvironment)?,
+            Err(err) => return Err(err),
+        };
+        let mut findings = doctor_layout(&root, fix)?;
+
+        // Only check for the active boot environment once the layout has
+        // been fixed (if it could be), so that it can be activated again.
+        let active = match get_active_boot_environment_root() {
+            Ok(active) if active.to_dataset() == root => get_rootfs()?,
+            _ => None,
+        };
+        findings.extend(doctor_bootfs(&root, active.as_ref(), fix)?);
+        findings.extend(doctor_shared(&root, active.as_ref(), fix)?);
+        findings.extend(doctor_home(&root.pool(), fix)?);
+        findings.extend(doctor::check_temp_mountpoints(
+            Path::new("/run/be"),
+            Path::new("/proc"),
+            fix,
+        ));
+        Ok(findings)
+    }
+
     fn activate(&self, be_name: &str, temporary: bool, root: Option<&Root>) -> Result<(), Error> {
         let root = self.effective_root(root)?;
         let dataset = root.append(be_name)?;
@@ -436,32 +952,184 @@ impl Client for LibZfsClient {
         let zpool = Zpool::open(&lzh, &root.pool())?;
 
         if !temporary {
-            // Unset any temporary activations *before* setting the new `bootfs`
-            // value. That way we don't end up in an inconsistent state if
-            // either operation fails.
+            // Unset any temporary activations (and trials) *before* setting the
+            // new `bootfs` value. That way we don't end up in an inconsistent
+            // state if either operation fails.
             zpool.clear_previous_bootfs(&lzh)?;
-        } else if zpool.get_previous_bootfs().is_none() {
-            // For temporary activation, copy the current `bootfs` into the
-            // `previous-bootfs` property before write the new `bootfs` value,
-            // but *only* if there isn't a value already.
-            let current_bootfs = zpool
-                .get_bootfs()
-                // TODO: We could potentially have a more useful error here.
-                .ok_or_else(|| Error::NoActiveBootEnvironment)?;
-            zpool.set_previous_bootfs(&lzh, &current_bootfs)?;
+            zpool.clear_trial(&lzh)?;
+        } else {
+            // A temporary activation of another boot environment supersedes
+            // any trial, which would otherwise be reported as failed.
+            if zpool.get_trial().is_some_and(|trial| trial != dataset) {
+                zpool.clear_trial(&lzh)?;
+            }
+            if zpool.get_previous_bootfs().is_none() {
+                // For temporary activation, copy the current `bootfs` into the
+                // `previous-bootfs` property before write the new `bootfs`
+                // value, but *only* if there isn't a value already.
+                let current_bootfs = zpool
+                    .get_bootfs()
+                    // TODO: We could potentially have a more useful error here.
+                    .ok_or_else(|| Error::NoActiveBootEnvironment)?;
+                zpool.set_previous_bootfs(&lzh, &current_bootfs)?;
+            }
         }
 
         zpool.set_bootfs(&lzh, &dataset)
     }
 
-    fn rollback(&self, be_name: &str, snapshot: &str, root: Option<&Root>) -> Result<(), Error> {
+    fn activate_trial(&self, be_name: &str, root: Option<&Root>) -> Result<(), Error> {
+        // A trial is a temporary activation that we remember, so that the
+        // boot can be confirmed once it has completed.
+        self.activate(be_name, true, root)?;
         let root = self.effective_root(root)?;
         let lzh = LibHandle::get();
-        let be_path = root.append(be_name)?;
+        let zpool = Zpool::open(&lzh, &root.pool())?;
+        zpool.set_trial(&lzh, &root.append(be_name)?)
+    }
+
+    fn cancel_trial(&self, root: Option<&Root>) -> Result<(), Error> {
+        let root = self.effective_root(root)?;
+        let lzh = LibHandle::get();
+        Zpool::open(&lzh, &root.pool())?.clear_trial(&lzh)
+    }
+
+    fn confirm(&self, root: Option<&Root>) -> Result<Option<String>, Error> {
+        let root = self.effective_root(root)?;
+        let lzh = LibHandle::get();
+        let zpool = Zpool::open(&lzh, &root.pool())?;
+        let trial = match zpool.get_trial() {
+            Some(trial) => trial,
+            None => return Ok(None), // Nothing to confirm.
+        };
+        let name = trial.basename();
+
+        if get_rootfs()?.as_ref() == Some(&trial) {
+            // We made it: activate the boot environment for good, in the same
+            // order as a permanent activation.
+            zpool.clear_previous_bootfs(&lzh)?;
+            zpool.set_bootfs(&lzh, &trial)?;
+            zpool.clear_trial(&lzh)?;
+            return Ok(Some(name));
+        }
+
+        // The temporary activation is cleared early in the trial boot, so if
+        // it is still there we haven't rebooted yet.
+        if zpool.get_previous_bootfs().is_some() && zpool.get_bootfs().as_ref() == Some(&trial) {
+            return Err(Error::TrialNotBooted { name });
+        }
+
+        // Otherwise the trial boot never completed and we have fallen back.
+        zpool.clear_trial(&lzh)?;
+        Err(Error::TrialFailed { name })
+    }
+
+    fn record_boot(&self, root: Option<&Root>) -> Result<Option<BootRecord>, Error> {
+        let root = self.effective_root(root)?;
+        let rootfs = match get_rootfs()? {
+            Some(rootfs) if rootfs.parent().as_ref() == Some(&root) => rootfs,
+            _ => return Ok(None), // Not booted into a boot environment here.
+        };
+        let lzh = LibHandle::get();
+        let dataset = Dataset::filesystem(&lzh, &rootfs)?;
+        let zpool = Zpool::open(&lzh, &root.pool())?;
+
+        // This needs to happen before the temporary activation is cleared.
+        let boot_once =
+            zpool.get_previous_bootfs().is_some() && zpool.get_bootfs().as_ref() == Some(&rootfs);
+        let record = BootRecord {
+            timestamp: Utc::now().timestamp(),
+            guid: dataset.get_guid(),
+            name: rootfs.basename(),
+            boot_once,
+        };
+        let mut history = zpool.get_boot_history();
+        history.push(record.clone());
+        zpool.set_boot_history(&lzh, &history)?;
+        Ok(Some(record))
+    }
+
+    fn get_boot_history(&self, root: Option<&Root>) -> Result<Vec<BootRecord>, Error> {
+        let root = self.effective_root(root)?;
+        let lzh = LibHandle::get();
+        let zpool = Zpool::open(&lzh, &root.pool())?;
+        Ok(zpool.get_boot_history())
+    }
+
+    fn rollback(
+        &self,
+        be_name: &str,
+        snapshot: &str,
+        destroy_newer: bool,
+        force: bool,
+        safety_snapshot: bool,
+        root: Option<&Root>,
+    ) -> Result<RolledBack, Error> {
+        let root_path = self.effective_root(root)?;
+        let be_path = root_path.append(be_name)?;
+        let newer: Vec<String> = {
+            let lzh = LibHandle::get();
+            Dataset::boot_environment(&lzh, be_name, &be_path)?;
+            open_snapshot(&lzh, &root_path, be_name, snapshot)?;
+            if !force && get_rootfs()?.as_ref() == Some(&be_path) {
+                return Err(Error::RollbackActive {
+                    name: be_name.to_string(),
+                    snapshot: snapshot.to_string(),
+                });
+            }
+            snapshots_by_age(&lzh, &be_path)?
+                .into_iter()
+                .map(|(name, _)| name)
+                .skip_while(|name| name != snapshot)
+                .skip(1)
+                .collect()
+        };
+        if !newer.is_empty() && !destroy_newer {
+            return Err(Error::NewerSnapshots {
+                name: be_name.to_string(),
+                snapshot: snapshot.to_string(),
+                newer: newer
+                    .iter()
+                    .map(|name| format!("{}@{}", be_name, name))
+                    .collect(),
+            });
+        }
+
+        check_destroyable(self, be_name, &newer, root)?;
+
+        let mut rolled_back = RolledBack::default();
+        for name in newer.iter().rev() {
+            let label = Label::Snapshot(be_name.to_string(), name.clone());
+            self.destroy(&label, false, false, false, false, root)?;
+            rolled_back.destroyed.push(label.to_string());
+        }
+
+        let lzh = LibHandle::get();
+        if safety_snapshot {
+            rolled_back.safety_snapshot = Some(rollback_keeping_current(
+                &lzh, &root_path, be_name, snapshot,
+            )?);
+            return Ok(rolled_back);
+        }
+
         let be_dataset = Dataset::filesystem(&lzh, &be_path)?;
-        let snap_path = be_path.snapshot(snapshot)?;
-        let snap_dataset = Dataset::snapshot(&lzh, &snap_path)?;
-        be_dataset.rollback_to(&lzh, &snap_dataset)
+        let snap_dataset = Dataset::snapshot(&lzh, &be_path.snapshot(snapshot)?)?;
+        be_dataset.rollback_to(&lzh, &snap_dataset)?;
+
+        // Roll back any child datasets that have a matching snapshot, too.
+        for child in descendant_filesystems(&lzh, &be_dataset)? {
+            let child_snapshot = match Dataset::snapshot(&lzh, &child.snapshot(snapshot)?) {
+                Ok(child_snapshot) => child_snapshot,
+                // The child was created after the snapshot was taken.
+                Err(Error::LibzfsError(LibzfsError {
+                    errno: ffi::EZFS_NOENT,
+                    ..
+                })) => continue,
+                Err(err) => return Err(err),
+            };
+            Dataset::filesystem(&lzh, &child)?.rollback_to(&lzh, &child_snapshot)?;
+        }
+        Ok(rolled_back)
     }
 
     fn get_boot_environments(&self, root: Option<&Root>) -> Result<Vec<BootEnvironment>, Error> {
@@ -472,6 +1140,7 @@ impl Client for LibZfsClient {
         let zpool = Zpool::open(&lzh, &root.pool())?;
         let bootfs = zpool.get_bootfs();
         let previous_bootfs = zpool.get_previous_bootfs();
+        let trial = zpool.get_trial();
         let mut bes = Vec::new();
         root_dataset.iter_children(&lzh, |dataset| {
             let path = match dataset.get_name() {
@@ -501,7 +1170,20 @@ impl Client for LibZfsClient {
                 active,
                 next_boot,
                 boot_once,
+                trial: trial.as_ref() == Some(&path),
+                pinned: dataset.get_user_property(PINNED_PROP, false).as_deref() == Some("on"),
+                tags: dataset.get_tags(),
                 space: dataset.get_used_space(),
+                usage: dataset.get_space_usage(),
+                origin: dataset.get_origin().map(|origin| {
+                    // Name the origin relative to the boot environment root,
+                    // as we do for snapshots, when it lives there.
+                    let origin = origin.to_string();
+                    match origin.strip_prefix(&format!("{}/", root.to_string())) {
+                        Some(relative) => relative.to_string(),
+                        None => origin,
+                    }
+                }),
                 created: dataset.get_creation_time(),
             });
             Ok(())
@@ -516,20 +1198,51 @@ impl Client for LibZfsClient {
         let dataset = Dataset::filesystem(&lzh, &be_path)?;
         let mut snapshots = Vec::new();
         dataset.iter_snapshots(&lzh, |snapshot| {
-            if let Some(path) = snapshot.get_name() {
-                snapshots.push(Snapshot {
-                    name: path.basename(),
-                    root: Root::from(root.clone()),
-                    description: snapshot.get_user_property(DESCRIPTION_PROP, false),
-                    space: snapshot.get_used_space(),
-                    created: snapshot.get_creation_time(),
-                });
-            }
+            snapshots.extend(snapshot_info(&root, snapshot));
             Ok(())
         })?;
         Ok(snapshots)
     }
 
+    fn get_orphaned_snapshots(&self, root: Option<&Root>) -> Result<Vec<Snapshot>, Error> {
+        let root = self.effective_root(root)?;
+        let lzh = LibHandle::get();
+        let root_dataset = Dataset::filesystem(&lzh, &root)?;
+        let mut orphans = Vec::new();
+        root_dataset.iter_children(&lzh, |dataset| {
+            dataset.iter_snapshots(&lzh, |snapshot| {
+                // Only consider snapshots taken by `beadm create`.
+                if snapshot
+                    .get_user_property(CREATED_FOR_PROP, false)
+                    .is_none()
+                {
+                    return Ok(());
+                }
+                let mut has_clones = false;
+                snapshot.iter_clones(&lzh, false, |_| {
+                    has_clones = true;
+                    Ok(())
+                })?;
+                orphans.extend(
+                    snapshot_info(&root, snapshot)
+                        .filter(|info| super::is_orphaned(info, has_clones)),
+                );
+                Ok(())
+            })
+        })?;
+        Ok(orphans)
+    }
+
+    fn get_datasets(&self, be_name: &str, root: Option<&Root>) -> Result<Vec<ChildDataset>, Error> {
+        let root = self.effective_root(root)?;
+        let be_path = root.append(be_name)?;
+        let lzh = LibHandle::get();
+        let dataset = Dataset::boot_environment(&lzh, be_name, &be_path)?;
+        let mut datasets = Vec::new();
+        collect_child_datasets(&lzh, &root, &dataset, &mut datasets)?;
+        Ok(datasets)
+    }
+
     fn snapshot(
         &self,
         source: Option<&Label>,
@@ -653,26 +1366,124 @@ impl Client for LibZfsClient {
     ) -> Result<(), Error> {
         let root = self.effective_root(root)?;
         let lzh = LibHandle::get();
-        let dataset = match target {
+        open_target(&lzh, &root, target)?.set_property(&lzh, DESCRIPTION_PROP, description)
+    }
+
+    fn pin(&self, target: &Label, root: Option<&Root>) -> Result<(), Error> {
+        let root = self.effective_root(root)?;
+        let lzh = LibHandle::get();
+        match target {
             Label::Snapshot(name, snapshot) => {
-                let dataset_path = root.append(name)?.snapshot(snapshot)?;
-                Dataset::snapshot(&lzh, &dataset_path).map_err(|err| {
-                    if let Error::LibzfsError(LibzfsError {
-                        errno: ffi::EZFS_NOENT,
-                        ..
-                    }) = err
-                    {
-                        return Error::not_found(&format!("{}", target));
-                    }
-                    err
-                })?
+                // Holds can only be placed once per tag.
+                if open_snapshot(&lzh, &root, name, snapshot)?.has_hold(PIN_HOLD_TAG) {
+                    return Ok(());
+                }
+                // Hold the matching snapshots of child datasets too, since
+                // they are destroyed along with this one.
+                Dataset::filesystem(&lzh, &root.append(name)?)?.hold(
+                    &lzh,
+                    snapshot,
+                    PIN_HOLD_TAG,
+                    true,
+                )
             }
             Label::Name(name) => {
-                let dataset_path = root.append(name)?;
-                Dataset::boot_environment(&lzh, name, &dataset_path)?
+                let path = root.append(name)?;
+                Dataset::boot_environment(&lzh, name, &path)?.set_property(&lzh, PINNED_PROP, "on")
             }
-        };
-        dataset.set_property(&lzh, DESCRIPTION_PROP, description)
+        }
+    }
+
+    fn unpin(&self, target: &Label, root: Option<&Root>) -> Result<(), Error> {
+        let root = self.effective_root(root)?;
+        let lzh = LibHandle::get();
+        match target {
+            Label::Snapshot(name, snapshot) => {
+                if !open_snapshot(&lzh, &root, name, snapshot)?.has_hold(PIN_HOLD_TAG) {
+                    return Ok(());
+                }
+                Dataset::filesystem(&lzh, &root.append(name)?)?.release(
+                    &lzh,
+                    snapshot,
+                    PIN_HOLD_TAG,
+                    true,
+                )
+            }
+            Label::Name(name) => {
+                let path = root.append(name)?;
+                Dataset::boot_environment(&lzh, name, &path)?.inherit_property(&lzh, PINNED_PROP)
+            }
+        }
+    }
+
+    fn tag(
+        &self,
+        target: &Label,
+        key: &str,
+        value: &str,
+        root: Option<&Root>,
+    ) -> Result<(), Error> {
+        validate_tag(key, Some(value))?;
+        let root = self.effective_root(root)?;
+        let lzh = LibHandle::get();
+        let prop_name = format!("{}{}", TAG_PROP_PREFIX, key);
+        open_target(&lzh, &root, target)?.set_property(&lzh, &prop_name, value)
+    }
+
+    fn untag(&self, target: &Label, key: &str, root: Option<&Root>) -> Result<(), Error> {
+        validate_tag(key, None)?;
+        let root = self.effective_root(root)?;
+        let lzh = LibHandle::get();
+        let prop_name = format!("{}{}", TAG_PROP_PREFIX, key);
+        let dataset = open_target(&lzh, &root, target)?;
+        if dataset.get_user_property(&prop_name, false).is_none() {
+            return Ok(());
+        }
+        dataset.inherit_property(&lzh, &prop_name)
+    }
+
+    fn get_zfs_properties(
+        &self,
+        target: &Label,
+        names: &[String],
+        root: Option<&Root>,
+    ) -> Result<Vec<Property>, Error> {
+        let root = self.effective_root(root)?;
+        let lzh = LibHandle::get();
+        let dataset = open_target(&lzh, &root, target)?;
+        if names.is_empty() {
+            return Ok(dataset.get_all_properties(false));
+        }
+        names
+            .iter()
+            .map(|name| dataset.get_property_by_name(name))
+            .collect()
+    }
+
+    fn set_zfs_properties(
+        &self,
+        be_name: &str,
+        properties: &[String],
+        root: Option<&Root>,
+    ) -> Result<(), Error> {
+        let root = self.effective_root(root)?;
+        let be_path = root.append(be_name)?;
+
+        let mut props = NvList::new()?;
+        for prop_string in properties {
+            let (name, value) = super::parse_property(prop_string)?;
+
+            // Protect critical properties that are essential for boot environments
+            if name == "canmount" || name == "mountpoint" {
+                return Err(Error::invalid_prop(name, value));
+            }
+            super::check_reserved_property(name)?;
+
+            props.add_string(name, value)?;
+        }
+
+        let lzh = LibHandle::get();
+        Dataset::boot_environment(&lzh, be_name, &be_path)?.set_properties(&lzh, &props)
     }
 
     fn active_root(&self) -> Option<&Root> {
@@ -680,6 +1491,949 @@ impl Client for LibZfsClient {
     }
 }
 
+/// Recursively collect the descendant filesystems of a dataset, naming them
+/// relative to the boot environment root.
+fn collect_child_datasets(
+    lzh: &LibHandle,
+    root: &DatasetName,
+    dataset: &Dataset,
+    datasets: &mut Vec<ChildDataset>,
+) -> Result<(), Error> {
+    let prefix = format!("{}/", root.to_string());
+    dataset.iter_children(lzh, |child| {
+        let path = match child.get_name() {
+            Some(name) if !name.is_snapshot() => name,
+            _ => return Ok(()), // Skip snapshots (and unnamed datasets).
+        };
+        let name = path.to_string();
+        datasets.push(ChildDataset {
+            name: name.strip_prefix(&prefix).unwrap_or(&name).to_string(),
+            root: Root::from(root.clone()),
+            mountpoint: child.get_mountpoint(),
+            canmount: child.get_canmount().unwrap_or_else(|| "-".to_string()),
+            space: child.get_used_space(),
+            created: child.get_creation_time(),
+        });
+        collect_child_datasets(lzh, root, child, datasets)
+    })
+}
+
+/// Collect the names of all descendant filesystems of a dataset, with parents
+/// ahead of their children.
+fn descendant_filesystems(lzh: &LibHandle, dataset: &Dataset) -> Result<Vec<DatasetName>, Error> {
+    let mut names = Vec::new();
+    dataset.iter_children(lzh, |child| {
+        let name = match child.get_name() {
+            Some(name) if !name.is_snapshot() => name,
+            _ => return Ok(()), // Skip snapshots (and unnamed datasets).
+        };
+        names.push(name);
+        names.extend(descendant_filesystems(lzh, child)?);
+        Ok(())
+    })?;
+    Ok(names)
+}
+
+/// Clone the descendants of `source` from their snapshots with the given name
+/// into the same relative positions beneath `target`, which must already
+/// exist.
+///
+/// Children without a matching snapshot (because they were created after it
+/// was taken) are skipped, along with their own descendants.
+fn clone_children(
+    lzh: &LibHandle,
+    source: &Dataset,
+    source_path: &DatasetName,
+    snapshot_name: &str,
+    target: &DatasetName,
+) -> Result<(), Error> {
+    let prefix = format!("{}/", source_path.to_string());
+    let mut skipped: Vec<String> = Vec::new();
+    for child in descendant_filesystems(lzh, source)? {
+        let child_name = child.to_string();
+        if skipped.iter().any(|s| child_name.starts_with(s)) {
+            continue;
+        }
+        let child_snapshot = match Dataset::snapshot(lzh, &child.snapshot(snapshot_name)?) {
+            Ok(child_snapshot) => child_snapshot,
+            Err(Error::LibzfsError(LibzfsError {
+                errno: ffi::EZFS_NOENT,
+                ..
+            })) => {
+                skipped.push(format!("{}/", child_name));
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+
+        // Like the boot environment itself, cloned children must not be
+        // mounted automatically, or they would collide with the active boot
+        // environment's children on import. Locally-set mountpoints are
+        // carried over; everything else is inherited from the new parent.
+        let source_child = Dataset::filesystem(lzh, &child)?;
+        let mut props = NvList::from(&[("canmount", "noauto")])?;
+        let local_mountpoint = source_child
+            .get_mountpoint_property()
+            .filter(|_| source_child.is_property_local(ffi::ZFS_PROP_MOUNTPOINT));
+        if let Some(mountpoint) = local_mountpoint {
+            props.add_string("mountpoint", &mountpoint)?;
+        }
+
+        let relative = child_name.strip_prefix(&prefix).unwrap_or(&child_name);
+        let child_target = DatasetName::new(&format!("{}/{}", target.to_string(), relative))?;
+        child_snapshot.clone(lzh, &child_target, Some(&props))?;
+    }
+    Ok(())
+}
+
+/// The datasets of a boot environment to export: the boot environment itself
+/// and any descendants that have the snapshot being exported, parents first.
+fn exported_datasets(
+    lzh: &LibHandle,
+    dataset: &Dataset,
+    be_path: &DatasetName,
+    snapshot: &str,
+) -> Result<Vec<ExportedDataset>, Error> {
+    let prefix = format!("{}/", be_path.to_string());
+    let mut datasets = vec![ExportedDataset::default()];
+    let mut skipped: Vec<String> = Vec::new();
+    for child in descendant_filesystems(lzh, dataset)? {
+        let child_name = child.to_string();
+        if skipped.iter().any(|s| child_name.starts_with(s)) {
+            continue;
+        }
+        // Children created since the snapshot have nothing to send.
+        if Dataset::snapshot(lzh, &child.snapshot(snapshot)?).is_err() {
+            skipped.push(format!("{}/", child_name));
+            continue;
+        }
+        let child_dataset = Dataset::filesystem(lzh, &child)?;
+        let mountpoint = child_dataset
+            .get_mountpoint_property()
+            .filter(|_| child_dataset.is_property_local(ffi::ZFS_PROP_MOUNTPOINT));
+        datasets.push(ExportedDataset {
+            name: child_name
+                .strip_prefix(&prefix)
+                .unwrap_or(&child_name)
+                .to_string(),
+            mountpoint,
+        });
+    }
+    Ok(datasets)
+}
+
+/// The full name of a dataset in an export stream, for a boot environment at
+/// `be_path`.
+fn exported_path(be_path: &DatasetName, exported: &ExportedDataset) -> Result<DatasetName, Error> {
+    if exported.name.is_empty() {
+        return Ok(be_path.clone());
+    }
+    DatasetName::new(&format!("{}/{}", be_path.to_string(), exported.name))
+}
+
+/// Carry the description and tags of an exported boot environment over to
+/// an imported one.
+fn set_imported_metadata(
+    lzh: &LibHandle,
+    dataset: &Dataset,
+    header: &ExportHeader,
+) -> Result<(), Error> {
+    let mut props = NvList::new()?;
+    if let Some(description) = header.imported_description() {
+        props.add_string(DESCRIPTION_PROP, &description)?;
+    }
+    for (key, value) in &header.tags {
+        props.add_string(&format!("{}{}", TAG_PROP_PREFIX, key), value)?;
+    }
+    dataset.set_properties(lzh, &props)
+}
+
+/// Write a send stream for a snapshot to `fd`, incremental from `from` if
+/// given. Like `zfs send -Le`, blocks are sent as they are stored.
+fn send(
+    snapshot: &DatasetName,
+    from: Option<&DatasetName>,
+    fd: BorrowedFd<'_>,
+) -> Result<(), Error> {
+    let result = unsafe {
+        ffi::lzc_send(
+            snapshot.as_ptr(),
+            from.map_or(ptr::null(), |from| from.as_ptr()),
+            fd.as_raw_fd(),
+            ffi::LZC_SEND_FLAG_EMBED_DATA | ffi::LZC_SEND_FLAG_LARGE_BLOCK,
+        )
+    };
+    if result != 0 {
+        return Err(std::io::Error::from_raw_os_error(result).into());
+    }
+    Ok(())
+}
+
+/// Receive a single send stream from `fd` as a snapshot, creating its
+/// filesystem for full streams.
+fn receive(snapshot: &DatasetName, fd: BorrowedFd<'_>) -> Result<(), Error> {
+    let result = unsafe {
+        ffi::lzc_receive(
+            snapshot.as_ptr(),
+            ptr::null_mut(),
+            ptr::null(),
+            0, // force = false (boolean_t)
+            0, // raw = false (boolean_t)
+            fd.as_raw_fd(),
+        )
+    };
+    if result != 0 {
+        return Err(std::io::Error::from_raw_os_error(result).into());
+    }
+    Ok(())
+}
+
+/// Roll a boot environment back to a snapshot without losing its current
+/// state. A rollback in place would have to destroy a snapshot of the current
+/// state, so instead the boot environment is snapshotted and renamed out of
+/// the way, then recreated as a clone of the snapshot. Promoting the clone
+/// hands the older snapshots back to it, leaving the previous state as a
+/// boot environment of its own that depends on the new one. Returns the
+/// safety snapshot.
+fn rollback_keeping_current(
+    lzh: &LibHandle,
+    root: &DatasetName,
+    be_name: &str,
+    snapshot: &str,
+) -> Result<String, Error> {
+    let be_path = root.append(be_name)?;
+    let kept_name = format!(
+        "{}-pre-rollback-{}",
+        be_name,
+        Utc::now().format("%Y%m%d-%H%M%S")
+    );
+    let kept_path = root.append(&kept_name)?;
+    let safety_name = generate_snapshot_name();
+    let props = NvList::from(&[(
+        DESCRIPTION_PROP,
+        format!("Before rollback to '{}'", snapshot).as_str(),
+    )])?;
+    Dataset::create_snapshot(lzh, &be_path.snapshot(&safety_name)?, Some(&props))?;
+
+    let flags = ffi::RenameFlags {
+        recursive: 0,
+        nounmount: 1, // Leave boot environment mounts in place.
+        forceunmount: 0,
+    };
+    Dataset::filesystem(lzh, &be_path)?
+        .rename(lzh, &kept_path, flags)
+        .map_err(|err| match err {
+            Error::LibzfsError(LibzfsError {
+                errno: ffi::EZFS_EEXIST,
+                ..
+            }) => Error::conflict(&kept_name),
+            err => err,
+        })?;
+    let kept = Dataset::filesystem(lzh, &kept_path)?;
+
+    // Locally set properties aren't cloned, so carry them all over (including
+    // the description, tags and pin) to keep the boot environment as it was.
+    let mut clone_props = NvList::from(&[("canmount", "noauto"), ("mountpoint", "/")])?;
+    for prop in clone_properties(kept.get_local_properties(), is_fixed_property) {
+        clone_props.add_string(&prop.name, &prop.value)?;
+    }
+    let cloned = Dataset::snapshot(lzh, &kept_path.snapshot(snapshot)?)
+        .and_then(|target| target.clone(lzh, &be_path, Some(&clone_props)))
+        .and_then(|_| clone_children(lzh, &kept, &kept_path, snapshot, &be_path));
+    if let Err(err) = cloned {
+        // Put things back the way they were.
+        if let Ok(dataset) = Dataset::filesystem(lzh, &be_path) {
+            _ = destroy_children(lzh, &dataset).and_then(|_| dataset.destroy(lzh));
+        }
+        _ = kept.rename(lzh, &be_path, flags);
+        return Err(err);
+    }
+
+    let dataset = Dataset::filesystem(lzh, &be_path)?;
+    dataset.promote(lzh)?;
+    for child in descendant_filesystems(lzh, &dataset)? {
+        Dataset::filesystem(lzh, &child)?.promote(lzh)?;
+    }
+    kept.set_property(
+        lzh,
+        DESCRIPTION_PROP,
+        &format!("'{}' before rollback to '{}'", be_name, snapshot),
+    )?;
+    kept.inherit_property(lzh, PINNED_PROP)?;
+
+    // The pool's bootfs follows the dataset it was set to through renames,
+    // so point it back at the boot environment if need be.
+    let zpool = Zpool::open(lzh, &root.pool())?;
+    if zpool.get_bootfs().as_ref() == Some(&kept_path) {
+        zpool.set_bootfs(lzh, &be_path)?;
+    }
+    Ok(format!("{}@{}", kept_name, safety_name))
+}
+
+/// Properties that can only be set on encryption roots, which clones never
+/// are.
+const ENCRYPTION_ROOT_PROPS: &[&str] = &["keylocation"];
+
+/// Of the properties set locally on a boot environment, those to set again
+/// when recreating it as a clone in [`rollback_keeping_current`]. That leaves
+/// out `canmount` and `mountpoint`, which it sets itself, properties that
+/// clones can't have set, and those which are `fixed`: read-only or only
+/// settable at creation, and so inherited from the origin.
+fn clone_properties(local: Vec<Property>, fixed: impl Fn(&str) -> bool) -> Vec<Property> {
+    local
+        .into_iter()
+        .filter(|prop| {
+            !matches!(prop.name.as_str(), "canmount" | "mountpoint")
+                && !ENCRYPTION_ROOT_PROPS.contains(&prop.name.as_str())
+                && !fixed(&prop.name)
+        })
+        .collect()
+}
+
+/// Whether a native property is read-only or can only be set at creation.
+fn is_fixed_property(name: &str) -> bool {
+    let Ok(name) = CString::new(name) else {
+        return false;
+    };
+    let prop = unsafe { ffi::zfs_name_to_prop(name.as_ptr()) };
+    prop != ffi::ZPROP_INVAL
+        && unsafe { ffi::zfs_prop_readonly(prop) != 0 || ffi::zfs_prop_setonce(prop) != 0 }
+}
+
+/// Replicate a boot environment, its children and their snapshots from one
+/// boot environment root to another, snapshotting it first if it has changed
+/// since its latest snapshot. Only snapshots that the destination doesn't
+/// have yet are sent, and interrupted transfers are resumed.
+fn replicate_boot_environment(
+    src_root: &DatasetName,
+    dst_root: &DatasetName,
+    be: &BootEnvironment,
+) -> Result<Replication, Error> {
+    let src = src_root.append(&be.name)?;
+    let dst = dst_root.append(&be.name)?;
+    let mut replication = Replication {
+        name: be.name.clone(),
+        ..Replication::default()
+    };
+
+    let (datasets, recorded) = {
+        let lzh = LibHandle::get();
+        let dataset = Dataset::filesystem(&lzh, &src)?;
+        let recorded = dataset.get_user_property(REPLICATED_PROP, false);
+        let mut datasets = vec![src.clone()];
+        datasets.extend(descendant_filesystems(&lzh, &dataset)?);
+        let changed = snapshots_by_age(&lzh, &src)?.is_empty()
+            || datasets.iter().any(|name| {
+                Dataset::filesystem(&lzh, name)
+                    .ok()
+                    .and_then(|dataset| dataset.get_numeric_property(ffi::ZFS_PROP_WRITTEN))
+                    .unwrap_or(0)
+                    > 0
+            });
+        if changed {
+            Dataset::create_snapshot(&lzh, &src.snapshot(&generate_snapshot_name())?, None)?;
+        }
+        (datasets, recorded)
+    };
+    let replica = dst.to_string();
+    let replicated = recorded
+        .as_deref()
+        .and_then(|recorded| recorded_replication(recorded, &replica));
+
+    let prefix = src.to_string();
+    for src_dataset in &datasets {
+        let relative = &src_dataset.to_string()[prefix.len()..];
+        let dst_dataset = DatasetName::new(&format!("{}{}", dst.to_string(), relative))?;
+        if resume_transfer(src_dataset, &dst_dataset)? {
+            replication.resumed = true;
+        }
+
+        let (snapshots, common, origin) = {
+            let lzh = LibHandle::get();
+            let snapshots = snapshots_by_age(&lzh, src_dataset)?;
+            let dst_snapshots = snapshots_by_age(&lzh, &dst_dataset)?;
+            // Prefer the snapshot recorded as last replicated here, provided
+            // the destination still has it, over the latest one both sides
+            // have by name and GUID.
+            let common = replicated
+                .and_then(|name| snapshots.iter().position(|(snapshot, _)| snapshot == name))
+                .filter(|&i| dst_snapshots.contains(&snapshots[i]))
+                .or_else(|| {
+                    snapshots
+                        .iter()
+                        .rposition(|snapshot| dst_snapshots.contains(snapshot))
+                });
+            if common.is_none() && Dataset::filesystem(&lzh, &dst_dataset).is_ok() {
+                return Err(Error::conflict(&dst_dataset.to_string()));
+            }
+            let origin = Dataset::filesystem(&lzh, src_dataset)?
+                .get_origin()
+                .and_then(|origin| replicated_origin(&lzh, src_root, dst_root, &origin));
+            (snapshots, common, origin)
+        };
+
+        // Send everything since the latest snapshot both sides have or, for
+        // new datasets, from the replicated origin (if a clone) or in full.
+        let (mut from, mut origin) = match common {
+            Some(i) => (Some(src_dataset.snapshot(&snapshots[i].0)?), None),
+            None => match origin {
+                Some((src_origin, dst_origin)) => (Some(src_origin), Some(dst_origin)),
+                None => (None, None),
+            },
+        };
+        // Only the first receive creates the dataset.
+        let mut props = match common {
+            Some(_) => None,
+            None => Some(replicated_props(src_dataset, relative)?),
+        };
+        let start = common.map_or(0, |i| i + 1);
+        for (snapshot, _) in &snapshots[start..] {
+            let to = src_dataset.snapshot(snapshot)?;
+            transfer(
+                |fd| send(&to, from.as_ref(), fd),
+                &dst_dataset.snapshot(snapshot)?,
+                origin.take().as_ref(),
+                props.take().as_ref(),
+            )?;
+            if relative.is_empty() {
+                replication.snapshots.push(snapshot.clone());
+            }
+            from = Some(to);
+        }
+    }
+
+    // Carry over beadm's own metadata, which isn't part of the send streams,
+    // and record how far replication got.
+    let lzh = LibHandle::get();
+    let mut props = NvList::new()?;
+    if let Some(description) = &be.description {
+        props.add_string(DESCRIPTION_PROP, description)?;
+    }
+    for (key, value) in &be.tags {
+        props.add_string(&format!("{}{}", TAG_PROP_PREFIX, key), value)?;
+    }
+    Dataset::filesystem(&lzh, &dst)?.set_properties(&lzh, &props)?;
+    for snapshot in &replication.snapshots {
+        let source = Dataset::snapshot(&lzh, &src.snapshot(snapshot)?)?;
+        let mut props = NvList::new()?;
+        if let Some(description) = source.get_user_property(DESCRIPTION_PROP, false) {
+            props.add_string(DESCRIPTION_PROP, &description)?;
+        }
+        for (key, value) in source.get_tags() {
+            props.add_string(&format!("{}{}", TAG_PROP_PREFIX, key), &value)?;
+        }
+        Dataset::snapshot(&lzh, &dst.snapshot(snapshot)?)?.set_properties(&lzh, &props)?;
+    }
+    if let Some((latest, _)) = snapshots_by_age(&lzh, &src)?.last() {
+        let recorded = record_replication(recorded.as_deref(), &replica, latest);
+        Dataset::filesystem(&lzh, &src)?.set_property(&lzh, REPLICATED_PROP, &recorded)?;
+    }
+    Ok(replication)
+}
+
+/// The properties to receive a new replicated dataset with. Like cloned boot
+/// environments, replicated ones must not be mounted automatically, and need
+/// a mountpoint of `/` to be activated from the destination root.
+fn replicated_props(src: &DatasetName, relative: &str) -> Result<NvList, Error> {
+    let mut props = NvList::from(&[("canmount", "noauto")])?;
+    if relative.is_empty() {
+        props.add_string("mountpoint", "/")?;
+        return Ok(props);
+    }
+    let lzh = LibHandle::get();
+    let dataset = Dataset::filesystem(&lzh, src)?;
+    if let Some(mountpoint) = dataset
+        .get_mountpoint_property()
+        .filter(|_| dataset.is_property_local(ffi::ZFS_PROP_MOUNTPOINT))
+    {
+        props.add_string("mountpoint", &mountpoint)?;
+    }
+    Ok(props)
+}
+
+/// The snapshots of a dataset, by their short names and with their GUIDs,
+/// oldest first. A dataset that doesn't exist has none.
+fn snapshots_by_age(lzh: &LibHandle, name: &DatasetName) -> Result<Vec<(String, u64)>, Error> {
+    let dataset = match Dataset::filesystem(lzh, name) {
+        Ok(dataset) => dataset,
+        Err(Error::LibzfsError(LibzfsError {
+            errno: ffi::EZFS_NOENT,
+            ..
+        })) => return Ok(vec![]),
+        Err(err) => return Err(err),
+    };
+    let mut snapshots = Vec::new();
+    dataset.iter_snapshots(lzh, |snapshot| {
+        if let Some(name) = snapshot.get_name()
+            && let Some((_, short)) = name.to_string().split_once('@')
+        {
+            let txg = snapshot
+                .get_numeric_property(ffi::ZFS_PROP_CREATETXG)
+                .unwrap_or(0);
+            snapshots.push((txg, short.to_string(), snapshot.get_guid()));
+        }
+        Ok(())
+    })?;
+    snapshots.sort();
+    Ok(snapshots
+        .into_iter()
+        .map(|(_, name, guid)| (name, guid))
+        .collect())
+}
+
+/// For a clone's origin snapshot in the source root, the same snapshot in
+/// the destination root, if it has been replicated there.
+fn replicated_origin(
+    lzh: &LibHandle,
+    src_root: &DatasetName,
+    dst_root: &DatasetName,
+    origin: &DatasetName,
+) -> Option<(DatasetName, DatasetName)> {
+    let relative = origin
+        .to_string()
+        .strip_prefix(&format!("{}/", src_root.to_string()))?
+        .to_string();
+    let dst_origin = DatasetName::new(&format!("{}/{}", dst_root.to_string(), relative)).ok()?;
+    let guid = Dataset::snapshot(lzh, origin).ok()?.get_guid();
+    let dst_guid = Dataset::snapshot(lzh, &dst_origin).ok()?.get_guid();
+    (guid == dst_guid).then(|| (origin.clone(), dst_origin))
+}
+
+/// Finish an interrupted transfer from `src` to `dst`, if there is one.
+/// Returns whether there was.
+fn resume_transfer(src: &DatasetName, dst: &DatasetName) -> Result<bool, Error> {
+    let (token, from) = {
+        let lzh = LibHandle::get();
+        let Ok(dataset) = Dataset::filesystem(&lzh, dst) else {
+            return Ok(false);
+        };
+        let Some(token) = dataset
+            .get_property(ffi::ZFS_PROP_RECEIVE_RESUME_TOKEN)
+            .filter(|token| !token.is_empty() && token != "-")
+        else {
+            return Ok(false);
+        };
+        let token = ResumeToken::parse(&lzh, &token)?;
+        if token
+            .toname
+            .to_string()
+            .split_once('@')
+            .map(|(name, _)| name)
+            != Some(src.to_string().as_str())
+        {
+            return Err(Error::conflict(&dst.to_string()));
+        }
+        let from = match token.fromguid {
+            Some(guid) => {
+                let snapshots = snapshots_by_age(&lzh, src)?;
+                let (name, _) = snapshots
+                    .iter()
+                    .find(|(_, g)| *g == guid)
+                    .ok_or_else(|| Error::not_found(&token.toname.to_string()))?;
+                Some(src.snapshot(name)?)
+            }
+            None => None,
+        };
+        (token, from)
+    };
+    let snapshot = token.toname.basename();
+    let short = snapshot
+        .split_once('@')
+        .map_or(snapshot.as_str(), |(_, s)| s);
+    transfer(
+        |fd| send_resume(&token, from.as_ref(), fd),
+        &dst.snapshot(short)?,
+        None,
+        None,
+    )?;
+    Ok(true)
+}
+
+/// Where an interrupted receive left off, from its `receive_resume_token`.
+struct ResumeToken {
+    /// The snapshot that was being sent.
+    toname: DatasetName,
+    /// For incremental sends, the GUID of the snapshot it was sent from.
+    fromguid: Option<u64>,
+    object: u64,
+    offset: u64,
+}
+
+impl ResumeToken {
+    fn parse(lzh: &LibHandle, token: &str) -> Result<Self, Error> {
+        let invalid = || Error::invalid_prop("receive_resume_token", token);
+        let token_cstr = CString::new(token).map_err(|_| invalid())?;
+        let nvl =
+            unsafe { ffi::zfs_send_resume_token_to_nvlist(lzh.as_ptr(), token_cstr.as_ptr()) };
+        let nvl = NvList::from_raw(nvl).ok_or_else(|| Error::from(lzh.libzfs_error()))?;
+        Ok(ResumeToken {
+            toname: DatasetName::new(&nvl.lookup_string("toname").ok_or_else(invalid)?)?,
+            fromguid: nvl.lookup_uint64("fromguid"),
+            object: nvl.lookup_uint64("object").ok_or_else(invalid)?,
+            offset: nvl.lookup_uint64("offset").ok_or_else(invalid)?,
+        })
+    }
+}
+
+/// Pipe a send stream into a (resumable) receive of `target`, as a clone of
+/// `origin` if given, with `props` set as received properties.
+fn transfer<F>(
+    send: F,
+    target: &DatasetName,
+    origin: Option<&DatasetName>,
+    props: Option<&NvList>,
+) -> Result<(), Error>
+where
+    F: FnOnce(BorrowedFd<'_>) -> Result<(), Error> + Send,
+{
+    let (reader, writer) = std::io::pipe()?;
+    std::thread::scope(|scope| {
+        let sender = scope.spawn(move || send(writer.as_fd()));
+        let received = receive_resumable(target, origin, props, reader.as_fd());
+        // Don't leave the sender blocked if the receive failed early.
+        drop(reader);
+        let sent = sender.join().expect("send thread panicked");
+        sent.and(received)
+    })
+}
+
+/// Resume an interrupted send to `fd`.
+fn send_resume(
+    token: &ResumeToken,
+    from: Option<&DatasetName>,
+    fd: BorrowedFd<'_>,
+) -> Result<(), Error> {
+    let result = unsafe {
+        ffi::lzc_send_resume(
+            token.toname.as_ptr(),
+            from.map_or(ptr::null(), |from| from.as_ptr()),
+            fd.as_raw_fd(),
+            ffi::LZC_SEND_FLAG_EMBED_DATA | ffi::LZC_SEND_FLAG_LARGE_BLOCK,
+            token.object,
+            token.offset,
+        )
+    };
+    if result != 0 {
+        return Err(std::io::Error::from_raw_os_error(result).into());
+    }
+    Ok(())
+}
+
+/// Receive a single send stream from `fd` as a snapshot, saving partial
+/// state so that an interrupted receive can be resumed.
+fn receive_resumable(
+    snapshot: &DatasetName,
+    origin: Option<&DatasetName>,
+    props: Option<&NvList>,
+    fd: BorrowedFd<'_>,
+) -> Result<(), Error> {
+    let result = unsafe {
+        ffi::lzc_receive_resumable(
+            snapshot.as_ptr(),
+            props.map_or(ptr::null_mut(), |props| props.as_nvlist_ptr()),
+            origin.map_or(ptr::null(), |origin| origin.as_ptr()),
+            0, // force = false (boolean_t)
+            0, // raw = false (boolean_t)
+            fd.as_raw_fd(),
+        )
+    };
+    if result != 0 {
+        return Err(std::io::Error::from_raw_os_error(result).into());
+    }
+    Ok(())
+}
+
+/// Destroy the descendants of a dataset, children before their parents.
+fn destroy_children(lzh: &LibHandle, dataset: &Dataset) -> Result<(), Error> {
+    for child in descendant_filesystems(lzh, dataset)?.iter().rev() {
+        Dataset::filesystem(lzh, child)?.destroy(lzh)?;
+    }
+    Ok(())
+}
+
+/// Mount the descendants of a boot environment (which must already be mounted
+/// at `mountpoint`) beneath it with the same options, with parents ahead of
+/// their children.
+fn mount_children(
+    lzh: &LibHandle,
+    dataset: &Dataset,
+    mountpoint: &Path,
+    options: &MountOptions,
+) -> Result<(), Error> {
+    for child in descendant_filesystems(lzh, dataset)? {
+        let child = Dataset::filesystem(lzh, &child)?;
+        if child.get_canmount().as_deref() == Some("off") || child.get_mountpoint().is_some() {
+            continue;
+        }
+        let target = match child
+            .get_mountpoint_property()
+            .and_then(|prop| child_mountpoint(mountpoint, &prop))
+        {
+            Some(target) => target,
+            None => continue, // Legacy or unmountable dataset.
+        };
+        child.mount_at(lzh, target.as_os_str(), options)?;
+    }
+    Ok(())
+}
+
+/// Unmount any mounted descendants of a boot environment, children before
+/// their parents.
+fn unmount_children(lzh: &LibHandle, dataset: &Dataset, force: bool) -> Result<(), Error> {
+    let mountpoint = match dataset.get_mountpoint() {
+        // Never touch the children of the running system; unmounting the root
+        // filesystem itself will fail anyway.
+        Some(mountpoint) if mountpoint != Path::new("/") => mountpoint,
+        _ => return Ok(()),
+    };
+    for child in descendant_filesystems(lzh, dataset)?.iter().rev() {
+        let child = Dataset::filesystem(lzh, child)?;
+        match child.get_mountpoint() {
+            Some(existing) if existing.starts_with(&mountpoint) => child.unmount(lzh, force)?,
+            _ => continue,
+        }
+    }
+    Ok(())
+}
+
+/// Determine where a child dataset with the given `mountpoint` property should
+/// be mounted when its boot environment is mounted at `be_mountpoint`.
+///
+/// Returns `None` for datasets that are not mounted by path.
+fn child_mountpoint(be_mountpoint: &Path, property: &str) -> Option<PathBuf> {
+    let relative = Path::new(property).strip_prefix("/").ok()?;
+    if relative.as_os_str().is_empty() {
+        // Mounting on top of the boot environment would hide it.
+        return None;
+    }
+    Some(be_mountpoint.join(relative))
+}
+
+/// Describe a boot environment snapshot.
+fn snapshot_info(root: &DatasetName, snapshot: &Dataset) -> Option<Snapshot> {
+    let path = snapshot.get_name()?;
+    Some(Snapshot {
+        name: path.basename(),
+        root: Root::from(root.clone()),
+        description: snapshot.get_user_property(DESCRIPTION_PROP, false),
+        space: snapshot.get_used_space(),
+        referenced: snapshot
+            .get_numeric_property(ffi::ZFS_PROP_REFERENCED)
+            .unwrap_or(0),
+        written: snapshot
+            .get_numeric_property(ffi::ZFS_PROP_WRITTEN)
+            .unwrap_or(0),
+        created: snapshot.get_creation_time(),
+        pinned: snapshot.has_hold(PIN_HOLD_TAG),
+        tags: snapshot.get_tags(),
+    })
+}
+
+/// Open a boot environment or snapshot.
+fn open_target(lzh: &LibHandle, root: &DatasetName, target: &Label) -> Result<Dataset, Error> {
+    match target {
+        Label::Snapshot(name, snapshot) => open_snapshot(lzh, root, name, snapshot),
+        Label::Name(name) => Dataset::boot_environment(lzh, name, &root.append(name)?),
+    }
+}
+
+/// Open a boot environment snapshot, reporting a missing one as not found.
+fn open_snapshot(
+    lzh: &LibHandle,
+    root: &DatasetName,
+    name: &str,
+    snapshot: &str,
+) -> Result<Dataset, Error> {
+    let path = root.append(name)?.snapshot(snapshot)?;
+    Dataset::snapshot(lzh, &path).map_err(|err| {
+        if let Error::LibzfsError(LibzfsError {
+            errno: ffi::EZFS_NOENT,
+            ..
+        }) = err
+        {
+            return Error::not_found(&format!("{}@{}", name, snapshot));
+        }
+        err
+    })
+}
+
+fn label_name(label: &Label) -> &str {
+    match label {
+        Label::Name(name) | Label::Snapshot(name, _) => name,
+    }
+}
+
+/// Compare two boot environments (or snapshots) with `zfs diff`, which needs
+/// `from` to be a snapshot that `to` descends from; returns None otherwise.
+/// Each child dataset is compared with its counterpart, and paths are made
+/// relative to `mountpoint`, where `to` is mounted.
+fn zfs_diff(
+    lzh: &LibHandle,
+    root: &DatasetName,
+    from: &Label,
+    to: &Label,
+    children: &[(String, PathBuf)],
+    mountpoint: &Path,
+) -> Result<Option<Vec<FileChange>>, Error> {
+    let Label::Snapshot(from_name, from_snapshot) = from else {
+        return Ok(None);
+    };
+    let (to_name, to_snapshot) = match to {
+        Label::Name(name) => (name, None),
+        Label::Snapshot(name, snapshot) => (name, Some(snapshot)),
+    };
+
+    let from_fs = root.append(from_name)?;
+    let to_fs = root.append(to_name)?;
+    let mut pairs = vec![(from_fs.clone(), to_fs.clone())];
+    for (child, _) in children {
+        pairs.push((
+            DatasetName::new(&format!("{}/{}", from_fs.to_string(), child))?,
+            DatasetName::new(&format!("{}/{}", to_fs.to_string(), child))?,
+        ));
+    }
+
+    let mut changes = vec![];
+    for (from_fs, to_fs) in pairs {
+        let from_path = from_fs.snapshot(from_snapshot)?;
+        let to_path = match to_snapshot {
+            Some(snapshot) => to_fs.snapshot(snapshot)?,
+            None => to_fs.clone(),
+        };
+        // This also covers child datasets created since the snapshot.
+        if !descends_from(lzh, &from_path, &to_path)? {
+            return Ok(None);
+        }
+        let output = Dataset::filesystem(lzh, &to_fs)?.show_diffs(lzh, &from_path, &to_path)?;
+        changes.extend(diff::parse_zfs_diff(&output, mountpoint));
+    }
+    Ok(Some(changes))
+}
+
+/// Whether a filesystem or snapshot descends from a snapshot, i.e. it is a
+/// later snapshot of the same filesystem, the filesystem itself, or (through
+/// clones) originated from one of those.
+fn descends_from(
+    lzh: &LibHandle,
+    snapshot: &DatasetName,
+    target: &DatasetName,
+) -> Result<bool, Error> {
+    let createtxg = |name: &DatasetName| {
+        Dataset::snapshot(lzh, name)
+            .ok()
+            .and_then(|snapshot| snapshot.get_numeric_property(ffi::ZFS_PROP_CREATETXG))
+    };
+    let Some(txg) = createtxg(snapshot) else {
+        return Ok(false);
+    };
+    let snapshot = snapshot.to_string();
+    let snapshot_fs = snapshot.split_once('@').map_or("", |(fs, _)| fs);
+
+    let target = target.to_string();
+    let (mut fs, mut target_txg) = match target.split_once('@') {
+        Some((fs, _)) => (fs.to_string(), createtxg(&DatasetName::new(&target)?)),
+        None => (target.clone(), Some(u64::MAX)),
+    };
+    loop {
+        if fs == snapshot_fs {
+            return Ok(target_txg.is_some_and(|target_txg| txg < target_txg));
+        }
+        // Follow clones back to their origins.
+        let Some(origin) = Dataset::filesystem(lzh, &DatasetName::new(&fs)?)?.get_origin() else {
+            return Ok(false);
+        };
+        target_txg = createtxg(&origin);
+        fs = origin
+            .to_string()
+            .split_once('@')
+            .map_or(String::new(), |(fs, _)| fs.to_string());
+    }
+}
+
+/// The directories to compare for a mounted boot environment (or a snapshot
+/// of one) and its child datasets.
+fn diff_tree(
+    label: &Label,
+    mountpoint: &Path,
+    children: &[(String, PathBuf)],
+) -> Result<diff::Tree, Error> {
+    let dir = |mountpoint: &Path| match label {
+        Label::Name(_) => mountpoint.to_path_buf(),
+        Label::Snapshot(_, snapshot) => mountpoint.join(".zfs/snapshot").join(snapshot),
+    };
+    let mut roots = BTreeMap::from([(PathBuf::from("/"), dir(mountpoint))]);
+    for (_, child_mountpoint) in children {
+        if let Ok(relative) = child_mountpoint.strip_prefix(mountpoint) {
+            roots.insert(Path::new("/").join(relative), dir(child_mountpoint));
+        }
+    }
+    diff::Tree::new(roots)
+}
+
+/// Add the removal of a dataset's snapshots to `plan`. If any of them have
+/// clones, the oldest clone is promoted, which hands over every snapshot up to
+/// and including its origin; the remaining snapshots are destroyed.
+fn plan_snapshot_removal(
+    lzh: &LibHandle,
+    dataset: &Dataset,
+    plan: &mut DestroyPlan,
+) -> Result<(), Error> {
+    let mut snapshots: Vec<(DatasetName, u64, u64)> = vec![];
+    let mut oldest_clone: Option<(DatasetName, i64)> = None;
+    dataset.iter_snapshots(lzh, |snapshot| {
+        find_oldest_clone(lzh, snapshot, &mut oldest_clone)?;
+        if let Some(name) = snapshot.get_name() {
+            let txg = snapshot
+                .get_numeric_property(ffi::ZFS_PROP_CREATETXG)
+                .unwrap_or(0);
+            snapshots.push((name, txg, snapshot.get_used_space()));
+        }
+        Ok(())
+    })?;
+
+    let mut promoted_txg = 0;
+    if let Some((name, _)) = oldest_clone {
+        let clone = Dataset::filesystem(lzh, &name)?;
+        if let Some(origin) = clone.get_origin() {
+            promoted_txg = snapshots
+                .iter()
+                .find(|(snapshot, _, _)| *snapshot == origin)
+                .map_or(0, |(_, txg, _)| *txg);
+        }
+        plan.promote.push(name.to_string());
+    }
+
+    for (name, txg, space) in snapshots {
+        if txg > promoted_txg {
+            plan.snapshots.push(name.to_string());
+            plan.space += space;
+        }
+    }
+    Ok(())
+}
+
+/// Record the oldest clone of a snapshot in `oldest`, if it is older than the
+/// one already there.
+fn find_oldest_clone(
+    lzh: &LibHandle,
+    snapshot: &Dataset,
+    oldest: &mut Option<(DatasetName, i64)>,
+) -> Result<(), Error> {
+    snapshot.iter_clones(lzh, false, |clone| {
+        // TODO: Do we need to verify the origin property matches here?
+        if let Some(name) = clone.get_name() {
+            let creation_time = clone.get_creation_time();
+            if oldest
+                .as_ref()
+                .is_none_or(|(_, time)| creation_time < *time)
+            {
+                *oldest = Some((name, creation_time));
+            }
+        }
+        Ok(())
+    })
+}
+
 /// Safe wrapper for various operations on an owned ZFS dataset handle.
 struct Dataset {
     handle: ptr::NonNull<ffi::ZfsHandle>,
@@ -760,7 +2514,8 @@ impl Dataset {
         Ok(())
     }
 
-    /// Create a snapshot of a dataset.
+    /// Create a snapshot of a dataset and, recursively, all of its
+    /// descendants.
     pub fn create_snapshot(
         lzh: &LibHandle,
         snapshot_path: &DatasetName,
@@ -771,7 +2526,7 @@ impl Dataset {
             ffi::zfs_snapshot(
                 lzh.as_ptr(),
                 snapshot_path.as_ptr(),
-                0, // recursive = false (boolean_t)
+                1, // recursive = true (boolean_t)
                 props_ptr,
             )
         };
@@ -823,6 +2578,27 @@ impl Dataset {
         self.get_numeric_property(ffi::ZFS_PROP_USED).unwrap_or(0)
     }
 
+    /// Get a breakdown of the space used by this dataset.
+    pub fn get_space_usage(&self) -> SpaceUsage {
+        let prop = |prop| self.get_numeric_property(prop).unwrap_or(0);
+        SpaceUsage {
+            used_by_dataset: prop(ffi::ZFS_PROP_USEDDS),
+            used_by_snapshots: prop(ffi::ZFS_PROP_USEDSNAP),
+            referenced: prop(ffi::ZFS_PROP_REFERENCED),
+            written: prop(ffi::ZFS_PROP_WRITTEN),
+            // ZFS stores this as a percentage, i.e. 185 for 1.85x.
+            compress_ratio: self
+                .get_numeric_property(ffi::ZFS_PROP_COMPRESSRATIO)
+                .map_or(1.0, |ratio| ratio as f64 / 100.0),
+        }
+    }
+
+    /// Get the snapshot this dataset was cloned from, if any.
+    pub fn get_origin(&self) -> Option<DatasetName> {
+        self.get_property(ffi::ZFS_PROP_ORIGIN)
+            .and_then(|origin| DatasetName::new(&origin).ok())
+    }
+
     /// Get the creation timestamp for this dataset.
     pub fn get_creation_time(&self) -> i64 {
         self.get_numeric_property(ffi::ZFS_PROP_CREATION)
@@ -848,6 +2624,43 @@ impl Dataset {
         Ok(())
     }
 
+    /// Get the differences between a snapshot of this filesystem and a later
+    /// snapshot (or the filesystem itself), as `zfs diff -FH` prints them.
+    pub fn show_diffs(
+        &self,
+        lzh: &LibHandle,
+        from: &DatasetName,
+        to: &DatasetName,
+    ) -> Result<String, Error> {
+        // libzfs closes the descriptor it writes to, so hand it a duplicate
+        // of an anonymous temporary file and read the output back from ours.
+        let mut output = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_TMPFILE)
+            .open(std::env::temp_dir())?;
+        let fd = unsafe { libc::dup(output.as_raw_fd()) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let result = unsafe {
+            ffi::zfs_show_diffs(
+                self.handle.as_ptr(),
+                fd,
+                from.as_ptr(),
+                to.as_ptr(),
+                ffi::ZFS_DIFF_PARSEABLE | ffi::ZFS_DIFF_CLASSIFY,
+            )
+        };
+        if result != 0 {
+            return Err(lzh.libzfs_error().into());
+        }
+        let mut diffs = String::new();
+        output.rewind()?;
+        output.read_to_string(&mut diffs)?;
+        Ok(diffs)
+    }
+
     /// Destroy this dataset.
     pub fn destroy(&self, lzh: &LibHandle) -> Result<(), Error> {
         let result = unsafe { ffi::zfs_destroy(self.handle.as_ptr(), 0) };
@@ -868,12 +2681,30 @@ impl Dataset {
     }
 
     /// Mount this dataset at the specified path.
-    pub fn mount_at(&self, lzh: &LibHandle, mountpoint: &OsStr) -> Result<(), Error> {
+    pub fn mount_at(
+        &self,
+        lzh: &LibHandle,
+        mountpoint: &OsStr,
+        options: &MountOptions,
+    ) -> Result<(), Error> {
         let c_mountpoint = CString::new(mountpoint.as_bytes()).map_err(|_| Error::InvalidPath {
             path: mountpoint.display().to_string(),
         })?;
+        // We know the option string contains no nul bytes.
+        let c_options = CString::new(options.to_option_string()).unwrap();
+        // libzfs parses "ro" out of the option string too, but be explicit.
+        let flags = if options.read_only() {
+            libc::MS_RDONLY as c_int
+        } else {
+            0
+        };
         let result = unsafe {
-            ffi::zfs_mount_at(self.handle.as_ptr(), ptr::null(), 0, c_mountpoint.as_ptr())
+            ffi::zfs_mount_at(
+                self.handle.as_ptr(),
+                c_options.as_ptr(),
+                flags,
+                c_mountpoint.as_ptr(),
+            )
         };
         if result != 0 {
             // TODO: zfs_mount_at() sets regular ELOOP, ENOENT, ENOTDIR, EPERM,
@@ -999,6 +2830,27 @@ impl Dataset {
         self.get_property(ffi::ZFS_PROP_MOUNTPOINT)
     }
 
+    /// Check whether a ZFS property is set locally on this dataset, rather
+    /// than inherited or left at its default.
+    fn is_property_local(&self, prop: c_int) -> bool {
+        const PROP_BUF_SIZE: usize = 1024;
+        let mut buf = vec![0u8; PROP_BUF_SIZE];
+        let mut source: c_int = 0;
+        let result = unsafe {
+            ffi::zfs_prop_get(
+                self.handle.as_ptr(),
+                prop,
+                buf.as_mut_ptr() as *mut std::os::raw::c_char,
+                PROP_BUF_SIZE,
+                &mut source as *mut c_int,
+                ptr::null_mut(),
+                0,
+                0,
+            )
+        };
+        result == 0 && source == ffi::ZPROP_SRC_LOCAL
+    }
+
     /// Get a ZFS property for this dataset.
     fn get_property(&self, prop: c_int) -> Option<String> {
         const PROP_BUF_SIZE: usize = 1024;
@@ -1010,6 +2862,8 @@ impl Dataset {
                 buf.as_mut_ptr() as *mut std::os::raw::c_char,
                 PROP_BUF_SIZE,
                 ptr::null_mut(),
+                ptr::null_mut(),
+                0,
                 0,
             )
         };
@@ -1041,6 +2895,24 @@ impl Dataset {
 
     /// Get a specific ZFS user property for this dataset.
     fn get_user_property(&self, prop_name: &str, allow_inherited: bool) -> Option<String> {
+        let (value, source) = self.lookup_user_property(prop_name)?;
+        if !allow_inherited {
+            let own_name = self.get_name()?.to_string();
+            if source != own_name && source != "$recvd" {
+                return None;
+            }
+        }
+        // TODO: Can this '-' ever be a legitimate value?
+        if !value.is_empty() && value != "-" {
+            Some(value)
+        } else {
+            None
+        }
+    }
+
+    /// Look up a ZFS user property for this dataset, returning its value and
+    /// source (the dataset it is set on, or `$recvd`).
+    fn lookup_user_property(&self, prop_name: &str) -> Option<(String, String)> {
         let prop_cstr = match CString::new(prop_name) {
             Ok(cstr) => cstr,
             Err(_) => return None,
@@ -1067,28 +2939,21 @@ impl Dataset {
             return None;
         }
 
-        if !allow_inherited {
-            let own_name = match self.get_name() {
-                Some(n) => n.to_string(),
-                None => return None,
-            };
-            let source_cstr = CString::new("source").unwrap();
-            let mut source_ptr: *mut std::os::raw::c_char = ptr::null_mut();
-            let result = unsafe {
-                ffi::nvlist_lookup_string(
-                    prop_nvlist_ptr,
-                    source_cstr.as_ptr(),
-                    &mut source_ptr as *mut *mut std::os::raw::c_char,
-                )
-            };
-            if result != 0 || source_ptr.is_null() {
-                return None;
-            }
-            let source = unsafe { CStr::from_ptr(source_ptr) }.to_string_lossy();
-            if source != own_name && source != "$recvd" {
-                return None;
-            }
+        let source_cstr = CString::new("source").unwrap();
+        let mut source_ptr: *mut std::os::raw::c_char = ptr::null_mut();
+        let result = unsafe {
+            ffi::nvlist_lookup_string(
+                prop_nvlist_ptr,
+                source_cstr.as_ptr(),
+                &mut source_ptr as *mut *mut std::os::raw::c_char,
+            )
+        };
+        if result != 0 || source_ptr.is_null() {
+            return None;
         }
+        let source = unsafe { CStr::from_ptr(source_ptr) }
+            .to_string_lossy()
+            .to_string();
 
         // The property is stored under the aptly-named "value" name.
         let value_cstr = CString::new("value").unwrap();
@@ -1107,15 +2972,159 @@ impl Dataset {
         }
 
         let cstr = unsafe { CStr::from_ptr(value_ptr) };
-        let value_str = cstr.to_string_lossy().to_string();
-        // TODO: Can this '-' ever be a legitimate value?
-        if !value_str.is_empty() && value_str != "-" {
-            Some(value_str)
+        Some((cstr.to_string_lossy().to_string(), source))
+    }
+
+    /// Get the names of the user properties set on (or inherited by) this
+    /// dataset.
+    fn get_user_property_names(&self) -> Vec<String> {
+        let user_props = unsafe { ffi::zfs_get_user_props(self.handle.as_ptr()) };
+        if user_props.is_null() {
+            return vec![];
+        }
+        let mut names = vec![];
+        let mut pair = unsafe { ffi::nvlist_next_nvpair(user_props, ptr::null_mut()) };
+        while !pair.is_null() {
+            let name = unsafe { CStr::from_ptr(ffi::nvpair_name(pair)) };
+            names.push(name.to_string_lossy().to_string());
+            pair = unsafe { ffi::nvlist_next_nvpair(user_props, pair) };
+        }
+        names
+    }
+
+    /// Get the tags set on this dataset (and not inherited), by key.
+    fn get_tags(&self) -> BTreeMap<String, String> {
+        self.get_user_property_names()
+            .into_iter()
+            .filter_map(|name| {
+                let key = name.strip_prefix(TAG_PROP_PREFIX)?.to_string();
+                let value = self.get_user_property(&name, false)?;
+                Some((key, value))
+            })
+            .collect()
+    }
+
+    /// Get a native ZFS property of this dataset along with its source, in
+    /// the same terms as `zfs get`.
+    fn get_property_with_source(&self, prop: c_int, literal: bool) -> Option<(String, String)> {
+        const PROP_BUF_SIZE: usize = 1024;
+        let mut buf = vec![0u8; PROP_BUF_SIZE];
+        let mut statbuf = vec![0u8; PROP_BUF_SIZE];
+        let mut source: c_int = 0;
+        let result = unsafe {
+            ffi::zfs_prop_get(
+                self.handle.as_ptr(),
+                prop,
+                buf.as_mut_ptr() as *mut std::os::raw::c_char,
+                PROP_BUF_SIZE,
+                &mut source as *mut c_int,
+                statbuf.as_mut_ptr() as *mut std::os::raw::c_char,
+                PROP_BUF_SIZE,
+                literal as c_int,
+            )
+        };
+        if result != 0 {
+            return None;
+        }
+        let value = unsafe { CStr::from_ptr(buf.as_ptr() as *const c_char) };
+        let statbuf = unsafe { CStr::from_ptr(statbuf.as_ptr() as *const c_char) };
+        let source = match source {
+            ffi::ZPROP_SRC_DEFAULT => "default".to_string(),
+            ffi::ZPROP_SRC_TEMPORARY => "temporary".to_string(),
+            ffi::ZPROP_SRC_LOCAL => "local".to_string(),
+            ffi::ZPROP_SRC_INHERITED => format!("inherited from {}", statbuf.to_string_lossy()),
+            ffi::ZPROP_SRC_RECEIVED => "received".to_string(),
+            _ => "-".to_string(),
+        };
+        Some((value.to_string_lossy().to_string(), source))
+    }
+
+    /// Get a native or user property of this dataset by name.
+    fn get_property_by_name(&self, name: &str) -> Result<Property, Error> {
+        let unknown = || Error::UnknownProp {
+            name: name.to_string(),
+        };
+        let name_cstr = CString::new(name).map_err(|_| unknown())?;
+        let prop = unsafe { ffi::zfs_name_to_prop(name_cstr.as_ptr()) };
+        let (value, source) = if prop != ffi::ZPROP_INVAL {
+            // Properties that don't apply to this type of dataset are shown
+            // as unset, like `zfs get` does.
+            self.get_property_with_source(prop, false)
+                .unwrap_or_else(|| ("-".to_string(), "-".to_string()))
+        } else if unsafe { ffi::zfs_prop_user(name_cstr.as_ptr()) } != 0 {
+            self.get_user_property_with_source(name)
         } else {
-            None
+            return Err(unknown());
+        };
+        Ok(Property {
+            name: name.to_string(),
+            value,
+            source,
+        })
+    }
+
+    /// Get a user property of this dataset along with its source, in the same
+    /// terms as `zfs get`.
+    fn get_user_property_with_source(&self, name: &str) -> (String, String) {
+        match self.lookup_user_property(name) {
+            Some((value, source)) if source == "$recvd" => (value, "received".to_string()),
+            Some((value, source))
+                if self
+                    .get_name()
+                    .is_some_and(|name| name.to_string() == source) =>
+            {
+                (value, "local".to_string())
+            }
+            Some((value, source)) => (value, format!("inherited from {}", source)),
+            None => ("-".to_string(), "-".to_string()),
         }
     }
 
+    /// Get all (visible) native properties of this dataset, followed by its
+    /// user properties. With `literal`, numbers are exact rather than rounded
+    /// for display, so that the values can be set again.
+    fn get_all_properties(&self, literal: bool) -> Vec<Property> {
+        let mut props: Vec<c_int> = vec![];
+        unsafe {
+            ffi::zprop_iter(
+                collect_prop_callback,
+                &mut props as *mut Vec<c_int> as *mut c_void,
+                0,
+                1,
+                ffi::zfs_get_type(self.handle.as_ptr()),
+            )
+        };
+        let native = props.into_iter().filter_map(|prop| {
+            let name = unsafe { CStr::from_ptr(ffi::zfs_prop_to_name(prop)) };
+            let (value, source) = self.get_property_with_source(prop, literal)?;
+            Some(Property {
+                name: name.to_string_lossy().to_string(),
+                value,
+                source,
+            })
+        });
+        let mut user_names = self.get_user_property_names();
+        user_names.sort();
+        let user = user_names.into_iter().map(|name| {
+            let (value, source) = self.get_user_property_with_source(&name);
+            Property {
+                name,
+                value,
+                source,
+            }
+        });
+        native.chain(user).collect()
+    }
+
+    /// Get the native and user properties set locally on this dataset, with
+    /// literal values.
+    fn get_local_properties(&self) -> Vec<Property> {
+        self.get_all_properties(true)
+            .into_iter()
+            .filter(|prop| prop.source == "local")
+            .collect()
+    }
+
     /// Set a ZFS property for this dataset.
     fn set_property(&self, lzh: &LibHandle, prop_name: &str, value: &str) -> Result<(), Error> {
         let prop_cstr =
@@ -1134,6 +3143,97 @@ impl Dataset {
         Ok(())
     }
 
+    /// Set several ZFS properties for this dataset at once.
+    fn set_properties(&self, lzh: &LibHandle, props: &NvList) -> Result<(), Error> {
+        let result = unsafe { ffi::zfs_prop_set_list(self.handle.as_ptr(), props.as_nvlist_ptr()) };
+        if result != 0 {
+            return Err(lzh.libzfs_error().into());
+        }
+        Ok(())
+    }
+
+    /// Remove a locally set ZFS property from this dataset, so that it is
+    /// inherited (or, for user properties, unset).
+    fn inherit_property(&self, lzh: &LibHandle, prop_name: &str) -> Result<(), Error> {
+        let prop_cstr = CString::new(prop_name).map_err(|_| Error::invalid_prop(prop_name, ""))?;
+        let result = unsafe { ffi::zfs_prop_inherit(self.handle.as_ptr(), prop_cstr.as_ptr(), 0) };
+        if result != 0 {
+            return Err(lzh.libzfs_error().into());
+        }
+        Ok(())
+    }
+
+    /// Place a hold with the given tag on a snapshot of this dataset and,
+    /// with `recursive`, on the snapshots of the same name of its
+    /// descendants.
+    pub fn hold(
+        &self,
+        lzh: &LibHandle,
+        snapshot: &str,
+        tag: &str,
+        recursive: bool,
+    ) -> Result<(), Error> {
+        let snapshot_cstr = CString::new(snapshot).map_err(|_| Error::InvalidName {
+            name: snapshot.to_string(),
+            reason: "contains a nul byte".to_string(),
+        })?;
+        // We only ever use our own tags.
+        let tag_cstr = CString::new(tag).unwrap();
+        let result = unsafe {
+            ffi::zfs_hold(
+                self.handle.as_ptr(),
+                snapshot_cstr.as_ptr(),
+                tag_cstr.as_ptr(),
+                recursive as c_int,
+                -1, // No cleanup file descriptor, i.e. the hold persists.
+            )
+        };
+        if result != 0 {
+            return Err(lzh.libzfs_error().into());
+        }
+        Ok(())
+    }
+
+    /// Release a hold placed with [`Dataset::hold`].
+    pub fn release(
+        &self,
+        lzh: &LibHandle,
+        snapshot: &str,
+        tag: &str,
+        recursive: bool,
+    ) -> Result<(), Error> {
+        let snapshot_cstr = CString::new(snapshot).map_err(|_| Error::InvalidName {
+            name: snapshot.to_string(),
+            reason: "contains a nul byte".to_string(),
+        })?;
+        let tag_cstr = CString::new(tag).unwrap();
+        let result = unsafe {
+            ffi::zfs_release(
+                self.handle.as_ptr(),
+                snapshot_cstr.as_ptr(),
+                tag_cstr.as_ptr(),
+                recursive as c_int,
+            )
+        };
+        if result != 0 {
+            return Err(lzh.libzfs_error().into());
+        }
+        Ok(())
+    }
+
+    /// Check whether this snapshot has a hold with the given tag.
+    pub fn has_hold(&self, tag: &str) -> bool {
+        let tag_cstr = CString::new(tag).unwrap();
+        let mut holds: *mut ffi::NvList = ptr::null_mut();
+        let result = unsafe { ffi::zfs_get_holds(self.handle.as_ptr(), &mut holds) };
+        if result != 0 || holds.is_null() {
+            return false;
+        }
+        let exists = unsafe { ffi::nvlist_exists(holds, tag_cstr.as_ptr()) } != 0;
+        unsafe { ffi::nvlist_free(holds) };
+        exists
+    }
+
     /// Clone a dataset from an existing snapshot.
     pub fn clone(
         &self,
@@ -1206,6 +3306,18 @@ where
     }
 }
 
+/// C-style callback for `zprop_iter` that collects property IDs.
+///
+/// SAFETY: This function assumes that the data is a valid `Vec<c_int>`.
+extern "C" fn collect_prop_callback(
+    prop: std::os::raw::c_int,
+    data: *mut std::os::raw::c_void,
+) -> std::os::raw::c_int {
+    let props = unsafe { &mut *(data as *mut Vec<std::os::raw::c_int>) };
+    props.push(prop);
+    ffi::ZPROP_CONT
+}
+
 /// Safe wrapper for zpool operations.
 struct Zpool {
     handle: ptr::NonNull<ffi::ZpoolHandle>,
@@ -1269,8 +3381,70 @@ impl Zpool {
 
     /// Get the "previous bootfs" property (used for temporary activation).
     pub fn get_previous_bootfs(&self) -> Option<DatasetName> {
-        let prop = CString::new(PREVIOUS_BOOTFS_PROP).unwrap();
-        const PROP_BUF_SIZE: usize = 1024;
+        self.get_dataset_userprop(PREVIOUS_BOOTFS_PROP)
+    }
+
+    /// Get the host ID of the system that last imported the pool, from its
+    /// configuration.
+    pub fn get_hostid(&self) -> Option<u32> {
+        let config = unsafe { ffi::zpool_get_config(self.handle.as_ptr(), ptr::null_mut()) };
+        // The configuration belongs to the pool handle, so mustn't be freed.
+        let config = std::mem::ManuallyDrop::new(NvList::from_raw(config)?);
+        config
+            .lookup_uint64("hostid")
+            .and_then(|hostid| u32::try_from(hostid).ok())
+    }
+
+    /// Set the "previous bootfs" property (used for temporary activation).
+    pub fn set_previous_bootfs(&self, lzh: &LibHandle, dataset: &DatasetName) -> Result<(), Error> {
+        self.set_userprop(lzh, PREVIOUS_BOOTFS_PROP, dataset.as_ptr())
+    }
+
+    /// Clear the "previous bootfs" property (used for temporary activation).
+    pub fn clear_previous_bootfs(&self, lzh: &LibHandle) -> Result<(), Error> {
+        let empty_value = CString::new("").unwrap();
+        self.set_userprop(lzh, PREVIOUS_BOOTFS_PROP, empty_value.as_ptr())
+    }
+
+    /// Get the boot environment on trial, pending confirmation.
+    pub fn get_trial(&self) -> Option<DatasetName> {
+        self.get_dataset_userprop(TRIAL_PROP)
+    }
+
+    /// Set the boot environment on trial, pending confirmation.
+    pub fn set_trial(&self, lzh: &LibHandle, dataset: &DatasetName) -> Result<(), Error> {
+        self.set_userprop(lzh, TRIAL_PROP, dataset.as_ptr())
+    }
+
+    /// Clear the boot environment on trial.
+    pub fn clear_trial(&self, lzh: &LibHandle) -> Result<(), Error> {
+        let empty_value = CString::new("").unwrap();
+        self.set_userprop(lzh, TRIAL_PROP, empty_value.as_ptr())
+    }
+
+    /// Get the boot history, oldest first.
+    pub fn get_boot_history(&self) -> Vec<BootRecord> {
+        history::decode(&self.get_userprop(BOOT_HISTORY_PROP).unwrap_or_default())
+    }
+
+    /// Replace the boot history. Older entries may be dropped to keep it
+    /// bounded.
+    pub fn set_boot_history(&self, lzh: &LibHandle, records: &[BootRecord]) -> Result<(), Error> {
+        // Entries only ever contain valid dataset name characters.
+        let value = CString::new(history::encode(records)).unwrap();
+        self.set_userprop(lzh, BOOT_HISTORY_PROP, value.as_ptr())
+    }
+
+    /// Get a user property holding a dataset name, if it is set.
+    fn get_dataset_userprop(&self, name: &str) -> Option<DatasetName> {
+        self.get_userprop(name)
+            .and_then(|value| DatasetName::new(&value).ok())
+    }
+
+    /// Get a user property, if it is set.
+    fn get_userprop(&self, name: &str) -> Option<String> {
+        let prop = CString::new(name).unwrap();
+        const PROP_BUF_SIZE: usize = 8192;
         let mut buf = vec![0u8; PROP_BUF_SIZE];
         let result = unsafe {
             ffi::zpool_get_userprop(
@@ -1285,39 +3459,24 @@ impl Zpool {
             if let Some(null_pos) = buf.iter().position(|&x| x == 0) {
                 buf.truncate(null_pos);
             }
-            if let Ok(value) = String::from_utf8(buf) {
-                if !value.is_empty() && value != "-" {
-                    DatasetName::new(&value).ok()
-                } else {
-                    None
-                }
-            } else {
-                None
+            match String::from_utf8(buf) {
+                Ok(value) if !value.is_empty() && value != "-" => Some(value),
+                _ => None,
             }
         } else {
             None
         }
     }
 
-    /// Set the "previous bootfs" property (used for temporary activation).
-    pub fn set_previous_bootfs(&self, lzh: &LibHandle, dataset: &DatasetName) -> Result<(), Error> {
-        let prop = CString::new(PREVIOUS_BOOTFS_PROP).unwrap();
-        let result =
-            unsafe { ffi::zpool_set_prop(self.handle.as_ptr(), prop.as_ptr(), dataset.as_ptr()) };
-        if result != 0 {
-            Err(lzh.libzfs_error().into())
-        } else {
-            Ok(())
-        }
-    }
-
-    /// Clear the "previous bootfs" property (used for temporary activation).
-    pub fn clear_previous_bootfs(&self, lzh: &LibHandle) -> Result<(), Error> {
-        let prop = CString::new(PREVIOUS_BOOTFS_PROP).unwrap();
-        let empty_value = CString::new("").unwrap();
-        let result = unsafe {
-            ffi::zpool_set_prop(self.handle.as_ptr(), prop.as_ptr(), empty_value.as_ptr())
-        };
+    /// Set a user property (or clear it, with an empty value).
+    fn set_userprop(
+        &self,
+        lzh: &LibHandle,
+        name: &str,
+        value: *const std::os::raw::c_char,
+    ) -> Result<(), Error> {
+        let prop = CString::new(name).unwrap();
+        let result = unsafe { ffi::zpool_set_prop(self.handle.as_ptr(), prop.as_ptr(), value) };
         if result != 0 {
             Err(lzh.libzfs_error().into())
         } else {
@@ -1396,6 +3555,11 @@ impl DatasetName {
         self.inner.to_str().unwrap().to_string()
     }
 
+    /// Whether this is the name of a snapshot.
+    pub fn is_snapshot(&self) -> bool {
+        self.inner.to_bytes().contains(&b'@')
+    }
+
     /// Get the pool name (the first component) for the dataset.
     pub fn pool(&self) -> Self {
         let mut v = Vec::from(self.inner.to_bytes());
@@ -1579,6 +3743,34 @@ impl NvList {
     pub fn as_nvlist_ptr(&self) -> *mut ffi::NvList {
         self.nvl.as_ptr()
     }
+
+    /// Take ownership of an nvlist allocated by libzfs, if there is one.
+    pub fn from_raw(nvl: *mut ffi::NvList) -> Option<Self> {
+        ptr::NonNull::new(nvl).map(|nvl| NvList { nvl })
+    }
+
+    pub fn lookup_string(&self, name: &str) -> Option<String> {
+        let name_cstr = CString::new(name).ok()?;
+        let mut value: *mut c_char = ptr::null_mut();
+        let result =
+            unsafe { ffi::nvlist_lookup_string(self.nvl.as_ptr(), name_cstr.as_ptr(), &mut value) };
+        if result != 0 || value.is_null() {
+            return None;
+        }
+        Some(
+            unsafe { CStr::from_ptr(value) }
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+
+    pub fn lookup_uint64(&self, name: &str) -> Option<u64> {
+        let name_cstr = CString::new(name).ok()?;
+        let mut value: u64 = 0;
+        let result =
+            unsafe { ffi::nvlist_lookup_uint64(self.nvl.as_ptr(), name_cstr.as_ptr(), &mut value) };
+        (result == 0).then_some(value)
+    }
 }
 
 impl Drop for NvList {
@@ -1638,6 +3830,378 @@ pub fn read_hostid<P: AsRef<Path>>(root_dir: Option<P>) -> Result<Option<u32>, s
     Ok(hostid)
 }
 
+/// Write a host ID to `/etc/hostid` beneath the given root directory, in the
+/// format expected by [`read_hostid`] and zgenhostid(8).
+pub fn write_hostid<P: AsRef<Path>>(root_dir: P, hostid: u32) -> Result<(), std::io::Error> {
+    let etc = root_dir.as_ref().join("etc");
+    std::fs::create_dir_all(&etc)?;
+    std::fs::write(etc.join("hostid"), hostid.to_le_bytes())
+}
+
+/// Seed the identity files of a freshly-created, empty root filesystem.
+///
+/// The host ID is written to `/etc/hostid` (when known) so that the new system
+/// can import its pool at boot. The machine ID is deliberately *not* copied
+/// from the running system; instead `/etc/machine-id` is marked as
+/// "uninitialized" so that systemd generates a fresh one and runs first-boot
+/// units when the new system is started. See machine-id(5).
+fn seed_empty_root(root_dir: &Path, hostid: Option<u32>) -> Result<(), std::io::Error> {
+    if let Some(hostid) = hostid {
+        write_hostid(root_dir, hostid)?;
+    }
+    let machine_id = root_dir.join("etc/machine-id");
+    if !machine_id.exists() {
+        std::fs::create_dir_all(root_dir.join("etc"))?;
+        std::fs::write(machine_id, "uninitialized\n")?;
+    }
+    Ok(())
+}
+
+/// Check that a boot environment root has `mountpoint=none`, and that each
+/// boot environment in it has `canmount=noauto` and `mountpoint=/`, as
+/// `beadm init` and `beadm create` leave them.
+fn doctor_layout(root: &DatasetName, fix: bool) -> Result<Vec<Finding>, Error> {
+    let lzh = LibHandle::get();
+    let root_dataset = Dataset::filesystem(&lzh, root)?;
+    let mut findings = vec![];
+    if let Some(mountpoint) = root_dataset
+        .get_mountpoint_property()
+        .filter(|mountpoint| mountpoint != "none")
+    {
+        findings.push(
+            Finding::new(
+                "layout",
+                &root.to_string(),
+                format!("The boot environment root has mountpoint={}", mountpoint),
+                "The boot environment root should have mountpoint=none, so that it is not \
+                 mounted itself. Otherwise beadm does not recognise it as the active boot \
+                 environment root.",
+            )
+            .with_fix("set mountpoint=none".to_string())
+            .apply(fix, || {
+                root_dataset.set_property(&lzh, "mountpoint", "none")
+            }),
+        );
+    }
+
+    let mut be_paths = vec![];
+    root_dataset.iter_children(&lzh, |child| {
+        if let Some(name) = child.get_name().filter(|name| !name.is_snapshot()) {
+            be_paths.push(name);
+        }
+        Ok(())
+    })?;
+    for be_path in be_paths {
+        let be_name = be_path.basename();
+        let dataset = Dataset::filesystem(&lzh, &be_path)?;
+        if let Some(canmount) = dataset
+            .get_canmount()
+            .filter(|canmount| canmount != "noauto")
+        {
+            findings.push(
+                Finding::new(
+                    "layout",
+                    &be_name,
+                    format!("Boot environment '{}' has canmount={}", be_name, canmount),
+                    "Boot environments should have canmount=noauto, so that only the one \
+                     being booted is mounted. With canmount=on, every boot environment is \
+                     mounted at / when the pool is imported; with canmount=off, it cannot be \
+                     booted at all.",
+                )
+                .with_fix("set canmount=noauto".to_string())
+                .apply(fix, || dataset.set_property(&lzh, "canmount", "noauto")),
+            );
+        }
+        if let Some(mountpoint) = dataset
+            .get_mountpoint_property()
+            .filter(|mountpoint| mountpoint != "/")
+        {
+            let message = format!(
+                "Boot environment '{}' has mountpoint={}",
+                be_name, mountpoint
+            );
+            let explanation = "Boot environments should have mountpoint=/, so that they can be \
+                               mounted as the root filesystem. The mountpoints of their child \
+                               datasets are relative to it.";
+            // Changing the mountpoint of a mounted dataset remounts it there,
+            // which is the last thing we want for the running system.
+            findings.push(if dataset.get_mountpoint().is_some() {
+                Finding::new(
+                    "layout",
+                    &be_name,
+                    message,
+                    &format!("{} Unmount it before fixing this.", explanation),
+                )
+            } else {
+                Finding::new("layout", &be_name, message, explanation)
+                    .with_fix("set mountpoint=/".to_string())
+                    .apply(fix, || dataset.set_property(&lzh, "mountpoint", "/"))
+            });
+        }
+    }
+    Ok(findings)
+}
+
+/// Check that the pool's `bootfs` is a boot environment in this root, and
+/// that any temporary activation (`previous-bootfs`) is still pending.
+/// `active` is the boot environment the system is running, if it is in this
+/// root.
+fn doctor_bootfs(
+    root: &DatasetName,
+    active: Option<&DatasetName>,
+    fix: bool,
+) -> Result<Vec<Finding>, Error> {
+    let lzh = LibHandle::get();
+    let pool = root.pool().to_string();
+    let zpool = Zpool::open(&lzh, &root.pool())?;
+    let exists = |name: &DatasetName| Dataset::filesystem(&lzh, name).is_ok();
+    let mut findings = vec![];
+
+    let bootfs = zpool.get_bootfs();
+    let missing = match &bootfs {
+        None => Some("The pool has no bootfs set".to_string()),
+        Some(bootfs) if !exists(bootfs) => Some(format!(
+            "The pool's bootfs '{}' does not exist",
+            bootfs.to_string()
+        )),
+        _ => None,
+    };
+    if let Some(message) = missing {
+        let finding = Finding::new(
+            "bootfs",
+            &pool,
+            message,
+            "The boot loader boots the dataset named by the pool's bootfs property by default, \
+             so there is nothing for it to boot. Activate a boot environment to fix this.",
+        );
+        findings.push(match active {
+            Some(active) => finding
+                .with_fix(format!("activate '{}'", active.basename()))
+                .apply(fix, || zpool.set_bootfs(&lzh, active)),
+            None => finding,
+        });
+    } else if let Some(bootfs) = &bootfs
+        && bootfs.parent().as_ref() != Some(root)
+    {
+        findings.push(Finding::new(
+            "bootfs",
+            &pool,
+            format!(
+                "The pool's bootfs '{}' is not a boot environment in '{}'",
+                bootfs.to_string(),
+                root.to_string()
+            ),
+            "None of the boot environments here will be booted by default, and beadm cannot \
+             undo temporary activations. If it belongs to another boot environment root on the \
+             same pool, check that root instead; otherwise, activate a boot environment.",
+        ));
+    }
+
+    let previous = match zpool.get_previous_bootfs() {
+        Some(previous) => previous,
+        None => return Ok(findings),
+    };
+    let name = previous.to_string();
+    if !exists(&previous) {
+        findings.push(
+            Finding::new(
+                "previous-bootfs",
+                &name,
+                format!(
+                    "The temporary activation falls back to '{}', which does not exist",
+                    name
+                ),
+                "A temporary activation records the boot environment to go back to after one \
+                 boot. That one has since been destroyed or renamed, so the temporary \
+                 activation cannot be undone and is effectively permanent.",
+            )
+            .with_fix("clear the temporary activation, keeping the current bootfs".to_string())
+            .apply(fix, || zpool.clear_previous_bootfs(&lzh)),
+        );
+    } else if bootfs.as_ref() == Some(&previous) {
+        findings.push(
+            Finding::new(
+                "previous-bootfs",
+                &name,
+                format!(
+                    "The temporary activation falls back to '{}', which is already the bootfs",
+                    name
+                ),
+                "Nothing is temporarily activated any more, but the leftover state makes beadm \
+                 report a temporary activation that isn't there.",
+            )
+            .with_fix("clear the temporary activation".to_string())
+            .apply(fix, || zpool.clear_previous_bootfs(&lzh)),
+        );
+    } else if let Some(bootfs) = &bootfs
+        && active == Some(bootfs)
+    {
+        findings.push(
+            Finding::new(
+                "previous-bootfs",
+                &name,
+                format!(
+                    "'{}' was activated temporarily and has been booted, but the temporary \
+                     activation was never cleared",
+                    bootfs.basename()
+                ),
+                "Temporary activations are cleared early in the boot by the \
+                 beadm-clear-boot-once service. It did not run, so the next boot will be into \
+                 the same boot environment again rather than back into the previous one. Check \
+                 that the service is enabled.",
+            )
+            .with_fix(format!("activate '{}' again", previous.basename()))
+            .apply(fix, || {
+                // As the service would have, in the same order as
+                // `clear_boot_once`.
+                zpool.set_bootfs(&lzh, &previous)?;
+                zpool.clear_previous_bootfs(&lzh)
+            }),
+        );
+    }
+    Ok(findings)
+}
+
+/// Check for datasets that are shared between boot environments but
+/// shouldn't be: children of inactive boot environments that are mounted
+/// automatically (over the active boot environment's own), and datasets
+/// outside the root that are mounted over files belonging to the boot
+/// environments.
+fn doctor_shared(
+    root: &DatasetName,
+    active: Option<&DatasetName>,
+    fix: bool,
+) -> Result<Vec<Finding>, Error> {
+    let lzh = LibHandle::get();
+    let pool = root.pool();
+    let prefix = format!("{}/", root.to_string());
+    let mut paths = vec![pool.clone()];
+    paths.extend(descendant_filesystems(
+        &lzh,
+        &Dataset::filesystem(&lzh, &pool)?,
+    )?);
+
+    let mut findings = vec![];
+    for path in paths {
+        let name = path.to_string();
+        let dataset = Dataset::filesystem(&lzh, &path)?;
+        let mountpoint = dataset.get_mountpoint_property().unwrap_or_default();
+        if dataset.get_canmount().as_deref() != Some("on") || !mountpoint.starts_with('/') {
+            continue; // Not mounted automatically.
+        }
+        if let Some(relative) = name.strip_prefix(&prefix) {
+            // Boot environments themselves are covered by the layout check,
+            // and the active boot environment's children are meant to be
+            // mounted.
+            let be_name = match relative.split_once('/') {
+                Some((be_name, _)) => be_name,
+                None => continue,
+            };
+            if active.is_some_and(|active| active.basename() == be_name) {
+                continue;
+            }
+            findings.push(
+                Finding::new(
+                    "shared",
+                    &name,
+                    format!(
+                        "'{}' has canmount=on, but boot environment '{}' is not active",
+                        name, be_name
+                    ),
+                    "Datasets with canmount=on are mounted when the pool is imported, whichever \
+                     boot environment is booted, so this one is mounted over the active boot \
+                     environment's own. Child datasets of boot environments should have \
+                     canmount=noauto, as beadm create leaves them.",
+                )
+                .with_fix("set canmount=noauto".to_string())
+                .apply(fix, || dataset.set_property(&lzh, "canmount", "noauto")),
+            );
+        } else if path != *root && doctor::is_system_mountpoint(&mountpoint) {
+            findings.push(Finding::new(
+                "shared",
+                &name,
+                format!(
+                    "'{}' is mounted at {} in every boot environment",
+                    name, mountpoint
+                ),
+                "Its contents are not part of any boot environment, so they are not \
+                 snapshotted, cloned or rolled back along with the rest of the system, and get \
+                 out of step with it when switching between boot environments. Move them into \
+                 the boot environments (e.g., as a child dataset of each) and set canmount=off \
+                 on this dataset.",
+            ));
+        }
+    }
+    Ok(findings)
+}
+
+/// Check that the shared home directory dataset is laid out as `beadm init`
+/// creates it.
+fn doctor_home(pool: &DatasetName, fix: bool) -> Result<Vec<Finding>, Error> {
+    let lzh = LibHandle::get();
+    let home_path = pool.append("home")?;
+    let name = home_path.to_string();
+    let home = match Dataset::filesystem(&lzh, &home_path) {
+        Ok(home) => home,
+        Err(Error::LibzfsError(LibzfsError {
+            errno: ffi::EZFS_NOENT,
+            ..
+        })) => {
+            return Ok(vec![Finding::new(
+                "home",
+                &name,
+                format!("'{}' does not exist", name),
+                "Without a shared home dataset, home directories are part of each boot \
+                 environment: they are rolled back along with it, and don't follow you from one \
+                 boot environment to the next. beadm init creates it, but existing home \
+                 directories need moving into it by hand. If they are kept in another dataset \
+                 already, this can be ignored.",
+            )]);
+        }
+        Err(err) => return Err(err),
+    };
+
+    let mut findings = vec![];
+    if let Some(mountpoint) = home
+        .get_mountpoint_property()
+        .filter(|mountpoint| mountpoint != "/home")
+    {
+        let message = format!("'{}' has mountpoint={}", name, mountpoint);
+        let explanation = "The shared home dataset should have mountpoint=/home, so that the \
+                           same home directories are mounted whichever boot environment is \
+                           booted.";
+        findings.push(if home.get_mountpoint().is_some() {
+            // Moving home directories out from under logged-in users is best
+            // left to them.
+            Finding::new(
+                "home",
+                &name,
+                message,
+                &format!("{} Unmount it before fixing this.", explanation),
+            )
+        } else {
+            Finding::new("home", &name, message, explanation)
+                .with_fix("set mountpoint=/home".to_string())
+                .apply(fix, || home.set_property(&lzh, "mountpoint", "/home"))
+        });
+    }
+    if let Some(canmount) = home.get_canmount().filter(|canmount| canmount != "on") {
+        findings.push(
+            Finding::new(
+                "home",
+                &name,
+                format!("'{}' has canmount={}", name, canmount),
+                "The shared home dataset should have canmount=on, so that it is mounted at \
+                 boot. Otherwise the home directories in the boot environment itself are used \
+                 instead.",
+            )
+            .with_fix("set canmount=on".to_string())
+            .apply(fix, || home.set_property(&lzh, "canmount", "on")),
+        );
+    }
+    Ok(findings)
+}
+
 /// Get the root ZFS filesystem, if any, from `/proc/mounts`.
 fn get_rootfs() -> Result<Option<DatasetName>, Error> {
     let file = File::open("/proc/mounts")?;
@@ -1701,6 +4265,30 @@ fn get_active_boot_environment_root() -> Result<Root, Error> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_clone_properties() {
+        let local = |name: &str, value: &str| Property {
+            name: name.to_string(),
+            value: value.to_string(),
+            source: "local".to_string(),
+        };
+        let props = clone_properties(
+            vec![
+                local("canmount", "noauto"),
+                local("mountpoint", "/"),
+                local("reservation", "10000000"),
+                local("keylocation", "prompt"),
+                local("normalization", "formD"),
+                local("encryption", "aes-256-gcm"),
+                local(DESCRIPTION_PROP, "Testing"),
+            ],
+            |name| matches!(name, "normalization" | "encryption"),
+        );
+        let names: Vec<&str> = props.iter().map(|prop| prop.name.as_str()).collect();
+        assert_eq!(names, vec!["reservation", DESCRIPTION_PROP]);
+        assert_eq!(props[0].value, "10000000");
+    }
+
     #[test]
     fn test_dataset_name_pool() {
         let name = DatasetName::new("rpool/ROOT/default").unwrap();
@@ -1784,6 +4372,28 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_child_mountpoint() {
+        let be_mountpoint = Path::new("/tmp/be_mount.XXXXXX");
+        assert_eq!(
+            child_mountpoint(be_mountpoint, "/var"),
+            Some(PathBuf::from("/tmp/be_mount.XXXXXX/var"))
+        );
+        assert_eq!(
+            child_mountpoint(be_mountpoint, "/usr/local"),
+            Some(PathBuf::from("/tmp/be_mount.XXXXXX/usr/local"))
+        );
+        assert_eq!(
+            child_mountpoint(Path::new("/"), "/var"),
+            Some(PathBuf::from("/var"))
+        );
+
+        // Datasets that aren't mounted by path.
+        assert_eq!(child_mountpoint(be_mountpoint, "legacy"), None);
+        assert_eq!(child_mountpoint(be_mountpoint, "none"), None);
+        assert_eq!(child_mountpoint(be_mountpoint, "/"), None);
+    }
+
     #[test]
     fn test_libzfs_error() {
         let libzfs_err = LibzfsError {
@@ -1834,6 +4444,51 @@ mod tests {
         assert!(read_hostid(Some(temp_root_dir)).is_err());
     }
 
+    #[test]
+    fn test_write_hostid() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let temp_root_dir = temp_dir.path();
+
+        // Round-trips through read_hostid(), creating /etc as needed.
+        write_hostid(temp_root_dir, 0x00bab10c).unwrap();
+        assert_eq!(
+            std::fs::read(temp_root_dir.join("etc/hostid")).unwrap(),
+            vec![0x0c, 0xb1, 0xba, 0x00]
+        );
+        assert_eq!(read_hostid(Some(temp_root_dir)).unwrap(), Some(0x00bab10c));
+
+        // Overwrites an existing value.
+        write_hostid(temp_root_dir, 0xdeadbeef).unwrap();
+        assert_eq!(read_hostid(Some(temp_root_dir)).unwrap(), Some(0xdeadbeef));
+    }
+
+    #[test]
+    fn test_seed_empty_root() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let temp_root_dir = temp_dir.path();
+
+        seed_empty_root(temp_root_dir, Some(0x00bab10c)).unwrap();
+        assert_eq!(read_hostid(Some(temp_root_dir)).unwrap(), Some(0x00bab10c));
+        assert_eq!(
+            std::fs::read_to_string(temp_root_dir.join("etc/machine-id")).unwrap(),
+            "uninitialized\n"
+        );
+
+        // An existing machine ID is left alone, and no host ID means no
+        // /etc/hostid.
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let temp_root_dir = temp_dir.path();
+        std::fs::create_dir(temp_root_dir.join("etc")).unwrap();
+        let machine_id = "0123456789abcdef0123456789abcdef\n";
+        std::fs::write(temp_root_dir.join("etc/machine-id"), machine_id).unwrap();
+        seed_empty_root(temp_root_dir, None).unwrap();
+        assert_eq!(read_hostid(Some(temp_root_dir)).unwrap(), None);
+        assert_eq!(
+            std::fs::read_to_string(temp_root_dir.join("etc/machine-id")).unwrap(),
+            machine_id
+        );
+    }
+
     #[test]
     fn test_nvlist() {
         assert!(
@@ -1906,6 +4561,11 @@ mod ffi {
         _opaque: [u8; 0],
     }
 
+    #[repr(C)]
+    pub struct NvPair {
+        _opaque: [u8; 0],
+    }
+
     // ZFS type constants from sys/fs/zfs.h
     pub const ZFS_TYPE_FILESYSTEM: c_int = 1 << 0;
     pub const ZFS_TYPE_SNAPSHOT: c_int = 1 << 1;
@@ -1913,9 +4573,36 @@ mod ffi {
     // ZFS property constants from sys/fs/zfs.h
     pub const ZFS_PROP_CREATION: c_int = 1;
     pub const ZFS_PROP_USED: c_int = 2;
+    pub const ZFS_PROP_REFERENCED: c_int = 4;
+    pub const ZFS_PROP_COMPRESSRATIO: c_int = 5;
+    pub const ZFS_PROP_ORIGIN: c_int = 7;
     pub const ZFS_PROP_MOUNTPOINT: c_int = 13;
+    pub const ZFS_PROP_CREATETXG: c_int = 26;
     pub const ZFS_PROP_CANMOUNT: c_int = 28;
     pub const ZFS_PROP_GUID: c_int = 42;
+    pub const ZFS_PROP_USEDSNAP: c_int = 45;
+    pub const ZFS_PROP_USEDDS: c_int = 46;
+    pub const ZFS_PROP_WRITTEN: c_int = 61;
+    pub const ZFS_PROP_RECEIVE_RESUME_TOKEN: c_int = 81;
+
+    // Property source constants from sys/fs/zfs.h
+    pub const ZPROP_SRC_DEFAULT: c_int = 0x2;
+    pub const ZPROP_SRC_TEMPORARY: c_int = 0x4;
+    pub const ZPROP_SRC_LOCAL: c_int = 0x8;
+    pub const ZPROP_SRC_INHERITED: c_int = 0x10;
+    pub const ZPROP_SRC_RECEIVED: c_int = 0x20;
+
+    // Special property IDs and iterator return values from sys/fs/zfs.h
+    pub const ZPROP_CONT: c_int = -2;
+    pub const ZPROP_INVAL: c_int = -1;
+
+    // Flags for lzc_send() from libzfs_core.h
+    pub const LZC_SEND_FLAG_EMBED_DATA: c_int = 1 << 0;
+    pub const LZC_SEND_FLAG_LARGE_BLOCK: c_int = 1 << 1;
+
+    // Flags for zfs_show_diffs() from libzfs.h
+    pub const ZFS_DIFF_PARSEABLE: c_int = 1 << 0;
+    pub const ZFS_DIFF_CLASSIFY: c_int = 1 << 2;
 
     // ZPool property constants from sys/fs/zfs.h
     pub const ZPOOL_PROP_BOOTFS: c_int = 7;
@@ -1929,6 +4616,7 @@ mod ffi {
 
     // Rename flags structure matching libzfs.h
     #[repr(C)]
+    #[derive(Clone, Copy)]
     pub struct RenameFlags {
         pub recursive: c_uint,    // : 1 bit field
         pub nounmount: c_uint,    // : 1 bit field
@@ -1978,9 +4666,9 @@ mod ffi {
         // Mount operations
         pub fn zfs_mount_at(
             zhp: *mut ZfsHandle,
-            path: *const c_char,
+            options: *const c_char,
             flags: c_int,
-            fstype: *const c_char,
+            mountpoint: *const c_char,
         ) -> c_int;
         pub fn zfs_unmount(zhp: *mut ZfsHandle, mountpoint: *const c_char, flags: c_int) -> c_int;
         pub fn zfs_is_mounted(zhp: *mut ZfsHandle, where_: *mut *mut c_char) -> c_int;
@@ -1988,6 +4676,50 @@ mod ffi {
         // Rename operation
         pub fn zfs_rename(zhp: *mut ZfsHandle, target: *const c_char, flags: RenameFlags) -> c_int;
 
+        pub fn zfs_show_diffs(
+            zhp: *mut ZfsHandle,
+            outfd: c_int,
+            fromsnap: *const c_char,
+            tosnap: *const c_char,
+            flags: c_int,
+        ) -> c_int;
+
+        // Send and receive, from libzfs_core
+        pub fn lzc_send(
+            snapname: *const c_char,
+            fromsnap: *const c_char,
+            fd: c_int,
+            flags: c_int, // enum lzc_send_flags
+        ) -> c_int;
+        pub fn lzc_receive(
+            snapname: *const c_char,
+            props: *mut NvList,
+            origin: *const c_char,
+            force: c_int, // boolean_t
+            raw: c_int,   // boolean_t
+            fd: c_int,
+        ) -> c_int;
+        pub fn lzc_send_resume(
+            snapname: *const c_char,
+            fromsnap: *const c_char,
+            fd: c_int,
+            flags: c_int, // enum lzc_send_flags
+            resumeobj: u64,
+            resumeoff: u64,
+        ) -> c_int;
+        pub fn lzc_receive_resumable(
+            snapname: *const c_char,
+            props: *mut NvList,
+            origin: *const c_char,
+            force: c_int, // boolean_t
+            raw: c_int,   // boolean_t
+            fd: c_int,
+        ) -> c_int;
+        pub fn zfs_send_resume_token_to_nvlist(
+            hdl: *mut LibzfsHandle,
+            token: *const c_char,
+        ) -> *mut NvList;
+
         // Rollback operation
         pub fn zfs_rollback(zhp: *mut ZfsHandle, snap: *mut ZfsHandle, force: c_int) -> c_int;
 
@@ -2025,6 +4757,8 @@ mod ffi {
             buf: *mut c_char,
             len: usize,
             source: *mut c_int,
+            statbuf: *mut c_char,
+            statlen: usize,
             literal: c_int,
         ) -> c_int;
         pub fn zfs_prop_get_numeric(
@@ -2041,6 +4775,41 @@ mod ffi {
             propname: *const c_char,
             propval: *const c_char,
         ) -> c_int;
+        pub fn zfs_prop_set_list(zhp: *mut ZfsHandle, props: *mut NvList) -> c_int;
+        pub fn zfs_prop_inherit(
+            zhp: *mut ZfsHandle,
+            propname: *const c_char,
+            received: c_int, // boolean_t
+        ) -> c_int;
+        pub fn zfs_get_type(zhp: *mut ZfsHandle) -> c_int; // zfs_type_t
+        pub fn zfs_name_to_prop(propname: *const c_char) -> ZfsProp;
+        pub fn zfs_prop_to_name(prop: ZfsProp) -> *const c_char;
+        pub fn zfs_prop_user(name: *const c_char) -> c_int; // boolean_t
+        pub fn zfs_prop_readonly(prop: ZfsProp) -> c_int; // boolean_t
+        pub fn zfs_prop_setonce(prop: ZfsProp) -> c_int; // boolean_t
+        pub fn zprop_iter(
+            func: extern "C" fn(c_int, *mut c_void) -> c_int,
+            cb: *mut c_void,
+            show_all: c_int, // boolean_t
+            ordered: c_int,  // boolean_t
+            zfs_type: c_int, // zfs_type_t
+        ) -> c_int;
+
+        // Hold functions
+        pub fn zfs_hold(
+            zhp: *mut ZfsHandle,
+            snapname: *const c_char,
+            tag: *const c_char,
+            recursive: c_int, // boolean_t
+            cleanup_fd: c_int,
+        ) -> c_int;
+        pub fn zfs_release(
+            zhp: *mut ZfsHandle,
+            snapname: *const c_char,
+            tag: *const c_char,
+            recursive: c_int, // boolean_t
+        ) -> c_int;
+        pub fn zfs_get_holds(zhp: *mut ZfsHandle, nvl: *mut *mut NvList) -> c_int;
 
         // Utility functions
         pub fn zfs_nicebytes(bytes: u64, buf: *mut c_char, len: usize);
@@ -2062,11 +4831,16 @@ mod ffi {
             name: *const c_char,
             val: *mut *mut NvList,
         ) -> c_int;
+        pub fn nvlist_lookup_uint64(nvl: *mut NvList, name: *const c_char, val: *mut u64) -> c_int;
+        pub fn nvlist_exists(nvl: *mut NvList, name: *const c_char) -> c_int; // boolean_t
+        pub fn nvlist_next_nvpair(nvl: *mut NvList, nvp: *mut NvPair) -> *mut NvPair;
+        pub fn nvpair_name(nvp: *mut NvPair) -> *const c_char;
         pub fn nvlist_free(nvl: *mut NvList);
 
         // ZPool functions
         pub fn zpool_open(hdl: *mut LibzfsHandle, name: *const c_char) -> *mut ZpoolHandle;
         pub fn zpool_close(zhp: *mut ZpoolHandle);
+        pub fn zpool_get_config(zhp: *mut ZpoolHandle, oldconfig: *mut *mut NvList) -> *mut NvList;
         pub fn zpool_get_prop(
             zhp: *mut ZpoolHandle,
             prop: ZpoolProp,
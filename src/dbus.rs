@@ -5,6 +5,7 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use std::collections::{BTreeMap, HashMap, HashSet};
+use std::os::fd::{AsFd, BorrowedFd};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::{Arc, Mutex, RwLock};
@@ -14,9 +15,14 @@ use tracing;
 use tracing_subscriber;
 use zbus::object_server::SignalEmitter;
 use zbus::{blocking, interface};
-use zvariant::ObjectPath;
+use zvariant::{DeserializeDict, ObjectPath, SerializeDict, Type};
 
-use crate::be::{BootEnvironment, Client, Error, Label, MountMode, Root, Snapshot};
+use crate::audit::{self, AuditedClient, Caller, Outcome};
+use crate::be::{
+    BootEnvironment, BootRecord, ChildDataset, Client, DestroyPlan, Error, FileChange, Finding,
+    HealthIssue, Label, MountMode, MountOption, MountOptions, Property, Pruned, Replication,
+    RetentionPolicy, RolledBack, Root, Snapshot, SpaceUsage,
+};
 
 // D-Bus service constants
 const SERVICE_NAME: &str = "ca.kamacite.BootEnvironments1";
@@ -118,8 +124,10 @@ impl Client for ClientProxy {
         target: &Label,
         force_unmount: bool,
         snapshots: bool,
+        unpin: bool,
+        dry_run: bool,
         root: Option<&Root>,
-    ) -> Result<(), Error> {
+    ) -> Result<DestroyPlan, Error> {
         let beroot = root.map(|r| r.as_str()).unwrap_or_default();
         match target {
             Label::Name(name) => self.connection.call_method(
@@ -127,30 +135,54 @@ impl Client for ClientProxy {
                 BOOT_ENV_PATH,
                 Some(MANAGER_INTERFACE),
                 "Destroy",
-                &(name, force_unmount, snapshots, beroot),
+                &(
+                    name,
+                    DestroyOptions {
+                        force_unmount,
+                        snapshots,
+                        unpin,
+                        dry_run,
+                    },
+                    beroot,
+                ),
             ),
             Label::Snapshot(name, snapshot) => self.connection.call_method(
                 Some(SERVICE_NAME),
                 BOOT_ENV_PATH,
                 Some(MANAGER_INTERFACE),
                 "DestroySnapshot",
-                &(name, snapshot, beroot),
+                &(
+                    name,
+                    snapshot,
+                    DestroyOptions {
+                        unpin,
+                        dry_run,
+                        ..DestroyOptions::default()
+                    },
+                    beroot,
+                ),
             ),
-        }?;
-        Ok(())
+        }?
+        .body()
+        .deserialize()
+        .map_err(From::from)
     }
 
     fn mount(
         &self,
         be_name: &str,
         mountpoint: Option<&Path>,
-        mode: MountMode,
+        options: &MountOptions,
         root: Option<&Root>,
     ) -> Result<PathBuf, Error> {
         let beroot = root.map(|r| r.as_str()).unwrap_or_default();
-        let read_only = match mode {
-            MountMode::ReadOnly => true,
-            MountMode::ReadWrite => false,
+        let args = MountArgs {
+            read_only: options.read_only(),
+            options: options
+                .options
+                .iter()
+                .map(|o| o.as_str().to_string())
+                .collect(),
         };
         let mountpoint = mountpoint.map_or("".to_string(), |mp| mp.to_string_lossy().to_string());
         self.connection
@@ -159,7 +191,7 @@ impl Client for ClientProxy {
                 BOOT_ENV_PATH,
                 Some(MANAGER_INTERFACE),
                 "Mount",
-                &(be_name, mountpoint, read_only, beroot),
+                &(be_name, mountpoint, args, beroot),
             )?
             .body()
             .deserialize()
@@ -209,42 +241,244 @@ impl Client for ClientProxy {
         Ok(())
     }
 
-    fn activate(&self, be_name: &str, temporary: bool, root: Option<&Root>) -> Result<(), Error> {
+    fn rename_snapshot(
+        &self,
+        be_name: &str,
+        snapshot: &str,
+        new_snapshot: &str,
+        root: Option<&Root>,
+    ) -> Result<(), Error> {
         let beroot = root.map(|r| r.as_str()).unwrap_or_default();
         self.connection.call_method(
             Some(SERVICE_NAME),
             BOOT_ENV_PATH,
             Some(MANAGER_INTERFACE),
-            "Activate",
-            &(be_name, temporary, beroot),
+            "RenameSnapshot",
+            &(be_name, snapshot, new_snapshot, beroot),
         )?;
         Ok(())
     }
 
-    fn clear_boot_once(&self, root: Option<&Root>) -> Result<(), Error> {
+    fn diff(
+        &self,
+        from: &Label,
+        to: Option<&Label>,
+        paths: &[PathBuf],
+        root: Option<&Root>,
+    ) -> Result<Vec<FileChange>, Error> {
+        let from_str = from.to_string();
+        let to_str = to.map(|label| label.to_string()).unwrap_or_default();
+        let paths: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+        let beroot = root.map(|r| r.as_str()).unwrap_or_default();
+        let changes: Vec<FileChangeTuple> = self
+            .connection
+            .call_method(
+                Some(SERVICE_NAME),
+                BOOT_ENV_PATH,
+                Some(MANAGER_INTERFACE),
+                "Diff",
+                &(from_str, to_str, paths, beroot),
+            )?
+            .body()
+            .deserialize()?;
+        changes.into_iter().map(file_change_from_tuple).collect()
+    }
+
+    fn export(
+        &self,
+        target: &Label,
+        base: Option<&str>,
+        fd: BorrowedFd<'_>,
+        root: Option<&Root>,
+    ) -> Result<String, Error> {
+        let beroot = root.map(|r| r.as_str()).unwrap_or_default();
+        let snapshot: String = self
+            .connection
+            .call_method(
+                Some(SERVICE_NAME),
+                BOOT_ENV_PATH,
+                Some(MANAGER_INTERFACE),
+                "Export",
+                &(
+                    target.to_string(),
+                    base.unwrap_or_default(),
+                    zvariant::Fd::from(fd),
+                    beroot,
+                ),
+            )?
+            .body()
+            .deserialize()?;
+        Ok(snapshot)
+    }
+
+    fn import(&self, be_name: &str, fd: BorrowedFd<'_>, root: Option<&Root>) -> Result<(), Error> {
         let beroot = root.map(|r| r.as_str()).unwrap_or_default();
         self.connection.call_method(
             Some(SERVICE_NAME),
             BOOT_ENV_PATH,
             Some(MANAGER_INTERFACE),
-            "ClearBootOnce",
+            "Import",
+            &(be_name, zvariant::Fd::from(fd), beroot),
+        )?;
+        Ok(())
+    }
+
+    fn replicate(
+        &self,
+        dest: &Root,
+        names: &[String],
+        root: Option<&Root>,
+    ) -> Result<Vec<Replication>, Error> {
+        let beroot = root.map(|r| r.as_str()).unwrap_or_default();
+        self.connection
+            .call_method(
+                Some(SERVICE_NAME),
+                BOOT_ENV_PATH,
+                Some(MANAGER_INTERFACE),
+                "Replicate",
+                &(dest.as_str(), names, beroot),
+            )?
+            .body()
+            .deserialize()
+            .map_err(From::from)
+    }
+
+    fn check(&self, be_name: &str, root: Option<&Root>) -> Result<Vec<HealthIssue>, Error> {
+        let beroot = root.map(|r| r.as_str()).unwrap_or_default();
+        self.connection
+            .call_method(
+                Some(SERVICE_NAME),
+                BOOT_ENV_PATH,
+                Some(MANAGER_INTERFACE),
+                "Check",
+                &(be_name, beroot),
+            )?
+            .body()
+            .deserialize()
+            .map_err(From::from)
+    }
+
+    fn doctor(&self, fix: bool, root: Option<&Root>) -> Result<Vec<Finding>, Error> {
+        let beroot = root.map(|r| r.as_str()).unwrap_or_default();
+        self.connection
+            .call_method(
+                Some(SERVICE_NAME),
+                BOOT_ENV_PATH,
+                Some(MANAGER_INTERFACE),
+                "Doctor",
+                &(fix, beroot),
+            )?
+            .body()
+            .deserialize()
+            .map_err(From::from)
+    }
+
+    // The service checks boot environments before activating them, so these
+    // fail if the checks do.
+
+    fn activate(&self, be_name: &str, temporary: bool, root: Option<&Root>) -> Result<(), Error> {
+        self.activate_checked(be_name, temporary, false, root)?;
+        Ok(())
+    }
+
+    fn activate_trial(&self, be_name: &str, root: Option<&Root>) -> Result<(), Error> {
+        self.activate_trial_checked(be_name, false, root)?;
+        Ok(())
+    }
+
+    fn cancel_trial(&self, root: Option<&Root>) -> Result<(), Error> {
+        let beroot = root.map(|r| r.as_str()).unwrap_or_default();
+        self.connection.call_method(
+            Some(SERVICE_NAME),
+            BOOT_ENV_PATH,
+            Some(MANAGER_INTERFACE),
+            "CancelTrial",
             &(beroot,),
         )?;
         Ok(())
     }
 
-    fn rollback(&self, be_name: &str, snapshot: &str, root: Option<&Root>) -> Result<(), Error> {
+    fn confirm(&self, root: Option<&Root>) -> Result<Option<String>, Error> {
+        let beroot = root.map(|r| r.as_str()).unwrap_or_default();
+        let name: String = self
+            .connection
+            .call_method(
+                Some(SERVICE_NAME),
+                BOOT_ENV_PATH,
+                Some(MANAGER_INTERFACE),
+                "Confirm",
+                &(beroot,),
+            )?
+            .body()
+            .deserialize()?;
+        Ok(Some(name).filter(|name| !name.is_empty()))
+    }
+
+    fn record_boot(&self, _root: Option<&Root>) -> Result<Option<BootRecord>, Error> {
+        // Boots are only recorded by the early-boot service, which uses libzfs
+        // directly.
+        Err(zbus::Error::Unsupported.into())
+    }
+
+    fn get_boot_history(&self, root: Option<&Root>) -> Result<Vec<BootRecord>, Error> {
+        let beroot = root.map(|r| r.as_str()).unwrap_or_default();
+        self.connection
+            .call_method(
+                Some(SERVICE_NAME),
+                BOOT_ENV_PATH,
+                Some(MANAGER_INTERFACE),
+                "GetBootHistory",
+                &(beroot,),
+            )?
+            .body()
+            .deserialize()
+            .map_err(From::from)
+    }
+
+    fn clear_boot_once(&self, root: Option<&Root>) -> Result<(), Error> {
         let beroot = root.map(|r| r.as_str()).unwrap_or_default();
         self.connection.call_method(
             Some(SERVICE_NAME),
             BOOT_ENV_PATH,
             Some(MANAGER_INTERFACE),
-            "Rollback",
-            &(be_name, snapshot, beroot),
+            "ClearBootOnce",
+            &(beroot,),
         )?;
         Ok(())
     }
 
+    fn rollback(
+        &self,
+        be_name: &str,
+        snapshot: &str,
+        destroy_newer: bool,
+        force: bool,
+        safety_snapshot: bool,
+        root: Option<&Root>,
+    ) -> Result<RolledBack, Error> {
+        let beroot = root.map(|r| r.as_str()).unwrap_or_default();
+        self.connection
+            .call_method(
+                Some(SERVICE_NAME),
+                BOOT_ENV_PATH,
+                Some(MANAGER_INTERFACE),
+                "Rollback",
+                &(
+                    be_name,
+                    snapshot,
+                    RollbackOptions {
+                        destroy_newer,
+                        force,
+                        safety_snapshot,
+                    },
+                    beroot,
+                ),
+            )?
+            .body()
+            .deserialize()
+            .map_err(From::from)
+    }
+
     fn get_boot_environments(&self, root: Option<&Root>) -> Result<Vec<BootEnvironment>, Error> {
         let root = match root.or(self.active_root.as_ref()) {
             Some(root) => root,
@@ -268,7 +502,10 @@ impl Client for ClientProxy {
         for (_path, interfaces) in managed_objects {
             if let Some(be) = interfaces.get(BOOT_ENV_INTERFACE) {
                 if be.root == *root {
-                    boot_environments.push(be.clone());
+                    let mut be = be.clone();
+                    // The Origin property is empty when there is no origin.
+                    be.origin = be.origin.filter(|origin| !origin.is_empty());
+                    boot_environments.push(be);
                 }
             }
         }
@@ -277,7 +514,7 @@ impl Client for ClientProxy {
 
     fn get_snapshots(&self, be_name: &str, root: Option<&Root>) -> Result<Vec<Snapshot>, Error> {
         let beroot = root.map(|r| r.as_str()).unwrap_or_default();
-        let snapshots_data: Vec<(String, Root, String, u64, i64)> = self
+        let snapshots: Vec<SnapshotTuple> = self
             .connection
             .call_method(
                 Some(SERVICE_NAME),
@@ -288,23 +525,38 @@ impl Client for ClientProxy {
             )?
             .body()
             .deserialize()?;
+        Ok(snapshots.into_iter().map(snapshot_from_tuple).collect())
+    }
 
-        let snapshots = snapshots_data
-            .into_iter()
-            .map(|(name, root, description, space, created)| Snapshot {
-                name,
-                root,
-                description: if description.is_empty() {
-                    None
-                } else {
-                    Some(description)
-                },
-                space,
-                created,
-            })
-            .collect();
+    fn get_orphaned_snapshots(&self, root: Option<&Root>) -> Result<Vec<Snapshot>, Error> {
+        let beroot = root.map(|r| r.as_str()).unwrap_or_default();
+        let snapshots: Vec<SnapshotTuple> = self
+            .connection
+            .call_method(
+                Some(SERVICE_NAME),
+                BOOT_ENV_PATH,
+                Some(MANAGER_INTERFACE),
+                "GetOrphanedSnapshots",
+                &(beroot,),
+            )?
+            .body()
+            .deserialize()?;
+        Ok(snapshots.into_iter().map(snapshot_from_tuple).collect())
+    }
 
-        Ok(snapshots)
+    fn get_datasets(&self, be_name: &str, root: Option<&Root>) -> Result<Vec<ChildDataset>, Error> {
+        let beroot = root.map(|r| r.as_str()).unwrap_or_default();
+        self.connection
+            .call_method(
+                Some(SERVICE_NAME),
+                BOOT_ENV_PATH,
+                Some(MANAGER_INTERFACE),
+                "GetDatasets",
+                &(be_name, beroot),
+            )?
+            .body()
+            .deserialize()
+            .map_err(From::from)
     }
 
     fn snapshot(
@@ -358,9 +610,167 @@ impl Client for ClientProxy {
         Ok(())
     }
 
+    fn pin(&self, target: &Label, root: Option<&Root>) -> Result<(), Error> {
+        let target_str = target.to_string();
+        let beroot = root.map(|r| r.as_str()).unwrap_or_default();
+        self.connection.call_method(
+            Some(SERVICE_NAME),
+            BOOT_ENV_PATH,
+            Some(MANAGER_INTERFACE),
+            "Pin",
+            &(target_str, beroot),
+        )?;
+        Ok(())
+    }
+
+    fn unpin(&self, target: &Label, root: Option<&Root>) -> Result<(), Error> {
+        let target_str = target.to_string();
+        let beroot = root.map(|r| r.as_str()).unwrap_or_default();
+        self.connection.call_method(
+            Some(SERVICE_NAME),
+            BOOT_ENV_PATH,
+            Some(MANAGER_INTERFACE),
+            "Unpin",
+            &(target_str, beroot),
+        )?;
+        Ok(())
+    }
+
+    fn tag(
+        &self,
+        target: &Label,
+        key: &str,
+        value: &str,
+        root: Option<&Root>,
+    ) -> Result<(), Error> {
+        let target_str = target.to_string();
+        let beroot = root.map(|r| r.as_str()).unwrap_or_default();
+        self.connection.call_method(
+            Some(SERVICE_NAME),
+            BOOT_ENV_PATH,
+            Some(MANAGER_INTERFACE),
+            "Tag",
+            &(target_str, key, value, beroot),
+        )?;
+        Ok(())
+    }
+
+    fn untag(&self, target: &Label, key: &str, root: Option<&Root>) -> Result<(), Error> {
+        let target_str = target.to_string();
+        let beroot = root.map(|r| r.as_str()).unwrap_or_default();
+        self.connection.call_method(
+            Some(SERVICE_NAME),
+            BOOT_ENV_PATH,
+            Some(MANAGER_INTERFACE),
+            "Untag",
+            &(target_str, key, beroot),
+        )?;
+        Ok(())
+    }
+
+    fn get_zfs_properties(
+        &self,
+        target: &Label,
+        names: &[String],
+        root: Option<&Root>,
+    ) -> Result<Vec<Property>, Error> {
+        let target_str = target.to_string();
+        let beroot = root.map(|r| r.as_str()).unwrap_or_default();
+        self.connection
+            .call_method(
+                Some(SERVICE_NAME),
+                BOOT_ENV_PATH,
+                Some(MANAGER_INTERFACE),
+                "GetZfsProperties",
+                &(target_str, names, beroot),
+            )?
+            .body()
+            .deserialize()
+            .map_err(From::from)
+    }
+
+    fn set_zfs_properties(
+        &self,
+        be_name: &str,
+        properties: &[String],
+        root: Option<&Root>,
+    ) -> Result<(), Error> {
+        let beroot = root.map(|r| r.as_str()).unwrap_or_default();
+        self.connection.call_method(
+            Some(SERVICE_NAME),
+            BOOT_ENV_PATH,
+            Some(MANAGER_INTERFACE),
+            "SetZfsProperties",
+            &(be_name, properties, beroot),
+        )?;
+        Ok(())
+    }
+
     fn active_root(&self) -> Option<&Root> {
         self.active_root.as_ref()
     }
+
+    fn prune(
+        &self,
+        policy: &RetentionPolicy,
+        boot_environments: bool,
+        dry_run: bool,
+        root: Option<&Root>,
+    ) -> Result<Vec<Pruned>, Error> {
+        let beroot = root.map(|r| r.as_str()).unwrap_or_default();
+        self.connection
+            .call_method(
+                Some(SERVICE_NAME),
+                BOOT_ENV_PATH,
+                Some(MANAGER_INTERFACE),
+                "Prune",
+                &(policy, boot_environments, dry_run, beroot),
+            )?
+            .body()
+            .deserialize()
+            .map_err(From::from)
+    }
+
+    fn activate_checked(
+        &self,
+        be_name: &str,
+        temporary: bool,
+        force: bool,
+        root: Option<&Root>,
+    ) -> Result<Vec<HealthIssue>, Error> {
+        let beroot = root.map(|r| r.as_str()).unwrap_or_default();
+        self.connection
+            .call_method(
+                Some(SERVICE_NAME),
+                BOOT_ENV_PATH,
+                Some(MANAGER_INTERFACE),
+                "Activate",
+                &(be_name, temporary, force, beroot),
+            )?
+            .body()
+            .deserialize()
+            .map_err(From::from)
+    }
+
+    fn activate_trial_checked(
+        &self,
+        be_name: &str,
+        force: bool,
+        root: Option<&Root>,
+    ) -> Result<Vec<HealthIssue>, Error> {
+        let beroot = root.map(|r| r.as_str()).unwrap_or_default();
+        self.connection
+            .call_method(
+                Some(SERVICE_NAME),
+                BOOT_ENV_PATH,
+                Some(MANAGER_INTERFACE),
+                "ActivateTrial",
+                &(be_name, force, beroot),
+            )?
+            .body()
+            .deserialize()
+            .map_err(From::from)
+    }
 }
 
 // ============================================================================
@@ -382,6 +792,11 @@ impl<T: Client + 'static> BootEnvironmentObject<T> {
         }
     }
 
+    /// The client, auditing changes on behalf of an authorized caller.
+    fn audited(&self, caller: Caller) -> AuditedClient<&T> {
+        AuditedClient::new(self.client.as_ref(), caller)
+    }
+
     /// Synchronize the object with the current state of the boot environment
     /// and emit property changed signals as needed.
     pub async fn sync(
@@ -397,6 +812,10 @@ impl<T: Client + 'static> BootEnvironmentObject<T> {
             mountpoint: bool,
             next_boot: bool,
             boot_once: bool,
+            trial: bool,
+            pinned: bool,
+            tags: bool,
+            origin: bool,
         }
         let changed = self
             .data
@@ -408,19 +827,15 @@ impl<T: Client + 'static> BootEnvironmentObject<T> {
                 mountpoint: stored.mountpoint != current.mountpoint,
                 next_boot: stored.next_boot != current.next_boot,
                 boot_once: stored.boot_once != current.boot_once,
+                trial: stored.trial != current.trial,
+                pinned: stored.pinned != current.pinned,
+                tags: stored.tags != current.tags,
+                origin: stored.origin != current.origin,
             })
             .expect("Failed to acquire read lock");
 
-        if !(changed.name
-            || changed.root
-            || changed.description
-            || changed.mountpoint
-            || changed.next_boot
-            || changed.boot_once)
-        {
-            return Ok(());
-        }
-
+        // Always store the current data, since properties that don't emit
+        // change signals (like space usage) may still have changed.
         {
             *self.data.write().expect("Failed to acquire write lock") = current;
         } // Write lock dropped.
@@ -445,6 +860,18 @@ impl<T: Client + 'static> BootEnvironmentObject<T> {
         if changed.boot_once {
             self.boot_once_changed(signal_emitter).await?;
         }
+        if changed.trial {
+            self.trial_changed(signal_emitter).await?;
+        }
+        if changed.pinned {
+            self.pinned_changed(signal_emitter).await?;
+        }
+        if changed.tags {
+            self.tags_changed(signal_emitter).await?;
+        }
+        if changed.origin {
+            self.origin_changed(signal_emitter).await?;
+        }
 
         Ok(())
     }
@@ -525,76 +952,179 @@ impl<T: Client + 'static> BootEnvironmentObject<T> {
         self.data.read().unwrap().boot_once
     }
 
+    /// Whether this boot environment is on trial, pending confirmation.
+    #[zbus(property)]
+    fn trial(&self) -> bool {
+        self.data.read().unwrap().trial
+    }
+
+    /// Whether this boot environment is pinned, protecting it from being
+    /// destroyed.
+    #[zbus(property)]
+    fn pinned(&self) -> bool {
+        self.data.read().unwrap().pinned
+    }
+
+    /// User-defined tags, by key.
+    #[zbus(property)]
+    fn tags(&self) -> HashMap<String, String> {
+        self.data.read().unwrap().tags.clone().into_iter().collect()
+    }
+
     /// Bytes on the filesystem associated with this boot environment.
     #[zbus(property(emits_changed_signal = "false"))]
     fn space(&self) -> u64 {
         self.data.read().unwrap().space
     }
 
+    /// A breakdown of the space used by this boot environment.
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn usage(&self) -> SpaceUsage {
+        self.data.read().unwrap().usage.clone()
+    }
+
+    /// The snapshot this boot environment was cloned from, if any.
+    #[zbus(property)]
+    fn origin(&self) -> String {
+        self.data.read().unwrap().origin.clone().unwrap_or_default()
+    }
+
     /// Unix timestamp for when this boot environment was created.
     #[zbus(property(emits_changed_signal = "const"))]
     fn created(&self) -> i64 {
         self.data.read().unwrap().created
     }
 
-    /// Mark this boot environment as the default root filesystem.
+    /// Mark this boot environment as the default root filesystem, once it
+    /// has passed health checks (unless `force` is set). Returns any issues
+    /// found, which are only warnings if it was activated.
+    #[zbus(out_args("issues"))]
     async fn activate(
         &self,
         temporary: bool,
+        force: bool,
         #[zbus(header)] header: zbus::message::Header<'_>,
         #[zbus(connection)] conn: &zbus::Connection,
-    ) -> zbus::fdo::Result<()> {
-        check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
-        {
+    ) -> zbus::fdo::Result<Vec<HealthIssue>> {
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
+        let issues = {
             let data = self.data.read().unwrap();
-            self.client
-                .activate(&data.name, temporary, Some(&data.root))?;
+            let issues = client.activate_checked(&data.name, temporary, force, Some(&data.root))?;
             tracing::info!(name = data.name, temporary, "Activated boot environment");
-        }
-        self.refresh(conn).await
+            issues
+        };
+        self.refresh(conn).await?;
+        Ok(issues)
     }
 
     /// Destroy this boot environment.
+    ///
+    /// Pinned boot environments and snapshots are only destroyed with
+    /// `Unpin`. With `DryRun`, nothing is changed and the returned plan
+    /// describes what would be done.
+    #[zbus(out_args("plan"))]
     async fn destroy(
         &self,
-        force_unmount: bool,
-        snapshots: bool,
+        options: DestroyOptions,
         #[zbus(header)] header: zbus::message::Header<'_>,
         #[zbus(connection)] conn: &zbus::Connection,
-    ) -> zbus::fdo::Result<()> {
-        check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
-        {
+    ) -> zbus::fdo::Result<DestroyPlan> {
+        let DestroyOptions {
+            force_unmount,
+            snapshots,
+            unpin,
+            dry_run,
+        } = options;
+        // Dry runs only reveal what can already be queried without privileges.
+        if dry_run {
+            let data = self.data.read().unwrap();
+            return Ok(self.client.destroy(
+                &Label::Name(data.name.clone()),
+                force_unmount,
+                snapshots,
+                unpin,
+                true,
+                Some(&data.root),
+            )?);
+        }
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
+        let plan = {
             let data = self.data.read().unwrap();
-            self.client.destroy(
+            let plan = client.destroy(
                 &Label::Name(data.name.clone()),
                 force_unmount,
                 snapshots,
+                unpin,
+                false,
                 Some(&data.root),
             )?;
             tracing::info!(
                 name = data.name,
                 force_unmount,
                 snapshots,
+                unpin,
+                promoted = ?plan.promote,
                 "Destroyed boot environment"
             );
-        }
-        self.refresh(conn).await
+            plan
+        };
+        self.refresh(conn).await?;
+        Ok(plan)
     }
 
     /// Destroy a snapshot of this boot environment.
+    ///
+    /// A pinned snapshot is only destroyed with `Unpin`. With `DryRun`,
+    /// nothing is changed and the returned plan describes what would be done.
+    #[zbus(out_args("plan"))]
     async fn destroy_snapshot(
         &self,
         snapshot: &str,
+        options: DestroyOptions,
         #[zbus(header)] header: zbus::message::Header<'_>,
         #[zbus(connection)] conn: &zbus::Connection,
-    ) -> zbus::fdo::Result<()> {
-        check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
-        {
+    ) -> zbus::fdo::Result<DestroyPlan> {
+        let DestroyOptions { unpin, dry_run, .. } = options;
+        if dry_run {
             let data = self.data.read().unwrap();
             let label = Label::Snapshot(data.name.clone(), snapshot.to_string());
-            self.client
-                .destroy(&label, false, false, Some(&data.root))?;
+            return Ok(self
+                .client
+                .destroy(&label, false, false, unpin, true, Some(&data.root))?);
+        }
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
+        let plan = {
+            let data = self.data.read().unwrap();
+            let label = Label::Snapshot(data.name.clone(), snapshot.to_string());
+            let plan = client.destroy(&label, false, false, unpin, false, Some(&data.root))?;
             tracing::info!(snapshot = label.to_string(), "Destroyed snapshot");
+            plan
+        };
+        self.refresh(conn).await?;
+        Ok(plan)
+    }
+
+    /// Rename a snapshot of this boot environment.
+    async fn rename_snapshot(
+        &self,
+        snapshot: &str,
+        new_snapshot: &str,
+        #[zbus(header)] header: zbus::message::Header<'_>,
+        #[zbus(connection)] conn: &zbus::Connection,
+    ) -> zbus::fdo::Result<()> {
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
+        {
+            let data = self.data.read().unwrap();
+            client.rename_snapshot(&data.name, snapshot, new_snapshot, Some(&data.root))?;
+            tracing::info!(name = data.name, snapshot, new_snapshot, "Renamed snapshot");
         }
         self.refresh(conn).await
     }
@@ -603,14 +1133,13 @@ impl<T: Client + 'static> BootEnvironmentObject<T> {
     async fn mount(
         &self,
         mountpoint: &str,
-        read_only: bool,
+        options: MountArgs,
+        #[zbus(header)] header: zbus::message::Header<'_>,
         #[zbus(connection)] conn: &zbus::Connection,
     ) -> zbus::fdo::Result<()> {
-        let mode = if read_only {
-            MountMode::ReadOnly
-        } else {
-            MountMode::ReadWrite
-        };
+        let caller = identify_caller(conn, &header).await?;
+        let client = self.audited(caller);
+        let options = mount_options_from_args(options)?;
         let mountpoint = if mountpoint.is_empty() {
             None
         } else {
@@ -618,16 +1147,16 @@ impl<T: Client + 'static> BootEnvironmentObject<T> {
         };
         {
             let data = self.data.read().unwrap();
-            let result = self.client.mount(
+            let result = client.mount(
                 &data.name,
                 mountpoint.as_ref().map(|mp| mp.as_path()),
-                mode,
+                &options,
                 Some(&data.root),
             )?;
             tracing::info!(
                 name = data.name,
                 mountpoint = result.display().to_string(),
-                read_only,
+                options = options.to_option_string(),
                 "Mounted boot environment"
             );
         }
@@ -639,12 +1168,14 @@ impl<T: Client + 'static> BootEnvironmentObject<T> {
     async fn unmount(
         &self,
         force: bool,
+        #[zbus(header)] header: zbus::message::Header<'_>,
         #[zbus(connection)] conn: &zbus::Connection,
     ) -> zbus::fdo::Result<String> {
+        let caller = identify_caller(conn, &header).await?;
+        let client = self.audited(caller);
         let mountpoint = {
             let data = self.data.read().unwrap();
-            let mountpoint = self
-                .client
+            let mountpoint = client
                 .unmount(&data.name, force, Some(&data.root))?
                 .map(|p| p.display().to_string());
             tracing::info!(name = data.name, mountpoint, "Unmounted boot environment");
@@ -661,53 +1192,101 @@ impl<T: Client + 'static> BootEnvironmentObject<T> {
         #[zbus(header)] header: zbus::message::Header<'_>,
         #[zbus(connection)] conn: &zbus::Connection,
     ) -> zbus::fdo::Result<()> {
-        check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
         {
             let data = self.data.read().unwrap();
-            self.client.rename(&data.name, new_name, Some(&data.root))?;
+            client.rename(&data.name, new_name, Some(&data.root))?;
             tracing::info!(name = data.name, new_name, "Renamed boot environment");
         }
         self.refresh(conn).await
     }
 
-    /// Roll this boot environment back to a snapshot.
+    /// Roll this boot environment back to a snapshot, destroying any newer
+    /// snapshots if `DestroyNewer` is set. With `SafetySnapshot`, the
+    /// current state is kept in a new boot environment.
+    #[zbus(out_args("result"))]
     async fn rollback(
         &self,
         snapshot: &str,
+        options: RollbackOptions,
         #[zbus(header)] header: zbus::message::Header<'_>,
         #[zbus(connection)] conn: &zbus::Connection,
-    ) -> zbus::fdo::Result<()> {
-        check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
-        {
+    ) -> zbus::fdo::Result<RolledBack> {
+        let RollbackOptions {
+            destroy_newer,
+            force,
+            safety_snapshot,
+        } = options;
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
+        let rolled_back = {
             let data = self.data.read().unwrap();
-            self.client
-                .rollback(&data.name, snapshot, Some(&data.root))?;
+            let rolled_back = client.rollback(
+                &data.name,
+                snapshot,
+                destroy_newer,
+                force,
+                safety_snapshot,
+                Some(&data.root),
+            )?;
             tracing::info!(
                 name = data.name,
                 snapshot,
                 "Rolled boot environment back to snapshot"
             );
-        }
-        self.refresh(conn).await
+            rolled_back
+        };
+        self.refresh(conn).await?;
+        Ok(rolled_back)
     }
 
     /// Get snapshots for this boot environment.
     #[zbus(out_args("snapshots"))]
-    fn get_snapshots(&self) -> zbus::fdo::Result<Vec<(String, Root, String, u64, i64)>> {
+    fn get_snapshots(&self) -> zbus::fdo::Result<Vec<SnapshotTuple>> {
         let data = self.data.read().unwrap();
         let snapshots = self.client.get_snapshots(&data.name, Some(&data.root))?;
-        Ok(snapshots
-            .into_iter()
-            .map(|snap| {
-                (
-                    snap.name,
-                    snap.root,
-                    snap.description.unwrap_or_default(),
-                    snap.space,
-                    snap.created,
-                )
-            })
-            .collect())
+        Ok(snapshots.into_iter().map(snapshot_to_tuple).collect())
+    }
+
+    /// Get the descendant datasets of this boot environment.
+    #[zbus(out_args("datasets"))]
+    fn get_datasets(&self) -> zbus::fdo::Result<Vec<ChildDataset>> {
+        let data = self.data.read().unwrap();
+        Ok(self.client.get_datasets(&data.name, Some(&data.root))?)
+    }
+
+    /// Get ZFS properties of this boot environment, or all of them if no
+    /// names are given.
+    #[zbus(out_args("properties"))]
+    fn get_zfs_properties(&self, names: Vec<String>) -> zbus::fdo::Result<Vec<Property>> {
+        let data = self.data.read().unwrap();
+        Ok(self.client.get_zfs_properties(
+            &Label::Name(data.name.clone()),
+            &names,
+            Some(&data.root),
+        )?)
+    }
+
+    /// Set ZFS properties (in the form `property=value`) on this boot
+    /// environment.
+    async fn set_zfs_properties(
+        &self,
+        properties: Vec<String>,
+        #[zbus(header)] header: zbus::message::Header<'_>,
+        #[zbus(connection)] conn: &zbus::Connection,
+    ) -> zbus::fdo::Result<()> {
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
+        {
+            let data = self.data.read().unwrap();
+            client.set_zfs_properties(&data.name, &properties, Some(&data.root))?;
+            tracing::info!(name = data.name, ?properties, "Set properties");
+        }
+        self.refresh(conn).await
     }
 
     // TODO: This is probably not useful, so hide it for now.
@@ -727,7 +1306,9 @@ impl<T: Client + 'static> BootEnvironmentObject<T> {
         #[zbus(header)] header: zbus::message::Header<'_>,
         #[zbus(connection)] conn: &zbus::Connection,
     ) -> zbus::fdo::Result<String> {
-        check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
         let snapshot = {
             let data = self.data.read().unwrap();
             let label = if snapshot_name.is_empty() {
@@ -740,7 +1321,7 @@ impl<T: Client + 'static> BootEnvironmentObject<T> {
             } else {
                 None
             };
-            let snapshot = self.client.snapshot(Some(&label), desc, Some(&data.root))?;
+            let snapshot = client.snapshot(Some(&label), desc, Some(&data.root))?;
             tracing::info!(snapshot, "Created snapshot");
             snapshot
         };
@@ -755,10 +1336,12 @@ impl<T: Client + 'static> BootEnvironmentObject<T> {
         #[zbus(header)] header: zbus::message::Header<'_>,
         #[zbus(connection)] conn: &zbus::Connection,
     ) -> zbus::fdo::Result<()> {
-        check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
         {
             let data = self.data.read().unwrap();
-            self.client.describe(
+            client.describe(
                 &Label::Name(data.name.clone()),
                 description,
                 Some(&data.root),
@@ -767,6 +1350,82 @@ impl<T: Client + 'static> BootEnvironmentObject<T> {
         }
         self.refresh(conn).await
     }
+
+    /// Pin this boot environment, protecting it from being destroyed.
+    async fn pin(
+        &self,
+        #[zbus(header)] header: zbus::message::Header<'_>,
+        #[zbus(connection)] conn: &zbus::Connection,
+    ) -> zbus::fdo::Result<()> {
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
+        {
+            let data = self.data.read().unwrap();
+            client.pin(&Label::Name(data.name.clone()), Some(&data.root))?;
+            tracing::info!(name = data.name, "Pinned boot environment");
+        }
+        self.refresh(conn).await
+    }
+
+    /// Unpin this boot environment.
+    async fn unpin(
+        &self,
+        #[zbus(header)] header: zbus::message::Header<'_>,
+        #[zbus(connection)] conn: &zbus::Connection,
+    ) -> zbus::fdo::Result<()> {
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
+        {
+            let data = self.data.read().unwrap();
+            client.unpin(&Label::Name(data.name.clone()), Some(&data.root))?;
+            tracing::info!(name = data.name, "Unpinned boot environment");
+        }
+        self.refresh(conn).await
+    }
+
+    /// Set a tag on this boot environment, replacing any existing value.
+    async fn tag(
+        &self,
+        key: &str,
+        value: &str,
+        #[zbus(header)] header: zbus::message::Header<'_>,
+        #[zbus(connection)] conn: &zbus::Connection,
+    ) -> zbus::fdo::Result<()> {
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
+        {
+            let data = self.data.read().unwrap();
+            client.tag(
+                &Label::Name(data.name.clone()),
+                key,
+                value,
+                Some(&data.root),
+            )?;
+            tracing::info!(name = data.name, key, value, "Set tag");
+        }
+        self.refresh(conn).await
+    }
+
+    /// Remove a tag from this boot environment.
+    async fn untag(
+        &self,
+        key: &str,
+        #[zbus(header)] header: zbus::message::Header<'_>,
+        #[zbus(connection)] conn: &zbus::Connection,
+    ) -> zbus::fdo::Result<()> {
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
+        {
+            let data = self.data.read().unwrap();
+            client.untag(&Label::Name(data.name.clone()), key, Some(&data.root))?;
+            tracing::info!(name = data.name, key, "Removed tag");
+        }
+        self.refresh(conn).await
+    }
 }
 
 /// Main beadm manager implementing ObjectManager
@@ -786,6 +1445,11 @@ impl<T: Client> BootEnvironmentManager<T> {
             guids: Arc::new(Mutex::new(HashSet::new())),
         }
     }
+
+    /// The client, auditing changes on behalf of an authorized caller.
+    fn audited(&self, caller: Caller) -> AuditedClient<&T> {
+        AuditedClient::new(self.client.as_ref(), caller)
+    }
 }
 
 #[interface(name = "ca.kamacite.BootEnvironmentManager")]
@@ -842,38 +1506,143 @@ impl<T: Client + 'static> BootEnvironmentManager<T> {
             }
         }
 
-        *self.guids.lock().unwrap() = guids;
-        Ok(())
+        *self.guids.lock().unwrap() = guids;
+        Ok(())
+    }
+
+    /// Check that a boot environment looks bootable: that it has a kernel
+    /// and initramfs, an init and an os-release file, and a host ID matching
+    /// the pool's.
+    #[zbus(out_args("issues"))]
+    async fn check(&self, name: &str, beroot: &str) -> zbus::fdo::Result<Vec<HealthIssue>> {
+        // Like mounting, this is not a privileged operation.
+        Ok(self.client.check(name, root_from_arg(beroot)?.as_ref())?)
+    }
+
+    /// Check the boot environment layout and pool state for problems that
+    /// would only show up at boot, fixing those that can be fixed
+    /// automatically when `fix` is set.
+    #[zbus(out_args("findings"))]
+    async fn doctor(
+        &self,
+        fix: bool,
+        beroot: &str,
+        #[zbus(header)] header: zbus::message::Header<'_>,
+        #[zbus(connection)] conn: &zbus::Connection,
+    ) -> zbus::fdo::Result<Vec<Finding>> {
+        let root = root_from_arg(beroot)?;
+        if !fix {
+            // Only looking is not a privileged operation.
+            return Ok(self.client.doctor(false, root.as_ref())?);
+        }
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
+        let findings = client.doctor(true, root.as_ref())?;
+        tracing::info!(beroot, "Fixed boot environment problems");
+        self.refresh(conn.object_server()).await?;
+        Ok(findings)
+    }
+
+    /// Mark a boot environment as the default root filesystem, once it has
+    /// passed health checks (unless `force` is set). Returns any issues
+    /// found, which are only warnings if it was activated.
+    #[zbus(out_args("issues"))]
+    async fn activate(
+        &self,
+        name: &str,
+        temporary: bool,
+        force: bool,
+        beroot: &str,
+        #[zbus(header)] header: zbus::message::Header<'_>,
+        #[zbus(connection)] conn: &zbus::Connection,
+    ) -> zbus::fdo::Result<Vec<HealthIssue>> {
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
+        let root = root_from_arg(beroot)?;
+        let issues = client.activate_checked(name, temporary, force, root.as_ref())?;
+        tracing::info!(name, temporary, "Activated boot environment");
+        self.refresh(conn.object_server()).await?;
+        Ok(issues)
+    }
+
+    /// Activate a boot environment for a single trial boot, falling back to
+    /// the current default unless the boot is confirmed. It must pass health
+    /// checks first, unless `force` is set.
+    #[zbus(out_args("issues"))]
+    async fn activate_trial(
+        &self,
+        name: &str,
+        force: bool,
+        beroot: &str,
+        #[zbus(header)] header: zbus::message::Header<'_>,
+        #[zbus(connection)] conn: &zbus::Connection,
+    ) -> zbus::fdo::Result<Vec<HealthIssue>> {
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
+        let root = root_from_arg(beroot)?;
+        let issues = client.activate_trial_checked(name, force, root.as_ref())?;
+        tracing::info!(name, "Activated boot environment for a trial boot");
+        self.refresh(conn.object_server()).await?;
+        Ok(issues)
+    }
+
+    /// Confirm a trial boot, making the boot environment on trial the
+    /// default. Returns its name, or an empty string if there is no trial.
+    #[zbus(out_args("name"))]
+    async fn confirm(
+        &self,
+        beroot: &str,
+        #[zbus(header)] header: zbus::message::Header<'_>,
+        #[zbus(connection)] conn: &zbus::Connection,
+    ) -> zbus::fdo::Result<String> {
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
+        let result = client.confirm(root_from_arg(beroot)?.as_ref());
+        // A failed trial is still a change worth picking up.
+        self.refresh(conn.object_server()).await?;
+        let confirmed = result?;
+        if let Some(name) = &confirmed {
+            tracing::info!(name, "Confirmed trial boot");
+        }
+        Ok(confirmed.unwrap_or_default())
     }
 
-    /// Mark a boot environment as the default root filesystem.
-    async fn activate(
+    /// Clear any temporary boot environment activations, cancelling any
+    /// pending trial boot along with them.
+    async fn clear_temporary_activations(
         &self,
-        name: &str,
-        temporary: bool,
         beroot: &str,
         #[zbus(header)] header: zbus::message::Header<'_>,
         #[zbus(connection)] conn: &zbus::Connection,
     ) -> zbus::fdo::Result<()> {
-        check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
-        self.client
-            .activate(name, temporary, root_from_arg(beroot)?.as_ref())?;
-        tracing::info!(name, temporary, "Activated boot environment");
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
+        let root = root_from_arg(beroot)?;
+        client.clear_boot_once(root.as_ref())?;
+        client.cancel_trial(root.as_ref())?;
+        tracing::info!("Removed temporary boot environment activations");
         self.refresh(conn.object_server()).await?;
         Ok(())
     }
 
-    /// Clear any temporary boot environment activations.
-    async fn clear_temporary_activations(
+    /// Cancel a pending trial boot, leaving the temporary activation it
+    /// relies on in place.
+    async fn cancel_trial(
         &self,
         beroot: &str,
         #[zbus(header)] header: zbus::message::Header<'_>,
         #[zbus(connection)] conn: &zbus::Connection,
     ) -> zbus::fdo::Result<()> {
-        check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
-        self.client
-            .clear_boot_once(root_from_arg(beroot)?.as_ref())?;
-        tracing::info!("Removed temporary boot environment activations");
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
+        client.cancel_trial(root_from_arg(beroot)?.as_ref())?;
+        tracing::info!("Cancelled trial boot");
         self.refresh(conn.object_server()).await?;
         Ok(())
     }
@@ -890,7 +1659,9 @@ impl<T: Client + 'static> BootEnvironmentManager<T> {
         #[zbus(header)] header: zbus::message::Header<'_>,
         #[zbus(connection)] conn: &zbus::Connection,
     ) -> zbus::fdo::Result<ObjectPath<'static>> {
-        check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
         let desc = if description.is_empty() {
             None
         } else {
@@ -902,7 +1673,7 @@ impl<T: Client + 'static> BootEnvironmentManager<T> {
             Some(source.parse::<Label>()?)
         };
 
-        self.client.create(
+        client.create(
             name,
             desc,
             src.as_ref(),
@@ -911,7 +1682,7 @@ impl<T: Client + 'static> BootEnvironmentManager<T> {
         )?;
 
         // Get the newly created BE to find its GUID
-        let bes = self.client.get_boot_environments(None)?;
+        let bes = client.get_boot_environments(None)?;
         let guid = bes
             .into_iter()
             .find(|be| be.name == name)
@@ -934,24 +1705,30 @@ impl<T: Client + 'static> BootEnvironmentManager<T> {
         &self,
         name: &str,
         description: &str,
+        host_id: &str,
         properties: Vec<String>,
         beroot: &str,
         #[zbus(header)] header: zbus::message::Header<'_>,
         #[zbus(connection)] conn: &zbus::Connection,
     ) -> zbus::fdo::Result<ObjectPath<'static>> {
-        check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
         let desc = if description.is_empty() {
             None
         } else {
             Some(description)
         };
-        self.client
-            .create_empty(name, desc, None, &properties, None)?;
+        let host_id = if host_id.is_empty() {
+            None
+        } else {
+            Some(host_id)
+        };
+        let root = root_from_arg(beroot)?;
+        client.create_empty(name, desc, host_id, &properties, root.as_ref())?;
 
         // Get the newly created BE to find its GUID
-        let bes = self
-            .client
-            .get_boot_environments(root_from_arg(beroot)?.as_ref())?;
+        let bes = client.get_boot_environments(root.as_ref())?;
         let guid = bes
             .into_iter()
             .find(|be| be.name == name)
@@ -973,7 +1750,9 @@ impl<T: Client + 'static> BootEnvironmentManager<T> {
         #[zbus(header)] header: zbus::message::Header<'_>,
         #[zbus(connection)] conn: &zbus::Connection,
     ) -> zbus::fdo::Result<String> {
-        check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
         let target_opt = if target.is_empty() {
             None
         } else {
@@ -984,7 +1763,7 @@ impl<T: Client + 'static> BootEnvironmentManager<T> {
         } else {
             Some(description)
         };
-        let snapshot = self.client.snapshot(
+        let snapshot = client.snapshot(
             target_opt.as_ref(),
             desc_opt,
             root_from_arg(beroot)?.as_ref(),
@@ -995,79 +1774,247 @@ impl<T: Client + 'static> BootEnvironmentManager<T> {
     }
 
     /// Destroy an existing boot environment or snapshot.
+    ///
+    /// Pinned boot environments and snapshots are only destroyed with
+    /// `Unpin`. With `DryRun`, nothing is changed and the returned plan
+    /// describes what would be done.
+    #[zbus(out_args("plan"))]
     async fn destroy(
         &self,
         name: &str,
-        force_unmount: bool,
-        snapshots: bool,
+        options: DestroyOptions,
         beroot: &str,
         #[zbus(header)] header: zbus::message::Header<'_>,
         #[zbus(connection)] conn: &zbus::Connection,
-    ) -> zbus::fdo::Result<()> {
-        check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+    ) -> zbus::fdo::Result<DestroyPlan> {
+        let DestroyOptions {
+            force_unmount,
+            snapshots,
+            unpin,
+            dry_run,
+        } = options;
         let label = Label::Name(name.to_string());
-        self.client.destroy(
+        let root = root_from_arg(beroot)?;
+        // Dry runs only reveal what can already be queried without privileges.
+        if dry_run {
+            return Ok(self.client.destroy(
+                &label,
+                force_unmount,
+                snapshots,
+                unpin,
+                true,
+                root.as_ref(),
+            )?);
+        }
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
+        let plan = client.destroy(
             &label,
             force_unmount,
             snapshots,
-            root_from_arg(beroot)?.as_ref(),
+            unpin,
+            false,
+            root.as_ref(),
         )?;
-        tracing::info!(name, force_unmount, snapshots, "Destroyed boot environment");
+        tracing::info!(
+            name,
+            force_unmount,
+            snapshots,
+            unpin,
+            promoted = ?plan.promote,
+            "Destroyed boot environment"
+        );
         self.refresh(conn.object_server()).await?;
-        Ok(())
+        Ok(plan)
     }
 
     /// Destroy an existing boot environment snapshot.
+    ///
+    /// A pinned snapshot is only destroyed with `Unpin`. With `DryRun`,
+    /// nothing is changed and the returned plan describes what would be done.
+    #[zbus(out_args("plan"))]
     async fn destroy_snapshot(
         &self,
         name: &str,
         snapshot: &str,
+        options: DestroyOptions,
         beroot: &str,
         #[zbus(header)] header: zbus::message::Header<'_>,
         #[zbus(connection)] conn: &zbus::Connection,
-    ) -> zbus::fdo::Result<()> {
-        check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+    ) -> zbus::fdo::Result<DestroyPlan> {
+        let DestroyOptions { unpin, dry_run, .. } = options;
         let label = Label::Snapshot(name.to_string(), snapshot.to_string());
-        self.client
-            .destroy(&label, false, false, root_from_arg(beroot)?.as_ref())?;
+        let root = root_from_arg(beroot)?;
+        if dry_run {
+            return Ok(self
+                .client
+                .destroy(&label, false, false, unpin, true, root.as_ref())?);
+        }
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
+        let plan = client.destroy(&label, false, false, unpin, false, root.as_ref())?;
         tracing::info!(snapshot = label.to_string(), "Destroyed snapshot");
         self.refresh(conn.object_server()).await?;
+        Ok(plan)
+    }
+
+    /// Rename a snapshot of a boot environment.
+    async fn rename_snapshot(
+        &self,
+        name: &str,
+        snapshot: &str,
+        new_snapshot: &str,
+        beroot: &str,
+        #[zbus(header)] header: zbus::message::Header<'_>,
+        #[zbus(connection)] conn: &zbus::Connection,
+    ) -> zbus::fdo::Result<()> {
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
+        client.rename_snapshot(
+            name,
+            snapshot,
+            new_snapshot,
+            root_from_arg(beroot)?.as_ref(),
+        )?;
+        tracing::info!(name, snapshot, new_snapshot, "Renamed snapshot");
+        self.refresh(conn.object_server()).await?;
+        Ok(())
+    }
+
+    /// List the files that differ between two boot environments or
+    /// snapshots (or, when `to` is empty, the active boot environment),
+    /// optionally limited to those beneath `paths`.
+    async fn diff(
+        &self,
+        from: &str,
+        to: &str,
+        paths: Vec<String>,
+        beroot: &str,
+        #[zbus(header)] header: zbus::message::Header<'_>,
+        #[zbus(connection)] conn: &zbus::Connection,
+    ) -> zbus::fdo::Result<Vec<FileChangeTuple>> {
+        // This reveals the names of files the caller might not otherwise be
+        // able to see.
+        check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let from = from.parse::<Label>()?;
+        let to = if to.is_empty() {
+            None
+        } else {
+            Some(to.parse::<Label>()?)
+        };
+        let paths: Vec<PathBuf> = paths.into_iter().map(PathBuf::from).collect();
+        let changes =
+            self.client
+                .diff(&from, to.as_ref(), &paths, root_from_arg(beroot)?.as_ref())?;
+        Ok(changes.into_iter().map(file_change_to_tuple).collect())
+    }
+
+    /// Write a boot environment or snapshot to `fd` as an export stream,
+    /// incremental from the `base` snapshot unless it is empty. Returns the
+    /// name of the snapshot that was exported.
+    #[zbus(out_args("snapshot"))]
+    async fn export(
+        &self,
+        target: &str,
+        base: &str,
+        fd: zvariant::OwnedFd,
+        beroot: &str,
+        #[zbus(header)] header: zbus::message::Header<'_>,
+        #[zbus(connection)] conn: &zbus::Connection,
+    ) -> zbus::fdo::Result<String> {
+        // This reveals the entire contents of the boot environment.
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let target = target.parse::<Label>()?;
+        let base = if base.is_empty() { None } else { Some(base) };
+        let client = self.audited(caller);
+        let snapshot = client.export(&target, base, fd.as_fd(), root_from_arg(beroot)?.as_ref())?;
+        tracing::info!(
+            target = target.to_string(),
+            snapshot,
+            "Exported boot environment"
+        );
+        self.refresh(conn.object_server()).await?;
+        Ok(snapshot)
+    }
+
+    /// Receive an export stream from `fd` as a new boot environment (or, for
+    /// an incremental stream, on top of an existing one).
+    async fn import(
+        &self,
+        name: &str,
+        fd: zvariant::OwnedFd,
+        beroot: &str,
+        #[zbus(header)] header: zbus::message::Header<'_>,
+        #[zbus(connection)] conn: &zbus::Connection,
+    ) -> zbus::fdo::Result<()> {
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
+        client.import(name, fd.as_fd(), root_from_arg(beroot)?.as_ref())?;
+        tracing::info!(name, "Imported boot environment");
+        self.refresh(conn.object_server()).await?;
         Ok(())
     }
 
+    /// Replicate boot environments (all of them if `names` is empty) and
+    /// their snapshots to another boot environment root, sending only what
+    /// the destination does not have yet.
+    #[zbus(out_args("replications"))]
+    async fn replicate(
+        &self,
+        to: &str,
+        names: Vec<String>,
+        beroot: &str,
+        #[zbus(header)] header: zbus::message::Header<'_>,
+        #[zbus(connection)] conn: &zbus::Connection,
+    ) -> zbus::fdo::Result<Vec<Replication>> {
+        let dest = Root::from_str(to)?;
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
+        let replications = client.replicate(&dest, &names, root_from_arg(beroot)?.as_ref())?;
+        tracing::info!(to, "Replicated boot environments");
+        self.refresh(conn.object_server()).await?;
+        Ok(replications)
+    }
+
     /// Mount a boot environment.
     #[zbus(out_args("mountpoint"))]
     async fn mount(
         &self,
         name: &str,
         mountpoint: &str,
-        read_only: bool,
+        options: MountArgs,
         beroot: &str,
+        #[zbus(header)] header: zbus::message::Header<'_>,
         #[zbus(connection)] conn: &zbus::Connection,
     ) -> zbus::fdo::Result<PathBuf> {
         // Note: this is not a privileged operation (yet), because mounting a
         // boot environment doesn't give you any more permissions to modify it
-        // than you already have.
-        let mode = if read_only {
-            MountMode::ReadOnly
-        } else {
-            MountMode::ReadWrite
-        };
+        // than you already have. The extra options can only further restrict
+        // the mount. It is still audited.
+        let caller = identify_caller(conn, &header).await?;
+        let client = self.audited(caller);
+        let options = mount_options_from_args(options)?;
         let mountpoint = if mountpoint.is_empty() {
             None
         } else {
             Some(PathBuf::from(mountpoint))
         };
-        let result = self.client.mount(
+        let result = client.mount(
             name,
             mountpoint.as_ref().map(|mp| mp.as_path()),
-            mode,
+            &options,
             root_from_arg(beroot)?.as_ref(),
         )?;
         tracing::info!(
             name,
             mountpoint = result.display().to_string(),
-            read_only,
+            options = options.to_option_string(),
             "Mounted boot environment"
         );
         self.refresh(conn.object_server()).await?;
@@ -1081,10 +2028,12 @@ impl<T: Client + 'static> BootEnvironmentManager<T> {
         name: &str,
         force: bool,
         beroot: &str,
+        #[zbus(header)] header: zbus::message::Header<'_>,
         #[zbus(connection)] conn: &zbus::Connection,
     ) -> zbus::fdo::Result<String> {
-        let mountpoint = self
-            .client
+        let caller = identify_caller(conn, &header).await?;
+        let client = self.audited(caller);
+        let mountpoint = client
             .unmount(name, force, root_from_arg(beroot)?.as_ref())?
             .map(|p| p.display().to_string());
         tracing::info!(name, mountpoint, "Unmounted boot environment");
@@ -1101,9 +2050,10 @@ impl<T: Client + 'static> BootEnvironmentManager<T> {
         #[zbus(header)] header: zbus::message::Header<'_>,
         #[zbus(connection)] conn: &zbus::Connection,
     ) -> zbus::fdo::Result<()> {
-        check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
-        self.client
-            .rename(name, new_name, root_from_arg(beroot)?.as_ref())?;
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
+        client.rename(name, new_name, root_from_arg(beroot)?.as_ref())?;
         tracing::info!(name, new_name, "Renamed boot environment");
         self.refresh(conn.object_server()).await?;
         Ok(())
@@ -1118,54 +2068,227 @@ impl<T: Client + 'static> BootEnvironmentManager<T> {
         #[zbus(header)] header: zbus::message::Header<'_>,
         #[zbus(connection)] conn: &zbus::Connection,
     ) -> zbus::fdo::Result<()> {
-        check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
         let label = target.parse::<Label>()?;
-        self.client
-            .describe(&label, description, root_from_arg(beroot)?.as_ref())?;
+        client.describe(&label, description, root_from_arg(beroot)?.as_ref())?;
         tracing::info!(target, description, "Set description");
         self.refresh(conn.object_server()).await?;
         Ok(())
     }
 
-    /// Roll back a boot environment to an earlier snapshot.
+    /// Pin a boot environment or snapshot, protecting it from being
+    /// destroyed.
+    async fn pin(
+        &self,
+        target: &str,
+        beroot: &str,
+        #[zbus(header)] header: zbus::message::Header<'_>,
+        #[zbus(connection)] conn: &zbus::Connection,
+    ) -> zbus::fdo::Result<()> {
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
+        let label = target.parse::<Label>()?;
+        client.pin(&label, root_from_arg(beroot)?.as_ref())?;
+        tracing::info!(target, "Pinned");
+        self.refresh(conn.object_server()).await?;
+        Ok(())
+    }
+
+    /// Unpin a boot environment or snapshot.
+    async fn unpin(
+        &self,
+        target: &str,
+        beroot: &str,
+        #[zbus(header)] header: zbus::message::Header<'_>,
+        #[zbus(connection)] conn: &zbus::Connection,
+    ) -> zbus::fdo::Result<()> {
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
+        let label = target.parse::<Label>()?;
+        client.unpin(&label, root_from_arg(beroot)?.as_ref())?;
+        tracing::info!(target, "Unpinned");
+        self.refresh(conn.object_server()).await?;
+        Ok(())
+    }
+
+    /// Set a tag on a boot environment or snapshot, replacing any existing
+    /// value.
+    async fn tag(
+        &self,
+        target: &str,
+        key: &str,
+        value: &str,
+        beroot: &str,
+        #[zbus(header)] header: zbus::message::Header<'_>,
+        #[zbus(connection)] conn: &zbus::Connection,
+    ) -> zbus::fdo::Result<()> {
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
+        let label = target.parse::<Label>()?;
+        client.tag(&label, key, value, root_from_arg(beroot)?.as_ref())?;
+        tracing::info!(target, key, value, "Set tag");
+        self.refresh(conn.object_server()).await?;
+        Ok(())
+    }
+
+    /// Remove a tag from a boot environment or snapshot.
+    async fn untag(
+        &self,
+        target: &str,
+        key: &str,
+        beroot: &str,
+        #[zbus(header)] header: zbus::message::Header<'_>,
+        #[zbus(connection)] conn: &zbus::Connection,
+    ) -> zbus::fdo::Result<()> {
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
+        let label = target.parse::<Label>()?;
+        client.untag(&label, key, root_from_arg(beroot)?.as_ref())?;
+        tracing::info!(target, key, "Removed tag");
+        self.refresh(conn.object_server()).await?;
+        Ok(())
+    }
+
+    /// Get ZFS properties of a boot environment or snapshot, or all of them
+    /// if no names are given.
+    #[zbus(out_args("properties"))]
+    fn get_zfs_properties(
+        &self,
+        target: &str,
+        names: Vec<String>,
+        beroot: &str,
+    ) -> zbus::fdo::Result<Vec<Property>> {
+        let label = target.parse::<Label>()?;
+        Ok(self
+            .client
+            .get_zfs_properties(&label, &names, root_from_arg(beroot)?.as_ref())?)
+    }
+
+    /// Set ZFS properties (in the form `property=value`) on a boot
+    /// environment.
+    async fn set_zfs_properties(
+        &self,
+        name: &str,
+        properties: Vec<String>,
+        beroot: &str,
+        #[zbus(header)] header: zbus::message::Header<'_>,
+        #[zbus(connection)] conn: &zbus::Connection,
+    ) -> zbus::fdo::Result<()> {
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
+        client.set_zfs_properties(name, &properties, root_from_arg(beroot)?.as_ref())?;
+        tracing::info!(name, ?properties, "Set properties");
+        self.refresh(conn.object_server()).await?;
+        Ok(())
+    }
+
+    /// Roll back a boot environment to an earlier snapshot, destroying any
+    /// newer snapshots if `DestroyNewer` is set. With `SafetySnapshot`, the
+    /// current state is kept in a new boot environment.
+    #[zbus(out_args("result"))]
     async fn rollback(
         &self,
         name: &str,
         snapshot: &str,
+        options: RollbackOptions,
         beroot: &str,
         #[zbus(header)] header: zbus::message::Header<'_>,
         #[zbus(connection)] conn: &zbus::Connection,
-    ) -> zbus::fdo::Result<()> {
-        check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
-        self.client
-            .rollback(name, snapshot, root_from_arg(beroot)?.as_ref())?;
+    ) -> zbus::fdo::Result<RolledBack> {
+        let RollbackOptions {
+            destroy_newer,
+            force,
+            safety_snapshot,
+        } = options;
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
+        let rolled_back = client.rollback(
+            name,
+            snapshot,
+            destroy_newer,
+            force,
+            safety_snapshot,
+            root_from_arg(beroot)?.as_ref(),
+        )?;
         tracing::info!(name, snapshot, "Rolled boot environment back to snapshot");
         self.refresh(conn.object_server()).await?;
-        Ok(())
+        Ok(rolled_back)
     }
 
-    /// Get snapshots for a boot environment.
-    #[zbus(out_args("snapshots"))]
-    fn get_snapshots(
+    /// Destroy snapshots (or whole boot environments) that fall outside a
+    /// retention policy.
+    ///
+    /// With `dry_run`, nothing is changed and the returned list describes what
+    /// would be destroyed.
+    #[zbus(out_args("pruned"))]
+    async fn prune(
         &self,
-        be_name: &str,
+        policy: RetentionPolicy,
+        boot_environments: bool,
+        dry_run: bool,
         beroot: &str,
-    ) -> zbus::fdo::Result<Vec<(String, Root, String, u64, i64)>> {
+        #[zbus(header)] header: zbus::message::Header<'_>,
+        #[zbus(connection)] conn: &zbus::Connection,
+    ) -> zbus::fdo::Result<Vec<Pruned>> {
+        let root = root_from_arg(beroot)?;
+        // Dry runs only reveal what can already be queried without privileges.
+        if dry_run {
+            return Ok(self
+                .client
+                .prune(&policy, boot_environments, true, root.as_ref())?);
+        }
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
+        let pruned = client.prune(&policy, boot_environments, false, root.as_ref())?;
+        for item in &pruned {
+            tracing::info!(name = item.name, space = item.space, "Pruned");
+        }
+        self.refresh(conn.object_server()).await?;
+        Ok(pruned)
+    }
+
+    /// Get snapshots for a boot environment.
+    #[zbus(out_args("snapshots"))]
+    fn get_snapshots(&self, be_name: &str, beroot: &str) -> zbus::fdo::Result<Vec<SnapshotTuple>> {
         let snapshots = self
             .client
             .get_snapshots(be_name, root_from_arg(beroot)?.as_ref())?;
-        Ok(snapshots
-            .into_iter()
-            .map(|snap| {
-                (
-                    snap.name,
-                    snap.root,
-                    snap.description.unwrap_or_default(),
-                    snap.space,
-                    snap.created,
-                )
-            })
-            .collect())
+        Ok(snapshots.into_iter().map(snapshot_to_tuple).collect())
+    }
+
+    /// Get the boot history, oldest first.
+    #[zbus(out_args("history"))]
+    fn get_boot_history(&self, beroot: &str) -> zbus::fdo::Result<Vec<BootRecord>> {
+        Ok(self
+            .client
+            .get_boot_history(root_from_arg(beroot)?.as_ref())?)
+    }
+
+    /// Get the snapshots left behind by `create` that nothing depends on any
+    /// more.
+    #[zbus(out_args("snapshots"))]
+    fn get_orphaned_snapshots(&self, beroot: &str) -> zbus::fdo::Result<Vec<SnapshotTuple>> {
+        let snapshots = self
+            .client
+            .get_orphaned_snapshots(root_from_arg(beroot)?.as_ref())?;
+        Ok(snapshots.into_iter().map(snapshot_to_tuple).collect())
+    }
+
+    /// Get the descendant datasets of a boot environment.
+    #[zbus(out_args("datasets"))]
+    fn get_datasets(&self, be_name: &str, beroot: &str) -> zbus::fdo::Result<Vec<ChildDataset>> {
+        Ok(self
+            .client
+            .get_datasets(be_name, root_from_arg(beroot)?.as_ref())?)
     }
 
     /// Create the ZFS dataset layout for boot environments.
@@ -1175,8 +2298,10 @@ impl<T: Client + 'static> BootEnvironmentManager<T> {
         #[zbus(header)] header: zbus::message::Header<'_>,
         #[zbus(connection)] conn: &zbus::Connection,
     ) -> zbus::fdo::Result<()> {
-        check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
-        self.client.init(pool)?;
+        let caller =
+            check_authorization(conn, &header, "ca.kamacite.BootEnvironments1.manage").await?;
+        let client = self.audited(caller);
+        client.init(pool)?;
         tracing::info!(pool, "Initialized boot environment dataset layout");
         Ok(())
     }
@@ -1191,21 +2316,79 @@ impl<T: Client + 'static> BootEnvironmentManager<T> {
     }
 }
 
+/// Check that the sender of a message is authorized to carry out an action,
+/// returning who they are so that what they do can be audited. Denials are
+/// audited here.
 async fn check_authorization(
     conn: &zbus::Connection,
     header: &zbus::message::Header<'_>,
     action_id: &str,
-) -> Result<(), zbus::Error> {
-    // Check if the sender is privileged (i.e. root, currently).
+) -> Result<Caller, zbus::Error> {
+    let caller = match identify_caller(conn, header).await {
+        Ok(caller) => Caller {
+            action: Some(action_id.to_string()),
+            ..caller
+        },
+        Err(err) => {
+            tracing::error!(action_id, "Denying authorization to an unidentified sender");
+            return Err(err);
+        }
+    };
+    match authorize(conn, header, action_id, caller.uid).await {
+        Ok(()) => Ok(caller),
+        Err(err) => {
+            let operation = header
+                .member()
+                .map_or(String::new(), |member| operation_name(member.as_str()));
+            audit::record(&operation, None, &caller, &Outcome::Denied);
+            Err(err)
+        }
+    }
+}
+
+/// Identify the sender of a message, for auditing operations that do not
+/// need authorization.
+async fn identify_caller(
+    conn: &zbus::Connection,
+    header: &zbus::message::Header<'_>,
+) -> Result<Caller, zbus::Error> {
     let sender_name = match header.sender() {
         Some(name) => zbus::names::BusName::Unique(name.clone()),
-        None => {
-            tracing::error!(action_id, "Denying authorization due to missing sender");
-            return Err(zbus::fdo::Error::AccessDenied("Access denied".to_string()).into());
-        }
+        None => return Err(zbus::fdo::Error::AccessDenied("Access denied".to_string()).into()),
     };
     let dbus_proxy = zbus::fdo::DBusProxy::new(conn).await?;
-    let uid = dbus_proxy.get_connection_unix_user(sender_name).await?;
+    Ok(Caller {
+        uid: dbus_proxy
+            .get_connection_unix_user(sender_name.clone())
+            .await?,
+        pid: dbus_proxy
+            .get_connection_unix_process_id(sender_name)
+            .await
+            .ok(),
+        action: None,
+    })
+}
+
+/// Name an operation for auditing after the D-Bus method that was called,
+/// e.g. `activate-trial` for `ActivateTrial`.
+fn operation_name(member: &str) -> String {
+    let mut name = String::new();
+    for (i, c) in member.chars().enumerate() {
+        if c.is_ascii_uppercase() && i > 0 {
+            name.push('-');
+        }
+        name.push(c.to_ascii_lowercase());
+    }
+    name
+}
+
+async fn authorize(
+    conn: &zbus::Connection,
+    header: &zbus::message::Header<'_>,
+    action_id: &str,
+    uid: u32,
+) -> Result<(), zbus::Error> {
+    // Check if the sender is privileged (i.e. root, currently).
     if uid == 0 {
         tracing::debug!(action_id, uid, "Authorization granted for privileged user");
         return Ok(());
@@ -1260,6 +2443,111 @@ async fn check_authorization(
     }
 }
 
+/// A snapshot as sent over D-Bus: its name, root, description (or an empty
+/// string), space, creation time, referenced and written bytes, whether it is
+/// pinned, and its tags.
+type SnapshotTuple = (
+    String,
+    Root,
+    String,
+    u64,
+    i64,
+    u64,
+    u64,
+    bool,
+    BTreeMap<String, String>,
+);
+
+fn snapshot_to_tuple(snap: Snapshot) -> SnapshotTuple {
+    (
+        snap.name,
+        snap.root,
+        snap.description.unwrap_or_default(),
+        snap.space,
+        snap.created,
+        snap.referenced,
+        snap.written,
+        snap.pinned,
+        snap.tags,
+    )
+}
+
+fn snapshot_from_tuple(
+    (name, root, description, space, created, referenced, written, pinned, tags): SnapshotTuple,
+) -> Snapshot {
+    Snapshot {
+        name,
+        root,
+        description: if description.is_empty() {
+            None
+        } else {
+            Some(description)
+        },
+        space,
+        referenced,
+        written,
+        created,
+        pinned,
+        tags,
+    }
+}
+
+/// A changed file as sent over D-Bus: its path, the kind of change and file
+/// type (e.g., `modified` and `file`), and its new path for renames (or an
+/// empty string).
+type FileChangeTuple = (String, String, String, String);
+
+fn file_change_to_tuple(change: FileChange) -> FileChangeTuple {
+    (
+        change.path,
+        change.change.as_str().to_string(),
+        change.file_type.as_str().to_string(),
+        change.new_path.unwrap_or_default(),
+    )
+}
+
+fn file_change_from_tuple(
+    (path, change, file_type, new_path): FileChangeTuple,
+) -> Result<FileChange, Error> {
+    Ok(FileChange {
+        path,
+        change: change.parse()?,
+        file_type: file_type.parse()?,
+        new_path: if new_path.is_empty() {
+            None
+        } else {
+            Some(new_path)
+        },
+    })
+}
+
+/// The options of the D-Bus `Destroy` and `DestroySnapshot` methods, as for
+/// [`Client::destroy`]. Only `Unpin` and `DryRun` apply to snapshots.
+#[derive(Clone, Debug, Default, PartialEq, SerializeDict, DeserializeDict, Type)]
+#[zvariant(signature = "a{sv}", rename_all = "PascalCase")]
+struct DestroyOptions {
+    /// Unmount the boot environment first if it is mounted.
+    force_unmount: bool,
+    /// Destroy its snapshots too.
+    snapshots: bool,
+    /// Unpin anything pinned rather than refusing to destroy it.
+    unpin: bool,
+    /// Only work out what would be done.
+    dry_run: bool,
+}
+
+/// The options of the D-Bus `Rollback` methods, as for [`Client::rollback`].
+#[derive(Clone, Debug, Default, PartialEq, SerializeDict, DeserializeDict, Type)]
+#[zvariant(signature = "a{sv}", rename_all = "PascalCase")]
+struct RollbackOptions {
+    /// Destroy any snapshots newer than the one rolled back to.
+    destroy_newer: bool,
+    /// Roll back the active boot environment.
+    force: bool,
+    /// Keep the current state in a new boot environment.
+    safety_snapshot: bool,
+}
+
 fn root_from_arg(root: &str) -> Result<Option<Root>, zbus::fdo::Error> {
     if root.is_empty() {
         Ok(None)
@@ -1268,6 +2556,32 @@ fn root_from_arg(root: &str) -> Result<Option<Root>, zbus::fdo::Error> {
     }
 }
 
+/// The options of the D-Bus `Mount` methods.
+#[derive(Clone, Debug, Default, PartialEq, SerializeDict, DeserializeDict, Type)]
+#[zvariant(signature = "a{sv}", rename_all = "PascalCase")]
+struct MountArgs {
+    /// Mount read-only rather than read-write.
+    read_only: bool,
+    /// Additional restrictions, by name (e.g., `nosuid`).
+    options: Vec<String>,
+}
+
+fn mount_options_from_args(
+    MountArgs { read_only, options }: MountArgs,
+) -> Result<MountOptions, zbus::fdo::Error> {
+    Ok(MountOptions {
+        mode: if read_only {
+            MountMode::ReadOnly
+        } else {
+            MountMode::ReadWrite
+        },
+        options: options
+            .iter()
+            .map(|o| MountOption::from_str(o))
+            .collect::<Result<_, _>>()?,
+    })
+}
+
 /// Start a D-Bus service for boot environment administration.
 pub async fn serve<T: Client + 'static>(client: T, use_session_bus: bool) -> zbus::Result<()> {
     // Logs in journald don't need colours.
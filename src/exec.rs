@@ -0,0 +1,205 @@
+// SPDX-License-Identifier: MPL-2.0
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::ffi::{CString, OsString};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use std::ptr;
+
+use crate::be::{Client, Error, MountOptions, Root};
+
+/// The API filesystems made available inside the boot environment, bound
+/// (recursively) from the running system.
+const API_FILESYSTEMS: &[&str] = &["proc", "sys", "dev", "run"];
+
+/// Run a command chrooted into a boot environment, returning its exit status.
+/// Without a command, start an interactive shell: `$SHELL` if the boot
+/// environment has it, and `/bin/sh` otherwise.
+///
+/// Everything happens in a private mount namespace, so the mounts made along
+/// the way are out of sight of the rest of the system. This needs to mount
+/// directly, so the client must not be the D-Bus proxy.
+pub fn run_in_boot_environment<C: Client + ?Sized>(
+    client: &C,
+    be_name: &str,
+    command: Option<&[OsString]>,
+    root: Option<&Root>,
+) -> Result<ExitStatus, Error> {
+    enter_private_mount_namespace()?;
+    with_boot_environment(client, be_name, root, |mountpoint| {
+        let shell = [shell(mountpoint)];
+        let Some((program, args)) = command.unwrap_or(&shell).split_first() else {
+            return Err(io::Error::from(io::ErrorKind::InvalidInput).into());
+        };
+        let mut command = Command::new(program);
+        command.args(args);
+        run_chrooted(mountpoint, &mut command)
+    })
+}
+
+/// Call `f` with the mountpoint of a boot environment, with the API
+/// filesystems bound into it. The boot environment (and its children) is
+/// mounted if it isn't already, and all of that is torn down again
+/// afterwards, even if `f` fails.
+///
+/// Callers should have entered a private mount namespace first (see
+/// [`enter_private_mount_namespace`]), so that none of these mounts outlive
+/// them.
+pub fn with_boot_environment<C: Client + ?Sized, T>(
+    client: &C,
+    be_name: &str,
+    root: Option<&Root>,
+    f: impl FnOnce(&Path) -> Result<T, Error>,
+) -> Result<T, Error> {
+    let existing = client
+        .get_boot_environments(root)?
+        .into_iter()
+        .find(|be| be.name == be_name)
+        .and_then(|be| be.mountpoint);
+    let (mountpoint, mounted) = match existing {
+        Some(mountpoint) => (mountpoint, false),
+        None => (
+            client.mount(be_name, None, &MountOptions::default(), root)?,
+            true,
+        ),
+    };
+
+    let mut api_mounts = vec![];
+    let result = mount_api_filesystems(&mountpoint, &mut api_mounts)
+        .map_err(Error::from)
+        .and_then(|()| f(&mountpoint));
+
+    for target in api_mounts.iter().rev() {
+        _ = unmount_detached(target);
+    }
+    if mounted {
+        let unmounted = client.unmount(be_name, false, root);
+        if result.is_ok() {
+            unmounted?;
+        }
+    }
+    result
+}
+
+/// Run a command chrooted into `mountpoint`, returning its exit status. The
+/// program is looked up (on `$PATH`) inside the chroot.
+pub fn run_chrooted(mountpoint: &Path, command: &mut Command) -> Result<ExitStatus, Error> {
+    let root = path_to_cstring(mountpoint)?;
+    unsafe {
+        command.pre_exec(move || {
+            if libc::chroot(root.as_ptr()) != 0 || libc::chdir(c"/".as_ptr()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+            libc::signal(libc::SIGQUIT, libc::SIG_DFL);
+            Ok(())
+        });
+    }
+
+    // As system(3) does, leave interrupts to the command while it runs, so
+    // that we're still around to clean up after it.
+    let (sigint, sigquit) = unsafe {
+        (
+            libc::signal(libc::SIGINT, libc::SIG_IGN),
+            libc::signal(libc::SIGQUIT, libc::SIG_IGN),
+        )
+    };
+    let status = command.status();
+    unsafe {
+        libc::signal(libc::SIGINT, sigint);
+        libc::signal(libc::SIGQUIT, sigquit);
+    }
+    Ok(status?)
+}
+
+/// Move this process into a new mount namespace, so that mounts made from
+/// now on are not visible to the rest of the system, and do not outlive it.
+pub fn enter_private_mount_namespace() -> io::Result<()> {
+    if unsafe { libc::unshare(libc::CLONE_NEWNS) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Stop mounts propagating back out to the original namespace.
+    mount(None, Path::new("/"), libc::MS_REC | libc::MS_PRIVATE)
+}
+
+/// The exit code to pass on for a command's exit status, using the shell's
+/// convention of 128 plus the signal number for commands that were killed.
+pub fn exit_code(status: ExitStatus) -> i32 {
+    status
+        .code()
+        .unwrap_or_else(|| 128 + status.signal().unwrap_or(0))
+}
+
+fn shell(mountpoint: &Path) -> OsString {
+    std::env::var_os("SHELL")
+        .filter(|shell| {
+            Path::new(shell)
+                .strip_prefix("/")
+                .is_ok_and(|relative| mountpoint.join(relative).is_file())
+        })
+        .unwrap_or_else(|| OsString::from("/bin/sh"))
+}
+
+fn mount_api_filesystems(mountpoint: &Path, mounted: &mut Vec<PathBuf>) -> io::Result<()> {
+    for name in API_FILESYSTEMS {
+        let target = mountpoint.join(name);
+        if !target.is_dir() {
+            continue;
+        }
+        let source = Path::new("/").join(name);
+        mount(Some(&source), &target, libc::MS_BIND | libc::MS_REC)?;
+        mounted.push(target);
+    }
+    Ok(())
+}
+
+fn mount(source: Option<&Path>, target: &Path, flags: libc::c_ulong) -> io::Result<()> {
+    let source = source.map(path_to_cstring).transpose()?;
+    let target = path_to_cstring(target)?;
+    let result = unsafe {
+        libc::mount(
+            source
+                .as_ref()
+                .map_or(ptr::null(), |source| source.as_ptr()),
+            target.as_ptr(),
+            ptr::null(),
+            flags,
+            ptr::null(),
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn unmount_detached(target: &Path) -> io::Result<()> {
+    let target = path_to_cstring(target)?;
+    if unsafe { libc::umount2(target.as_ptr(), libc::MNT_DETACH) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn path_to_cstring(path: &Path) -> io::Result<CString> {
+    Ok(CString::new(path.as_os_str().as_bytes())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(exit_code(ExitStatus::from_raw(0)), 0);
+        assert_eq!(exit_code(ExitStatus::from_raw(3 << 8)), 3);
+        // Killed by SIGKILL.
+        assert_eq!(exit_code(ExitStatus::from_raw(9)), 137);
+    }
+}
@@ -4,22 +4,32 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::io::IsTerminal;
+use std::os::fd::AsFd;
 use std::path::PathBuf;
 
 use anyhow::{Context, Result};
 use chrono::TimeZone;
 use clap::{Parser, Subcommand, ValueEnum};
 
+use audit::{AuditEntry, AuditedClient, Caller};
 use be::{
-    BootEnvironment, Client, EmulatorClient, Error, Label, LibZfsClient, MountMode, Root, Snapshot,
-    format_zfs_bytes, is_temp_mountpoint, scan,
+    BootEnvironment, BootRecord, ChildDataset, Client, DestroyPlan, EmulatorClient, Error,
+    FileChange, Finding, HealthIssue, Label, LibZfsClient, MountMode, MountOption, MountOptions,
+    PackageChange, Property, Pruned, RetentionPolicy, Root, Snapshot, SpaceUsage, format_zfs_bytes,
+    is_temp_mountpoint, scan,
 };
 
+mod audit;
 mod be;
 #[cfg(feature = "dbus")]
 mod dbus;
+mod exec;
 #[cfg(feature = "hooks")]
 mod hooks;
+mod update;
 
 #[derive(Parser)]
 #[command(version, about = "Boot Environment Administration")]
@@ -66,10 +76,26 @@ enum Commands {
         #[arg(short = 't', conflicts_with = "deactivate")]
         temporary: bool,
 
+        /// Activate the boot environment for a trial boot, which only makes it
+        /// the default once confirmed with 'beadm confirm'.
+        #[arg(long, conflicts_with_all = ["temporary", "deactivate"])]
+        trial: bool,
+
         /// Remove any temporary activations instead.
         #[arg(short = 'T', conflicts_with = "temporary")]
         deactivate: bool,
+
+        /// With -T, leave a pending trial boot in place, as is done early in
+        /// the trial boot itself.
+        #[arg(long, requires = "deactivate")]
+        keep_trial: bool,
+
+        /// Activate the boot environment even if it fails health checks.
+        #[arg(short = 'f', long, conflicts_with = "deactivate")]
+        force: bool,
     },
+    /// Confirm a trial boot, making the boot environment on trial the default.
+    Confirm,
     /// Create a new boot environment.
     Create {
         /// A name for the new boot environment.
@@ -147,6 +173,59 @@ enum Commands {
         /// Destroy snapshots of the boot environment if needed.
         #[arg(short = 's')]
         destroy_snapshots: bool,
+
+        /// Unpin the boot environment or snapshot (and any pinned snapshots
+        /// being destroyed) if needed.
+        #[arg(long)]
+        unpin: bool,
+
+        /// Print what would be destroyed, promoted or unmounted without
+        /// changing anything.
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+    },
+    /// Destroy old snapshots (or boot environments) according to a retention
+    /// policy.
+    Prune {
+        /// Keep the most recent N snapshots of each boot environment.
+        #[arg(long, value_name = "N")]
+        keep_last: Option<u32>,
+
+        /// Keep snapshots newer than this age (e.g., '12h', '30d' or '8w').
+        #[arg(long, value_name = "age", value_parser = parse_age)]
+        keep_newer_than: Option<u64>,
+
+        /// Keep the most recent snapshot from each of the last N days.
+        #[arg(long, value_name = "N")]
+        keep_daily: Option<u32>,
+
+        /// Keep the most recent snapshot from each of the last N weeks.
+        #[arg(long, value_name = "N")]
+        keep_weekly: Option<u32>,
+
+        /// Only prune snapshots with a description starting with this prefix
+        /// (e.g., 'before apt').
+        #[arg(long, value_name = "prefix")]
+        description_prefix: Option<String>,
+
+        /// Prune inactive boot environments instead of snapshots.
+        #[arg(short = 'b', long)]
+        boot_environments: bool,
+
+        /// Print what would be destroyed without changing anything.
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+    },
+    /// Destroy snapshots taken by 'create' that are no longer the origin of
+    /// any boot environment.
+    Gc {
+        /// Destroy them without asking for confirmation.
+        #[arg(short = 'y', long)]
+        yes: bool,
+
+        /// Print what would be destroyed without changing anything.
+        #[arg(short = 'n', long, conflicts_with = "yes")]
+        dry_run: bool,
     },
     /// List boot environments.
     List {
@@ -170,6 +249,16 @@ enum Commands {
         #[arg(short = 'H')]
         parseable: bool,
 
+        /// Print boot environments as JSON, using a versioned schema (see
+        /// beadm(8) for details).
+        #[arg(long, conflicts_with = "parseable")]
+        json: bool,
+
+        /// Show a detailed breakdown of space usage instead of the default
+        /// columns.
+        #[arg(long, conflicts_with = "json")]
+        space: bool,
+
         /// Sort boot environments by this property, ascending.
         #[arg(
             short = 'k',
@@ -182,6 +271,42 @@ enum Commands {
         /// Sort boot environments by this property, descending.
         #[arg(short = 'K', value_name = "property", conflicts_with = "sort_asc")]
         sort_des: Option<SortField>,
+
+        /// Include only boot environments and snapshots with this tag (and
+        /// value, if given). May be repeated, in which case all must match.
+        #[arg(long, value_name = "tag[=value]", value_parser = parse_tag_filter)]
+        filter: Vec<TagFilter>,
+    },
+    /// Show how space is used by a boot environment and its snapshots.
+    Space {
+        /// The boot environment. Defaults to the active boot environment.
+        #[arg(value_name = "name")]
+        be_name: Option<String>,
+    },
+    /// Show the audit log of changes to boot environments.
+    Audit {
+        /// Only show changes since this time (e.g., 'yesterday' or
+        /// '2021-06-10 05:00'), in any format journalctl accepts.
+        #[arg(long, value_name = "time")]
+        since: Option<String>,
+
+        /// Only show the N most recent changes.
+        #[arg(short = 'n', long, value_name = "N")]
+        lines: Option<usize>,
+
+        /// Omit headers and formatting, separate fields by a single tab.
+        #[arg(short = 'H')]
+        parseable: bool,
+    },
+    /// Show which boot environment was booted, and when.
+    History {
+        /// Omit headers and formatting, separate fields by a single tab.
+        #[arg(short = 'H')]
+        parseable: bool,
+
+        /// Record the current boot instead (used by the early-boot service).
+        #[arg(long, conflicts_with = "parseable")]
+        record: bool,
     },
     /// Mount a boot environment.
     Mount {
@@ -199,6 +324,10 @@ enum Commands {
         /// Mount as read/write or read-only.
         #[arg(short = 's', value_name = "mode", default_value = "rw")]
         mode: MountMode,
+
+        /// Additional mount options, separated by commas.
+        #[arg(short = 'o', value_name = "options", value_delimiter = ',')]
+        options: Vec<MountOption>,
     },
     /// Unmount an inactive boot environment.
     ///
@@ -216,15 +345,44 @@ enum Commands {
         #[arg(short = 'f')]
         force: bool,
     },
-    /// Rename a boot environment.
-    Rename {
+    /// Upgrade the system inside a new boot environment and activate it for
+    /// the next boot.
+    Update {
+        /// The name of the new boot environment. Defaults to one based on the
+        /// current time.
+        #[arg(long, value_name = "name")]
+        name: Option<String>,
+    },
+    /// Run a command inside a boot environment, with /proc, /sys, /dev and
+    /// /run available.
+    Exec {
+        /// The boot environment.
+        #[arg(value_name = "name")]
+        be_name: String,
+
+        /// The command to run, and its arguments.
+        #[arg(value_name = "command", last = true, required = true)]
+        command: Vec<OsString>,
+    },
+    /// Start a shell inside a boot environment, with /proc, /sys, /dev and
+    /// /run available.
+    Chroot {
         /// The boot environment.
         #[arg(value_name = "name")]
         be_name: String,
+    },
+    /// Rename a boot environment or snapshot.
+    Rename {
+        /// The boot environment or snapshot (in the form 'name' or
+        /// 'name@snapshot').
+        #[arg(value_name = "name | name@snapshot")]
+        target: Label,
 
-        /// A new name for the boot environment.
-        #[arg(value_name = "new-name")]
-        new_name: String,
+        /// A new name for the boot environment, or for the snapshot (in the
+        /// form 'name@new-snapshot'). Snapshots cannot be renamed to another
+        /// boot environment.
+        #[arg(value_name = "new-name | name@new-snapshot")]
+        new_name: Label,
     },
     /// Set a description for an existing boot environment or snapshot.
     Describe {
@@ -237,6 +395,132 @@ enum Commands {
         #[arg(value_name = "desc")]
         description: String,
     },
+    /// Pin a boot environment or snapshot, protecting it from being
+    /// destroyed.
+    Pin {
+        /// The boot environment or snapshot (in the form 'name' or
+        /// 'name@snapshot').
+        #[arg(value_name = "name | name@snapshot")]
+        target: Label,
+    },
+    /// Unpin a boot environment or snapshot.
+    Unpin {
+        /// The boot environment or snapshot (in the form 'name' or
+        /// 'name@snapshot').
+        #[arg(value_name = "name | name@snapshot")]
+        target: Label,
+    },
+    /// Set tags on a boot environment or snapshot, or show its tags.
+    Tag {
+        /// The boot environment or snapshot (in the form 'name' or
+        /// 'name@snapshot').
+        #[arg(value_name = "name | name@snapshot")]
+        target: Label,
+
+        /// The tags to set. If none are given, the existing tags are shown.
+        #[arg(value_name = "tag=value", value_parser = parse_tag)]
+        tags: Vec<(String, String)>,
+    },
+    /// Remove tags from a boot environment or snapshot.
+    Untag {
+        /// The boot environment or snapshot (in the form 'name' or
+        /// 'name@snapshot').
+        #[arg(value_name = "name | name@snapshot")]
+        target: Label,
+
+        /// The tags to remove.
+        #[arg(value_name = "tag", required = true)]
+        keys: Vec<String>,
+    },
+    /// Show ZFS properties of a boot environment or snapshot.
+    Get {
+        /// Omit headers and formatting, separate fields by a single tab.
+        #[arg(short = 'H')]
+        parseable: bool,
+
+        /// The boot environment or snapshot (in the form 'name' or
+        /// 'name@snapshot').
+        #[arg(value_name = "name | name@snapshot")]
+        target: Label,
+
+        /// The properties to show. If none are given, all are shown.
+        #[arg(value_name = "property")]
+        properties: Vec<String>,
+    },
+    /// Set ZFS properties on an existing boot environment.
+    Set {
+        /// The boot environment.
+        be_name: String,
+
+        /// The properties to set (in 'property=value' format).
+        #[arg(value_name = "property=value", required = true)]
+        properties: Vec<String>,
+    },
+    /// Show the files that differ between two boot environments or
+    /// snapshots.
+    Diff {
+        /// Omit headers and formatting, separate fields by a single tab.
+        #[arg(short = 'H')]
+        parseable: bool,
+
+        /// Print changes as JSON, using a versioned schema (see beadm(8) for
+        /// details).
+        #[arg(long, conflicts_with = "parseable")]
+        json: bool,
+
+        /// Only show changes beneath this path (e.g., '/etc'). May be given
+        /// more than once.
+        #[arg(short = 'p', long = "path", value_name = "path")]
+        paths: Vec<PathBuf>,
+
+        /// The boot environment or snapshot to compare from.
+        #[arg(value_name = "name | name@snapshot")]
+        from: Label,
+
+        /// The boot environment or snapshot to compare to. Defaults to the
+        /// active boot environment.
+        #[arg(value_name = "name | name@snapshot")]
+        to: Option<Label>,
+    },
+    /// Inspect the packages installed in boot environments.
+    Packages {
+        #[command(subcommand)]
+        command: PackagesCommands,
+    },
+    /// Write a boot environment or snapshot to a portable stream file.
+    Export {
+        /// Send an incremental stream from this earlier snapshot.
+        #[arg(short = 'i', long = "base", value_name = "snapshot")]
+        base: Option<String>,
+
+        /// Write the stream to this file instead of standard output.
+        #[arg(short = 'o', value_name = "file")]
+        output: Option<PathBuf>,
+
+        /// The boot environment or snapshot to export.
+        #[arg(value_name = "name | name@snapshot")]
+        target: Label,
+    },
+    /// Create a boot environment from a stream written by `beadm export`.
+    Import {
+        /// The stream file, or `-` for standard input.
+        #[arg(value_name = "file")]
+        input: PathBuf,
+
+        /// The name of the new boot environment.
+        #[arg(value_name = "name")]
+        be_name: String,
+    },
+    /// Replicate boot environments and their snapshots to another root.
+    Replicate {
+        /// The destination boot environment root (e.g., `backup/ROOT`).
+        #[arg(long = "to", value_name = "beroot")]
+        to: Root,
+
+        /// The boot environments to replicate (defaults to all of them).
+        #[arg(value_name = "name")]
+        names: Vec<String>,
+    },
     /// Roll back a boot environment to an earlier snapshot.
     Rollback {
         /// The boot environment.
@@ -246,6 +530,18 @@ enum Commands {
         /// The snapshot name.
         #[arg(value_name = "snapshot")]
         snapshot: String,
+
+        /// Destroy any snapshots newer than the one rolled back to.
+        #[arg(short = 'R', long = "destroy-newer")]
+        destroy_newer: bool,
+
+        /// Roll back the active boot environment.
+        #[arg(short = 'f', long)]
+        force: bool,
+
+        /// Don't snapshot and keep the current state first.
+        #[arg(long)]
+        no_snapshot: bool,
     },
     /// Get the host ID from a boot environment.
     #[command(hide = true)]
@@ -253,6 +549,12 @@ enum Commands {
         /// The boot environment.
         be_name: String,
     },
+    /// Check the boot environment layout and state for problems.
+    Doctor {
+        /// Fix the problems that can be fixed automatically.
+        #[arg(long)]
+        fix: bool,
+    },
     /// Create the ZFS dataset layout for boot environments.
     Init {
         /// The ZFS pool to target.
@@ -300,10 +602,12 @@ enum ClientType {
     Mock,
 }
 
-/// A row in `beadm list` output, either a boot environment or a snapshot.
+/// A row in `beadm list` output, either a boot environment, one of its child
+/// datasets, or a snapshot.
 #[derive(Clone)]
 enum ListRow {
     BootEnvironment(BootEnvironment),
+    Dataset(ChildDataset),
     Snapshot(Snapshot),
 }
 
@@ -311,6 +615,7 @@ impl ListRow {
     fn name(&self) -> &str {
         match self {
             ListRow::BootEnvironment(be) => &be.name,
+            ListRow::Dataset(dataset) => &dataset.name,
             ListRow::Snapshot(snapshot) => &snapshot.name,
         }
     }
@@ -318,13 +623,45 @@ impl ListRow {
     fn space(&self) -> u64 {
         match self {
             ListRow::BootEnvironment(be) => be.space,
+            ListRow::Dataset(dataset) => dataset.space,
             ListRow::Snapshot(snapshot) => snapshot.space,
         }
     }
 
+    fn usage(&self) -> Option<&SpaceUsage> {
+        match self {
+            ListRow::BootEnvironment(be) => Some(&be.usage),
+            ListRow::Dataset(_) | ListRow::Snapshot(_) => None,
+        }
+    }
+
+    fn referenced(&self) -> Option<u64> {
+        match self {
+            ListRow::BootEnvironment(be) => Some(be.usage.referenced),
+            ListRow::Dataset(_) => None,
+            ListRow::Snapshot(snapshot) => Some(snapshot.referenced),
+        }
+    }
+
+    fn written(&self) -> Option<u64> {
+        match self {
+            ListRow::BootEnvironment(be) => Some(be.usage.written),
+            ListRow::Dataset(_) => None,
+            ListRow::Snapshot(snapshot) => Some(snapshot.written),
+        }
+    }
+
+    fn origin(&self) -> Option<&str> {
+        match self {
+            ListRow::BootEnvironment(be) => be.origin.as_deref(),
+            ListRow::Dataset(_) | ListRow::Snapshot(_) => None,
+        }
+    }
+
     fn created(&self) -> i64 {
         match self {
             ListRow::BootEnvironment(be) => be.created,
+            ListRow::Dataset(dataset) => dataset.created,
             ListRow::Snapshot(snapshot) => snapshot.created,
         }
     }
@@ -332,7 +669,8 @@ impl ListRow {
     fn active_flags(&self) -> Option<String> {
         match self {
             ListRow::BootEnvironment(be) => format_active_flags(be),
-            ListRow::Snapshot(_) => None,
+            ListRow::Snapshot(snapshot) if snapshot.pinned => Some("K".to_string()),
+            ListRow::Dataset(_) | ListRow::Snapshot(_) => None,
         }
     }
 
@@ -342,20 +680,31 @@ impl ListRow {
                 Some(m) => Some(m.display().to_string()),
                 None => None,
             },
+            ListRow::Dataset(dataset) => {
+                dataset.mountpoint.as_ref().map(|m| m.display().to_string())
+            }
             ListRow::Snapshot(_) => None,
         }
     }
 
+    fn canmount(&self) -> Option<&str> {
+        match self {
+            ListRow::Dataset(dataset) => Some(&dataset.canmount),
+            ListRow::BootEnvironment(_) | ListRow::Snapshot(_) => None,
+        }
+    }
+
     fn description(&self) -> Option<&str> {
         match self {
             ListRow::BootEnvironment(be) => be.description.as_deref(),
+            ListRow::Dataset(_) => None,
             ListRow::Snapshot(snapshot) => snapshot.description.as_deref(),
         }
     }
 }
 
 fn format_active_flags(be: &BootEnvironment) -> Option<String> {
-    if !be.next_boot && !be.active && !be.boot_once {
+    if !be.next_boot && !be.active && !be.boot_once && !be.trial && !be.pinned {
         return None;
     }
     let mut flags = String::new();
@@ -368,6 +717,12 @@ fn format_active_flags(be: &BootEnvironment) -> Option<String> {
     if be.boot_once {
         flags.push('T');
     }
+    if be.trial {
+        flags.push('P');
+    }
+    if be.pinned {
+        flags.push('K');
+    }
     Some(flags)
 }
 
@@ -386,13 +741,162 @@ fn format_timestamp(timestamp: i64) -> String {
     }
 }
 
+#[derive(Subcommand)]
+enum PackagesCommands {
+    /// Show the packages added, removed, upgraded or downgraded between two
+    /// boot environments.
+    Diff {
+        /// Omit headers and formatting, separate fields by a single tab.
+        #[arg(short = 'H')]
+        parseable: bool,
+
+        /// The boot environment to compare from.
+        #[arg(value_name = "name")]
+        from: String,
+
+        /// The boot environment to compare to. Defaults to the active boot
+        /// environment.
+        #[arg(value_name = "name")]
+        to: Option<String>,
+    },
+}
+
+/// The version of the schema used by `beadm list --json`.
+///
+/// This must be incremented whenever fields are removed or their meaning
+/// changes. Adding new fields is considered backwards-compatible.
+const LIST_JSON_VERSION: u32 = 1;
+
+/// The version of the schema used by `beadm diff --json`, with the same
+/// compatibility rules as [`LIST_JSON_VERSION`].
+const DIFF_JSON_VERSION: u32 = 1;
+
+/// A condition on the tags of a boot environment or snapshot, as given to
+/// `beadm list --filter`.
+#[derive(Clone, Debug, PartialEq)]
+struct TagFilter {
+    key: String,
+    /// The required value, or `None` to match any value.
+    value: Option<String>,
+}
+
+impl TagFilter {
+    fn matches(&self, tags: &BTreeMap<String, String>) -> bool {
+        match (tags.get(&self.key), &self.value) {
+            (Some(actual), Some(expected)) => actual == expected,
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+}
+
+/// Parse a `--filter` argument of the form `tag` or `tag=value`.
+fn parse_tag_filter(value: &str) -> Result<TagFilter, String> {
+    let (key, value) = match value.split_once('=') {
+        Some((key, value)) => (key, Some(value.to_string())),
+        None => (value, None),
+    };
+    if key.is_empty() {
+        return Err("tag cannot be empty".to_string());
+    }
+    Ok(TagFilter {
+        key: key.to_string(),
+        value,
+    })
+}
+
+/// Parse a `beadm tag` argument of the form `tag=value`.
+fn parse_tag(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, value)) => Ok((key.to_string(), value.to_string())),
+        None => Err(format!("expected 'tag=value', got '{}'", value)),
+    }
+}
+
 /// Options to control printing boot environments with `beadm list`.
 struct PrintOptions<'a> {
     be_name: &'a Option<String>,
     sort_field: SortField,
     descending: bool,
     parseable: bool,
+    json: bool,
+    datasets: bool,
     snapshots: bool,
+    space: bool,
+    filters: &'a [TagFilter],
+}
+
+impl PrintOptions<'_> {
+    /// Whether a boot environment or snapshot with these tags passes the
+    /// filters.
+    fn matches(&self, tags: &BTreeMap<String, String>) -> bool {
+        self.filters.iter().all(|filter| filter.matches(tags))
+    }
+}
+
+fn sort_snapshots(snapshots: &mut [Snapshot], sort_field: SortField) {
+    match sort_field {
+        SortField::Date => {
+            snapshots.sort_by_key(|snap| snap.created);
+        }
+        SortField::Name => {
+            snapshots.sort_by(|a, b| a.name.cmp(&b.name));
+        }
+        SortField::Space => {
+            snapshots.sort_by_key(|snap| snap.space);
+        }
+    }
+}
+
+fn dataset_to_json(dataset: &ChildDataset) -> serde_json::Value {
+    serde_json::json!({
+        "name": dataset.name,
+        "root": dataset.root.as_str(),
+        "mountpoint": dataset.mountpoint.as_ref().map(|mp| mp.display().to_string()),
+        "canmount": dataset.canmount,
+        "space": dataset.space,
+        "created": dataset.created,
+    })
+}
+
+fn snapshot_to_json(snapshot: &Snapshot) -> serde_json::Value {
+    serde_json::json!({
+        "name": snapshot.name,
+        "root": snapshot.root.as_str(),
+        "description": snapshot.description,
+        "space": snapshot.space,
+        "referenced": snapshot.referenced,
+        "written": snapshot.written,
+        "created": snapshot.created,
+        "pinned": snapshot.pinned,
+        "tags": snapshot.tags,
+    })
+}
+
+fn boot_environment_to_json(be: &BootEnvironment) -> serde_json::Value {
+    serde_json::json!({
+        "name": be.name,
+        "root": be.root.as_str(),
+        "guid": be.guid,
+        "description": be.description,
+        "mountpoint": be.mountpoint.as_ref().map(|mp| mp.display().to_string()),
+        "active": be.active,
+        "next_boot": be.next_boot,
+        "boot_once": be.boot_once,
+        "trial": be.trial,
+        "pinned": be.pinned,
+        "tags": be.tags,
+        "space": be.space,
+        "usage": {
+            "used_by_dataset": be.usage.used_by_dataset,
+            "used_by_snapshots": be.usage.used_by_snapshots,
+            "referenced": be.usage.referenced,
+            "written": be.usage.written,
+            "compress_ratio": be.usage.compress_ratio,
+        },
+        "origin": be.origin,
+        "created": be.created,
+    })
 }
 
 /// Prints a list of boot environments in the traditional `beadm list` format.
@@ -409,6 +913,24 @@ fn print_boot_environments<T: Client>(
         bes.retain(|be| be.name == *filter_name);
     }
 
+    // Filter by tags, keeping boot environments that have matching snapshots
+    // when those are listed.
+    if !options.filters.is_empty() {
+        let mut matching = Vec::with_capacity(bes.len());
+        for be in bes {
+            if options.matches(&be.tags)
+                || (options.snapshots
+                    && client
+                        .get_snapshots(&be.name, root)?
+                        .iter()
+                        .any(|snapshot| options.matches(&snapshot.tags)))
+            {
+                matching.push(be);
+            }
+        }
+        bes = matching;
+    }
+
     // Sort boot environments first.
     match options.sort_field {
         SortField::Date => {
@@ -425,31 +947,62 @@ fn print_boot_environments<T: Client>(
         bes.reverse();
     }
 
+    // JSON output nests snapshots under their boot environment rather than
+    // interleaving them as rows.
+    if options.json {
+        let mut entries = Vec::with_capacity(bes.len());
+        for be in &bes {
+            let mut entry = boot_environment_to_json(be);
+            if options.datasets {
+                let mut datasets = client.get_datasets(&be.name, root)?;
+                datasets.sort_by(|a, b| a.name.cmp(&b.name));
+                entry["datasets"] = datasets.iter().map(dataset_to_json).collect();
+            }
+            if options.snapshots {
+                let mut snapshots = client.get_snapshots(&be.name, root)?;
+                snapshots.retain(|snapshot| options.matches(&snapshot.tags));
+                sort_snapshots(&mut snapshots, options.sort_field);
+                entry["snapshots"] = snapshots.iter().map(snapshot_to_json).collect();
+            }
+            entries.push(entry);
+        }
+        let document = serde_json::json!({
+            "version": LIST_JSON_VERSION,
+            "boot_environments": entries,
+        });
+        serde_json::to_writer_pretty(&mut writer, &document).map_err(std::io::Error::from)?;
+        writeln!(writer)?;
+        return Ok(());
+    }
+
     // Convert boot environments (and optionally their snapshots) to rows.
     let mut rows: Vec<ListRow> = Vec::new();
     for be in bes.into_iter() {
         let name = be.name.clone();
         rows.push(ListRow::BootEnvironment(be));
 
+        // Child datasets are always listed in hierarchical (i.e. name) order
+        // below their boot environment, regardless of the sort field.
+        if options.datasets {
+            let mut datasets = client.get_datasets(&name, root)?;
+            datasets.sort_by(|a, b| a.name.cmp(&b.name));
+            rows.extend(datasets.into_iter().map(ListRow::Dataset));
+        }
+
         // Group snapshots under their respective boot environment.
         if options.snapshots {
             let mut snapshots = client.get_snapshots(&name, root)?;
+            snapshots.retain(|snapshot| options.matches(&snapshot.tags));
             // Sort snapshots by the same field as boot environments
-            match options.sort_field {
-                SortField::Date => {
-                    snapshots.sort_by_key(|snap| snap.created);
-                }
-                SortField::Name => {
-                    snapshots.sort_by(|a, b| a.name.cmp(&b.name));
-                }
-                SortField::Space => {
-                    snapshots.sort_by_key(|snap| snap.space);
-                }
-            }
+            sort_snapshots(&mut snapshots, options.sort_field);
             rows.extend(snapshots.into_iter().map(ListRow::Snapshot));
         }
     }
 
+    if options.space {
+        return print_space_rows(&rows, writer, options.parseable);
+    }
+
     // "Machine-parsable" output: no headers, tab-separated fields.
     //
     // beadm from illumos uses semicolons for -H, but bectl from FreeBSD
@@ -457,12 +1010,19 @@ fn print_boot_environments<T: Client>(
     // behaviour of zfs list -H.
     if options.parseable {
         for row in rows {
+            // The CANMOUNT field is only present when listing datasets.
+            let canmount = if options.datasets {
+                format!("{}\t", row.canmount().unwrap_or(""))
+            } else {
+                String::new()
+            };
             writeln!(
                 writer,
-                "{}\t{}\t{}\t{}\t{}\t{}",
+                "{}\t{}\t{}\t{}{}\t{}\t{}",
                 row.name(),
                 row.active_flags().unwrap_or("".to_string()),
                 row.mountpoint().unwrap_or("".to_string()),
+                canmount,
                 row.space(),
                 row.created(),
                 row.description().unwrap_or("")
@@ -486,12 +1046,19 @@ fn print_boot_environments<T: Client>(
     //
     // - We support a "description" column.
     // - Headers are uppercase with no separator, similar to other zfs commands.
+    // - A "canmount" column is added when listing datasets.
+    let canmount_header = if options.datasets {
+        format!("{:<8}  ", "CANMOUNT")
+    } else {
+        String::new()
+    };
     writeln!(
         writer,
-        "{:<name_width$}  {:<6}  {:<mountpoint_width$}  {}  {:<16}  {}",
+        "{:<name_width$}  {:<6}  {:<mountpoint_width$}  {}{}  {:<16}  {}",
         "NAME",
         "ACTIVE",
         "MOUNTPOINT",
+        canmount_header,
         "SPACE",
         "CREATED",
         "DESCRIPTION",
@@ -499,12 +1066,18 @@ fn print_boot_environments<T: Client>(
         mountpoint_width = mountpoint_width
     )?;
     for row in rows {
+        let canmount = if options.datasets {
+            format!("{:<8}  ", row.canmount().unwrap_or("-"))
+        } else {
+            String::new()
+        };
         writeln!(
             writer,
-            "{:<name_width$}  {:<6}  {:<mountpoint_width$}  {:<5}  {:<16}  {}",
+            "{:<name_width$}  {:<6}  {:<mountpoint_width$}  {}{:<5}  {:<16}  {}",
             row.name(),
             row.active_flags().unwrap_or("-".to_string()),
             row.mountpoint().unwrap_or("-".to_string()),
+            canmount,
             format_zfs_bytes(row.space()),
             format_timestamp(row.created()),
             row.description().unwrap_or("-"),
@@ -516,58 +1089,712 @@ fn print_boot_environments<T: Client>(
     Ok(())
 }
 
-fn execute_command<T: Client + 'static>(
-    command: &Commands,
-    root: Option<&Root>,
-    client: T,
-) -> Result<()> {
-    match command {
-        Commands::Create {
-            be_name,
-            activate,
-            temp_activate,
-            description,
-            source,
-            property,
-            empty,
-            host_id,
-            use_os_release,
-        } => {
-            if *empty {
-                let final_description = if let Some(path) = use_os_release {
-                    let os_release = scan::OsRelease::from_path(path)
-                        .context("Failed to parse os-release file")?;
-                    Some(os_release.pretty)
-                } else {
-                    description.clone()
-                };
+/// Formats a compression ratio the way `zfs list` does, e.g. `1.85x`.
+fn format_compress_ratio(ratio: f64) -> String {
+    format!("{:.2}x", ratio)
+}
 
-                client
-                    .create_empty(
-                        be_name,
-                        final_description.as_deref(),
-                        host_id.as_deref(),
-                        property,
-                        root,
-                    )
-                    .context("Failed to create empty boot environment")?;
-                println!("Created empty boot environment '{}'.", be_name);
-                return Ok(());
-            }
+/// Prints rows in the `beadm list --space` format, which mirrors the columns
+/// of `zfs list -o space` (plus the origin of each boot environment).
+fn print_space_rows(
+    rows: &[ListRow],
+    mut writer: impl std::io::Write,
+    parseable: bool,
+) -> Result<(), Error> {
+    if parseable {
+        for row in rows {
+            let usage = row.usage();
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                row.name(),
+                row.space(),
+                usage
+                    .map(|u| u.used_by_snapshots.to_string())
+                    .unwrap_or_default(),
+                usage
+                    .map(|u| u.used_by_dataset.to_string())
+                    .unwrap_or_default(),
+                row.referenced().map(|n| n.to_string()).unwrap_or_default(),
+                row.written().map(|n| n.to_string()).unwrap_or_default(),
+                usage
+                    .map(|u| format!("{:.2}", u.compress_ratio))
+                    .unwrap_or_default(),
+                row.origin().unwrap_or("")
+            )?;
+        }
+        return Ok(());
+    }
 
-            client
-                .create(
-                    be_name,
-                    description.as_deref(),
-                    source.as_ref(),
-                    property,
-                    root,
-                )
-                .context("Failed to create boot environment")?;
-            if *activate || *temp_activate {
-                client
-                    .activate(be_name, *temp_activate, root)
-                    .context("Failed to activate newly-created boot environment")?;
+    let name_width = rows
+        .iter()
+        .map(|row| row.name().len())
+        .max()
+        .unwrap_or(0)
+        .max(4);
+    writeln!(
+        writer,
+        "{:<name_width$}  USED   USEDSNAP  USEDDS  REFER  WRITTEN  RATIO  ORIGIN",
+        "NAME",
+        name_width = name_width
+    )?;
+    for row in rows {
+        let usage = row.usage();
+        writeln!(
+            writer,
+            "{:<name_width$}  {:<5}  {:<8}  {:<6}  {:<5}  {:<7}  {:<5}  {}",
+            row.name(),
+            format_zfs_bytes(row.space()),
+            usage
+                .map(|u| format_zfs_bytes(u.used_by_snapshots))
+                .unwrap_or("-".to_string()),
+            usage
+                .map(|u| format_zfs_bytes(u.used_by_dataset))
+                .unwrap_or("-".to_string()),
+            row.referenced()
+                .map(format_zfs_bytes)
+                .unwrap_or("-".to_string()),
+            row.written()
+                .map(format_zfs_bytes)
+                .unwrap_or("-".to_string()),
+            usage
+                .map(|u| format_compress_ratio(u.compress_ratio))
+                .unwrap_or("-".to_string()),
+            row.origin().unwrap_or("-"),
+            name_width = name_width
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Prints audit records for `beadm audit`, oldest first.
+fn print_audit_entries(
+    entries: &[AuditEntry],
+    parseable: bool,
+    mut writer: impl std::io::Write,
+) -> std::io::Result<()> {
+    let optional = |value: Option<String>| value.unwrap_or("-".to_string());
+    let outcome = |entry: &AuditEntry| match &entry.error {
+        Some(error) => format!("{}: {}", entry.outcome, error),
+        None => entry.outcome.clone(),
+    };
+    if parseable {
+        for entry in entries {
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                entry.timestamp,
+                optional(entry.uid.map(|uid| uid.to_string())),
+                optional(entry.pid.map(|pid| pid.to_string())),
+                entry.operation,
+                optional(entry.target.clone()),
+                optional(entry.action.clone()),
+                outcome(entry)
+            )?;
+        }
+        return Ok(());
+    }
+
+    let operation_width = entries
+        .iter()
+        .map(|entry| entry.operation.len())
+        .fold(9, usize::max);
+    let target_width = entries
+        .iter()
+        .map(|entry| entry.target.as_ref().map_or(1, |target| target.len()))
+        .fold(6, usize::max);
+    writeln!(
+        writer,
+        "{:<16}  {:<5}  {:<7}  {:<operation_width$}  {:<target_width$}  OUTCOME",
+        "DATE", "UID", "PID", "OPERATION", "TARGET"
+    )?;
+    for entry in entries {
+        writeln!(
+            writer,
+            "{:<16}  {:<5}  {:<7}  {:<operation_width$}  {:<target_width$}  {}",
+            format_timestamp(entry.timestamp),
+            optional(entry.uid.map(|uid| uid.to_string())),
+            optional(entry.pid.map(|pid| pid.to_string())),
+            entry.operation,
+            optional(entry.target.clone()),
+            outcome(entry)
+        )?;
+    }
+    Ok(())
+}
+
+/// Prints the boot history for `beadm history`, oldest first.
+fn print_boot_history(
+    history: &[BootRecord],
+    parseable: bool,
+    mut writer: impl std::io::Write,
+) -> std::io::Result<()> {
+    if parseable {
+        for record in history {
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}",
+                record.timestamp,
+                record.name,
+                record.guid,
+                if record.boot_once { "yes" } else { "no" }
+            )?;
+        }
+        return Ok(());
+    }
+
+    let name_width = history
+        .iter()
+        .map(|record| record.name.len())
+        .fold(4, usize::max);
+    writeln!(
+        writer,
+        "{:<16}  {:<name_width$}  TEMPORARY",
+        "BOOTED", "NAME"
+    )?;
+    for record in history {
+        writeln!(
+            writer,
+            "{:<16}  {:<name_width$}  {}",
+            format_timestamp(record.timestamp),
+            record.name,
+            if record.boot_once { "yes" } else { "no" }
+        )?;
+    }
+    Ok(())
+}
+
+/// Prints the ZFS properties of a boot environment or snapshot for `beadm
+/// get`, in the same layout as `zfs get`.
+fn print_properties(
+    target: &Label,
+    props: &[Property],
+    parseable: bool,
+    mut writer: impl std::io::Write,
+) -> std::io::Result<()> {
+    let name = target.to_string();
+    if parseable {
+        for prop in props {
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}",
+                name, prop.name, prop.value, prop.source
+            )?;
+        }
+        return Ok(());
+    }
+
+    let name_width = name.len().max(4);
+    let prop_width = props.iter().map(|prop| prop.name.len()).fold(8, usize::max);
+    let value_width = props
+        .iter()
+        .map(|prop| prop.value.len())
+        .fold(5, usize::max);
+    writeln!(
+        writer,
+        "{:<name_width$}  {:<prop_width$}  {:<value_width$}  SOURCE",
+        "NAME", "PROPERTY", "VALUE"
+    )?;
+    for prop in props {
+        writeln!(
+            writer,
+            "{:<name_width$}  {:<prop_width$}  {:<value_width$}  {}",
+            name, prop.name, prop.value, prop.source
+        )?;
+    }
+    Ok(())
+}
+
+/// Prints the changes between two boot environments or snapshots for `beadm
+/// diff`. Parseable output matches `zfs diff -FH`.
+fn print_changes(
+    changes: &[FileChange],
+    parseable: bool,
+    json: bool,
+    mut writer: impl std::io::Write,
+) -> std::io::Result<()> {
+    if json {
+        let entries: Vec<serde_json::Value> = changes
+            .iter()
+            .map(|change| {
+                serde_json::json!({
+                    "change": change.change.as_str(),
+                    "type": change.file_type.as_str(),
+                    "path": change.path,
+                    "new_path": change.new_path,
+                })
+            })
+            .collect();
+        let document = serde_json::json!({
+            "version": DIFF_JSON_VERSION,
+            "changes": entries,
+        });
+        serde_json::to_writer_pretty(&mut writer, &document).map_err(std::io::Error::from)?;
+        writeln!(writer)?;
+        return Ok(());
+    }
+
+    if parseable {
+        for change in changes {
+            write!(
+                writer,
+                "{}\t{}\t{}",
+                change.change.as_char(),
+                change.file_type.as_char(),
+                change.path
+            )?;
+            match &change.new_path {
+                Some(new_path) => writeln!(writer, "\t{}", new_path)?,
+                None => writeln!(writer)?,
+            }
+        }
+        return Ok(());
+    }
+
+    writeln!(writer, "{:<8}  {:<9}  PATH", "CHANGE", "TYPE")?;
+    for change in changes {
+        let path = match &change.new_path {
+            Some(new_path) => format!("{} -> {}", change.path, new_path),
+            None => change.path.clone(),
+        };
+        writeln!(
+            writer,
+            "{:<8}  {:<9}  {}",
+            change.change.as_str(),
+            change.file_type.as_str(),
+            path
+        )?;
+    }
+    Ok(())
+}
+
+/// Prints the issues found by checking a boot environment before activating
+/// it, which were either warnings or ignored with `--force`.
+fn print_health_issues(issues: &[HealthIssue]) {
+    for issue in issues {
+        if issue.fatal {
+            eprintln!("Ignoring failed health check: {}", issue.message);
+        } else {
+            eprintln!("Warning: {}", issue.message);
+        }
+    }
+}
+
+/// Prints the package changes between two boot environments for `beadm
+/// packages diff`. Missing versions are shown as `-`.
+fn print_package_changes(
+    changes: &[PackageChange],
+    parseable: bool,
+    mut writer: impl std::io::Write,
+) -> std::io::Result<()> {
+    let rows: Vec<[&str; 4]> = changes
+        .iter()
+        .map(|change| {
+            [
+                change.change.as_str(),
+                change.name.as_str(),
+                change.old_version.as_deref().unwrap_or("-"),
+                change.new_version.as_deref().unwrap_or("-"),
+            ]
+        })
+        .collect();
+    if parseable {
+        for row in rows {
+            writeln!(writer, "{}", row.join("\t"))?;
+        }
+        return Ok(());
+    }
+
+    if rows.is_empty() {
+        return writeln!(writer, "No package changes.");
+    }
+    let width = |i: usize, header: &str| {
+        rows.iter()
+            .map(|row| row[i].len())
+            .fold(header.len(), usize::max)
+    };
+    let (change_width, name_width, old_width) =
+        (width(0, "CHANGE"), width(1, "PACKAGE"), width(2, "OLD"));
+    writeln!(
+        writer,
+        "{:<change_width$}  {:<name_width$}  {:<old_width$}  NEW",
+        "CHANGE", "PACKAGE", "OLD"
+    )?;
+    for [change, name, old, new] in rows {
+        writeln!(
+            writer,
+            "{:<change_width$}  {:<name_width$}  {:<old_width$}  {}",
+            change, name, old, new
+        )?;
+    }
+    Ok(())
+}
+
+/// Prints the problems found by `beadm doctor`, each with an explanation
+/// and what was (or could be) done about it.
+fn print_findings(findings: &[Finding], mut writer: impl std::io::Write) -> std::io::Result<()> {
+    if findings.is_empty() {
+        return writeln!(writer, "No problems found.");
+    }
+    for (i, finding) in findings.iter().enumerate() {
+        if i > 0 {
+            writeln!(writer)?;
+        }
+        writeln!(writer, "{}: {}", finding.check, finding.message)?;
+        writeln!(writer, "  {}", finding.explanation)?;
+        match &finding.fix {
+            Some(fix) if finding.fixed => writeln!(writer, "  Fixed: {}.", fix)?,
+            Some(fix) => writeln!(writer, "  Fix with --fix: {}.", fix)?,
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+/// Prints a breakdown of the space used by a single boot environment (the
+/// active one by default) for `beadm space`.
+fn print_space_usage<T: Client>(
+    client: &T,
+    root: Option<&Root>,
+    be_name: Option<&str>,
+    mut writer: impl std::io::Write,
+) -> Result<(), Error> {
+    let bes = client.get_boot_environments(root)?;
+    let be = match be_name {
+        Some(name) => bes
+            .into_iter()
+            .find(|be| be.name == name)
+            .ok_or_else(|| Error::not_found(name))?,
+        None => bes
+            .into_iter()
+            .find(|be| be.active)
+            .ok_or(Error::NoActiveBootEnvironment)?,
+    };
+    let mut snapshots = client.get_snapshots(&be.name, root)?;
+    sort_snapshots(&mut snapshots, SortField::Date);
+
+    writeln!(writer, "Boot environment:   {}", be.name)?;
+    writeln!(writer, "Used:               {}", format_zfs_bytes(be.space))?;
+    writeln!(
+        writer,
+        "  By dataset:       {}",
+        format_zfs_bytes(be.usage.used_by_dataset)
+    )?;
+    writeln!(
+        writer,
+        "  By snapshots:     {}",
+        format_zfs_bytes(be.usage.used_by_snapshots)
+    )?;
+    writeln!(
+        writer,
+        "Referenced:         {}",
+        format_zfs_bytes(be.usage.referenced)
+    )?;
+    writeln!(
+        writer,
+        "Written:            {}",
+        format_zfs_bytes(be.usage.written)
+    )?;
+    writeln!(
+        writer,
+        "Compression ratio:  {}",
+        format_compress_ratio(be.usage.compress_ratio)
+    )?;
+    writeln!(
+        writer,
+        "Origin:             {}",
+        be.origin.as_deref().unwrap_or("-")
+    )?;
+
+    if !snapshots.is_empty() {
+        let name_width = snapshots
+            .iter()
+            .map(|snap| snap.name.len())
+            .max()
+            .unwrap_or(0)
+            .max(8);
+        writeln!(writer)?;
+        writeln!(
+            writer,
+            "{:<name_width$}  USED   REFER  WRITTEN  CREATED",
+            "SNAPSHOT",
+            name_width = name_width
+        )?;
+        for snap in &snapshots {
+            writeln!(
+                writer,
+                "{:<name_width$}  {:<5}  {:<5}  {:<7}  {}",
+                snap.name,
+                format_zfs_bytes(snap.space),
+                format_zfs_bytes(snap.referenced),
+                format_zfs_bytes(snap.written),
+                format_timestamp(snap.created),
+                name_width = name_width
+            )?;
+        }
+    }
+
+    // Data shared with the origin or with other boot environments is not
+    // counted in "used", so this is exactly what destroying would free.
+    writeln!(writer)?;
+    writeln!(
+        writer,
+        "Destroying '{}' would free {}; destroying only its snapshots would free {}.",
+        be.name,
+        format_zfs_bytes(be.space),
+        format_zfs_bytes(be.usage.used_by_snapshots)
+    )?;
+
+    Ok(())
+}
+
+/// Prints the tags of a boot environment or snapshot as `tag=value` lines.
+fn print_tags<T: Client>(
+    client: &T,
+    target: &Label,
+    root: Option<&Root>,
+    mut writer: impl std::io::Write,
+) -> Result<()> {
+    let tags = match target {
+        Label::Name(name) => client
+            .get_boot_environments(root)?
+            .into_iter()
+            .find(|be| be.name == *name)
+            .map(|be| be.tags),
+        Label::Snapshot(name, _) => {
+            let label = target.to_string();
+            client
+                .get_snapshots(name, root)?
+                .into_iter()
+                .find(|snapshot| snapshot.name == label)
+                .map(|snapshot| snapshot.tags)
+        }
+    }
+    .ok_or_else(|| Error::not_found(&target.to_string()))?;
+    for (key, value) in tags {
+        writeln!(writer, "{}={}", key, value)?;
+    }
+    Ok(())
+}
+
+/// Parse an age such as `30d` into seconds. Supports `s`, `m`, `h`, `d` and
+/// `w` suffixes; a bare number is taken as seconds.
+fn parse_age(value: &str) -> Result<u64, String> {
+    let (digits, multiplier) = match value.char_indices().last() {
+        Some((i, 's')) => (&value[..i], 1),
+        Some((i, 'm')) => (&value[..i], 60),
+        Some((i, 'h')) => (&value[..i], 60 * 60),
+        Some((i, 'd')) => (&value[..i], 24 * 60 * 60),
+        Some((i, 'w')) => (&value[..i], 7 * 24 * 60 * 60),
+        _ => (value, 1),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid age '{}'", value))
+}
+
+/// Prints the snapshots or boot environments pruned by `beadm prune`.
+fn print_pruned(
+    pruned: &[Pruned],
+    dry_run: bool,
+    mut writer: impl std::io::Write,
+) -> std::io::Result<()> {
+    if pruned.is_empty() {
+        return writeln!(writer, "Nothing to prune.");
+    }
+    let verb = if dry_run {
+        "Would destroy"
+    } else {
+        "Destroyed"
+    };
+    for item in pruned {
+        writeln!(
+            writer,
+            "{} '{}' ({}).",
+            verb,
+            item.name,
+            format_zfs_bytes(item.space)
+        )?;
+    }
+    let total = pruned.iter().map(|item| item.space).sum();
+    let verb = if dry_run {
+        "Would reclaim"
+    } else {
+        "Reclaimed"
+    };
+    writeln!(
+        writer,
+        "{} approximately {}.",
+        verb,
+        format_zfs_bytes(total)
+    )
+}
+
+/// Finds the snapshots left behind by `beadm create` that nothing depends on
+/// any more and, once confirmed, destroys them.
+fn collect_garbage<T: Client>(
+    client: &T,
+    root: Option<&Root>,
+    dry_run: bool,
+    yes: bool,
+    mut input: impl std::io::BufRead,
+    mut writer: impl std::io::Write,
+) -> Result<()> {
+    let mut targets = vec![];
+    for snapshot in client
+        .get_orphaned_snapshots(root)
+        .context("Failed to find orphaned snapshots")?
+    {
+        let Some((name, snapshot)) = snapshot.name.split_once('@') else {
+            continue;
+        };
+        let label = Label::Snapshot(name.to_string(), snapshot.to_string());
+        // Leave anything that turns out to have clones after all.
+        let plan = client.destroy(&label, false, false, false, true, root)?;
+        if plan.promote.is_empty() {
+            targets.push((label, plan.space));
+        }
+    }
+    if targets.is_empty() {
+        writeln!(writer, "No orphaned snapshots.")?;
+        return Ok(());
+    }
+
+    for (label, space) in &targets {
+        writeln!(
+            writer,
+            "Would destroy '{}' ({}).",
+            label,
+            format_zfs_bytes(*space)
+        )?;
+    }
+    let total = targets.iter().map(|(_, space)| space).sum();
+    writeln!(
+        writer,
+        "Would reclaim approximately {}.",
+        format_zfs_bytes(total)
+    )?;
+    if dry_run {
+        return Ok(());
+    }
+
+    if !yes {
+        write!(writer, "Destroy these snapshots? [y/N] ")?;
+        writer.flush()?;
+        let mut answer = String::new();
+        input.read_line(&mut answer)?;
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            writeln!(writer, "Nothing destroyed.")?;
+            return Ok(());
+        }
+    }
+    for (label, _) in &targets {
+        client
+            .destroy(label, false, false, false, false, root)
+            .context("Failed to destroy snapshot")?;
+        writeln!(writer, "Destroyed '{}'.", label)?;
+    }
+    Ok(())
+}
+
+/// Prints the steps `beadm destroy --dry-run` would take, in order.
+/// Remove any temporary activation, along with any pending trial boot unless
+/// `keep_trial` is set, as it is at boot.
+fn remove_temporary_activation<T: Client>(
+    client: &T,
+    keep_trial: bool,
+    root: Option<&Root>,
+) -> Result<()> {
+    client
+        .clear_boot_once(root)
+        .context("Failed to remove temporary boot environment activation")?;
+    if !keep_trial {
+        client
+            .cancel_trial(root)
+            .context("Failed to cancel trial boot")?;
+    }
+    Ok(())
+}
+
+fn print_destroy_plan(plan: &DestroyPlan, mut writer: impl std::io::Write) -> std::io::Result<()> {
+    for name in &plan.unpin {
+        writeln!(writer, "Would unpin '{}'.", name)?;
+    }
+    for mountpoint in &plan.unmount {
+        writeln!(writer, "Would unmount '{}'.", mountpoint)?;
+    }
+    for clone in &plan.promote {
+        writeln!(writer, "Would promote '{}'.", clone)?;
+    }
+    for snapshot in &plan.snapshots {
+        writeln!(writer, "Would destroy snapshot '{}'.", snapshot)?;
+    }
+    for dataset in &plan.datasets {
+        writeln!(writer, "Would destroy '{}'.", dataset)?;
+    }
+    writeln!(
+        writer,
+        "Would reclaim approximately {}.",
+        format_zfs_bytes(plan.space)
+    )
+}
+
+fn execute_command<T: Client + 'static>(
+    command: &Commands,
+    root: Option<&Root>,
+    client: T,
+) -> Result<()> {
+    match command {
+        Commands::Create {
+            be_name,
+            activate,
+            temp_activate,
+            description,
+            source,
+            property,
+            empty,
+            host_id,
+            use_os_release,
+        } => {
+            if *empty {
+                let final_description = if let Some(path) = use_os_release {
+                    let os_release = scan::OsRelease::from_path(path)
+                        .context("Failed to parse os-release file")?;
+                    Some(os_release.pretty)
+                } else {
+                    description.clone()
+                };
+
+                client
+                    .create_empty(
+                        be_name,
+                        final_description.as_deref(),
+                        host_id.as_deref(),
+                        property,
+                        root,
+                    )
+                    .context("Failed to create empty boot environment")?;
+                println!("Created empty boot environment '{}'.", be_name);
+                return Ok(());
+            }
+
+            client
+                .create(
+                    be_name,
+                    description.as_deref(),
+                    source.as_ref(),
+                    property,
+                    root,
+                )
+                .context("Failed to create boot environment")?;
+            if *activate || *temp_activate {
+                let issues = client
+                    .activate_checked(be_name, *temp_activate, false, root)
+                    .context("Failed to activate newly-created boot environment")?;
+                print_health_issues(&issues);
             }
             println!(
                 "Created {} boot environment '{}'.",
@@ -586,49 +1813,129 @@ fn execute_command<T: Client + 'static>(
             target,
             force_unmount,
             destroy_snapshots,
+            unpin,
+            dry_run,
         } => {
-            client
-                .destroy(target, *force_unmount, *destroy_snapshots, root)
+            let plan = client
+                .destroy(
+                    target,
+                    *force_unmount,
+                    *destroy_snapshots,
+                    *unpin,
+                    *dry_run,
+                    root,
+                )
                 .context("Failed to destroy boot environment")?;
-            println!("Destroyed '{}'.", target);
+            if *dry_run {
+                print_destroy_plan(&plan, &mut std::io::stdout())?;
+            } else {
+                println!("Destroyed '{}'.", target);
+            }
             Ok(())
         }
+        Commands::Prune {
+            keep_last,
+            keep_newer_than,
+            keep_daily,
+            keep_weekly,
+            description_prefix,
+            boot_environments,
+            dry_run,
+        } => {
+            let policy = RetentionPolicy {
+                keep_last: *keep_last,
+                keep_newer_than: *keep_newer_than,
+                keep_daily: *keep_daily,
+                keep_weekly: *keep_weekly,
+                description_prefix: description_prefix.clone(),
+            };
+            let pruned = client
+                .prune(&policy, *boot_environments, *dry_run, root)
+                .context("Failed to prune")?;
+            print_pruned(&pruned, *dry_run, &mut std::io::stdout())?;
+            Ok(())
+        }
+        Commands::Gc { yes, dry_run } => collect_garbage(
+            &client,
+            root,
+            *dry_run,
+            *yes,
+            std::io::stdin().lock(),
+            std::io::stdout(),
+        ),
         Commands::List {
             be_name,
             all,
             datasets,
             snapshots,
             parseable,
+            json,
+            space,
             sort_asc,
             sort_des,
+            filter,
         } => {
-            if *all || *datasets {
-                anyhow::bail!("Listing datasets (via -a or -d) is not yet supported.");
-            }
-
             let sort_field = sort_des.unwrap_or(*sort_asc);
             let options = PrintOptions {
                 be_name,
                 sort_field,
                 descending: sort_des.is_some(),
                 parseable: *parseable,
-                snapshots: *snapshots,
+                json: *json,
+                datasets: *all || *datasets,
+                snapshots: *all || *snapshots,
+                space: *space,
+                filters: filter,
             };
 
             print_boot_environments(&client, root, &mut std::io::stdout(), options)
                 .context("Failed to list boot environments")?;
             Ok(())
         }
+        Commands::Space { be_name } => {
+            print_space_usage(&client, root, be_name.as_deref(), &mut std::io::stdout())
+                .context("Failed to show space usage")?;
+            Ok(())
+        }
+        Commands::Audit {
+            since,
+            lines,
+            parseable,
+        } => {
+            let entries =
+                audit::query(since.as_deref(), *lines).context("Failed to query audit log")?;
+            print_audit_entries(&entries, *parseable, &mut std::io::stdout())?;
+            Ok(())
+        }
+        Commands::History { parseable, record } => {
+            if *record {
+                match client.record_boot(root).context("Failed to record boot")? {
+                    Some(record) => println!("Recorded boot of '{}'.", record.name),
+                    None => println!("Not booted into a boot environment; nothing recorded."),
+                }
+                return Ok(());
+            }
+            let history = client
+                .get_boot_history(root)
+                .context("Failed to get boot history")?;
+            print_boot_history(&history, *parseable, &mut std::io::stdout())?;
+            Ok(())
+        }
         Commands::Mount {
             be_name,
             mountpoint,
             mode,
+            options,
         } => {
+            let options = MountOptions {
+                mode: *mode,
+                options: options.clone(),
+            };
             let mp = client
                 .mount(
                     be_name,
                     mountpoint.as_ref().map(|mp| mp.as_path()),
-                    *mode,
+                    &options,
                     root,
                 )
                 .context("Failed to mount boot environment")?;
@@ -643,29 +1950,86 @@ fn execute_command<T: Client + 'static>(
                 .context("Failed to unmount boot environment")?;
             Ok(())
         }
-        Commands::Rename { be_name, new_name } => {
-            client
-                .rename(be_name, new_name, root)
-                .context("Failed to rename boot environment")?;
-            println!("Renamed boot environment '{}' to '{}'.", be_name, new_name);
+        Commands::Update { name } => {
+            let Some(update) = update::update(&client, name.as_deref(), root)
+                .context("Failed to update in a new boot environment")?
+            else {
+                println!("Already up to date.");
+                return Ok(());
+            };
+            print_health_issues(&update.issues);
+            print_package_changes(&update.changes, false, &mut std::io::stdout())?;
+            println!(
+                "Created boot environment '{}' and activated it for the next boot.",
+                update.name
+            );
             Ok(())
         }
+        Commands::Exec { be_name, command } => {
+            let status = exec::run_in_boot_environment(&client, be_name, Some(command), root)
+                .context("Failed to run command in boot environment")?;
+            std::process::exit(exec::exit_code(status));
+        }
+        Commands::Chroot { be_name } => {
+            let status = exec::run_in_boot_environment(&client, be_name, None, root)
+                .context("Failed to start a shell in boot environment")?;
+            std::process::exit(exec::exit_code(status));
+        }
+        Commands::Rename { target, new_name } => match (target, new_name) {
+            (Label::Name(be_name), Label::Name(new_name)) => {
+                client
+                    .rename(be_name, new_name, root)
+                    .context("Failed to rename boot environment")?;
+                println!("Renamed boot environment '{}' to '{}'.", be_name, new_name);
+                Ok(())
+            }
+            (Label::Snapshot(be_name, snapshot), Label::Snapshot(new_be_name, new_snapshot))
+                if be_name == new_be_name =>
+            {
+                client
+                    .rename_snapshot(be_name, snapshot, new_snapshot, root)
+                    .context("Failed to rename snapshot")?;
+                println!("Renamed snapshot '{}' to '{}'.", target, new_name);
+                Ok(())
+            }
+            (Label::Snapshot(..), Label::Snapshot(..)) => Err(Error::InvalidName {
+                name: new_name.to_string(),
+                reason: "snapshots cannot be renamed to another boot environment".to_string(),
+            }
+            .into()),
+            _ => Err(Error::InvalidName {
+                name: new_name.to_string(),
+                reason: "a boot environment and a snapshot cannot be renamed to each other"
+                    .to_string(),
+            }
+            .into()),
+        },
         Commands::Activate {
             be_name,
             temporary,
+            trial,
             deactivate,
+            keep_trial,
+            force,
         } => {
             if *deactivate {
-                client
-                    .clear_boot_once(root)
-                    .context("Failed to remove temporary boot environment activation")?;
+                remove_temporary_activation(&client, *keep_trial, root)?;
                 println!("Removed temporary boot environment activation.");
+            } else if *trial {
+                // SAFETY: Safe due to required_unless_present.
+                let be_name = be_name.as_ref().unwrap();
+                let issues = client
+                    .activate_trial_checked(be_name, *force, root)
+                    .context("Failed to activate boot environment")?;
+                print_health_issues(&issues);
+                println!("Activated '{}' for a trial boot.", be_name);
             } else {
                 // SAFETY: Safe due to required_unless_present.
                 let be_name = be_name.as_ref().unwrap();
-                client
-                    .activate(be_name, *temporary, root)
+                let issues = client
+                    .activate_checked(be_name, *temporary, *force, root)
                     .context("Failed to activate boot environment")?;
+                print_health_issues(&issues);
                 println!(
                     "Activated '{}'{}.",
                     be_name,
@@ -674,10 +2038,39 @@ fn execute_command<T: Client + 'static>(
             }
             Ok(())
         }
-        Commands::Rollback { be_name, snapshot } => {
-            client
-                .rollback(be_name, snapshot, root)
+        Commands::Confirm => {
+            match client
+                .confirm(root)
+                .context("Failed to confirm trial boot")?
+            {
+                Some(be_name) => println!("Confirmed boot environment '{}'.", be_name),
+                None => println!("No trial boot to confirm."),
+            }
+            Ok(())
+        }
+        Commands::Rollback {
+            be_name,
+            snapshot,
+            destroy_newer,
+            force,
+            no_snapshot,
+        } => {
+            let rolled_back = client
+                .rollback(
+                    be_name,
+                    snapshot,
+                    *destroy_newer,
+                    *force,
+                    !*no_snapshot,
+                    root,
+                )
                 .context("Failed to rollback to snapshot")?;
+            for name in &rolled_back.destroyed {
+                println!("Destroyed newer snapshot '{}'.", name);
+            }
+            if let Some(safety) = &rolled_back.safety_snapshot {
+                println!("Kept the previous state as '{}'.", safety);
+            }
             println!("Rolled back to '{}'.", snapshot);
             Ok(())
         }
@@ -715,6 +2108,169 @@ fn execute_command<T: Client + 'static>(
             println!("Set description for '{}'.", target);
             Ok(())
         }
+        Commands::Pin { target } => {
+            client.pin(target, root).context("Failed to pin")?;
+            println!("Pinned '{}'.", target);
+            Ok(())
+        }
+        Commands::Unpin { target } => {
+            client.unpin(target, root).context("Failed to unpin")?;
+            println!("Unpinned '{}'.", target);
+            Ok(())
+        }
+        Commands::Tag { target, tags } => {
+            if tags.is_empty() {
+                print_tags(&client, target, root, &mut std::io::stdout())?;
+                return Ok(());
+            }
+            for (key, value) in tags {
+                client
+                    .tag(target, key, value, root)
+                    .context("Failed to set tag")?;
+            }
+            println!("Tagged '{}'.", target);
+            Ok(())
+        }
+        Commands::Untag { target, keys } => {
+            for key in keys {
+                client
+                    .untag(target, key, root)
+                    .context("Failed to remove tag")?;
+            }
+            println!("Untagged '{}'.", target);
+            Ok(())
+        }
+        Commands::Get {
+            parseable,
+            target,
+            properties,
+        } => {
+            let props = client
+                .get_zfs_properties(target, properties, root)
+                .context("Failed to get properties")?;
+            print_properties(target, &props, *parseable, &mut std::io::stdout())?;
+            Ok(())
+        }
+        Commands::Set {
+            be_name,
+            properties,
+        } => {
+            client
+                .set_zfs_properties(be_name, properties, root)
+                .context("Failed to set properties")?;
+            println!("Set properties for '{}'.", be_name);
+            Ok(())
+        }
+        Commands::Diff {
+            parseable,
+            json,
+            paths,
+            from,
+            to,
+        } => {
+            let changes = client
+                .diff(from, to.as_ref(), paths, root)
+                .context("Failed to compare boot environments")?;
+            print_changes(&changes, *parseable, *json, &mut std::io::stdout())?;
+            Ok(())
+        }
+        Commands::Export {
+            base,
+            output,
+            target,
+        } => {
+            let snapshot = match output {
+                Some(path) => {
+                    let file = std::fs::File::create(path)
+                        .with_context(|| format!("Failed to create {}", path.display()))?;
+                    client
+                        .export(target, base.as_deref(), file.as_fd(), root)
+                        .inspect_err(|_| _ = std::fs::remove_file(path))
+                }
+                None => {
+                    let stdout = std::io::stdout();
+                    if stdout.is_terminal() {
+                        eprintln!("Refusing to write an export stream to a terminal.");
+                        std::process::exit(1);
+                    }
+                    client.export(target, base.as_deref(), stdout.as_fd(), root)
+                }
+            }
+            .context("Failed to export boot environment")?;
+            // Standard output may be the stream itself.
+            eprintln!("Exported '{}'.", snapshot);
+            Ok(())
+        }
+        Commands::Import { input, be_name } => {
+            let stdin = std::io::stdin();
+            let file;
+            let fd = if input.as_os_str() == "-" {
+                stdin.as_fd()
+            } else {
+                file = std::fs::File::open(input)
+                    .with_context(|| format!("Failed to open {}", input.display()))?;
+                file.as_fd()
+            };
+            client
+                .import(be_name, fd, root)
+                .context("Failed to import boot environment")?;
+            println!("Imported boot environment '{}'.", be_name);
+            Ok(())
+        }
+        Commands::Replicate { to, names } => {
+            let replications = client
+                .replicate(to, names, root)
+                .context("Failed to replicate boot environments")?;
+            for replication in replications {
+                let resumed = if replication.resumed {
+                    " (resumed an interrupted transfer)"
+                } else {
+                    ""
+                };
+                match replication.snapshots.last() {
+                    Some(latest) => println!(
+                        "Replicated '{}' to '{}/{}@{}' ({} snapshot(s)){}.",
+                        replication.name,
+                        to.as_str(),
+                        replication.name,
+                        latest,
+                        replication.snapshots.len(),
+                        resumed
+                    ),
+                    None => println!(
+                        "'{}' is already up to date in '{}'{}.",
+                        replication.name,
+                        to.as_str(),
+                        resumed
+                    ),
+                }
+            }
+            Ok(())
+        }
+        Commands::Packages {
+            command:
+                PackagesCommands::Diff {
+                    parseable,
+                    from,
+                    to,
+                },
+        } => {
+            let changes = client
+                .diff_packages(from, to.as_deref(), root)
+                .context("Failed to compare packages")?;
+            print_package_changes(&changes, *parseable, &mut std::io::stdout())?;
+            Ok(())
+        }
+        Commands::Doctor { fix } => {
+            let findings = client
+                .doctor(*fix, root)
+                .context("Failed to check boot environments")?;
+            print_findings(&findings, &mut std::io::stdout())?;
+            if findings.iter().any(|finding| !finding.fixed) {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
         Commands::Init { pool } => {
             client
                 .init(pool)
@@ -751,6 +2307,14 @@ fn main() -> Result<()> {
         }
     }
 
+    // Commands run inside boot environments need them mounted in our own
+    // mount namespace, not the daemon's.
+    if let Commands::Update { .. } | Commands::Exec { .. } | Commands::Chroot { .. } = cli.command
+        && cli.client != ClientType::Mock
+    {
+        cli.client = ClientType::LibZfs;
+    }
+
     match cli.client {
         ClientType::Mock => {
             let client = EmulatorClient::sampled();
@@ -767,7 +2331,7 @@ fn main() -> Result<()> {
             }
 
             // Otherwise we fall back to using libzfs.
-            execute_command(&cli.command, cli.root.as_ref(), LibZfsClient::new())
+            execute_with_libzfs(&cli.command, cli.root.as_ref())
         }
         #[cfg(feature = "dbus")]
         ClientType::DBus => {
@@ -775,8 +2339,19 @@ fn main() -> Result<()> {
             let client = dbus::ClientProxy::new()?;
             execute_command(&cli.command, cli.root.as_ref(), client)
         }
-        ClientType::LibZfs => execute_command(&cli.command, cli.root.as_ref(), LibZfsClient::new()),
+        ClientType::LibZfs => execute_with_libzfs(&cli.command, cli.root.as_ref()),
+    }
+}
+
+/// Executes a command using libzfs directly, auditing any changes as made by
+/// this process. The D-Bus service audits changes on behalf of its callers
+/// instead.
+fn execute_with_libzfs(command: &Commands, root: Option<&Root>) -> Result<()> {
+    if let Commands::Daemon { .. } = command {
+        return execute_command(command, root, LibZfsClient::new());
     }
+    let client = AuditedClient::new(Box::new(LibZfsClient::new()), Caller::current());
+    execute_command(command, root, client)
 }
 
 #[cfg(test)]
@@ -798,7 +2373,11 @@ mod tests {
             sort_field: SortField::Date,
             descending: false,
             parseable: false,
+            json: false,
+            datasets: false,
             snapshots: false,
+            space: false,
+            filters: &[],
         };
         print_boot_environments(&client, None, &mut output, options).unwrap();
         assert_eq!(
@@ -823,7 +2402,11 @@ alt      -       -           8K     2021-06-10 06:11  Testing
                 sort_field: SortField::Date,
                 descending: false,
                 parseable: true,
+                json: false,
+                datasets: false,
                 snapshots: false,
+                space: false,
+                filters: &[],
             },
         )
         .unwrap();
@@ -848,7 +2431,11 @@ alt      -       -           8K     2021-06-10 06:11  Testing
                 sort_field: SortField::Date,
                 descending: false,
                 parseable: true,
+                json: false,
+                datasets: false,
                 snapshots: false,
+                space: false,
+                filters: &[],
             },
         )
         .unwrap();
@@ -873,7 +2460,11 @@ alt      -       -           8K     2021-06-10 06:11  Testing
                 sort_field: SortField::Name,
                 descending: true,
                 parseable: true,
+                json: false,
+                datasets: false,
                 snapshots: false,
+                space: false,
+                filters: &[],
             },
         )
         .unwrap();
@@ -897,27 +2488,315 @@ alt      -       -           8K     2021-06-10 06:11  Testing
             active: false,
             next_boot: false,
             boot_once: true, // This should yield the 'T' flag.
+            trial: false,
+            pinned: false,
+            tags: BTreeMap::new(),
             space: 8192,
+            usage: SpaceUsage::default(),
+            origin: None,
             created: 1623301740,
         }]);
         let mut output = Vec::new();
-        print_boot_environments(
-            &client,
-            None,
-            &mut output,
-            PrintOptions {
-                be_name: &None,
-                sort_field: SortField::Date,
-                descending: false,
-                parseable: true,
-                snapshots: false,
+        print_boot_environments(
+            &client,
+            None,
+            &mut output,
+            PrintOptions {
+                be_name: &None,
+                sort_field: SortField::Date,
+                descending: false,
+                parseable: true,
+                json: false,
+                datasets: false,
+                snapshots: false,
+                space: false,
+                filters: &[],
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "temp-boot\tT\t\t8192\t1623301740\t\n"
+        );
+    }
+
+    #[test]
+    fn test_format_active_flags_trial() {
+        let client = EmulatorClient::sampled();
+        client.activate_trial("alt", None).unwrap();
+        let bes = client.get_boot_environments(None).unwrap();
+        let alt = bes.iter().find(|be| be.name == "alt").unwrap();
+        assert_eq!(format_active_flags(alt), Some("TP".to_string()));
+    }
+
+    #[test]
+    fn test_print_boot_environments_pinned() {
+        let client = EmulatorClient::sampled();
+        client
+            .pin(&Label::Name("default".to_string()), None)
+            .unwrap();
+        client
+            .pin(
+                &Label::Snapshot("alt".to_string(), "backup".to_string()),
+                None,
+            )
+            .unwrap();
+        let mut output = Vec::new();
+        print_boot_environments(
+            &client,
+            None,
+            &mut output,
+            PrintOptions {
+                be_name: &None,
+                sort_field: SortField::Date,
+                descending: false,
+                parseable: true,
+                json: false,
+                datasets: false,
+                snapshots: true,
+                space: false,
+                filters: &[],
+            },
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let flags: Vec<(&str, &str)> = output
+            .lines()
+            .map(|line| {
+                let mut fields = line.split('\t');
+                (fields.next().unwrap(), fields.next().unwrap())
+            })
+            .collect();
+        assert_eq!(
+            flags,
+            vec![
+                ("default", "NRK"),
+                ("default@2021-06-10-04:30", ""),
+                ("default@2021-06-10-05:10", ""),
+                ("alt", ""),
+                ("alt@backup", "K"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_print_boot_history() {
+        let client = EmulatorClient::sampled();
+        let history = client.get_boot_history(None).unwrap();
+
+        let mut output = Vec::new();
+        print_boot_history(&history, false, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            r"BOOTED            NAME     TEMPORARY
+2021-06-10 06:30  default  no
+2021-06-10 07:30  alt      yes
+2021-06-10 08:30  default  no
+"
+        );
+
+        let mut output = Vec::new();
+        print_boot_history(&history[1..2], true, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!(
+                "1623310200\talt\t{}\tyes\n",
+                EmulatorClient::generate_guid("alt")
+            )
+        );
+    }
+
+    #[test]
+    fn test_print_properties() {
+        let client = EmulatorClient::sampled();
+        let target = Label::Name("alt".to_string());
+        let names = ["canmount".to_string(), "compression".to_string()];
+        let props = client.get_zfs_properties(&target, &names, None).unwrap();
+
+        let mut output = Vec::new();
+        print_properties(&target, &props, false, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            r"NAME  PROPERTY     VALUE   SOURCE
+alt   canmount     noauto  local
+alt   compression  off     default
+"
+        );
+
+        let mut output = Vec::new();
+        print_properties(&target, &props[..1], true, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "alt\tcanmount\tnoauto\tlocal\n"
+        );
+    }
+
+    #[test]
+    fn test_print_changes() {
+        let client = EmulatorClient::sampled();
+        let from = Label::Name("alt".to_string());
+        let paths = [PathBuf::from("/etc/hostname"), PathBuf::from("/usr/bin")];
+        let changes = client.diff(&from, None, &paths, None).unwrap();
+
+        let mut output = Vec::new();
+        print_changes(&changes, false, false, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            r"CHANGE    TYPE       PATH
+modified  file       /etc/hostname
+renamed   file       /usr/bin/tool -> /usr/local/bin/tool
+"
+        );
+
+        let mut output = Vec::new();
+        print_changes(&changes, true, false, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "M\tF\t/etc/hostname\nR\tF\t/usr/bin/tool\t/usr/local/bin/tool\n"
+        );
+
+        let mut output = Vec::new();
+        print_changes(&changes, false, true, &mut output).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(value["version"], DIFF_JSON_VERSION);
+        assert_eq!(value["changes"][0]["change"], "modified");
+        assert_eq!(value["changes"][0]["new_path"], serde_json::Value::Null);
+        assert_eq!(value["changes"][1]["new_path"], "/usr/local/bin/tool");
+    }
+
+    #[test]
+    fn test_print_package_changes() {
+        use be::packages::PackageChangeKind;
+
+        let changes = vec![
+            PackageChange {
+                name: "linux-lts".to_string(),
+                change: PackageChangeKind::Upgraded,
+                old_version: Some("6.6.30-r0".to_string()),
+                new_version: Some("6.6.31-r0".to_string()),
+            },
+            PackageChange {
+                name: "htop".to_string(),
+                change: PackageChangeKind::Added,
+                old_version: None,
+                new_version: Some("3.3.0-r0".to_string()),
+            },
+        ];
+
+        let mut output = Vec::new();
+        print_package_changes(&changes, false, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            r"CHANGE    PACKAGE    OLD        NEW
+upgraded  linux-lts  6.6.30-r0  6.6.31-r0
+added     htop       -          3.3.0-r0
+"
+        );
+
+        let mut output = Vec::new();
+        print_package_changes(&changes[1..], true, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "added\thtop\t-\t3.3.0-r0\n"
+        );
+
+        let mut output = Vec::new();
+        print_package_changes(&[], false, &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "No package changes.\n");
+    }
+
+    #[test]
+    fn test_print_findings() {
+        let findings = vec![
+            Finding {
+                check: "layout".to_string(),
+                name: "alt".to_string(),
+                message: "Boot environment 'alt' has canmount=on".to_string(),
+                explanation: "Boot environments should have canmount=noauto.".to_string(),
+                fix: Some("set canmount=noauto".to_string()),
+                fixed: true,
+            },
+            Finding {
+                check: "bootfs".to_string(),
+                name: "zroot".to_string(),
+                message: "The pool has no bootfs set".to_string(),
+                explanation: "There is nothing to boot.".to_string(),
+                fix: Some("activate 'default'".to_string()),
+                fixed: false,
+            },
+            Finding {
+                check: "shared".to_string(),
+                name: "zroot/usr".to_string(),
+                message: "'zroot/usr' is mounted at /usr in every boot environment".to_string(),
+                explanation: "Move it into the boot environments.".to_string(),
+                fix: None,
+                fixed: false,
+            },
+        ];
+
+        let mut output = Vec::new();
+        print_findings(&findings, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            r"layout: Boot environment 'alt' has canmount=on
+  Boot environments should have canmount=noauto.
+  Fixed: set canmount=noauto.
+
+bootfs: The pool has no bootfs set
+  There is nothing to boot.
+  Fix with --fix: activate 'default'.
+
+shared: 'zroot/usr' is mounted at /usr in every boot environment
+  Move it into the boot environments.
+"
+        );
+
+        let mut output = Vec::new();
+        print_findings(&[], &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "No problems found.\n");
+    }
+
+    #[test]
+    fn test_print_audit_entries() {
+        let entries = vec![
+            AuditEntry {
+                timestamp: 1623301740,
+                operation: "activate".to_string(),
+                target: Some("alt".to_string()),
+                uid: Some(1000),
+                pid: Some(4242),
+                action: Some("ca.kamacite.BootEnvironments1.manage".to_string()),
+                outcome: "success".to_string(),
+                error: None,
             },
-        )
-        .unwrap();
+            AuditEntry {
+                timestamp: 1623305460,
+                operation: "clear-boot-once".to_string(),
+                target: None,
+                uid: Some(0),
+                pid: None,
+                action: None,
+                outcome: "failure".to_string(),
+                error: Some("oops".to_string()),
+            },
+        ];
 
+        let mut output = Vec::new();
+        print_audit_entries(&entries, false, &mut output).unwrap();
         assert_eq!(
             String::from_utf8(output).unwrap(),
-            "temp-boot\tT\t\t8192\t1623301740\t\n"
+            r"DATE              UID    PID      OPERATION        TARGET  OUTCOME
+2021-06-10 05:09  1000   4242     activate         alt     success
+2021-06-10 06:11  0      -        clear-boot-once  -       failure: oops
+"
+        );
+
+        let mut output = Vec::new();
+        print_audit_entries(&entries[1..], true, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "1623305460\t0\t-\tclear-boot-once\t-\t-\tfailure: oops\n"
         );
     }
 
@@ -953,6 +2832,26 @@ alt      -       -           8K     2021-06-10 06:11  Testing
         // integration tests since it calls std::process::exit(1)
     }
 
+    #[test]
+    fn test_remove_temporary_activation() {
+        let client = EmulatorClient::sampled();
+        let on_trial = || {
+            client
+                .get_boot_environments(None)
+                .unwrap()
+                .iter()
+                .any(|be| be.trial)
+        };
+
+        // The boot-time service keeps the trial, but the user running -T
+        // cancels it.
+        client.activate_trial("alt", None).unwrap();
+        remove_temporary_activation(&client, true, None).unwrap();
+        assert!(on_trial());
+        remove_temporary_activation(&client, false, None).unwrap();
+        assert!(!on_trial());
+    }
+
     #[test]
     fn test_mount_command_with_mountpoint() {
         let client = EmulatorClient::sampled();
@@ -963,6 +2862,7 @@ alt      -       -           8K     2021-06-10 06:11  Testing
                 be_name: "alt".to_string(),
                 mountpoint: Some(PathBuf::from("/mnt/test")),
                 mode: MountMode::ReadWrite,
+                options: vec![],
             },
             None,
             client,
@@ -980,6 +2880,7 @@ alt      -       -           8K     2021-06-10 06:11  Testing
                 be_name: "alt".to_string(),
                 mountpoint: None,
                 mode: MountMode::ReadOnly,
+                options: vec![MountOption::Nosuid, MountOption::Nodev, MountOption::Noexec],
             },
             None,
             client,
@@ -997,6 +2898,7 @@ alt      -       -           8K     2021-06-10 06:11  Testing
                 be_name: "non-existent".to_string(),
                 mountpoint: Some(PathBuf::from("/mnt/test")),
                 mode: MountMode::ReadWrite,
+                options: vec![],
             },
             None,
             client,
@@ -1012,7 +2914,7 @@ alt      -       -           8K     2021-06-10 06:11  Testing
         let client = EmulatorClient::sampled();
 
         // First mount a BE
-        let mount_result = client.mount("alt", None, MountMode::ReadWrite, None);
+        let mount_result = client.mount("alt", None, &MountOptions::default(), None);
         assert!(mount_result.is_ok());
 
         // Then unmount it
@@ -1055,7 +2957,11 @@ alt      -       -           8K     2021-06-10 06:11  Testing
                 sort_field: SortField::Date,
                 descending: false,
                 parseable: false,
+                json: false,
+                datasets: false,
                 snapshots: true,
+                space: false,
+                filters: &[],
             },
         )
         .unwrap();
@@ -1081,7 +2987,11 @@ alt@backup                -       -           1K     2021-06-10 06:20  Manual ba
                 sort_field: SortField::Date,
                 descending: false,
                 parseable: true,
+                json: false,
+                datasets: false,
                 snapshots: true,
+                space: false,
+                filters: &[],
             },
         )
         .unwrap();
@@ -1101,4 +3011,464 @@ alt@backup                -       -           1K     2021-06-10 06:20  Manual ba
         assert_eq!(lines[3], "alt\t\t\t8192\t1623305460\tTesting");
         assert_eq!(lines[4], "alt@backup\t\t\t1024\t1623306000\tManual backup");
     }
+
+    #[test]
+    fn test_print_boot_environments_with_datasets() {
+        let client = EmulatorClient::sampled();
+        let mut output = Vec::new();
+        print_boot_environments(
+            &client,
+            None,
+            &mut output,
+            PrintOptions {
+                be_name: &None,
+                sort_field: SortField::Date,
+                descending: false,
+                parseable: false,
+                json: false,
+                datasets: true,
+                snapshots: false,
+                space: false,
+                filters: &[],
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            r"NAME               ACTIVE  MOUNTPOINT  CANMOUNT  SPACE  CREATED           DESCRIPTION
+default            NR      /           -         906M   2021-06-10 05:09  -
+default/usr        -       -           off       195M   2021-06-10 05:09  -
+default/usr/local  -       /usr/local  on        3.91M  2021-06-10 05:09  -
+default/var        -       /var        on        50M    2021-06-10 05:09  -
+alt                -       -           -         8K     2021-06-10 06:11  Testing
+"
+        );
+    }
+
+    #[test]
+    fn test_print_boot_environments_all_parseable() {
+        let client = EmulatorClient::sampled();
+        let mut output = Vec::new();
+        print_boot_environments(
+            &client,
+            None,
+            &mut output,
+            PrintOptions {
+                be_name: &Some("default".to_string()),
+                sort_field: SortField::Date,
+                descending: false,
+                parseable: true,
+                json: false,
+                datasets: true,
+                snapshots: true,
+                space: false,
+                filters: &[],
+            },
+        )
+        .unwrap();
+
+        let output_str = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output_str.lines().collect();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[0], "default\tNR\t/\t\t950000000\t1623301740\t");
+        assert_eq!(lines[1], "default/usr\t\t\toff\t204800000\t1623301740\t");
+        assert_eq!(lines[3], "default/var\t\t/var\ton\t52428800\t1623301740\t");
+        // Datasets are listed before snapshots.
+        assert_eq!(
+            lines[4],
+            "default@2021-06-10-04:30\t\t\t\t404000\t1623303000\tAutomatic snapshot"
+        );
+    }
+
+    #[test]
+    fn test_print_boot_environments_json() {
+        let client = EmulatorClient::sampled();
+        let mut output = Vec::new();
+        print_boot_environments(
+            &client,
+            None,
+            &mut output,
+            PrintOptions {
+                be_name: &None,
+                sort_field: SortField::Date,
+                descending: false,
+                parseable: false,
+                json: true,
+                datasets: false,
+                snapshots: false,
+                space: false,
+                filters: &[],
+            },
+        )
+        .unwrap();
+
+        let value: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(value["version"], LIST_JSON_VERSION);
+        let bes = value["boot_environments"].as_array().unwrap();
+        assert_eq!(bes.len(), 2);
+        assert_eq!(
+            bes[0],
+            serde_json::json!({
+                "name": "default",
+                "root": "zfake/ROOT",
+                "guid": EmulatorClient::generate_guid("default"),
+                "description": null,
+                "mountpoint": "/",
+                "active": true,
+                "next_boot": true,
+                "boot_once": false,
+                "trial": false,
+                "pinned": false,
+                "tags": {},
+                "space": 950_000_000,
+                "usage": {
+                    "used_by_dataset": 949_192_000,
+                    "used_by_snapshots": 808_000,
+                    "referenced": 949_192_000,
+                    "written": 1_228_800,
+                    "compress_ratio": 1.85,
+                },
+                "origin": null,
+                "created": 1623301740,
+            })
+        );
+        assert_eq!(bes[1]["name"], "alt");
+        assert_eq!(bes[1]["description"], "Testing");
+        assert_eq!(bes[1]["mountpoint"], serde_json::Value::Null);
+        assert_eq!(bes[1]["origin"], "default@2021-06-10-05:10");
+        // Snapshots and datasets are only included on request.
+        assert!(bes[0].get("snapshots").is_none());
+        assert!(bes[0].get("datasets").is_none());
+    }
+
+    #[test]
+    fn test_print_boot_environments_json_with_snapshots() {
+        let client = EmulatorClient::sampled();
+        let mut output = Vec::new();
+        print_boot_environments(
+            &client,
+            None,
+            &mut output,
+            PrintOptions {
+                be_name: &Some("alt".to_string()),
+                sort_field: SortField::Date,
+                descending: false,
+                parseable: false,
+                json: true,
+                datasets: false,
+                snapshots: true,
+                space: false,
+                filters: &[],
+            },
+        )
+        .unwrap();
+
+        let value: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        let bes = value["boot_environments"].as_array().unwrap();
+        assert_eq!(bes.len(), 1);
+        assert_eq!(
+            bes[0]["snapshots"],
+            serde_json::json!([{
+                "name": "alt@backup",
+                "root": "zfake/ROOT",
+                "description": "Manual backup",
+                "space": 1024,
+                "referenced": 949_196_800,
+                "written": 8192,
+                "created": 1623306000,
+                "pinned": false,
+                "tags": {},
+            }])
+        );
+    }
+
+    #[test]
+    fn test_print_boot_environments_filter() {
+        let client = EmulatorClient::sampled();
+        client
+            .tag(&Label::Name("alt".to_string()), "purpose", "testing", None)
+            .unwrap();
+        client
+            .tag(
+                &Label::Snapshot("default".to_string(), "2021-06-10-04:30".to_string()),
+                "purpose",
+                "upgrade",
+                None,
+            )
+            .unwrap();
+        let print = |filters: &[TagFilter], snapshots: bool| {
+            let mut output = Vec::new();
+            print_boot_environments(
+                &client,
+                None,
+                &mut output,
+                PrintOptions {
+                    be_name: &None,
+                    sort_field: SortField::Date,
+                    descending: false,
+                    parseable: true,
+                    json: false,
+                    datasets: false,
+                    snapshots,
+                    space: false,
+                    filters,
+                },
+            )
+            .unwrap();
+            String::from_utf8(output)
+                .unwrap()
+                .lines()
+                .map(|line| line.split('\t').next().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let any = [parse_tag_filter("purpose").unwrap()];
+        assert_eq!(print(&any, false), vec!["alt"]);
+        assert_eq!(
+            print(&any, true),
+            vec!["default", "default@2021-06-10-04:30", "alt"]
+        );
+        let upgrade = [parse_tag_filter("purpose=upgrade").unwrap()];
+        assert!(print(&upgrade, false).is_empty());
+        assert_eq!(
+            print(&upgrade, true),
+            vec!["default", "default@2021-06-10-04:30"]
+        );
+        assert!(parse_tag_filter("=value").is_err());
+    }
+
+    #[test]
+    fn test_print_boot_environments_json_with_datasets() {
+        let client = EmulatorClient::sampled();
+        let mut output = Vec::new();
+        print_boot_environments(
+            &client,
+            None,
+            &mut output,
+            PrintOptions {
+                be_name: &Some("default".to_string()),
+                sort_field: SortField::Date,
+                descending: false,
+                parseable: false,
+                json: true,
+                datasets: true,
+                snapshots: false,
+                space: false,
+                filters: &[],
+            },
+        )
+        .unwrap();
+
+        let value: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        let datasets = value["boot_environments"][0]["datasets"]
+            .as_array()
+            .unwrap();
+        assert_eq!(datasets.len(), 3);
+        assert_eq!(
+            datasets[2],
+            serde_json::json!({
+                "name": "default/var",
+                "root": "zfake/ROOT",
+                "mountpoint": "/var",
+                "canmount": "on",
+                "space": 52_428_800,
+                "created": 1623301740,
+            })
+        );
+    }
+
+    #[test]
+    fn test_print_boot_environments_space() {
+        let client = EmulatorClient::sampled();
+        let mut output = Vec::new();
+        print_boot_environments(
+            &client,
+            None,
+            &mut output,
+            PrintOptions {
+                be_name: &Some("alt".to_string()),
+                sort_field: SortField::Date,
+                descending: false,
+                parseable: false,
+                json: false,
+                datasets: false,
+                snapshots: true,
+                space: true,
+                filters: &[],
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            r"NAME        USED   USEDSNAP  USEDDS  REFER  WRITTEN  RATIO  ORIGIN
+alt         8K     1K        7K      905M   7K       1.85x  default@2021-06-10-05:10
+alt@backup  1K     -         -       905M   8K       -      -
+"
+        );
+
+        let mut output = Vec::new();
+        print_boot_environments(
+            &client,
+            None,
+            &mut output,
+            PrintOptions {
+                be_name: &None,
+                sort_field: SortField::Date,
+                descending: false,
+                parseable: true,
+                json: false,
+                datasets: false,
+                snapshots: false,
+                space: true,
+                filters: &[],
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "default\t950000000\t808000\t949192000\t949192000\t1228800\t1.85\t\n\
+             alt\t8192\t1024\t7168\t949196800\t7168\t1.85\tdefault@2021-06-10-05:10\n"
+        );
+    }
+
+    #[test]
+    fn test_space_command() {
+        let client = EmulatorClient::sampled();
+        let mut output = Vec::new();
+        print_space_usage(&client, None, Some("alt"), &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            r"Boot environment:   alt
+Used:               8K
+  By dataset:       7K
+  By snapshots:     1K
+Referenced:         905M
+Written:            7K
+Compression ratio:  1.85x
+Origin:             default@2021-06-10-05:10
+
+SNAPSHOT    USED   REFER  WRITTEN  CREATED
+alt@backup  1K     905M   8K       2021-06-10 06:20
+
+Destroying 'alt' would free 8K; destroying only its snapshots would free 1K.
+"
+        );
+
+        // Defaults to the active boot environment.
+        let mut output = Vec::new();
+        print_space_usage(&client, None, None, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("Boot environment:   default\n"));
+
+        let result = print_space_usage(&client, None, Some("nonexistent"), Vec::new());
+        assert!(matches!(result, Err(Error::NotFound { .. })));
+    }
+
+    #[test]
+    fn test_print_destroy_plan() {
+        let plan = DestroyPlan {
+            unpin: vec!["alt@backup".to_string()],
+            unmount: vec!["/mnt/alt".to_string()],
+            promote: vec!["clone".to_string()],
+            snapshots: vec!["alt@backup".to_string()],
+            datasets: vec!["alt/var".to_string(), "alt".to_string()],
+            space: 8192,
+        };
+        let mut output = Vec::new();
+        print_destroy_plan(&plan, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            r"Would unpin 'alt@backup'.
+Would unmount '/mnt/alt'.
+Would promote 'clone'.
+Would destroy snapshot 'alt@backup'.
+Would destroy 'alt/var'.
+Would destroy 'alt'.
+Would reclaim approximately 8K.
+"
+        );
+    }
+
+    #[test]
+    fn test_parse_age() {
+        assert_eq!(parse_age("90"), Ok(90));
+        assert_eq!(parse_age("90s"), Ok(90));
+        assert_eq!(parse_age("5m"), Ok(300));
+        assert_eq!(parse_age("12h"), Ok(43_200));
+        assert_eq!(parse_age("30d"), Ok(2_592_000));
+        assert_eq!(parse_age("2w"), Ok(1_209_600));
+        assert!(parse_age("").is_err());
+        assert!(parse_age("d").is_err());
+        assert!(parse_age("-1d").is_err());
+        assert!(parse_age("3y").is_err());
+    }
+
+    #[test]
+    fn test_print_pruned() {
+        let pruned = vec![Pruned {
+            name: "default@2021-06-10-04:30".to_string(),
+            space: 404_000,
+        }];
+        let mut output = Vec::new();
+        print_pruned(&pruned, true, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            r"Would destroy 'default@2021-06-10-04:30' (395K).
+Would reclaim approximately 395K.
+"
+        );
+
+        let mut output = Vec::new();
+        print_pruned(&[], false, &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "Nothing to prune.\n");
+    }
+
+    #[test]
+    fn test_collect_garbage() {
+        let client = EmulatorClient::sampled();
+
+        // The only undescribed snapshot is still the origin of alt.
+        let mut output = Vec::new();
+        collect_garbage(&client, None, false, false, "".as_bytes(), &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "No orphaned snapshots.\n"
+        );
+
+        client
+            .destroy(
+                &Label::Name("alt".to_string()),
+                false,
+                true,
+                false,
+                false,
+                None,
+            )
+            .unwrap();
+        let mut output = Vec::new();
+        collect_garbage(&client, None, true, false, "".as_bytes(), &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            r"Would destroy 'default@2021-06-10-05:10' (395K).
+Would reclaim approximately 395K.
+"
+        );
+
+        let mut output = Vec::new();
+        collect_garbage(&client, None, false, false, "n\n".as_bytes(), &mut output).unwrap();
+        assert!(
+            String::from_utf8(output)
+                .unwrap()
+                .ends_with("Destroy these snapshots? [y/N] Nothing destroyed.\n")
+        );
+
+        let mut output = Vec::new();
+        collect_garbage(&client, None, false, false, "y\n".as_bytes(), &mut output).unwrap();
+        assert!(
+            String::from_utf8(output)
+                .unwrap()
+                .ends_with("[y/N] Destroyed 'default@2021-06-10-05:10'.\n")
+        );
+    }
 }
@@ -0,0 +1,360 @@
+// SPDX-License-Identifier: MPL-2.0
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::path::Path;
+use std::process::Command;
+use std::str::FromStr;
+
+use crate::be::packages::{PackageChangeKind, compare_packages};
+use crate::be::scan::PackageManager;
+use crate::be::{Client, Error, HealthIssue, Label, PackageChange, Root};
+use crate::exec;
+
+/// Initramfs generators, and the arguments that regenerate images for every
+/// installed kernel. Alpine's mkinitfs is absent because it only handles one
+/// kernel at a time, and is run by apk whenever a kernel is upgraded anyway.
+const INITRAMFS_GENERATORS: &[(&str, &[&str])] = &[
+    ("update-initramfs", &["-u", "-k", "all"]),
+    ("dracut", &["--regenerate-all", "--force"]),
+    ("mkinitcpio", &["-P"]),
+];
+
+/// A boot environment created by [`update`].
+#[derive(Debug)]
+pub struct Update {
+    pub name: String,
+    pub changes: Vec<PackageChange>,
+    /// Non-fatal issues found when checking it before activation.
+    pub issues: Vec<HealthIssue>,
+}
+
+/// Upgrade the system inside a new boot environment, rather than live: clone
+/// the active boot environment, run the package manager and regenerate the
+/// initramfs inside it, then describe it with the package changes and
+/// (provided it passes health checks) activate it for the next boot only.
+///
+/// If any step fails, the new boot environment is destroyed again, along with
+/// the snapshot it was cloned from. So is it if there turned out to be nothing
+/// to upgrade, in which case this returns None. Like
+/// [`exec::run_in_boot_environment`], this enters a private mount namespace
+/// and so the client must not be the D-Bus proxy.
+pub fn update<C: Client + ?Sized>(
+    client: &C,
+    name: Option<&str>,
+    root: Option<&Root>,
+) -> Result<Option<Update>, Error> {
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => generate_update_name(),
+    };
+    exec::enter_private_mount_namespace()?;
+    update_with(client, name, root, upgrade)
+}
+
+/// The steps of [`update`] around the upgrade itself, which is done by
+/// `upgrade`.
+fn update_with<C, F>(
+    client: &C,
+    name: String,
+    root: Option<&Root>,
+    upgrade: F,
+) -> Result<Option<Update>, Error>
+where
+    C: Client + ?Sized,
+    F: FnOnce(&C, &str, Option<&Root>) -> Result<Vec<PackageChange>, Error>,
+{
+    let label = Label::Name(name.clone());
+    client.create(&name, None, None, &[], root)?;
+
+    let result = client
+        .describe(&label, "beadm update (incomplete)", root)
+        .and_then(|()| upgrade(client, &name, root))
+        .and_then(|changes| {
+            if changes.is_empty() {
+                return Ok(None);
+            }
+            client.describe(&label, &describe_changes(&changes), root)?;
+            let issues = client.activate_checked(&name, true, false, root)?;
+            Ok(Some(Update {
+                name: name.clone(),
+                changes,
+                issues,
+            }))
+        });
+    if !matches!(result, Ok(Some(_))) {
+        let discarded = discard(client, &name, root);
+        if result.is_ok() {
+            discarded?;
+        }
+    }
+    result
+}
+
+/// Destroy a boot environment created by [`update`], along with the snapshot
+/// it was cloned from, which nothing else depends on.
+fn discard<C: Client + ?Sized>(client: &C, name: &str, root: Option<&Root>) -> Result<(), Error> {
+    let origin = client
+        .get_boot_environments(root)?
+        .into_iter()
+        .find(|be| be.name == name)
+        .and_then(|be| be.origin);
+    client.destroy(
+        &Label::Name(name.to_string()),
+        true,
+        true,
+        false,
+        false,
+        root,
+    )?;
+    if let Some(origin) = origin
+        && client
+            .get_orphaned_snapshots(root)?
+            .iter()
+            .any(|snapshot| snapshot.name == origin)
+    {
+        client.destroy(&Label::from_str(&origin)?, false, false, false, false, root)?;
+    }
+    Ok(())
+}
+
+/// Run the package manager inside a boot environment and regenerate its
+/// initramfs, returning the packages that changed.
+fn upgrade<C: Client + ?Sized>(
+    client: &C,
+    be_name: &str,
+    root: Option<&Root>,
+) -> Result<Vec<PackageChange>, Error> {
+    exec::with_boot_environment(client, be_name, root, |mountpoint| {
+        let manager =
+            PackageManager::detect(mountpoint).ok_or_else(|| Error::NoPackageDatabase {
+                name: be_name.to_string(),
+            })?;
+        let before = manager.installed_packages(mountpoint)?;
+        for args in upgrade_commands(manager) {
+            let mut command = Command::new(args[0]);
+            command.args(&args[1..]);
+            if manager == PackageManager::Dpkg {
+                command.env("DEBIAN_FRONTEND", "noninteractive");
+            }
+            run(mountpoint, &mut command, args)?;
+        }
+        let after = manager.installed_packages(mountpoint)?;
+        let changes = compare_packages(manager, &before, &after);
+
+        if !changes.is_empty()
+            && let Some((program, args)) = find_initramfs_generator(mountpoint)
+        {
+            let mut command = Command::new(program);
+            command.args(args);
+            run(mountpoint, &mut command, &[&[program], args].concat())?;
+        }
+        Ok(changes)
+    })
+}
+
+fn run(mountpoint: &Path, command: &mut Command, args: &[&str]) -> Result<(), Error> {
+    let status = exec::run_chrooted(mountpoint, command)?;
+    if !status.success() {
+        return Err(Error::CommandFailed {
+            command: args.join(" "),
+            status,
+        });
+    }
+    Ok(())
+}
+
+/// The commands that refresh the package lists and upgrade every package.
+fn upgrade_commands(manager: PackageManager) -> &'static [&'static [&'static str]] {
+    match manager {
+        PackageManager::Dpkg => &[&["apt-get", "update"], &["apt-get", "-y", "dist-upgrade"]],
+        PackageManager::Apk => &[&["apk", "upgrade", "--update-cache"]],
+        PackageManager::Pacman => &[&["pacman", "-Syu", "--noconfirm"]],
+        PackageManager::Rpm => &[&["dnf", "-y", "upgrade", "--refresh"]],
+    }
+}
+
+/// Find the initramfs generator installed in a boot environment, if any.
+fn find_initramfs_generator(mountpoint: &Path) -> Option<(&'static str, &'static [&'static str])> {
+    INITRAMFS_GENERATORS.iter().copied().find(|(program, _)| {
+        ["usr/sbin", "usr/bin", "sbin", "bin"]
+            .iter()
+            .any(|dir| mountpoint.join(dir).join(program).is_file())
+    })
+}
+
+/// Summarise package changes for the description of the boot environment,
+/// e.g. "beadm update: 12 upgraded, 1 added".
+fn describe_changes(changes: &[PackageChange]) -> String {
+    let counts: Vec<String> = [
+        PackageChangeKind::Upgraded,
+        PackageChangeKind::Added,
+        PackageChangeKind::Removed,
+        PackageChangeKind::Downgraded,
+    ]
+    .into_iter()
+    .filter_map(|kind| {
+        let count = changes.iter().filter(|c| c.change == kind).count();
+        (count > 0).then(|| format!("{} {}", count, kind.as_str()))
+    })
+    .collect();
+    format!("beadm update: {}", counts.join(", "))
+}
+
+/// Generate a name for the new boot environment based on the current time.
+fn generate_update_name() -> String {
+    chrono::Utc::now()
+        .format("update-%Y%m%d-%H%M%S")
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::be::EmulatorClient;
+
+    fn upgraded(name: &str) -> PackageChange {
+        PackageChange {
+            name: name.to_string(),
+            change: PackageChangeKind::Upgraded,
+            old_version: None,
+            new_version: None,
+        }
+    }
+
+    #[test]
+    fn test_update_with() {
+        let client = EmulatorClient::sampled();
+        let update = update_with(&client, "update".to_string(), None, |_, name, _| {
+            assert_eq!(name, "update");
+            Ok(vec![upgraded("bash")])
+        })
+        .unwrap()
+        .unwrap();
+        assert_eq!(update.name, "update");
+        assert_eq!(update.changes, vec![upgraded("bash")]);
+
+        let bes = client.get_boot_environments(None).unwrap();
+        let be = bes.iter().find(|be| be.name == "update").unwrap();
+        assert_eq!(be.description.as_deref(), Some("beadm update: 1 upgraded"));
+        assert!(be.boot_once);
+    }
+
+    #[test]
+    fn test_update_with_ends_trial() {
+        // The update is booted next instead of the boot environment on trial.
+        let client = EmulatorClient::sampled();
+        client.activate_trial("alt", None).unwrap();
+        update_with(&client, "update".to_string(), None, |_, _, _| {
+            Ok(vec![upgraded("bash")])
+        })
+        .unwrap()
+        .unwrap();
+        let bes = client.get_boot_environments(None).unwrap();
+        assert!(bes.iter().all(|be| !be.trial));
+        assert_eq!(client.confirm(None).unwrap(), None);
+    }
+
+    #[test]
+    fn test_update_with_nothing_to_upgrade() {
+        let client = EmulatorClient::sampled();
+        let before = client.get_boot_environments(None).unwrap();
+        let update = update_with(&client, "update".to_string(), None, |_, _, _| Ok(vec![]));
+        assert!(matches!(update, Ok(None)));
+        assert_eq!(client.get_boot_environments(None).unwrap(), before);
+    }
+
+    #[test]
+    fn test_update_with_failure() {
+        let client = EmulatorClient::sampled();
+        let before = client.get_boot_environments(None).unwrap();
+        let update = update_with(&client, "update".to_string(), None, |client, name, root| {
+            // The boot environment exists while the upgrade runs.
+            let bes = client.get_boot_environments(root).unwrap();
+            let be = bes.iter().find(|be| be.name == name).unwrap();
+            assert_eq!(be.description.as_deref(), Some("beadm update (incomplete)"));
+            Err(Error::NoPackageDatabase {
+                name: name.to_string(),
+            })
+        });
+        assert!(matches!(update, Err(Error::NoPackageDatabase { .. })));
+        assert_eq!(client.get_boot_environments(None).unwrap(), before);
+
+        // Nothing is created if the name is taken.
+        let update = update_with(&client, "alt".to_string(), None, |_, _, _| {
+            panic!("should not upgrade")
+        });
+        assert!(matches!(update, Err(Error::Conflict { .. })));
+        assert_eq!(client.get_boot_environments(None).unwrap(), before);
+    }
+
+    #[test]
+    fn test_update_with_unhealthy() {
+        let client = EmulatorClient::sampled();
+        let before = client.get_boot_environments(None).unwrap();
+        client.set_health_issues(
+            "update",
+            vec![HealthIssue {
+                check: "init".to_string(),
+                fatal: true,
+                message: "/sbin/init is missing".to_string(),
+            }],
+        );
+        let update = update_with(&client, "update".to_string(), None, |_, _, _| {
+            Ok(vec![upgraded("bash")])
+        });
+        assert!(matches!(update, Err(Error::Unhealthy { .. })));
+        assert_eq!(client.get_boot_environments(None).unwrap(), before);
+    }
+
+    #[test]
+    fn test_discard() {
+        // alt is the only clone of default@2021-06-10-05:10, which goes with
+        // it.
+        let client = EmulatorClient::sampled();
+        discard(&client, "alt", None).unwrap();
+        let bes = client.get_boot_environments(None).unwrap();
+        assert!(!bes.iter().any(|be| be.name == "alt"));
+        assert!(matches!(
+            discard(&client, "alt", None),
+            Err(Error::NotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn test_describe_changes() {
+        let change = |name: &str, change| PackageChange {
+            name: name.to_string(),
+            change,
+            old_version: None,
+            new_version: None,
+        };
+        let changes = [
+            change("bash", PackageChangeKind::Upgraded),
+            change("curl", PackageChangeKind::Upgraded),
+            change("htop", PackageChangeKind::Added),
+            change("zsh", PackageChangeKind::Removed),
+        ];
+        assert_eq!(
+            describe_changes(&changes),
+            "beadm update: 2 upgraded, 1 added, 1 removed"
+        );
+    }
+
+    #[test]
+    fn test_find_initramfs_generator() {
+        let root = tempfile::tempdir().unwrap();
+        assert_eq!(find_initramfs_generator(root.path()), None);
+
+        fs::create_dir_all(root.path().join("usr/bin")).unwrap();
+        fs::write(root.path().join("usr/bin/dracut"), "").unwrap();
+        assert_eq!(
+            find_initramfs_generator(root.path()),
+            Some(("dracut", &["--regenerate-all", "--force"][..]))
+        );
+    }
+}